@@ -1,109 +1,143 @@
-#![feature(test)]
-
-extern crate test;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+#[cfg(feature = "stream")]
+use lzma_rs::decompress::DynStream;
 
-use std::io::Read;
-use test::Bencher;
+const FOO_TXT: &[u8] = include_bytes!("../tests/files/foo.txt");
+const FOO_TXT_LZMA: &[u8] = include_bytes!("../tests/files/foo.txt.lzma");
+#[cfg(feature = "stream")]
+const HUGEDICT_TXT_LZMA: &[u8] = include_bytes!("../tests/files/hugedict.txt.lzma");
 
-fn decompress_after_compress_bench(x: &[u8], b: &mut Bencher) {
-    let mut compressed: Vec<u8> = Vec::new();
-    lzma_rs::lzma_compress(&mut std::io::BufReader::new(x), &mut compressed).unwrap();
+/// Header of a stream with a 4 GiB dictionary, followed by a short payload.
+const HUGE_DICT_HEADER: &[u8] = b"\x5d\x00\x40\x00\x00\xff\xff\xff\
+                                  \xff\xff\xff\xff\xff\x00\x24\x19\
+                                  \x49\x98\x6f\x10\x19\xc6\xd7\x31\
+                                  \xeb\x36\x50\xb2\x98\x48\xff\xfe\
+                                  \xa5\xb0\x00";
 
-    b.iter(|| {
-        let mut bf = std::io::BufReader::new(compressed.as_slice());
-        let mut decomp: Vec<u8> = Vec::new();
-        lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut bf, &mut decomp).unwrap();
-        decomp
-    });
-}
+/// Chunk sizes used to feed the streaming decoder.
+#[cfg(feature = "stream")]
+const CHUNK_SIZES: [usize; 4] = [1, 64, 4096, 1 << 16];
 
-fn decompress_bench<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>(
-    compressed: &[u8],
-    b: &mut Bencher,
-) {
-    b.iter(|| {
-        let mut bf = std::io::BufReader::new(compressed);
-        let mut decomp: Vec<u8> = Vec::new();
-        lzma_rs::lzma_decompress::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(&mut bf, &mut decomp)
-            .unwrap();
-        decomp
-    });
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress(&mut std::io::BufReader::new(data), &mut compressed).unwrap();
+    compressed
 }
 
-#[cfg(feature = "stream")]
-fn decompress_stream_bench<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>(
+fn decompress<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>(
     compressed: &[u8],
-    b: &mut Bencher,
-) {
-    use std::io::Write;
-    b.iter(|| {
-        let mut sink = Vec::new();
-        let mut stream = lzma_rs::decompress::Stream::<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>::new();
-        stream.reset();
-        stream.write_all(&mut sink, compressed).unwrap();
-        stream.finish(&mut sink).unwrap()
-    });
-}
-
-fn decompress_bench_file(compfile: &str, b: &mut Bencher) {
-    let mut f = std::fs::File::open(compfile).unwrap();
-    let mut compressed = Vec::new();
-    f.read_to_end(&mut compressed).unwrap();
-    decompress_bench::<4096, 8>(&compressed, b);
+) -> Vec<u8> {
+    let mut bf = std::io::BufReader::new(compressed);
+    let mut decomp = Vec::new();
+    lzma_rs::lzma_decompress::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(&mut bf, &mut decomp)
+        .unwrap();
+    decomp
 }
 
+/// Decode `compressed` with a heap-allocated stream, `chunk_size` bytes at a time.
 #[cfg(feature = "stream")]
-fn decompress_stream_bench_file(compfile: &str, b: &mut Bencher) {
-    let mut f = std::fs::File::open(compfile).unwrap();
-    let mut compressed = Vec::new();
-    f.read_to_end(&mut compressed).unwrap();
-    decompress_stream_bench::<4096, 8>(&compressed, b);
+fn decompress_stream(compressed: &[u8], dict_size: usize, chunk_size: usize) -> Vec<u8> {
+    let mut dict = vec![0; dict_size];
+    let mut probs = vec![[0; 0x300]; 8];
+    let mut stream = DynStream::new(&mut dict, &mut probs[..], &mut []);
+    stream.reset();
+    let mut decomp = Vec::new();
+    for chunk in compressed.chunks(chunk_size) {
+        stream.write_all(&mut decomp, chunk).unwrap();
+    }
+    stream.finish(&mut decomp).unwrap();
+    decomp
 }
 
-#[bench]
-fn decompress_after_compress_empty(b: &mut Bencher) {
-    #[cfg(feature = "enable_logging")]
+fn bench_decompress(c: &mut Criterion) {
+    #[cfg(feature = "log")]
     let _ = env_logger::try_init();
-    decompress_after_compress_bench(b"", b);
+    let mut group = c.benchmark_group("decompress");
+    for (name, data) in [
+        ("empty", &b""[..]),
+        ("hello", &b"Hello world"[..]),
+        ("zeros_65536", &[0; 0x10000][..]),
+    ] {
+        let compressed = compress(data);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("after_compress", name),
+            &compressed,
+            |b, c| b.iter(|| decompress::<4096, 8>(c)),
+        );
+    }
+    group.throughput(Throughput::Bytes(FOO_TXT.len() as u64));
+    group.bench_function("big_file", |b| {
+        b.iter(|| decompress::<4096, 8>(FOO_TXT_LZMA))
+    });
+    group.bench_function("big_file_from_slice", |b| {
+        b.iter(|| {
+            let mut decomp = Vec::new();
+            lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut &FOO_TXT_LZMA[..], &mut decomp).unwrap();
+            decomp
+        })
+    });
+    group.finish();
 }
 
-#[bench]
-fn decompress_after_compress_hello(b: &mut Bencher) {
-    #[cfg(feature = "enable_logging")]
+#[cfg(feature = "stream")]
+fn bench_decompress_stream(c: &mut Criterion) {
+    #[cfg(feature = "log")]
     let _ = env_logger::try_init();
-    decompress_after_compress_bench(b"Hello world", b);
+    let mut group = c.benchmark_group("decompress_stream");
+    group.throughput(Throughput::Bytes(FOO_TXT.len() as u64));
+    for chunk_size in CHUNK_SIZES {
+        group.bench_with_input(
+            BenchmarkId::new("big_file", chunk_size),
+            &chunk_size,
+            |b, &chunk_size| b.iter(|| decompress_stream(FOO_TXT_LZMA, 4096, chunk_size)),
+        );
+    }
+    group.finish();
 }
 
-#[bench]
-fn decompress_after_compress_65536(b: &mut Bencher) {
-    #[cfg(feature = "enable_logging")]
-    let _ = env_logger::try_init();
-    decompress_after_compress_bench(&[0; 0x10000], b);
-}
+#[cfg(not(feature = "stream"))]
+fn bench_decompress_stream(_: &mut Criterion) {}
 
-#[bench]
-fn decompress_big_file(b: &mut Bencher) {
-    #[cfg(feature = "enable_logging")]
+fn bench_big_dict(c: &mut Criterion) {
+    #[cfg(feature = "log")]
     let _ = env_logger::try_init();
-    decompress_bench_file("tests/files/foo.txt.lzma", b);
+    let mut group = c.benchmark_group("big_dict");
+    group.bench_function("huge_dict_header", |b| {
+        b.iter(|| decompress::<16384, 8>(HUGE_DICT_HEADER))
+    });
+    #[cfg(feature = "stream")]
+    {
+        // foo.txt.lzma has a 4 KiB dictionary, decode it with a much larger
+        // buffer to see the cost of an oversized dictionary.
+        group.throughput(Throughput::Bytes(FOO_TXT.len() as u64));
+        group.bench_function("big_file_1mib_dict", |b| {
+            b.iter(|| decompress_stream(FOO_TXT_LZMA, 1 << 20, 1 << 16))
+        });
+        // hugedict.txt.lzma has a 128 KiB dictionary.
+        let len = decompress_stream(HUGEDICT_TXT_LZMA, 1 << 17, 1 << 16).len();
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_function("hugedict", |b| {
+            b.iter(|| decompress_stream(HUGEDICT_TXT_LZMA, 1 << 17, 1 << 16))
+        });
+    }
+    group.finish();
 }
 
-#[cfg(feature = "stream")]
-#[bench]
-fn decompress_stream_big_file(b: &mut Bencher) {
-    #[cfg(feature = "enable_logging")]
+fn bench_compress(c: &mut Criterion) {
+    #[cfg(feature = "log")]
     let _ = env_logger::try_init();
-    decompress_stream_bench_file("tests/files/foo.txt.lzma", b);
+    let mut group = c.benchmark_group("compress");
+    group.throughput(Throughput::Bytes(FOO_TXT.len() as u64));
+    group.bench_function("big_file", |b| b.iter(|| compress(FOO_TXT)));
+    group.finish();
 }
 
-#[bench]
-fn decompress_huge_dict(b: &mut Bencher) {
-    #[cfg(feature = "enable_logging")]
-    let _ = env_logger::try_init();
-    let compressed: &[u8] = b"\x5d\x00\x40\x00\x00\xff\xff\xff\
-                              \xff\xff\xff\xff\xff\x00\x24\x19\
-                              \x49\x98\x6f\x10\x19\xc6\xd7\x31\
-                              \xeb\x36\x50\xb2\x98\x48\xff\xfe\
-                              \xa5\xb0\x00";
-    decompress_bench::<16384, 8>(&compressed, b);
-}
+criterion_group!(
+    benches,
+    bench_decompress,
+    bench_decompress_stream,
+    bench_big_dict,
+    bench_compress
+);
+criterion_main!(benches);
@@ -0,0 +1,20 @@
+/* Memory layout of an STM32H743, adjust it for your chip. */
+MEMORY
+{
+  FLASH  : ORIGIN = 0x08000000, LENGTH = 2M
+  /* DTCM, used for the stack and regular statics */
+  RAM    : ORIGIN = 0x20000000, LENGTH = 128K
+  /* AXI SRAM in domain D2, holds the decoder state */
+  RAM_D2 : ORIGIN = 0x30000000, LENGTH = 288K
+}
+
+SECTIONS
+{
+  /* Neither zeroed nor initialized by the runtime, only put
+     `MaybeUninit` statics here. */
+  .ram_d2 (NOLOAD) : ALIGN(4)
+  {
+    *(.ram_d2 .ram_d2.*);
+    . = ALIGN(4);
+  } > RAM_D2
+} INSERT AFTER .bss;
@@ -0,0 +1,106 @@
+//! Decode an LZMA stream stored in flash, with the dictionary placed in a
+//! dedicated RAM bank.
+//!
+//! Build with `cargo build --release` from the `examples` directory, see
+//! `memory.x` for the memory layout.
+
+#![no_std]
+#![no_main]
+
+use core::mem::MaybeUninit;
+use core::ptr;
+
+use cortex_m::asm;
+use cortex_m_rt::entry;
+use lzma_rs::decompress::BorrowedDictStream;
+use lzma_rs::error::Error;
+use lzma_rs::io;
+use panic_halt as _;
+
+/// Compressed data, stored in flash.
+static COMPRESSED: &[u8] = include_bytes!("../tests/files/foo.txt.lzma");
+/// Expected output, to check the decoder.
+static EXPECTED: &[u8] = include_bytes!("../tests/files/foo.txt");
+
+/// Largest dictionary size of the streams we accept.
+const DICT_SIZE: usize = 0x10000;
+/// Enough literal coders for the default `lc = 3, lp = 0`.
+const PROBS_MEM_LIMIT: usize = 8;
+
+/// The dictionary lives in RAM_D2, which is not initialized at startup.
+#[link_section = ".ram_d2"]
+static mut DICT: MaybeUninit<[u8; DICT_SIZE]> = MaybeUninit::uninit();
+
+/// Sink comparing the decompressed data with [`EXPECTED`]. A real
+/// application would write it to a peripheral or to external flash instead.
+struct Checker {
+    pos: usize,
+}
+
+impl io::Write for Checker {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match EXPECTED.get(self.pos..self.pos + buf.len()) {
+            Some(expected) if expected == buf => {
+                self.pos += buf.len();
+                Ok(buf.len())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected output",
+            )),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    // Safety: `main` runs once and is the only user of `DICT`. Zeroing it
+    // makes the bytes initialized, the decoder never reads them before
+    // writing them anyway.
+    let dict: &'static mut [u8] = unsafe {
+        let dict = &mut *ptr::addr_of_mut!(DICT);
+        ptr::write_bytes(dict.as_mut_ptr(), 0, 1);
+        dict.assume_init_mut()
+    };
+
+    // The rest of the decoder state (~16 KiB) is on the stack.
+    let mut stream = BorrowedDictStream::<PROBS_MEM_LIMIT>::with_dict(dict);
+    stream.reset();
+
+    let mut checker = Checker { pos: 0 };
+    let result = stream
+        .write_all(&mut checker, COMPRESSED)
+        .and_then(|()| stream.finish(&mut checker));
+
+    match result {
+        Ok(()) if checker.pos == EXPECTED.len() => {
+            // Success
+        }
+        Ok(()) => {
+            // The stream ended early
+            asm::bkpt();
+        }
+        Err(Error::DictionaryBufferTooSmall { needed, available }) => {
+            // The stream was compressed with a larger dictionary than
+            // `DICT_SIZE`, recompress it with e.g. `xz --lzma1=dict=64KiB`.
+            let _ = (needed, available);
+            asm::bkpt();
+        }
+        Err(Error::ProbabilitiesBufferTooSmall { .. }) => {
+            // `lc + lp` is too large for `PROBS_MEM_LIMIT`
+            asm::bkpt();
+        }
+        Err(_) => {
+            // Corrupted input, or `Checker` rejected the output
+            asm::bkpt();
+        }
+    }
+
+    loop {
+        asm::wfi();
+    }
+}
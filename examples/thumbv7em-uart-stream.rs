@@ -0,0 +1,148 @@
+//! Decode an LZMA stream received in small chunks over a (fake) UART, and
+//! recover from a dropped transmission.
+//!
+//! Build with `cargo build --release` from the `examples` directory, see
+//! `memory.x` for the memory layout.
+
+#![no_std]
+#![no_main]
+
+use core::ptr;
+
+use cortex_m::asm;
+use cortex_m_rt::entry;
+use lzma_rs::decompress::Stream;
+use lzma_rs::error::Error;
+use lzma_rs::io;
+use panic_halt as _;
+
+/// Data sent by the other end of the link.
+static TRANSMITTED: &[u8] = include_bytes!("../tests/files/foo.txt.lzma");
+/// Expected output, to check the decoder.
+static EXPECTED: &[u8] = include_bytes!("../tests/files/foo.txt");
+
+const DICT_SIZE: usize = 0x1000;
+const PROBS_MEM_LIMIT: usize = 8;
+/// Decompressed data is handed to the sink in chunks of this size, e.g. to
+/// match a DMA transfer or a flash page.
+const WRITE_BUF_LEN: usize = 256;
+
+type UartStream = Stream<DICT_SIZE, PROBS_MEM_LIMIT, WRITE_BUF_LEN>;
+
+// Fail the build if the decoder state grows past its budget.
+const _: () = assert!(UartStream::memory_footprint().total <= 24 * 1024);
+
+/// The decoder state, in `.bss`. `Stream::new` is `const`, so nothing is
+/// built on the stack.
+static mut STREAM: UartStream = Stream::new();
+
+/// Stand-in for a UART driver: returns whatever is in the RX FIFO, in chunks
+/// of varying size, and `None` once the line is idle.
+struct FakeUart {
+    data: &'static [u8],
+    pos: usize,
+    seed: u32,
+    /// Simulate a link failure by going idle after this many bytes.
+    drop_after: Option<usize>,
+}
+
+impl FakeUart {
+    fn new(data: &'static [u8]) -> Self {
+        FakeUart {
+            data,
+            pos: 0,
+            seed: 1,
+            drop_after: None,
+        }
+    }
+
+    /// Restart the transmission from the beginning.
+    fn request_retransmit(&mut self) {
+        self.pos = 0;
+        self.drop_after = None;
+    }
+
+    fn read<'a>(&mut self, fifo: &'a mut [u8; 16]) -> Option<&'a [u8]> {
+        let end = self.drop_after.unwrap_or(self.data.len());
+        if self.pos >= end {
+            return None;
+        }
+        self.seed = self.seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        let len = 1 + (self.seed >> 16) as usize % fifo.len();
+        let len = len.min(end - self.pos);
+        fifo[..len].copy_from_slice(&self.data[self.pos..self.pos + len]);
+        self.pos += len;
+        Some(&fifo[..len])
+    }
+}
+
+/// Sink comparing the decompressed data with [`EXPECTED`].
+struct Checker {
+    pos: usize,
+}
+
+impl io::Write for Checker {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match EXPECTED.get(self.pos..self.pos + buf.len()) {
+            Some(expected) if expected == buf => {
+                self.pos += buf.len();
+                Ok(buf.len())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected output",
+            )),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Feed everything received until the line goes idle to `stream`.
+fn receive(
+    stream: &mut UartStream,
+    uart: &mut FakeUart,
+    checker: &mut Checker,
+) -> Result<(), Error> {
+    let mut fifo = [0; 16];
+    while let Some(chunk) = uart.read(&mut fifo) {
+        stream.write_all(checker, chunk)?;
+    }
+    // Fails if the stream is incomplete
+    stream.finish(checker)
+}
+
+#[entry]
+fn main() -> ! {
+    // Safety: `main` runs once and is the only user of `STREAM`.
+    let stream = unsafe { &mut *ptr::addr_of_mut!(STREAM) };
+    stream.reset();
+
+    let mut uart = FakeUart::new(TRANSMITTED);
+    uart.drop_after = Some(1000);
+
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let mut checker = Checker { pos: 0 };
+        match receive(stream, &mut uart, &mut checker) {
+            Ok(()) if checker.pos == EXPECTED.len() => break,
+            _ if attempts < 3 => {
+                // Start over, the stream does not need to be reallocated.
+                stream.reset();
+                uart.request_retransmit();
+            }
+            _ => {
+                // Give up
+                asm::bkpt();
+                break;
+            }
+        }
+    }
+
+    loop {
+        asm::wfi();
+    }
+}
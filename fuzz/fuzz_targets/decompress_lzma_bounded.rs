@@ -0,0 +1,7 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+fuzz_target!(|data: &[u8]| {
+    let _decomp = lzma_rs::decompress::fuzz_decode(data, 1 << 20, 1 << 20);
+});
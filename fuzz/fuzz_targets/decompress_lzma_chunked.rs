@@ -0,0 +1,58 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use arbitrary::Arbitrary;
+use lzma_fuzz::FuzzOptions;
+use lzma_rs::decompress::{Options, Stream};
+use lzma_rs::error::Result;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    options: FuzzOptions,
+    /// Lengths of the chunks given to `Stream::write_all`, cycled over.
+    chunk_lens: Vec<u8>,
+    data: Vec<u8>,
+}
+
+fn decode_lzma_stream<'a>(
+    options: &Options,
+    chunks: impl Iterator<Item = &'a [u8]>,
+) -> Result<Vec<u8>> {
+    let mut stream = Box::new(Stream::<0x10000, 16>::new_with_options(options));
+    stream.reset();
+    let mut decomp = Vec::new();
+    for chunk in chunks {
+        stream.write_all(&mut decomp, chunk)?;
+    }
+    stream.finish(&mut decomp)?;
+    Ok(decomp)
+}
+
+/// Split `data` into chunks of the given lengths, cycled over. Zero lengths
+/// produce empty chunks, which must be accepted too.
+fn split<'a>(mut data: &'a [u8], lens: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+    let mut lens = lens.iter().cycle();
+    core::iter::from_fn(move || {
+        if data.is_empty() {
+            return None;
+        }
+        let len = usize::from(*lens.next().unwrap_or(&u8::MAX)).min(data.len());
+        let (chunk, rest) = data.split_at(len);
+        data = rest;
+        Some(chunk)
+    })
+}
+
+fuzz_target!(|input: Input| {
+    // Chunk boundaries must not change the result
+    let options = input.options.into();
+    let whole = decode_lzma_stream(&options, core::iter::once(&input.data[..]));
+    let chunked = decode_lzma_stream(&options, split(&input.data, &input.chunk_lens));
+    match (whole, chunked) {
+        (Err(_), Err(_)) => (),
+        (Ok(_), Err(e)) => panic!("whole input succeeded but chunked input failed: {:?}", e),
+        (Err(e), Ok(_)) => panic!("chunked input succeeded but whole input failed: {:?}", e),
+        (Ok(a), Ok(b)) => assert!(a == b),
+    }
+});
@@ -0,0 +1,21 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use arbitrary::Arbitrary;
+use lzma_fuzz::FuzzOptions;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    options: FuzzOptions,
+    data: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut output = vec![0; 1 << 16];
+    let _decomp = lzma_rs::lzma_decompress_to_slice_with_options::<_, 16>(
+        &mut &input.data[..],
+        &mut output,
+        &input.options.into(),
+    );
+});
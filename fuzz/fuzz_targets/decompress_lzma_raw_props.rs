@@ -0,0 +1,40 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use arbitrary::Arbitrary;
+use lzma_rs::error::{lzma::LzmaError, Error};
+
+/// Stream parameters given separately from the compressed data, as a
+/// container storing them out of band would. The header is rebuilt from
+/// them until the decoder takes them directly.
+#[derive(Arbitrary, Debug)]
+struct Input {
+    props: u8,
+    dict_size: u32,
+    unpacked_size: Option<u16>,
+    data: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut compressed = vec![input.props];
+    compressed.extend_from_slice(&input.dict_size.to_le_bytes());
+    let unpacked_size = input.unpacked_size.map_or(u64::MAX, u64::from);
+    compressed.extend_from_slice(&unpacked_size.to_le_bytes());
+    compressed.extend_from_slice(&input.data);
+
+    let result = lzma_rs::decompress::fuzz_decode(&compressed, 1 << 16, 1 << 20);
+    if input.props >= 225 {
+        assert!(
+            matches!(
+                result,
+                Err(Error::LzmaError(LzmaError::InvalidProperties { .. }))
+            ),
+            "invalid properties accepted: {:?}",
+            result
+        );
+    }
+    if let (Ok(decomp), Some(unpacked_size)) = (result, input.unpacked_size) {
+        assert_eq!(decomp.len(), usize::from(unpacked_size));
+    }
+});
@@ -0,0 +1,26 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use arbitrary::Arbitrary;
+use lzma_fuzz::FuzzOptions;
+use lzma_rs::decompress::{LzmaParams, Options};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    options: FuzzOptions,
+    data: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    let options: Options = input.options.into();
+    let params = match LzmaParams::read_header(&mut &input.data[..], &options) {
+        Ok(params) => params,
+        Err(_) => return,
+    };
+    assert_eq!(params.props().to_byte(), input.data[0]);
+    assert!(params.dict_size() >= 0x1000);
+    if let Some(max_dict_size) = options.max_dict_size {
+        assert!(params.dict_size() <= max_dict_size);
+    }
+});
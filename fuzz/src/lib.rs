@@ -0,0 +1,54 @@
+//! Structured inputs shared by the fuzzing targets.
+
+use arbitrary::Arbitrary;
+use lzma_rs::decompress::{Options, TrailingBytes, UnpackedSize};
+
+/// Arbitrary counterpart of [`UnpackedSize`].
+#[derive(Arbitrary, Clone, Copy, Debug)]
+pub enum FuzzUnpackedSize {
+    ReadFromHeader,
+    ReadHeaderButUseProvided(Option<u64>),
+    ReadFromHeader32,
+    UseProvided(Option<u64>),
+}
+
+/// Arbitrary counterpart of [`TrailingBytes`].
+#[derive(Arbitrary, Clone, Copy, Debug)]
+pub enum FuzzTrailingBytes {
+    Error,
+    Ignore,
+    ReturnCount,
+}
+
+/// Arbitrary counterpart of [`Options`].
+#[derive(Arbitrary, Clone, Copy, Debug)]
+pub struct FuzzOptions {
+    pub unpacked_size: FuzzUnpackedSize,
+    pub reject_small_dict: bool,
+    pub max_dict_size: Option<u32>,
+    pub max_symbols_per_write: Option<u32>,
+    pub trailing_bytes: Option<FuzzTrailingBytes>,
+}
+
+impl From<FuzzOptions> for Options {
+    fn from(options: FuzzOptions) -> Self {
+        Options {
+            unpacked_size: match options.unpacked_size {
+                FuzzUnpackedSize::ReadFromHeader => UnpackedSize::ReadFromHeader,
+                FuzzUnpackedSize::ReadHeaderButUseProvided(x) => {
+                    UnpackedSize::ReadHeaderButUseProvided(x)
+                }
+                FuzzUnpackedSize::ReadFromHeader32 => UnpackedSize::ReadFromHeader32,
+                FuzzUnpackedSize::UseProvided(x) => UnpackedSize::UseProvided(x),
+            },
+            reject_small_dict: options.reject_small_dict,
+            max_dict_size: options.max_dict_size,
+            max_symbols_per_write: options.max_symbols_per_write,
+            trailing_bytes: options.trailing_bytes.map(|x| match x {
+                FuzzTrailingBytes::Error => TrailingBytes::Error,
+                FuzzTrailingBytes::Ignore => TrailingBytes::Ignore,
+                FuzzTrailingBytes::ReturnCount => TrailingBytes::ReturnCount,
+            }),
+        }
+    }
+}
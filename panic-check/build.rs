@@ -0,0 +1,5 @@
+fn main() {
+    // The binary provides its own entry point
+    println!("cargo:rustc-link-arg-bins=-nostartfiles");
+    println!("cargo:rustc-link-arg-bins=-lc");
+}
@@ -0,0 +1,56 @@
+//! Link-time check that the decoder contains no panic path.
+//!
+//! The panic handler references a symbol that does not exist, so linking
+//! fails if any panic survives optimizations. Build with
+//! `cargo build --release`.
+
+#![no_std]
+#![no_main]
+
+use lzma_rs::decompress::Stream;
+use lzma_rs::io::{Result, Write};
+
+struct Sink;
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        // Keep the decoded data observable
+        for &byte in buf {
+            unsafe { core::ptr::read_volatile(&byte) };
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+static mut STREAM: Stream<4096, 8, 64> = Stream::new();
+static mut INPUT: [u8; 256] = [0; 256];
+static mut OUTPUT: [u8; 4096] = [0; 4096];
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    let input = unsafe { core::ptr::read_volatile(&*core::ptr::addr_of!(INPUT)) };
+    let stream = unsafe { &mut *core::ptr::addr_of_mut!(STREAM) };
+    stream.reset();
+    let _ = stream.write_all(&mut Sink, &input);
+    let _ = stream.finish(&mut Sink);
+
+    // Decoding straight into a slice, which is also the dictionary
+    let output = unsafe { &mut *core::ptr::addr_of_mut!(OUTPUT) };
+    if let Ok(len) = lzma_rs::lzma_decompress_to_slice::<_, 8>(&mut &input[..], output) {
+        let _ = Sink.write(output.get(..len).unwrap_or(&[]));
+    }
+    loop {}
+}
+
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    extern "Rust" {
+        #[link_name = "\n\nERROR: the decoder contains a panic path\n\n"]
+        fn panic_path_found() -> !;
+    }
+    unsafe { panic_path_found() }
+}
@@ -0,0 +1,146 @@
+//! Command line front end to the crate, for build scripts and end-to-end
+//! testing of the public API.
+//!
+//! ```text
+//! lzma-ns <compress|decompress|probe-header|stats|dict-size> [INPUT [OUTPUT]]
+//! ```
+//!
+//! `INPUT` and `OUTPUT` default to the standard input and output, also
+//! selected with `-`.
+
+use lzma_rs::decompress::{self, DynStream, LzmaParams};
+use lzma_rs::error::Result;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+
+const USAGE: &str =
+    "usage: lzma-ns <compress|decompress|probe-header|stats|dict-size> [INPUT [OUTPUT]]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (command, input, output) = match args.as_slice() {
+        [command] => (command, None, None),
+        [command, input] => (command, Some(input), None),
+        [command, input, output] => (command, Some(input), Some(output)),
+        _ => return usage(),
+    };
+    let run = match command.as_str() {
+        "compress" => compress,
+        "decompress" => decompress,
+        "probe-header" => probe_header,
+        "stats" => stats,
+        "dict-size" => dict_size,
+        _ => return usage(),
+    };
+    let res = (|| -> Result<()> {
+        let data = read_input(input)?;
+        let mut output = open_output(output)?;
+        run(&data, &mut output)?;
+        output.flush()?;
+        Ok(())
+    })();
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("lzma-ns: {:?}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::from(2)
+}
+
+fn read_input(path: Option<&String>) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    match path.map(String::as_str) {
+        None | Some("-") => io::stdin().lock().read_to_end(&mut data)?,
+        Some(path) => File::open(path)?.read_to_end(&mut data)?,
+    };
+    Ok(data)
+}
+
+fn open_output(path: Option<&String>) -> io::Result<Box<dyn Write>> {
+    Ok(match path.map(String::as_str) {
+        None | Some("-") => Box::new(io::stdout().lock()),
+        Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+    })
+}
+
+fn compress(data: &[u8], mut output: &mut dyn Write) -> Result<()> {
+    lzma_rs::lzma_compress(&mut &data[..], &mut output)?;
+    Ok(())
+}
+
+/// Decompress `data` with buffers sized after its header, calling `done`
+/// with the stream once finished.
+fn decompress_with(
+    data: &[u8],
+    output: &mut dyn Write,
+    done: impl FnOnce(&DynStream) -> Result<()>,
+) -> Result<()> {
+    let params = LzmaParams::read_header(&mut &data[..], &decompress::Options::default())?;
+    let mut dict = vec![0; params.dict_size().max(4096) as usize];
+    let mut probs = vec![[0; 0x300]; params.props().literal_coders()];
+    let mut stream = DynStream::new(&mut dict, &mut probs, &mut []);
+    stream.reset();
+    stream.write_all(output, data)?;
+    done(&stream)?;
+    stream.finish(output)
+}
+
+fn decompress(data: &[u8], output: &mut dyn Write) -> Result<()> {
+    decompress_with(data, output, |_| Ok(()))
+}
+
+fn probe_header(data: &[u8], output: &mut dyn Write) -> Result<()> {
+    let format = decompress::detect_format(data);
+    writeln!(output, "format: {:?}", format)?;
+    if format == Some(decompress::Format::Lzma) {
+        let params = LzmaParams::read_header(&mut &data[..], &decompress::Options::default())?;
+        let props = params.props();
+        writeln!(output, "lc: {}", props.lc)?;
+        writeln!(output, "lp: {}", props.lp)?;
+        writeln!(output, "pb: {}", props.pb)?;
+        writeln!(output, "dict_size: {}", params.dict_size())?;
+        match params.unpacked_size() {
+            Some(size) => writeln!(output, "unpacked_size: {}", size)?,
+            None => writeln!(output, "unpacked_size: unknown")?,
+        }
+        writeln!(output, "literal_coders: {}", props.literal_coders())?;
+    }
+    Ok(())
+}
+
+fn stats(data: &[u8], output: &mut dyn Write) -> Result<()> {
+    let mut stats = None;
+    decompress_with(data, &mut io::sink(), |stream| {
+        stats = Some(stream.stats());
+        Ok(())
+    })?;
+    if let Some(stats) = stats {
+        writeln!(output, "literals: {}", stats.literals)?;
+        writeln!(output, "matches: {}", stats.matches)?;
+        writeln!(output, "rep_matches: {}", stats.rep_matches)?;
+        writeln!(output, "match_bytes: {}", stats.match_bytes)?;
+        writeln!(
+            output,
+            "average_match_len: {:.2}",
+            stats.average_match_len()
+        )?;
+        writeln!(output, "max_distance: {}", stats.max_distance)?;
+        writeln!(output, "dict_high_water: {}", stats.dict_high_water)?;
+    }
+    Ok(())
+}
+
+fn dict_size(data: &[u8], output: &mut dyn Write) -> Result<()> {
+    let analysis = decompress::analyze_dict_size(data, &decompress::Options::default())?;
+    writeln!(output, "dict_size: {}", analysis.params.dict_size())?;
+    writeln!(output, "max_distance: {}", analysis.max_distance)?;
+    writeln!(output, "min_dict_size: {}", analysis.min_dict_size())?;
+    Ok(())
+}
@@ -0,0 +1,165 @@
+//! CRC checksums used by the LZMA container formats.
+//!
+//! Both checksums use 16-entry (nibble) lookup tables generated at compile
+//! time, trading some speed for a tiny memory footprint.
+//!
+//! ```
+//! use lzma_rs::checksum::{Crc32, Crc64};
+//!
+//! assert_eq!(Crc32::checksum(b"123456789"), 0xCBF4_3926);
+//!
+//! let mut crc = Crc64::new();
+//! crc.update(b"1234");
+//! crc.update(b"56789");
+//! assert_eq!(crc.finalize(), 0x995D_C9BB_DF19_39FA);
+//! ```
+
+/// Reflected polynomial of CRC-32 (IEEE 802.3), used by XZ and lzip.
+const CRC32_POLY: u32 = 0xEDB8_8320;
+/// Reflected polynomial of CRC-64 (ECMA-182), used by XZ.
+const CRC64_POLY: u64 = 0xC96C_5795_D787_0F42;
+
+const CRC32_TABLE: [u32; 16] = {
+    let mut table = [0; 16];
+    let mut i = 0;
+    while i < 16 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 4 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC32_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+const CRC64_TABLE: [u64; 16] = {
+    let mut table = [0; 16];
+    let mut i = 0;
+    while i < 16 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 4 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC64_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Incremental CRC-32 (IEEE 802.3) computation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    /// Start a new checksum computation.
+    pub const fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    /// Compute the checksum of `data` in one go.
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut crc = Self::new();
+        crc.update(data);
+        crc.finalize()
+    }
+
+    /// Feed `data` into the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        let mut crc = self.state;
+        for &byte in data {
+            crc ^= byte as u32;
+            crc = (crc >> 4) ^ CRC32_TABLE[(crc & 0xF) as usize];
+            crc = (crc >> 4) ^ CRC32_TABLE[(crc & 0xF) as usize];
+        }
+        self.state = crc;
+    }
+
+    /// Checksum of the data fed so far.
+    pub const fn finalize(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Incremental CRC-64 (ECMA-182, as used by XZ) computation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crc64 {
+    state: u64,
+}
+
+impl Crc64 {
+    /// Start a new checksum computation.
+    pub const fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    /// Compute the checksum of `data` in one go.
+    pub fn checksum(data: &[u8]) -> u64 {
+        let mut crc = Self::new();
+        crc.update(data);
+        crc.finalize()
+    }
+
+    /// Feed `data` into the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        let mut crc = self.state;
+        for &byte in data {
+            crc ^= byte as u64;
+            crc = (crc >> 4) ^ CRC64_TABLE[(crc & 0xF) as usize];
+            crc = (crc >> 4) ^ CRC64_TABLE[(crc & 0xF) as usize];
+        }
+        self.state = crc;
+    }
+
+    /// Checksum of the data fed so far.
+    pub const fn finalize(&self) -> u64 {
+        !self.state
+    }
+}
+
+impl Default for Crc64 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn verify_crc32() {
+        assert_eq!(Crc32::checksum(b""), 0);
+        assert_eq!(Crc32::checksum(b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            Crc32::checksum(b"The quick brown fox jumps over the lazy dog"),
+            0x414F_A339
+        );
+    }
+
+    #[test]
+    fn verify_crc64() {
+        assert_eq!(Crc64::checksum(b""), 0);
+        assert_eq!(Crc64::checksum(b"123456789"), 0x995D_C9BB_DF19_39FA);
+    }
+}
@@ -0,0 +1,5 @@
+//! Framing of LZMA payloads inside other data.
+
+#[cfg(feature = "mini-frame")]
+pub mod mini_frame;
+pub mod simple_frame;
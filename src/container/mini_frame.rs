@@ -0,0 +1,227 @@
+//! Compact framing of payloads up to 255 bytes, e.g. radio messages, for
+//! which the 13-byte `.lzma` header would cost more than compression saves.
+//!
+//! A frame is one byte holding the length of the uncompressed payload,
+//! followed by raw LZMA data. The [`LzmaProperties`] are not part of the
+//! frame: both ends agree on them beforehand. The LZMA data is also trimmed of
+//! what the decoder can infer, i.e. the first byte written by the range
+//! encoder, which is always zero, and any trailing zero bytes. A frame is
+//! therefore not self-delimiting: it must span the whole message carrying it.
+//!
+//! ```
+//! # #[cfg(feature = "encoder")]
+//! # {
+//! use lzma_rs::container::mini_frame;
+//! use lzma_rs::properties::LzmaProperties;
+//!
+//! // Agreed on out of band
+//! let properties = LzmaProperties::new(0, 0, 0).unwrap();
+//! let message = b"{\"t\":21.5,\"h\":40,\"t2\":21.5}";
+//!
+//! let mut frame = Vec::new();
+//! mini_frame::compress_frame(&mut frame, properties, message).unwrap();
+//! assert!(frame.len() < 13 + message.len());
+//!
+//! let mut decompressed = [0; mini_frame::MAX_PAYLOAD_LEN];
+//! let len = mini_frame::decompress_frame::<1>(&frame, properties, &mut decompressed).unwrap();
+//! assert_eq!(&decompressed[..len], &message[..]);
+//! # }
+//! ```
+
+use crate::decode::lzbuffer::LzSliceBuffer;
+use crate::decode::lzma::{DecoderState, LiteralCoder, LzmaParams, MIN_DICT_SIZE};
+use crate::decode::rangecoder::RangeDecoder;
+use crate::decode::util;
+use crate::error::{self, frame::FrameError};
+use crate::io;
+use crate::option::GuaranteedOption;
+use crate::properties::LzmaProperties;
+
+/// Largest payload a frame can hold, as its length is stored on one byte.
+pub const MAX_PAYLOAD_LEN: usize = 255;
+
+/// Compress `payload` with `properties` and write the resulting frame to
+/// `output`.
+///
+/// Fails with [`FrameError::PayloadTooLarge`] if `payload` is longer than
+/// [`MAX_PAYLOAD_LEN`].
+#[cfg(feature = "encoder")]
+pub fn compress_frame<W: io::Write>(
+    output: &mut W,
+    properties: LzmaProperties,
+    payload: &[u8],
+) -> error::Result<()> {
+    use crate::compress::{HeaderFormat, Options, UnpackedSize};
+    use core::convert::TryFrom;
+
+    let len = u8::try_from(payload.len()).map_err(|_| FrameError::PayloadTooLarge {
+        len: payload.len() as u64,
+    })?;
+    let mut data = Vec::new();
+    crate::lzma_compress_with_options(
+        &mut &payload[..],
+        &mut data,
+        &Options {
+            // A known size leaves out the end marker
+            unpacked_size: UnpackedSize::WriteToHeader(Some(u64::from(len))),
+            properties,
+            header: HeaderFormat::Raw,
+            ..Options::default()
+        },
+    )?;
+    let end = data
+        .iter()
+        .rposition(|&byte| byte != 0)
+        .map_or(0, |i| i + 1);
+    output.write_all(&[len])?;
+    output.write_all(data.get(1..end).unwrap_or(&[]))?;
+    Ok(())
+}
+
+/// Decompress `frame`, compressed with `properties`, into `output`. Returns
+/// the length of the payload, which starts `output`.
+///
+/// Fails with [`error::Error::OutputBufferTooSmall`] if the payload does not
+/// fit into `output`, which never happens for an `output` of
+/// [`MAX_PAYLOAD_LEN`] bytes.
+pub fn decompress_frame<const PROBS_MEM_LIMIT: usize>(
+    frame: &[u8],
+    properties: LzmaProperties,
+    output: &mut [u8],
+) -> error::Result<usize> {
+    let (len, data) = match frame.split_first() {
+        Some((len, data)) => (usize::from(*len), data),
+        None => return Err(FrameError::Truncated { have: 0 }.into()),
+    };
+    let available = output.len();
+    let output = output
+        .get_mut(..len)
+        .ok_or(error::Error::OutputBufferTooSmall { available })?;
+
+    let mut decoder =
+        DecoderState::<_, [LiteralCoder; PROBS_MEM_LIMIT]>::with_buffer(LzSliceBuffer::new(output));
+    decoder.reset();
+    decoder.set_params(LzmaParams {
+        props: properties,
+        dict_size: MIN_DICT_SIZE,
+        unpacked_size: GuaranteedOption::Some(len as u64),
+    })?;
+
+    // The leading zero byte is left out of the frame, so the range decoder is
+    // started by hand
+    let mut input = ZeroPadded(data);
+    let mut code = 0;
+    for _ in 0..4 {
+        code = (code << 8) | u32::from(util::read_byte(&mut input)?);
+    }
+    let mut rangecoder = RangeDecoder::from_parts(&mut input, 0xFFFF_FFFF, code);
+    // `LzSliceBuffer` never writes to the sink; decompressed data stays in
+    // `output`
+    let mut sink: &mut [u8] = &mut [];
+    decoder.process(&mut sink, &mut rangecoder)?;
+    Ok(len)
+}
+
+/// Reader giving back the trailing zero bytes left out of a frame: the data,
+/// followed by as many zeros as the decoder asks for. The decoder stops at the
+/// payload length, so it never reads past the original data.
+struct ZeroPadded<'a>(&'a [u8]);
+
+const ZEROS: [u8; 8] = [0; 8];
+
+impl io::Read for ZeroPadded<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match self.0.read(buf)? {
+            0 => {
+                buf.fill(0);
+                buf.len()
+            }
+            n => n,
+        };
+        Ok(n)
+    }
+}
+
+impl io::BufRead for ZeroPadded<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.0.is_empty() {
+            Ok(&ZEROS)
+        } else {
+            Ok(self.0)
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.0 = self.0.get(amt..).unwrap_or(&[]);
+    }
+}
+
+#[cfg(all(test, feature = "encoder"))]
+mod test {
+    use super::*;
+
+    const MESSAGE: &[u8] = b"Temperature 21.5C, humidity 40%, battery 3.71V, \
+        temperature 21.5C, humidity 41%, battery 3.70V, temperature 21.6C, \
+        humidity 41%, battery 3.70V, temperature 21.6C, humidity 41%, battery 3.69V, \
+        temperature 21.7C, humidity 42%, battery 3.69V, temperature 21.7C, \
+        humidity 42%, battery 3.68V, temperature 21.8C, humidity 42%, battery";
+
+    #[test]
+    fn test_round_trip() {
+        for &(lc, lp, pb) in &[(3, 0, 2), (0, 0, 0), (0, 2, 2), (1, 1, 0)] {
+            let properties = LzmaProperties::new(lc, lp, pb).unwrap();
+            for len in (0..=64).chain(Some(MAX_PAYLOAD_LEN)) {
+                let payload = &MESSAGE[..len];
+                let mut frame = Vec::new();
+                compress_frame(&mut frame, properties, payload).unwrap();
+                assert_eq!(frame[0] as usize, len);
+
+                // Against the same data in a `.lzma` file, at least the
+                // header and the first range coder byte are saved, minus the
+                // length byte
+                let mut lzma = Vec::new();
+                let options = crate::compress::Options {
+                    unpacked_size: crate::compress::UnpackedSize::WriteToHeader(Some(len as u64)),
+                    properties,
+                    ..Default::default()
+                };
+                crate::lzma_compress_with_options(&mut &payload[..], &mut lzma, &options).unwrap();
+                assert!(frame.len() + 13 <= lzma.len(), "{} bytes", len);
+
+                let mut output = [0xAA; MAX_PAYLOAD_LEN];
+                assert_eq!(
+                    decompress_frame::<8>(&frame, properties, &mut output).unwrap(),
+                    len
+                );
+                assert_eq!(&output[..len], payload, "{:?} {} bytes", properties, len);
+            }
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let properties = LzmaProperties::DEFAULT;
+        match compress_frame(&mut Vec::new(), properties, &[0; MAX_PAYLOAD_LEN + 1]) {
+            Err(error::Error::FrameError(FrameError::PayloadTooLarge { len: 256 })) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match decompress_frame::<8>(&[], properties, &mut [0; 16]) {
+            Err(error::Error::FrameError(FrameError::Truncated { have: 0 })) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let mut frame = Vec::new();
+        compress_frame(&mut frame, properties, &MESSAGE[..20]).unwrap();
+        match decompress_frame::<8>(&frame, properties, &mut [0; 19]) {
+            Err(error::Error::OutputBufferTooSmall { available: 19 }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match decompress_frame::<4>(&frame, properties, &mut [0; 20]) {
+            Err(error::Error::ProbabilitiesBufferTooSmall {
+                needed: 8,
+                available: 4,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
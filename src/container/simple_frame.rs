@@ -0,0 +1,193 @@
+//! Minimal `[magic|len|crc32|payload]` framing of a compressed payload.
+//!
+//! The 12-byte header holds three little-endian `u32` fields, whatever the
+//! endianness of the target:
+//!
+//! | Offset | Field   | Content                                     |
+//! |--------|---------|---------------------------------------------|
+//! | 0      | `magic` | Application-defined value                   |
+//! | 4      | `len`   | Length of the payload in bytes              |
+//! | 8      | `crc32` | [`Crc32`] of the payload                    |
+//!
+//! The payload follows the header. It is usually an `.lzma` stream, but the
+//! framing does not care about its content.
+//!
+//! ```
+//! # #[cfg(feature = "std")]
+//! # {
+//! use lzma_rs::container::simple_frame;
+//!
+//! const MAGIC: u32 = 0x4C5A_4D41;
+//! let compressed = include_bytes!("../../tests/files/foo.txt.lzma");
+//!
+//! let mut framed = Vec::new();
+//! simple_frame::write_frame(&mut framed, MAGIC, compressed).unwrap();
+//! framed.extend_from_slice(b"next record");
+//!
+//! let mut decompressed = Vec::new();
+//! let rest = simple_frame::decompress_frame::<_, 4096, 8>(&framed, MAGIC, &mut decompressed)
+//!     .unwrap();
+//! assert_eq!(decompressed, include_bytes!("../../tests/files/foo.txt"));
+//! assert_eq!(rest, b"next record");
+//! # }
+//! ```
+
+use crate::checksum::Crc32;
+use crate::error::{self, frame::FrameError};
+use crate::io;
+use core::convert::TryFrom;
+
+/// Size of the frame header in bytes.
+pub const HEADER_LEN: usize = 12;
+
+/// Header of a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FrameHeader {
+    /// Application-defined value identifying the frame.
+    pub magic: u32,
+    /// Length of the payload in bytes.
+    pub len: u32,
+    /// CRC-32 of the payload.
+    pub crc32: u32,
+}
+
+impl FrameHeader {
+    /// Build the header of a frame holding `payload`.
+    pub fn new(magic: u32, payload: &[u8]) -> Result<Self, FrameError> {
+        let len = u32::try_from(payload.len()).map_err(|_| FrameError::PayloadTooLarge {
+            len: payload.len() as u64,
+        })?;
+        Ok(Self {
+            magic,
+            len,
+            crc32: Crc32::checksum(payload),
+        })
+    }
+
+    /// Serialize the header.
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&self.magic.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.len.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.crc32.to_le_bytes());
+        bytes
+    }
+
+    /// Deserialize a header, without checking any field.
+    pub fn from_bytes(bytes: &[u8; HEADER_LEN]) -> Self {
+        let field =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Self {
+            magic: field(0),
+            len: field(4),
+            crc32: field(8),
+        }
+    }
+}
+
+/// Write a frame holding `payload` to `output`.
+pub fn write_frame<W: io::Write>(output: &mut W, magic: u32, payload: &[u8]) -> error::Result<()> {
+    let header = FrameHeader::new(magic, payload)?;
+    output.write_all(&header.to_bytes())?;
+    output.write_all(payload)?;
+    Ok(())
+}
+
+/// Parse the frame at the start of `input` and check its magic value and
+/// checksum. Returns the payload and the data following the frame.
+pub fn read_frame(input: &[u8], magic: u32) -> Result<(&[u8], &[u8]), FrameError> {
+    let truncated = FrameError::Truncated {
+        have: input.len() as u64,
+    };
+    let header = match input.get(..HEADER_LEN) {
+        Some(bytes) => {
+            let mut header = [0; HEADER_LEN];
+            header.copy_from_slice(bytes);
+            FrameHeader::from_bytes(&header)
+        }
+        None => return Err(truncated),
+    };
+    if header.magic != magic {
+        return Err(FrameError::MagicMismatch {
+            magic: header.magic,
+        });
+    }
+    let end = usize::try_from(header.len)
+        .ok()
+        .and_then(|len| len.checked_add(HEADER_LEN))
+        .ok_or(truncated)?;
+    let payload = input.get(HEADER_LEN..end).ok_or(truncated)?;
+    let crc32 = Crc32::checksum(payload);
+    if crc32 != header.crc32 {
+        return Err(FrameError::ChecksumMismatch {
+            expected: header.crc32,
+            actual: crc32,
+        });
+    }
+    Ok((payload, input.get(end..).unwrap_or(&[])))
+}
+
+/// Parse the frame at the start of `input` and decompress its LZMA payload
+/// to `output`, with default [`Options`](crate::decompress::Options).
+/// Returns the data following the frame.
+///
+/// The checksum is verified before decompressing, so nothing is written to
+/// `output` for a corrupted frame.
+pub fn decompress_frame<
+    'a,
+    W: io::Write,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    input: &'a [u8],
+    magic: u32,
+    output: &mut W,
+) -> error::Result<&'a [u8]> {
+    let (mut payload, rest) = read_frame(input, magic)?;
+    crate::lzma_decompress::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(&mut payload, output)?;
+    Ok(rest)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_layout() {
+        let mut framed = Vec::new();
+        write_frame(&mut framed, 0x0403_0201, b"123456789").unwrap();
+        assert_eq!(framed[..8], [1, 2, 3, 4, 9, 0, 0, 0]);
+        assert_eq!(framed[8..12], 0xCBF4_3926u32.to_le_bytes());
+        assert_eq!(&framed[12..], b"123456789");
+        assert_eq!(
+            read_frame(&framed, 0x0403_0201),
+            Ok((&b"123456789"[..], &[][..]))
+        );
+    }
+
+    #[test]
+    fn test_read_frame_errors() {
+        let mut framed = Vec::new();
+        write_frame(&mut framed, 1, b"payload").unwrap();
+
+        assert_eq!(
+            read_frame(&framed, 2),
+            Err(FrameError::MagicMismatch { magic: 1 })
+        );
+        for len in [0, HEADER_LEN - 1, framed.len() - 1].iter() {
+            assert_eq!(
+                read_frame(&framed[..*len], 1),
+                Err(FrameError::Truncated { have: *len as u64 })
+            );
+        }
+        let last = framed.len() - 1;
+        framed[last] ^= 1;
+        assert_eq!(
+            read_frame(&framed, 1),
+            Err(FrameError::ChecksumMismatch {
+                expected: Crc32::checksum(b"payload"),
+                actual: Crc32::checksum(b"payloae"),
+            })
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! Analysis of the dictionary actually needed by a stream.
+
+use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
+use crate::decode::lzma::{DecoderState, LzmaParams, MIN_DICT_SIZE};
+use crate::decode::options::Options;
+use crate::decode::rangecoder::RangeDecoder;
+use crate::error;
+use crate::io;
+
+/// Dictionary usage of a stream, returned by [`analyze_dict_size`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DictAnalysis {
+    /// Parameters read from the header.
+    pub params: LzmaParams,
+    /// Number of decompressed bytes.
+    pub unpacked_size: u64,
+    /// Largest distance back into the dictionary referenced by a match, 0 if
+    /// the stream is made of literals only. Literals only look back as far as
+    /// the last match.
+    pub max_distance: usize,
+}
+
+impl DictAnalysis {
+    /// Smallest dictionary size the stream decodes with: the largest
+    /// distance, rounded up to the 4 KiB minimum of the format.
+    ///
+    /// Decoding with a `DICT_MEM_LIMIT` below the dictionary size of the
+    /// header still fails with [`error::Error::DictionaryBufferTooSmall`],
+    /// so the header must be patched to declare this size first, e.g. with
+    /// `compress::rewrite_header`.
+    pub fn min_dict_size(&self) -> u32 {
+        core::cmp::max(self.max_distance as u32, MIN_DICT_SIZE)
+    }
+}
+
+/// Decode the stream of `input`, discarding the output, and report the
+/// dictionary it actually needs, so that static buffers can be sized for
+/// the data shipped rather than for the dictionary size of its header.
+///
+/// The dictionary is allocated on the heap, up to the dictionary size of the
+/// header or the unpacked size if it is smaller.
+///
+/// ```
+/// let compressed: &[u8] = include_bytes!("../../tests/files/foo.txt.lzma");
+/// let analysis =
+///     lzma_rs::decompress::analyze_dict_size(compressed, &Default::default())?;
+/// assert!(analysis.min_dict_size() <= analysis.params.dict_size());
+/// # Ok::<(), lzma_rs::error::Error>(())
+/// ```
+pub fn analyze_dict_size<R: io::BufRead>(
+    mut input: R,
+    options: &Options,
+) -> error::Result<DictAnalysis> {
+    let params = LzmaParams::read_header(&mut input, options)?;
+    // No distance exceeds the unpacked size, so a smaller buffer decodes the
+    // same stream
+    let mut dict_size = params.dict_size as u64;
+    if let core::option::Option::Some(unpacked_size) = params.unpacked_size() {
+        dict_size = core::cmp::min(dict_size, unpacked_size);
+    }
+    let dict_size = core::cmp::max(dict_size, MIN_DICT_SIZE as u64) as usize;
+
+    let output = TrackingBuffer {
+        inner: LzCircularBuffer::from_buf(vec![0; dict_size]),
+        dict_size,
+        max_distance: 0,
+    };
+    let literal_probs = vec![[0; 0x300]; params.props.literal_coders()];
+    let mut decoder = DecoderState::from_parts(output, literal_probs);
+    decoder.reset();
+    decoder.set_params(params)?;
+
+    let mut rangecoder =
+        RangeDecoder::new(&mut input).map_err(|_| error::lzma::LzmaError::DataStreamIsTooShort)?;
+    let mut sink = std::io::sink();
+    decoder.process(&mut sink, &mut rangecoder)?;
+    decoder.output.finish(&mut sink)?;
+    Ok(DictAnalysis {
+        params,
+        unpacked_size: decoder.output.len(),
+        max_distance: decoder.output.max_distance,
+    })
+}
+
+// Dictionary recording the largest distance read from it, with a fixed size
+// standing in for the dictionary size of the header
+struct TrackingBuffer {
+    inner: LzCircularBuffer<Vec<u8>>,
+    dict_size: usize,
+    max_distance: usize,
+}
+
+impl LzBuffer for TrackingBuffer {
+    fn set_dict_size(&mut self, _dict_size: usize) -> error::Result<()> {
+        self.inner.set_dict_size(self.dict_size)
+    }
+
+    fn len(&self) -> u64 {
+        self.inner.len()
+    }
+
+    fn last_or(&self, lit: u8) -> u8 {
+        self.inner.last_or(lit)
+    }
+
+    fn last_n(&self, dist: usize) -> error::Result<u8> {
+        self.inner.last_n(dist)
+    }
+
+    fn append_literal(&mut self, stream: &mut dyn io::Write, lit: u8) -> error::Result<()> {
+        self.inner.append_literal(stream, lit)
+    }
+
+    fn append_lz(
+        &mut self,
+        stream: &mut dyn io::Write,
+        len: usize,
+        dist: usize,
+    ) -> error::Result<()> {
+        self.max_distance = core::cmp::max(self.max_distance, dist);
+        self.inner.append_lz(stream, len, dist)
+    }
+
+    fn finish(&mut self, stream: &mut dyn io::Write) -> io::Result<()> {
+        self.inner.finish(stream)
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset();
+        self.max_distance = 0;
+    }
+}
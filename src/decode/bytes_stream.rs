@@ -0,0 +1,228 @@
+//! [`futures_core::Stream`] of decompressed [`Bytes`], e.g. for HTTP bodies.
+
+use crate::decode::lzma::LiteralCoder;
+use crate::decode::stream::GenericStream;
+use crate::error;
+use crate::io;
+use bytes::Bytes;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_core::Stream;
+
+/// Decodes a [`Stream`] of compressed chunks, such as the body of an HTTP
+/// request, into a [`Stream`] of decompressed [`Bytes`].
+///
+/// Each compressed chunk is decoded as it arrives with a
+/// [`GenericStream`]; chunks producing no output are skipped. Errors of the
+/// input are returned as [`error::Error::IoError`], with their kind if they
+/// are I/O errors, and any error ends the stream.
+///
+/// ```no_run
+/// # #[cfg(feature = "futures-stream")]
+/// # {
+/// # use core::pin::Pin;
+/// # use core::task::{Context, Poll};
+/// # // Stand-in for the body of a request
+/// # struct Body;
+/// # impl futures_core::Stream for Body {
+/// #     type Item = Result<bytes::Bytes, std::io::Error>;
+/// #     fn poll_next(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+/// #         Poll::Ready(None)
+/// #     }
+/// # }
+/// # fn feed(_: &[u8]) {}
+/// # async fn handle(body: Body) -> lzma_rs::error::Result<()> {
+/// use core::future::poll_fn;
+/// use core::pin::Pin;
+/// use futures_core::Stream;
+/// use lzma_rs::decompress::{BytesDecoder, GenericStream, Options};
+///
+/// // Buffers sized at runtime, e.g. from the server configuration
+/// let stream = GenericStream::with_buffers(
+///     vec![0; 0x10000],
+///     vec![[0; 0x300]; 8],
+///     [0; 0],
+///     &Options::default(),
+/// );
+/// let mut decoded = BytesDecoder::new(body, Box::new(stream));
+/// while let Some(data) = poll_fn(|cx| Pin::new(&mut decoded).poll_next(cx)).await {
+///     feed(&data?);
+/// }
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+pub struct BytesDecoder<S, DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    input: S,
+    stream: Box<GenericStream<DICT, PROBS, WBUF>>,
+    done: bool,
+}
+
+impl<S, DICT, PROBS, WBUF> BytesDecoder<S, DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Decode the chunks of `input` with `stream`, which is reset first.
+    pub fn new(input: S, mut stream: Box<GenericStream<DICT, PROBS, WBUF>>) -> Self {
+        stream.reset();
+        Self {
+            input,
+            stream,
+            done: false,
+        }
+    }
+
+    /// Consume the decoder, returning the input and the stream.
+    pub fn into_inner(self) -> (S, Box<GenericStream<DICT, PROBS, WBUF>>) {
+        (self.input, self.stream)
+    }
+}
+
+impl<S, B, E, DICT, PROBS, WBUF> Stream for BytesDecoder<S, DICT, PROBS, WBUF>
+where
+    S: Stream<Item = Result<B, E>> + Unpin,
+    B: AsRef<[u8]>,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    type Item = error::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        while !this.done {
+            let mut output = Vec::new();
+            let res = match Pin::new(&mut this.input).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Ok(chunk))) => this.stream.write_all(&mut output, chunk.as_ref()),
+                Poll::Ready(Some(Err(e))) => Err(into_io_error(e.into()).into()),
+                Poll::Ready(None) => {
+                    this.done = true;
+                    this.stream.finish(&mut output)
+                }
+            };
+            if let Err(e) = res {
+                this.done = true;
+                return Poll::Ready(Some(Err(e)));
+            }
+            if !output.is_empty() {
+                return Poll::Ready(Some(Ok(output.into())));
+            }
+        }
+        Poll::Ready(None)
+    }
+}
+
+// Keep the kind of I/O errors, wrapping other errors
+fn into_io_error(e: Box<dyn std::error::Error + Send + Sync>) -> io::Error {
+    match e.downcast::<io::Error>() {
+        Ok(e) => *e,
+        Err(e) => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
+
+impl<S, DICT, PROBS, WBUF> core::fmt::Debug for BytesDecoder<S, DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("BytesDecoder")
+            .field("stream", &self.stream)
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decompress::Stream as LzmaStream;
+    use std::task::Waker;
+
+    // Input stream returning the given chunks, pending before each one
+    struct Chunks {
+        chunks: std::vec::IntoIter<Result<Vec<u8>, std::io::Error>>,
+        pending: bool,
+    }
+
+    impl Stream for Chunks {
+        type Item = Result<Vec<u8>, std::io::Error>;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            self.pending = !self.pending;
+            if self.pending {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Poll::Ready(self.chunks.next())
+        }
+    }
+
+    fn collect(chunks: Vec<Result<Vec<u8>, std::io::Error>>) -> (Vec<u8>, Option<error::Error>) {
+        let input = Chunks {
+            chunks: chunks.into_iter(),
+            pending: false,
+        };
+        let mut decoder = BytesDecoder::new(input, Box::new(LzmaStream::<4096, 8>::new()));
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut output = Vec::new();
+        let mut error = None;
+        loop {
+            match Pin::new(&mut decoder).poll_next(&mut cx) {
+                Poll::Pending => {}
+                Poll::Ready(Some(Ok(bytes))) => {
+                    assert!(!bytes.is_empty());
+                    output.extend_from_slice(&bytes);
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    assert!(error.replace(e).is_none());
+                }
+                Poll::Ready(None) => break,
+            }
+        }
+        (output, error)
+    }
+
+    #[test]
+    fn test_bytes_decoder() {
+        let compressed = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+
+        let chunks = compressed.chunks(100).map(|c| Ok(c.to_vec())).collect();
+        let (output, error) = collect(chunks);
+        assert_eq!(error, None);
+        assert_eq!(&output[..], &expected[..]);
+
+        // The stream ends with the input
+        let chunks = vec![Ok(compressed[..compressed.len() / 2].to_vec())];
+        let (output, error) = collect(chunks);
+        assert!(error.is_some());
+        assert!(output.len() < expected.len());
+
+        // Errors of the input are passed on
+        let chunks = vec![
+            Ok(compressed[..100].to_vec()),
+            Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionReset,
+                "reset",
+            )),
+            Ok(compressed[100..].to_vec()),
+        ];
+        let (_, error) = collect(chunks);
+        assert!(
+            matches!(&error, Some(error::Error::IoError(e)) if e.kind() == std::io::ErrorKind::ConnectionReset),
+            "{:?}",
+            error
+        );
+    }
+}
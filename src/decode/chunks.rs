@@ -0,0 +1,211 @@
+//! Pull-based decoding, handing out views of the dictionary.
+
+use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
+use crate::decode::lzma::{DecoderState, LiteralCoder, LzmaParams, MATCH_LEN_MAX};
+use crate::decode::options::Options;
+use crate::decode::rangecoder::RangeDecoder;
+use crate::error;
+use crate::io;
+
+/// Decompressed data returned by [`OutputChunks::next_chunk`], borrowed from
+/// the dictionary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputChunk<'a> {
+    /// Decompressed bytes.
+    pub data: &'a [u8],
+    /// Offset of the first byte of `data` in the decompressed output.
+    pub position: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Header,
+    Decoding,
+    Finished,
+    Done,
+}
+
+/// Decoder returned by [`iter_chunks`], decoding as chunks are requested.
+///
+/// This is not an [`Iterator`]: each chunk borrows the dictionary, which the
+/// next call overwrites, so chunks are pulled with a `while let` loop.
+pub struct OutputChunks<R, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
+where
+    R: io::BufRead,
+{
+    input: R,
+    decoder: DecoderState<LzCircularBuffer<[u8; DICT_MEM_LIMIT]>, [LiteralCoder; PROBS_MEM_LIMIT]>,
+    phase: Phase,
+    dict_size: usize,
+    range: u32,
+    code: u32,
+    // Start of the decompressed data not returned yet
+    emitted: usize,
+    // Start of the data left before the end of the dictionary when decoding
+    // wrapped around it
+    tail: core::option::Option<usize>,
+    position: u64,
+}
+
+/// Decode the LZMA stream of `input`, with default
+/// [`Options`](crate::decompress::Options), as chunks of decompressed data
+/// are pulled from the returned [`OutputChunks`], without any output sink.
+///
+/// Chunks are views of the dictionary: decoding stops before wrapping
+/// around it, so a chunk is as large as the dictionary at most and
+/// usually not much smaller.
+///
+/// ```
+/// let compressed: &[u8] = include_bytes!("../../tests/files/foo.txt.lzma");
+/// let mut chunks = lzma_rs::decompress::iter_chunks::<_, 4096, 8>(compressed);
+/// let mut lines = 0;
+/// while let Some(chunk) = chunks.next_chunk() {
+///     lines += chunk?.data.iter().filter(|&&b| b == b'\n').count();
+/// }
+/// assert_eq!(lines, 3736);
+/// # Ok::<(), lzma_rs::error::Error>(())
+/// ```
+pub fn iter_chunks<R, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>(
+    input: R,
+) -> OutputChunks<R, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+where
+    R: io::BufRead,
+{
+    OutputChunks {
+        input,
+        decoder: DecoderState::new(),
+        phase: Phase::Header,
+        dict_size: 0,
+        range: 0,
+        code: 0,
+        emitted: 0,
+        tail: core::option::Option::None,
+        position: 0,
+    }
+}
+
+impl<R, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
+    OutputChunks<R, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+where
+    R: io::BufRead,
+{
+    /// Decode up to the next chunk of data and return it, or `None` once the
+    /// stream has ended or an error has been returned.
+    pub fn next_chunk(&mut self) -> core::option::Option<error::Result<OutputChunk<'_>>> {
+        match self.advance() {
+            Ok(core::option::Option::Some((start, end))) => {
+                let position = self.position;
+                self.position += (end - start) as u64;
+                core::option::Option::Some(Ok(OutputChunk {
+                    data: self.decoder.output.region(start, end),
+                    position,
+                }))
+            }
+            Ok(core::option::Option::None) => core::option::Option::None,
+            Err(e) => {
+                self.phase = Phase::Done;
+                core::option::Option::Some(Err(e))
+            }
+        }
+    }
+
+    /// Number of bytes returned so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Consume the decoder, returning the input, left after the end of the
+    /// stream once all the chunks have been returned.
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+
+    // Decode up to the next chunk and return its range in the dictionary
+    fn advance(&mut self) -> error::Result<core::option::Option<(usize, usize)>> {
+        loop {
+            if let core::option::Option::Some(start) = self.tail.take() {
+                self.emitted = 0;
+                return Ok(core::option::Option::Some((start, self.dict_size)));
+            }
+            let cursor = self.decoder.output.cursor();
+            match self.phase {
+                Phase::Header => self.start()?,
+                Phase::Decoding => {
+                    // The next symbols may not fit before the end of the
+                    // dictionary, and decoding past it overwrites up to
+                    // `MATCH_LEN_MAX` bytes at the start of the buffer; data
+                    // there is returned first, the rest once wrapped around
+                    let room = self.dict_size - cursor;
+                    if room < MATCH_LEN_MAX && self.emitted < MATCH_LEN_MAX {
+                        let start = core::mem::replace(&mut self.emitted, cursor);
+                        return Ok(core::option::Option::Some((start, cursor)));
+                    }
+                    // No more than `room` bytes, until the last few symbols
+                    let limit = core::cmp::max(room / MATCH_LEN_MAX, 1) as u32;
+                    let before = self.decoder.output.len();
+                    let mut rangecoder =
+                        RangeDecoder::from_parts(&mut self.input, self.range, self.code);
+                    let finished =
+                        self.decoder
+                            .process_bounded(&mut NullWriter, &mut rangecoder, limit)?;
+                    self.range = rangecoder.range;
+                    self.code = rangecoder.code;
+                    if self.decoder.output.len() - before >= room as u64 {
+                        self.tail = core::option::Option::Some(self.emitted);
+                    }
+                    if finished {
+                        self.phase = Phase::Finished;
+                    }
+                }
+                Phase::Finished => {
+                    self.phase = Phase::Done;
+                    if cursor > self.emitted {
+                        let start = core::mem::replace(&mut self.emitted, cursor);
+                        return Ok(core::option::Option::Some((start, cursor)));
+                    }
+                }
+                Phase::Done => return Ok(core::option::Option::None),
+            }
+        }
+    }
+
+    fn start(&mut self) -> error::Result<()> {
+        let params = LzmaParams::read_header(&mut self.input, &Options::default())?;
+        self.decoder.reset();
+        self.decoder.set_params(params)?;
+        self.dict_size = params.dict_size() as usize;
+        let rangecoder = RangeDecoder::new(&mut self.input)
+            .map_err(|_| error::lzma::LzmaError::DataStreamIsTooShort)?;
+        self.range = rangecoder.range;
+        self.code = rangecoder.code;
+        self.phase = Phase::Decoding;
+        Ok(())
+    }
+}
+
+impl<R, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> core::fmt::Debug
+    for OutputChunks<R, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+where
+    R: io::BufRead,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("OutputChunks")
+            .field("phase", &self.phase)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+// The dictionary is never flushed to a sink: data is returned before
+// decoding wraps around it
+struct NullWriter;
+
+impl io::Write for NullWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
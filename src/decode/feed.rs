@@ -0,0 +1,206 @@
+//! Streaming decoder fed from an interrupt handler through a ring buffer.
+
+use crate::decode::lzma::LiteralCoder;
+use crate::decode::stream::GenericStream;
+use crate::error;
+use crate::io::{self, BufRead, RingBuffer, RingBufferProducer, RingBufferReader, Write};
+
+/// Producer side of [`GenericStream::split_feed`], pushing compressed data
+/// from an interrupt handler. Dropping it marks the end of the input.
+pub type Feeder<'a, const N: usize> = RingBufferProducer<'a, N>;
+
+/// Outcome of [`FeedDecoder::poll`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeedStatus {
+    /// All the data pushed so far has been processed, or the
+    /// [`Options::max_symbols_per_write`](crate::decompress::Options::max_symbols_per_write)
+    /// limit was reached; poll again later.
+    Pending,
+    /// The feeder has been dropped and the stream has been finished.
+    Finished,
+}
+
+/// Consumer side of [`GenericStream::split_feed`], decoding the data pushed
+/// by the [`Feeder`] without ever waiting for it.
+pub struct FeedDecoder<'a, DICT, PROBS, WBUF, const N: usize>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    stream: &'a mut GenericStream<DICT, PROBS, WBUF>,
+    reader: RingBufferReader<'a, N>,
+}
+
+impl<DICT, PROBS, WBUF> GenericStream<DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Reset the stream and `ring`, and split them into a [`Feeder`], to be
+    /// moved to an interrupt handler, and a [`FeedDecoder`] driven from the
+    /// main loop.
+    ///
+    /// The feeder only uses atomic loads and stores, see [`RingBuffer`]. The
+    /// decoder processes whatever has been pushed each time it is polled;
+    /// set [`Options::max_symbols_per_write`](crate::decompress::Options::max_symbols_per_write)
+    /// to also bound the work done by each poll.
+    ///
+    /// ```
+    /// # #[cfg(feature = "std")]
+    /// # {
+    /// use lzma_rs::decompress::{FeedStatus, Stream};
+    /// use lzma_rs::io::RingBuffer;
+    ///
+    /// let compressed = include_bytes!("../../tests/files/foo.txt.lzma");
+    /// let mut stream = Stream::<4096, 8>::new();
+    /// let mut ring = RingBuffer::<64>::new();
+    /// let (feeder, mut decoder) = stream.split_feed(&mut ring);
+    ///
+    /// let mut output = Vec::new();
+    /// std::thread::scope(|s| {
+    ///     // Stands for the interrupt handler
+    ///     s.spawn(move || {
+    ///         let mut feeder = feeder;
+    ///         for chunk in compressed.chunks(16) {
+    ///             let mut chunk = &chunk[..];
+    ///             while !chunk.is_empty() {
+    ///                 chunk = &chunk[feeder.push(chunk)..];
+    ///             }
+    ///         }
+    ///     });
+    ///     // Main loop
+    ///     while decoder.poll(&mut output)? == FeedStatus::Pending {
+    ///         // Pet the watchdog, sleep until the next interrupt...
+    ///     }
+    ///     Ok::<(), lzma_rs::error::Error>(())
+    /// })?;
+    /// assert_eq!(output, include_bytes!("../../tests/files/foo.txt"));
+    /// # }
+    /// # Ok::<(), lzma_rs::error::Error>(())
+    /// ```
+    pub fn split_feed<'a, const N: usize>(
+        &'a mut self,
+        ring: &'a mut RingBuffer<N>,
+    ) -> (Feeder<'a, N>, FeedDecoder<'a, DICT, PROBS, WBUF, N>) {
+        self.reset();
+        let (feeder, reader) = ring.split();
+        (
+            feeder,
+            FeedDecoder {
+                stream: self,
+                reader,
+            },
+        )
+    }
+}
+
+impl<'a, DICT, PROBS, WBUF, const N: usize> FeedDecoder<'a, DICT, PROBS, WBUF, N>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Decode the data pushed so far to `output`, without waiting for more.
+    /// Once the feeder has been dropped and its data decoded, finish the
+    /// stream and return [`FeedStatus::Finished`].
+    ///
+    /// Data pushed after the end of the stream is an error, as for
+    /// [`GenericStream::write_all`].
+    pub fn poll(&mut self, output: &mut dyn Write) -> error::Result<FeedStatus> {
+        loop {
+            // `is_closed` must be checked before looking for data, so that no
+            // data pushed before closing is missed
+            let closed = self.reader.is_closed();
+            let data = self.reader.fill_available();
+            if data.is_empty() {
+                if !closed {
+                    return Ok(FeedStatus::Pending);
+                }
+                self.stream.finish(output)?;
+                return Ok(FeedStatus::Finished);
+            }
+            let n = self.stream.write(output, data)?;
+            self.reader.consume(n);
+            if self.stream.symbol_limit_reached() {
+                return Ok(FeedStatus::Pending);
+            }
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "data pushed after the end of the stream",
+                )
+                .into());
+            }
+        }
+    }
+
+    /// The stream being decoded.
+    pub fn stream(&self) -> &GenericStream<DICT, PROBS, WBUF> {
+        self.stream
+    }
+}
+
+impl<'a, DICT, PROBS, WBUF, const N: usize> core::fmt::Debug
+    for FeedDecoder<'a, DICT, PROBS, WBUF, N>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("FeedDecoder")
+            .field("stream", &self.stream)
+            .field("reader", &self.reader)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+    use crate::decompress::{Options, Stream};
+
+    #[test]
+    fn test_feed_decoder() {
+        let compressed = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+        for limit in &[None, Some(3)] {
+            let mut stream = Stream::<4096, 8>::new_with_options(&Options {
+                max_symbols_per_write: *limit,
+                ..Options::default()
+            });
+            let mut ring = RingBuffer::<16>::new();
+            let (mut feeder, mut decoder) = stream.split_feed(&mut ring);
+            let mut output = Vec::new();
+            // Interleave pushes and polls as an interrupt handler would
+            let mut input = &compressed[..];
+            while !input.is_empty() {
+                let n = feeder.push(&input[..input.len().min(5)]);
+                input = &input[n..];
+                assert_eq!(decoder.poll(&mut output).unwrap(), FeedStatus::Pending);
+            }
+            assert_eq!(decoder.poll(&mut output).unwrap(), FeedStatus::Pending);
+            drop(feeder);
+            while decoder.poll(&mut output).unwrap() == FeedStatus::Pending {}
+            assert_eq!(&output[..], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_feed_decoder_trailing_data() {
+        let compressed = include_bytes!("../../tests/files/small.txt.golden.lzma");
+        let mut stream = Stream::<4096, 8>::new();
+        let mut ring = RingBuffer::<1024>::new();
+        let (mut feeder, mut decoder) = stream.split_feed(&mut ring);
+        assert_eq!(feeder.push(compressed), compressed.len());
+        assert_eq!(feeder.push(b"junk"), 4);
+        drop(feeder);
+        match decoder.poll(&mut Vec::new()) {
+            Err(error::Error::LzmaError(error::lzma::LzmaError::EosFoundButMoreBytesAvailable)) => {
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Detection of the container format of compressed data.
+
+use crate::properties::LzmaProperties;
+use core::convert::TryFrom;
+
+const XZ_MAGIC: &[u8] = b"\xFD7zXZ\0";
+const LZIP_MAGIC: &[u8] = b"LZIP";
+/// Length of the `.lzma` header with its 8-byte unpacked size.
+const LZMA_HEADER_LEN: usize = 13;
+
+/// Container format of compressed data, see [`detect_format`].
+///
+/// Only [`Format::Lzma`] is decoded by this crate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// `.lzma` ("LZMA alone") stream, as read by
+    /// [`crate::lzma_decompress`].
+    Lzma,
+    /// `.xz` stream.
+    Xz,
+    /// `.lz` (lzip) member.
+    Lzip,
+}
+
+/// Guess the format of compressed data from its first bytes, before setting
+/// up a decoder.
+///
+/// XZ and lzip data start with a magic value. `.lzma` streams have no magic
+/// value; like xz-utils, their header is accepted if the properties are
+/// valid, the dictionary size is `2^n` or `2^n + 2^(n-1)` and the unpacked
+/// size is unknown or below 256 GiB. Streams written by common encoders
+/// pass, random data mostly does not. Streams with an unusual dictionary
+/// size are decodable nonetheless.
+///
+/// Returns `None` if `data` matches no format, including when it is too
+/// short to tell: XZ needs 6 bytes, lzip 4 and `.lzma` 13.
+///
+/// ```
+/// use lzma_rs::decompress::{detect_format, Format};
+///
+/// let data = include_bytes!("../../tests/files/foo.txt.lzma");
+/// assert_eq!(detect_format(data), Some(Format::Lzma));
+/// let data = include_bytes!("../../tests/files/foo.txt.xz");
+/// assert_eq!(detect_format(data), Some(Format::Xz));
+/// assert_eq!(detect_format(b"plain text, not compressed"), None);
+/// ```
+pub fn detect_format(data: &[u8]) -> Option<Format> {
+    if data.starts_with(XZ_MAGIC) {
+        Some(Format::Xz)
+    } else if data.starts_with(LZIP_MAGIC) {
+        Some(Format::Lzip)
+    } else if is_plausible_lzma_header(data) {
+        Some(Format::Lzma)
+    } else {
+        None
+    }
+}
+
+fn is_plausible_lzma_header(data: &[u8]) -> bool {
+    let header = match data.get(..LZMA_HEADER_LEN) {
+        Some(header) => header,
+        None => return false,
+    };
+    let mut dict_size = [0; 4];
+    dict_size.copy_from_slice(&header[1..5]);
+    let dict_size = u32::from_le_bytes(dict_size);
+    let mut unpacked_size = [0; 8];
+    unpacked_size.copy_from_slice(&header[5..13]);
+    let unpacked_size = u64::from_le_bytes(unpacked_size);
+
+    LzmaProperties::try_from(header[0]).is_ok()
+        && (dict_size == u32::MAX || round_dict_size(dict_size) == dict_size)
+        && (unpacked_size == u64::MAX || unpacked_size < 1 << 38)
+}
+
+/// Round `dict_size` up to the next `2^n` or `2^n + 2^(n-1)`, the same way
+/// xz-utils does.
+fn round_dict_size(dict_size: u32) -> u32 {
+    let mut d = dict_size.wrapping_sub(1);
+    d |= d >> 2;
+    d |= d >> 3;
+    d |= d >> 4;
+    d |= d >> 8;
+    d |= d >> 16;
+    d.wrapping_add(1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(detect_format(b"\xFD7zXZ\0\0\x01"), Some(Format::Xz));
+        assert_eq!(detect_format(b"\xFD7zXZ"), None);
+        assert_eq!(detect_format(b"LZIP\x01\x0C"), Some(Format::Lzip));
+        assert_eq!(detect_format(b""), None);
+
+        let header = |props: u8, dict_size: u32, unpacked_size: u64| {
+            let mut header = [0; LZMA_HEADER_LEN];
+            header[0] = props;
+            header[1..5].copy_from_slice(&dict_size.to_le_bytes());
+            header[5..].copy_from_slice(&unpacked_size.to_le_bytes());
+            header
+        };
+        for &dict_size in [0, 1, 4096, 3 << 20, 1 << 31, u32::MAX].iter() {
+            let data = header(0x5D, dict_size, u64::MAX);
+            assert_eq!(detect_format(&data), Some(Format::Lzma), "{}", dict_size);
+            assert_eq!(detect_format(&data[..LZMA_HEADER_LEN - 1]), None);
+        }
+        assert_eq!(detect_format(&header(0x5D, 4096, 1234)), Some(Format::Lzma));
+        assert_eq!(detect_format(&header(225, 4096, u64::MAX)), None);
+        assert_eq!(detect_format(&header(0x5D, 5000, u64::MAX)), None);
+        assert_eq!(detect_format(&header(0x5D, 7 << 20, u64::MAX)), None);
+        assert_eq!(detect_format(&header(0x5D, 4096, 1 << 38)), None);
+    }
+}
@@ -0,0 +1,80 @@
+//! Bounded decoding for fuzzing.
+
+use crate::decode::lzbuffer::{LzBuffer, LzSliceBuffer};
+use crate::decode::lzma::{DecoderState, LzmaParams};
+use crate::decode::options::Options;
+use crate::decode::rangecoder::RangeDecoder;
+use crate::error;
+
+/// Decompress LZMA data with default [`Options`], producing at most `max_out`
+/// bytes and running at most `max_iters` iterations of the decoding loop.
+///
+/// Meant for fuzzing code built on top of this crate: arbitrary input never
+/// makes it hang or allocate more than `max_out` bytes. Exceeding the limits
+/// fails with [`error::Error::OutputBufferTooSmall`] or
+/// [`error::Error::IterationLimitExceeded`]. The literal coders are allocated
+/// on the heap for the properties of the stream, so any valid `lc` and `lp`
+/// are accepted.
+pub fn fuzz_decode(input: &[u8], max_out: usize, max_iters: u64) -> error::Result<Vec<u8>> {
+    let mut input = input;
+    let params = LzmaParams::read_header(&mut input, &Options::default())?;
+    let mut output = vec![0; max_out];
+    let literal_probs = vec![[0; 0x300]; params.props.literal_coders()];
+    let mut decoder = DecoderState::from_parts(LzSliceBuffer::new(&mut output), literal_probs);
+    decoder.reset();
+    decoder.set_iteration_limit(max_iters);
+    decoder.set_params(params)?;
+
+    let mut rangecoder =
+        RangeDecoder::new(&mut input).map_err(|_| error::lzma::LzmaError::DataStreamIsTooShort)?;
+    let mut sink: &mut [u8] = &mut [];
+    decoder.process(&mut sink, &mut rangecoder)?;
+    // Cannot truncate, the data fits in `output`
+    let len = decoder.output.len() as usize;
+    drop(decoder);
+    output.truncate(len);
+    Ok(output)
+}
+
+#[cfg(all(test, feature = "encoder"))]
+mod test {
+    use super::*;
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        crate::lzma_compress(&mut &data[..], &mut compressed).unwrap();
+        compressed
+    }
+
+    #[test]
+    fn test_fuzz_decode() {
+        let data = b"Hello, fuzzer! Hello, fuzzer!";
+        let compressed = compress(data);
+        assert_eq!(fuzz_decode(&compressed, 64, 1000).unwrap(), &data[..]);
+    }
+
+    #[test]
+    fn test_fuzz_decode_limits() {
+        let compressed = compress(&[0; 100]);
+        match fuzz_decode(&compressed, 10, 1000) {
+            Err(error::Error::OutputBufferTooSmall { available: 10 }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match fuzz_decode(&compressed, 1000, 10) {
+            Err(error::Error::IterationLimitExceeded) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_fuzz_decode_large_literal_context() {
+        let data = b"Hello, fuzzer! Hello, fuzzer!";
+        let mut compressed = Vec::new();
+        let options = crate::compress::Options {
+            properties: crate::properties::LzmaProperties::new(8, 4, 2).unwrap(),
+            ..Default::default()
+        };
+        crate::lzma_compress_with_options(&mut &data[..], &mut compressed, &options).unwrap();
+        assert_eq!(fuzz_decode(&compressed, 64, 1000).unwrap(), &data[..]);
+    }
+}
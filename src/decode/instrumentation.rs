@@ -0,0 +1,65 @@
+//! Timing hooks for profiling the decoder.
+//!
+//! The decoder never reads a clock, so it runs the same on any target. To
+//! measure where time goes, e.g. with the DWT cycle counter of a Cortex-M,
+//! install an [`Instrumentation`] hook; the decoder calls it around header
+//! parsing and every few decoded symbols, and the hook reads whatever timer
+//! the target has.
+
+/// Hook called by the decoder at points of interest. All methods default to
+/// doing nothing.
+///
+/// Hooks are installed as `&'static` references with
+/// [`DecoderState::set_instrumentation`](crate::decompress::DecoderState::set_instrumentation)
+/// or
+/// [`Stream::set_instrumentation`](crate::decompress::Stream::set_instrumentation),
+/// so they keep their measurements in statics, e.g. atomics.
+///
+/// ```
+/// use core::sync::atomic::{AtomicU64, Ordering};
+/// use lzma_rs::decompress::{DecoderState, Instrumentation};
+///
+/// struct CountTicks;
+///
+/// static TICKS: AtomicU64 = AtomicU64::new(0);
+///
+/// impl Instrumentation for CountTicks {
+///     fn tick_interval(&self) -> u32 {
+///         100
+///     }
+///
+///     fn tick(&self, _symbols: u64) {
+///         TICKS.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// # #[cfg(feature = "std")]
+/// # {
+/// let mut state = DecoderState::<4096, 8>::new();
+/// state.set_instrumentation(Some(&CountTicks));
+/// let mut input: &[u8] = include_bytes!("../../tests/files/foo.txt.lzma");
+/// lzma_rs::lzma_decompress_with_state(&mut state, &mut input, &mut Vec::new())?;
+/// assert!(TICKS.load(Ordering::Relaxed) > 0);
+/// # }
+/// # Ok::<(), lzma_rs::error::Error>(())
+/// ```
+pub trait Instrumentation {
+    /// Called before parsing the header of a stream. The streaming decoder
+    /// calls it again each time it resumes parsing a header split across
+    /// writes.
+    fn header_enter(&self) {}
+
+    /// Called after parsing the header, whether it succeeded or not.
+    fn header_exit(&self) {}
+
+    /// Number of decoded symbols (literals or matches) between two calls to
+    /// [`Instrumentation::tick`], read once when the hook is installed. `0`
+    /// is treated as `1`. The default is 1024.
+    fn tick_interval(&self) -> u32 {
+        1024
+    }
+
+    /// Called every [`Instrumentation::tick_interval`] decoded symbols, with
+    /// the number of symbols decoded since the decoder was reset.
+    fn tick(&self, _symbols: u64) {}
+}
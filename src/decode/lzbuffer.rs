@@ -1,11 +1,17 @@
+use crate::decode::util;
 use crate::error;
 use crate::io;
 use crate::option::GuaranteedOption as Option;
 use crate::option::GuaranteedOption::*;
+use core::convert::TryFrom;
 
 pub trait LzBuffer {
+    // Check that a stream with this dictionary size can be decoded
+    fn check_dict_size(&self, _dict_size: usize) -> error::Result<()> {
+        Ok(())
+    }
     fn set_dict_size(&mut self, dict_size: usize) -> error::Result<()>;
-    fn len(&self) -> usize;
+    fn len(&self) -> u64;
     // Retrieve the last byte or return a default
     fn last_or(&self, lit: u8) -> u8;
     // Retrieve the n-th last byte
@@ -22,49 +28,183 @@ pub trait LzBuffer {
     // Consumes this buffer and flushes any data
     fn finish(&mut self, stream: &mut dyn io::Write) -> io::Result<()>;
     fn reset(&mut self);
+    // Reset for a new stream, keeping the data of the previous one available
+    // for back-references
+    fn reset_keep_dict(&mut self) {
+        self.reset()
+    }
 }
 
-// A circular buffer for LZ sequences
-pub struct LzCircularBuffer<const MEM_LIMIT: usize> {
-    buf: [u8; MEM_LIMIT],     // Circular buffer
+// A circular buffer for LZ sequences, stored in an array (`[u8; N]`) or a
+// borrowed slice (`&mut [u8]`)
+pub struct LzCircularBuffer<B>
+where
+    B: AsRef<[u8]> + AsMut<[u8]>,
+{
+    buf: B,                   // Circular buffer
     dict_size: Option<usize>, // Length of the buffer
     cursor: usize,            // Current position
-    len: usize,               // Total number of bytes sent through the buffer
+    flushed: usize,           // Position up to which data has been flushed
+    len: u64,                 // Total number of bytes sent through the buffer
+    history: usize,           // Bytes kept from previous streams
 }
 
-impl<const MEM_LIMIT: usize> LzCircularBuffer<MEM_LIMIT> {
+impl<const MEM_LIMIT: usize> LzCircularBuffer<[u8; MEM_LIMIT]> {
+    // Built in place rather than with `from_buf`, which would copy the
+    // buffer on the stack in debug builds
     pub const fn new() -> Self {
         Self {
             buf: [0_u8; MEM_LIMIT],
             dict_size: None,
             cursor: 0,
+            flushed: 0,
+            len: 0,
+            history: 0,
+        }
+    }
+}
+
+impl<B> LzCircularBuffer<B>
+where
+    B: AsRef<[u8]> + AsMut<[u8]>,
+{
+    #[cfg(any(feature = "stream", feature = "std"))]
+    pub const fn from_buf(buf: B) -> Self {
+        Self {
+            buf,
+            dict_size: None,
+            cursor: 0,
+            flushed: 0,
             len: 0,
+            history: 0,
+        }
+    }
+
+    // Size of the buffer, i.e. the largest supported dictionary size
+    #[cfg(feature = "stream")]
+    pub fn mem_limit(&self) -> usize {
+        self.buf.as_ref().len()
+    }
+
+    // Swap the buffer for `buf`, returning the previous one. The buffer
+    // must be reset before being used again
+    #[cfg(feature = "stream")]
+    pub fn replace_buf(&mut self, buf: B) -> B {
+        core::mem::replace(&mut self.buf, buf)
+    }
+
+    // Number of bytes that can be referenced by a match
+    fn available(&self) -> usize {
+        // Saturate rather than overflow on targets with a small `usize`; only
+        // the first `dict_size` bytes matter anyway
+        usize::try_from(self.len)
+            .unwrap_or(usize::MAX)
+            .saturating_add(self.history)
+    }
+
+    /// Copy the last bytes of the dictionary into `buf`, oldest first, and
+    /// return the number of bytes copied.
+    #[cfg(feature = "stream")]
+    pub fn copy_last(&self, buf: &mut [u8]) -> usize {
+        let dict_size = match self.dict_size {
+            Some(v) => v,
+            None => return 0,
+        };
+        let n = core::cmp::min(buf.len(), core::cmp::min(self.available(), dict_size));
+        let mut offset = self.offset_back(dict_size, n);
+        for v in buf.iter_mut().take(n) {
+            *v = self.get(offset);
+            offset += 1;
+            if offset == dict_size {
+                offset = 0
+            }
         }
+        n
+    }
+
+    /// Decompressed bytes not yet written to the output sink, oldest first.
+    #[cfg(feature = "stream")]
+    pub fn unflushed(&self) -> &[u8] {
+        self.buf
+            .as_ref()
+            .get(self.flushed..self.cursor)
+            .unwrap_or(&[])
+    }
+
+    // Position at which the next byte is written
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    // Bytes of the buffer between `start` and `end`, empty if out of bounds
+    pub fn region(&self, start: usize, end: usize) -> &[u8] {
+        self.buf.as_ref().get(start..end).unwrap_or(&[])
     }
 
     fn get(&self, index: usize) -> u8 {
-        *self.buf.get(index).unwrap_or(&0)
+        *self.buf.as_ref().get(index).unwrap_or(&0)
+    }
+
+    fn set(&mut self, index: usize, value: u8) -> io::Result<()> {
+        *self
+            .buf
+            .as_mut()
+            .get_mut(index)
+            .ok_or_else(util::out_of_bounds)? = value;
+        Ok(())
+    }
+
+    // Position `distance` bytes before the cursor, with `distance <= dict_size`.
+    // Cheaper than a modulo, and cannot divide by zero
+    fn offset_back(&self, dict_size: usize, distance: usize) -> usize {
+        if self.cursor >= distance {
+            self.cursor - distance
+        } else {
+            dict_size + self.cursor - distance
+        }
     }
 
-    fn set(&mut self, index: usize, value: u8) {
-        self.buf[index] = value;
+    // Write out the data between `flushed` and `cursor`
+    fn flush(&mut self, stream: &mut dyn io::Write) -> io::Result<()> {
+        let data = self
+            .buf
+            .as_ref()
+            .get(self.flushed..self.cursor)
+            .ok_or_else(util::out_of_bounds)?;
+        util::write_all(stream, data)
     }
 }
 
-impl<const MEM_LIMIT: usize> LzBuffer for LzCircularBuffer<MEM_LIMIT> {
-    fn set_dict_size(&mut self, dict_size: usize) -> error::Result<()> {
-        lzma_info!("Dict size in LZ buffer: {}", dict_size);
-        if dict_size > MEM_LIMIT {
+impl<B> LzBuffer for LzCircularBuffer<B>
+where
+    B: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fn check_dict_size(&self, dict_size: usize) -> error::Result<()> {
+        let mem_limit = self.buf.as_ref().len();
+        if dict_size > mem_limit {
             return Err(error::Error::DictionaryBufferTooSmall {
                 needed: dict_size,
-                available: MEM_LIMIT,
+                available: mem_limit,
             });
         }
+        Ok(())
+    }
+
+    fn set_dict_size(&mut self, dict_size: usize) -> error::Result<()> {
+        lzma_info!("Dict size in LZ buffer: {}", dict_size);
+        self.check_dict_size(dict_size)?;
+        // Data kept from previous streams is laid out for the previous
+        // dictionary size and cannot be reused with a different one
+        if self.dict_size != Some(dict_size) {
+            self.cursor = 0;
+            self.flushed = 0;
+            self.history = 0;
+        }
         self.dict_size = Some(dict_size);
         Ok(())
     }
 
-    fn len(&self) -> usize {
+    fn len(&self) -> u64 {
         self.len
     }
 
@@ -72,21 +212,24 @@ impl<const MEM_LIMIT: usize> LzBuffer for LzCircularBuffer<MEM_LIMIT> {
     fn last_or(&self, lit: u8) -> u8 {
         // TODO: resolve optional dict_size in a different way
         let dict_size = match self.dict_size {
-            Some(v) => v.clone(),
-            None => panic!("LzCircularBuffer::dict_size is not initialized"),
+            Some(v) => v,
+            None => lzma_panic!(lit, "LzCircularBuffer::dict_size is not initialized"),
         };
-        if self.len == 0 {
+        if self.available() == 0 {
             lit
         } else {
-            self.get((dict_size + self.cursor - 1) % dict_size)
+            self.get(self.offset_back(dict_size, 1))
         }
     }
 
     // Retrieve the n-th last byte
     fn last_n(&self, distance: usize) -> error::Result<u8> {
         let dict_size = match self.dict_size {
-            Some(v) => v.clone(),
-            None => panic!("LzCircularBuffer::dict_size is not initialized"),
+            Some(v) => v,
+            None => lzma_panic!(
+                Err(error::Error::Uninitialized),
+                "LzCircularBuffer::dict_size is not initialized"
+            ),
         };
         if distance > dict_size {
             return Err(
@@ -97,32 +240,35 @@ impl<const MEM_LIMIT: usize> LzBuffer for LzCircularBuffer<MEM_LIMIT> {
                 .into(),
             );
         }
-        if distance > self.len {
+        if distance > self.available() {
             return Err(error::lzma::LzmaError::MatchDistanceIsBeyondOutputSize {
                 distance,
-                output_len: self.len,
+                output_len: self.available(),
             }
             .into());
         }
 
-        let offset = (dict_size + self.cursor - distance) % dict_size;
-        Ok(self.get(offset))
+        Ok(self.get(self.offset_back(dict_size, distance)))
     }
 
     // Append a literal
     fn append_literal(&mut self, stream: &mut dyn io::Write, lit: u8) -> error::Result<()> {
         let dict_size = match self.dict_size {
-            Some(v) => v.clone(),
-            None => panic!("LzCircularBuffer::dict_size is not initialized"),
+            Some(v) => v,
+            None => lzma_panic!(
+                Err(error::Error::Uninitialized),
+                "LzCircularBuffer::dict_size is not initialized"
+            ),
         };
-        self.set(self.cursor, lit);
+        self.set(self.cursor, lit)?;
         self.cursor += 1;
         self.len += 1;
 
         // Flush the circular buffer to the output
         if self.cursor == dict_size {
-            stream.write_all(&self.buf[..self.cursor])?;
+            self.flush(stream)?;
             self.cursor = 0;
+            self.flushed = 0;
         }
 
         Ok(())
@@ -136,8 +282,11 @@ impl<const MEM_LIMIT: usize> LzBuffer for LzCircularBuffer<MEM_LIMIT> {
         distance: usize,
     ) -> error::Result<()> {
         let dict_size = match self.dict_size {
-            Some(v) => v.clone(),
-            None => panic!("LzCircularBuffer::dict_size is not initialized"),
+            Some(v) => v,
+            None => lzma_panic!(
+                Err(error::Error::Uninitialized),
+                "LzCircularBuffer::dict_size is not initialized"
+            ),
         };
         lzma_debug!("LZ {{ len: {}, distance: {} }}", len, distance);
         if distance > dict_size {
@@ -147,15 +296,39 @@ impl<const MEM_LIMIT: usize> LzBuffer for LzCircularBuffer<MEM_LIMIT> {
             }
             .into());
         }
-        if distance > self.len {
+        if distance > self.available() {
             return Err(error::lzma::LzmaError::LzDistanceIsBeyondOutputSize {
                 distance,
-                output_len: self.len,
+                output_len: self.available(),
             }
             .into());
         }
 
-        let mut offset = (dict_size + self.cursor - distance) % dict_size;
+        // Runs of a repeated byte (padding, zeroed pages) are filled
+        // directly, up to the end of the circular buffer at a time
+        if distance == 1 {
+            let byte = self.get(self.offset_back(dict_size, 1));
+            let mut remaining = len;
+            while remaining > 0 {
+                let n = core::cmp::min(remaining, dict_size - self.cursor);
+                self.buf
+                    .as_mut()
+                    .get_mut(self.cursor..self.cursor + n)
+                    .ok_or_else(util::out_of_bounds)?
+                    .fill(byte);
+                self.cursor += n;
+                self.len += n as u64;
+                remaining -= n;
+                if self.cursor == dict_size {
+                    self.flush(stream)?;
+                    self.cursor = 0;
+                    self.flushed = 0;
+                }
+            }
+            return Ok(());
+        }
+
+        let mut offset = self.offset_back(dict_size, distance);
         for _ in 0..len {
             let x = self.get(offset);
             self.append_literal(stream, x)?;
@@ -169,18 +342,265 @@ impl<const MEM_LIMIT: usize> LzBuffer for LzCircularBuffer<MEM_LIMIT> {
 
     // Consumes this buffer and flushes any data
     fn finish(&mut self, stream: &mut dyn io::Write) -> io::Result<()> {
-        if self.cursor > 0 {
-            stream.write_all(&self.buf[..self.cursor])?;
+        if self.cursor > self.flushed {
+            self.flush(stream)?;
             stream.flush()?;
+            self.flushed = self.cursor;
         }
-        self.reset();
         Ok(())
     }
 
+    // The buffer content is not cleared; bytes are never read before being
+    // written in the current (or a kept) stream
     fn reset(&mut self) {
-        self.buf.iter_mut().for_each(|v| *v = 0);
         self.dict_size = None;
         self.cursor = 0;
+        self.flushed = 0;
+        self.len = 0;
+        self.history = 0;
+    }
+
+    fn reset_keep_dict(&mut self) {
+        self.history = match self.dict_size {
+            Some(dict_size) => core::cmp::min(self.available(), dict_size),
+            None => 0,
+        };
+        self.len = 0;
+        // Data of an unfinished stream not flushed yet is only kept as
+        // history, never written out with the next stream
+        self.flushed = self.cursor;
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<B> zeroize::Zeroize for LzCircularBuffer<B>
+where
+    B: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fn zeroize(&mut self) {
+        self.buf.as_mut().zeroize();
+        self.dict_size = None;
+        self.cursor.zeroize();
+        self.flushed.zeroize();
+        self.len.zeroize();
+        self.history.zeroize();
+    }
+}
+
+// The dictionary holds decompressed data, so it is wiped on drop
+#[cfg(feature = "zeroize")]
+impl<B> Drop for LzCircularBuffer<B>
+where
+    B: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(self)
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<B> zeroize::ZeroizeOnDrop for LzCircularBuffer<B> where B: AsRef<[u8]> + AsMut<[u8]> {}
+// A linear buffer decoding directly into the output slice; the output itself
+// acts as the dictionary
+pub struct LzSliceBuffer<'a> {
+    buf: &'a mut [u8],        // Output buffer
+    dict_size: Option<usize>, // Maximum match distance
+    len: usize,               // Total number of bytes written to the buffer
+}
+
+impl<'a> LzSliceBuffer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            dict_size: None,
+            len: 0,
+        }
+    }
+}
+
+impl<'a> LzBuffer for LzSliceBuffer<'a> {
+    fn set_dict_size(&mut self, dict_size: usize) -> error::Result<()> {
+        lzma_info!("Dict size in LZ buffer: {}", dict_size);
+        self.dict_size = Some(dict_size);
+        Ok(())
+    }
+
+    fn len(&self) -> u64 {
+        self.len as u64
+    }
+
+    // Retrieve the last byte or return a default
+    fn last_or(&self, lit: u8) -> u8 {
+        self.len
+            .checked_sub(1)
+            .and_then(|index| self.buf.get(index))
+            .copied()
+            .unwrap_or(lit)
+    }
+
+    // Retrieve the n-th last byte
+    fn last_n(&self, distance: usize) -> error::Result<u8> {
+        let dict_size = match self.dict_size {
+            Some(v) => v,
+            None => lzma_panic!(
+                Err(error::Error::Uninitialized),
+                "LzSliceBuffer::dict_size is not initialized"
+            ),
+        };
+        if distance > dict_size {
+            return Err(
+                error::lzma::LzmaError::MatchDistanceIsBeyondDictionarySize {
+                    distance,
+                    dict_size,
+                }
+                .into(),
+            );
+        }
+        if distance > self.len {
+            return Err(error::lzma::LzmaError::MatchDistanceIsBeyondOutputSize {
+                distance,
+                output_len: self.len,
+            }
+            .into());
+        }
+
+        self.buf
+            .get(self.len - distance)
+            .copied()
+            .ok_or_else(|| util::out_of_bounds().into())
+    }
+
+    // Append a literal
+    fn append_literal(&mut self, _stream: &mut dyn io::Write, lit: u8) -> error::Result<()> {
+        if self.len == self.buf.len() {
+            return Err(error::Error::output_buffer_too_small(self.buf.len()));
+        }
+        *self.buf.get_mut(self.len).ok_or_else(util::out_of_bounds)? = lit;
+        self.len += 1;
+        Ok(())
+    }
+
+    // Fetch an LZ sequence (length, distance) from inside the buffer
+    fn append_lz(
+        &mut self,
+        _stream: &mut dyn io::Write,
+        len: usize,
+        distance: usize,
+    ) -> error::Result<()> {
+        let dict_size = match self.dict_size {
+            Some(v) => v,
+            None => lzma_panic!(
+                Err(error::Error::Uninitialized),
+                "LzSliceBuffer::dict_size is not initialized"
+            ),
+        };
+        lzma_debug!("LZ {{ len: {}, distance: {} }}", len, distance);
+        if distance > dict_size {
+            return Err(error::lzma::LzmaError::LzDistanceIsBeyondDictionarySize {
+                distance,
+                dict_size,
+            }
+            .into());
+        }
+        if distance > self.len {
+            return Err(error::lzma::LzmaError::LzDistanceIsBeyondOutputSize {
+                distance,
+                output_len: self.len,
+            }
+            .into());
+        }
+        if len > self.buf.len() - self.len {
+            return Err(error::Error::output_buffer_too_small(self.buf.len()));
+        }
+
+        let end = self.len + len;
+        if distance == 1 {
+            let byte = self.last_n(1)?;
+            self.buf
+                .get_mut(self.len..end)
+                .ok_or_else(util::out_of_bounds)?
+                .fill(byte);
+            self.len = end;
+            return Ok(());
+        }
+
+        // Source and destination may overlap, so copy byte by byte
+        for i in self.len..end {
+            let byte = *self.buf.get(i - distance).ok_or_else(util::out_of_bounds)?;
+            *self.buf.get_mut(i).ok_or_else(util::out_of_bounds)? = byte;
+        }
+        self.len = end;
+        Ok(())
+    }
+
+    // Nothing to flush; data is already in place
+    fn finish(&mut self, _stream: &mut dyn io::Write) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.dict_size = None;
         self.len = 0;
     }
 }
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    /// Copy `len` bytes at `distance` back byte by byte, as a reference.
+    fn naive_lz(data: &mut Vec<u8>, len: usize, distance: usize) {
+        for _ in 0..len {
+            data.push(data[data.len() - distance]);
+        }
+    }
+
+    #[test]
+    fn test_append_lz_distance_1() {
+        let mut expected = Vec::new();
+        let mut out = Vec::new();
+        let mut circular = LzCircularBuffer::<[u8; 16]>::new();
+        circular.reset();
+        circular.set_dict_size(16).unwrap();
+        let mut slice = [0; 256];
+        let mut slice = LzSliceBuffer::new(&mut slice);
+        slice.set_dict_size(16).unwrap();
+        for lit in [0xA0, 0xA1, 0xA2].iter() {
+            expected.push(*lit);
+            circular.append_literal(&mut out, *lit).unwrap();
+            slice.append_literal(&mut out, *lit).unwrap();
+        }
+
+        for (i, &len) in [1, 15, 2, 40, 7, 100].iter().enumerate() {
+            let lit = i as u8 + 1;
+            expected.push(lit);
+            circular.append_literal(&mut out, lit).unwrap();
+            slice.append_literal(&mut out, lit).unwrap();
+
+            naive_lz(&mut expected, len, 1);
+            circular.append_lz(&mut out, len, 1).unwrap();
+            slice.append_lz(&mut out, len, 1).unwrap();
+            // Wrapping around the circular buffer
+            naive_lz(&mut expected, 5, 3);
+            circular.append_lz(&mut out, 5, 3).unwrap();
+            slice.append_lz(&mut out, 5, 3).unwrap();
+        }
+        circular.finish(&mut out).unwrap();
+
+        assert_eq!(out, expected);
+        assert_eq!(circular.len(), expected.len() as u64);
+        assert_eq!(&slice.buf[..slice.len], &expected[..]);
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_zeroize_on_drop() {
+        use crate::decode::lzma::{DecoderState, LiteralCoder};
+
+        fn assert_zeroize_on_drop<T: zeroize::ZeroizeOnDrop>(_: &T) {}
+        assert_zeroize_on_drop(&LzCircularBuffer::<[u8; 16]>::new());
+        assert_zeroize_on_drop(
+            &DecoderState::<LzCircularBuffer<[u8; 16]>, [LiteralCoder; 1]>::new(),
+        );
+    }
+}
@@ -1,19 +1,42 @@
+#[cfg(feature = "instrumentation")]
+use crate::decode::instrumentation::Instrumentation;
 use crate::decode::lzbuffer;
 use crate::decode::rangecoder;
+#[cfg(feature = "stats")]
+use crate::decode::stats::DecodeStats;
+use crate::decode::util;
 use crate::decompress::Options;
 use crate::decompress::UnpackedSize;
 use crate::error;
+use crate::error::lzma::HeaderField;
 use crate::io;
 use crate::option::GuaranteedOption as Option;
 use crate::option::GuaranteedOption::*;
-use byteorder::LittleEndian;
-use io::ReadBytesExt;
+use crate::properties::LzmaProperties;
+use byteorder::{ByteOrder, LittleEndian};
+use core::convert::TryFrom;
+use core::iter;
 
 /// Maximum input data that can be processed in one iteration.
 /// Libhtp uses the following equation to define the maximum number of bits
 /// for the worst case scenario:
 ///   log2((2^11 / 31) ^ 22) + 26 < 134 + 26 = 160
-const MAX_REQUIRED_INPUT: usize = 20;
+pub(crate) const MAX_REQUIRED_INPUT: usize = 20;
+
+/// Smallest dictionary size allowed by the specification; smaller sizes
+/// declared in headers are rounded up to it.
+pub(crate) const MIN_DICT_SIZE: u32 = 0x1000;
+
+/// Longest match allowed by the format.
+pub(crate) const MATCH_LEN_MAX: usize = 273;
+
+/// Distance of a match stored as `rep` in the decoder state, i.e. `rep + 1`.
+/// Fails if it does not fit in `usize`, as the end marker distance does on
+/// 32-bit targets.
+pub(crate) fn rep_distance(rep: usize) -> error::Result<usize> {
+    rep.checked_add(1)
+        .ok_or_else(|| error::Error::size_overflows_target((rep as u64).saturating_add(1)))
+}
 
 /// Processing mode for decompression.
 ///
@@ -29,6 +52,9 @@ enum ProcessingMode {
     /// compressed data, or after using `Mode::Partial` to check for the end
     /// of stream.
     Finish,
+    /// Same as `Finish`, but return once the symbol limit is used up, see
+    /// `DecoderState::process_bounded()`.
+    Bounded,
 }
 
 /// Result of the next iteration of processing.
@@ -42,59 +68,144 @@ pub(crate) enum ProcessingStatus {
     Finished,
 }
 
+/// Parameters of a stream, read from its header with
+/// [`crate::decompress::DecoderState::read_header`].
 #[repr(C)]
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LzmaParams {
-    // most lc significant bits of previous byte are part of the literal context
-    pub lc: u32, // 0..8
-    pub lp: u32, // 0..4
-    // context for literal/match is plaintext offset modulo 2^pb
-    pub pb: u32, // 0..4
-    pub dict_size: u32,
-    pub unpacked_size: Option<u64>,
+    pub(crate) props: LzmaProperties,
+    pub(crate) dict_size: u32,
+    pub(crate) unpacked_size: Option<u64>,
 }
 
 impl LzmaParams {
+    /// Properties of the stream.
+    pub fn props(&self) -> LzmaProperties {
+        self.props
+    }
+
+    /// Dictionary size, at least 4 KiB.
+    pub fn dict_size(&self) -> u32 {
+        self.dict_size
+    }
+
+    /// Unpacked size, `None` if the stream ends with an end marker.
+    pub fn unpacked_size(&self) -> core::option::Option<u64> {
+        self.unpacked_size.into()
+    }
+
+    /// Length of the header read with `options`.
+    pub(crate) const fn header_len(options: &Options) -> usize {
+        Self::header_len_for(&options.unpacked_size)
+    }
+
+    /// Length of the header read when the unpacked size is handled as
+    /// `unpacked_size` says.
+    pub(crate) const fn header_len_for(unpacked_size: &UnpackedSize) -> usize {
+        let unpacked_size_len = match unpacked_size {
+            UnpackedSize::ReadFromHeader | UnpackedSize::ReadHeaderButUseProvided(_) => 8,
+            UnpackedSize::ReadFromHeader32 => 4,
+            UnpackedSize::UseProvided(_) => 0,
+        };
+        1 + 4 + unpacked_size_len
+    }
+
+    /// Fill `field` from `input`, `have` counting the header bytes read so
+    /// far out of `need`. Running out of input reports `name` as the
+    /// truncated field.
+    pub(crate) fn read_header_field<R: io::BufRead>(
+        input: &mut R,
+        field: &mut [u8],
+        have: &mut usize,
+        need: usize,
+        name: HeaderField,
+    ) -> error::Result<()> {
+        let mut filled = 0;
+        while filled < field.len() {
+            match input.read(field.get_mut(filled..).ok_or_else(util::out_of_bounds)?) {
+                Ok(0) => {
+                    return Err(error::Error::HeaderTooShort {
+                        have: *have + filled,
+                        need,
+                        field: name,
+                    })
+                }
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        *have += filled;
+        Ok(())
+    }
+
+    /// Read the header from `input`, its layout being set by `options`.
     pub fn read_header<R>(input: &mut R, options: &Options) -> error::Result<LzmaParams>
     where
         R: io::BufRead,
     {
-        // Properties
-        let props = input.read_u8().map_err(error::Error::HeaderTooShort)?;
-
-        let mut pb = props as u32;
-        if pb >= 225 {
-            return Err(error::lzma::LzmaError::InvalidHeader {
-                invalid_properties: pb,
-            }
-            .into());
-        }
+        let need = Self::header_len(options);
+        let mut have = 0;
 
-        let lc: u32 = pb % 9;
-        pb /= 9;
-        let lp: u32 = pb % 5;
-        pb /= 5;
+        // Properties
+        let mut props = [0; 1];
+        Self::read_header_field(input, &mut props, &mut have, need, HeaderField::Properties)?;
+        let props = LzmaProperties::try_from(props[0])?;
 
-        lzma_info!("Properties {{ lc: {}, lp: {}, pb: {} }}", lc, lp, pb);
+        lzma_info!("Properties {:?}", props);
 
         // Dictionary
-        let dict_size_provided = input
-            .read_u32::<LittleEndian>()
-            .map_err(error::Error::HeaderTooShort)?;
-        let dict_size = if dict_size_provided < 0x1000 {
-            0x1000
+        let mut dict_size_provided = [0; 4];
+        Self::read_header_field(
+            input,
+            &mut dict_size_provided,
+            &mut have,
+            need,
+            HeaderField::DictSize,
+        )?;
+        let dict_size_provided = LittleEndian::read_u32(&dict_size_provided);
+        let dict_size = if dict_size_provided < MIN_DICT_SIZE {
+            if options.reject_small_dict {
+                return Err(error::lzma::LzmaError::DictionaryTooSmall {
+                    dict_size: dict_size_provided,
+                }
+                .into());
+            }
+            lzma_info!(
+                "Dict size {} is below the minimum, using {}",
+                dict_size_provided,
+                MIN_DICT_SIZE
+            );
+            MIN_DICT_SIZE
         } else {
             dict_size_provided
         };
 
+        if let core::option::Option::Some(max_dict_size) = options.max_dict_size {
+            if dict_size > max_dict_size {
+                return Err(error::lzma::LzmaError::DictionaryTooLarge {
+                    dict_size,
+                    max_dict_size,
+                }
+                .into());
+            }
+        }
+
         lzma_info!("Dict size: {}", dict_size);
 
         // Unpacked size
         let unpacked_size: Option<u64> = match options.unpacked_size {
             UnpackedSize::ReadFromHeader => {
-                let unpacked_size_provided = input
-                    .read_u64::<LittleEndian>()
-                    .map_err(error::Error::HeaderTooShort)?;
+                let mut unpacked_size_provided = [0; 8];
+                Self::read_header_field(
+                    input,
+                    &mut unpacked_size_provided,
+                    &mut have,
+                    need,
+                    HeaderField::UnpackedSize,
+                )?;
+                let unpacked_size_provided = LittleEndian::read_u64(&unpacked_size_provided);
                 let marker_mandatory: bool = unpacked_size_provided == 0xFFFF_FFFF_FFFF_FFFF;
                 if marker_mandatory {
                     None
@@ -103,20 +214,38 @@ impl LzmaParams {
                 }
             }
             UnpackedSize::ReadHeaderButUseProvided(x) => {
-                input
-                    .read_u64::<LittleEndian>()
-                    .map_err(error::Error::HeaderTooShort)?;
+                Self::read_header_field(
+                    input,
+                    &mut [0; 8],
+                    &mut have,
+                    need,
+                    HeaderField::UnpackedSize,
+                )?;
                 x.into()
             }
-            UnpackedSize::UseProvided(x) => x,
+            UnpackedSize::ReadFromHeader32 => {
+                let mut unpacked_size_provided = [0; 4];
+                Self::read_header_field(
+                    input,
+                    &mut unpacked_size_provided,
+                    &mut have,
+                    need,
+                    HeaderField::UnpackedSize,
+                )?;
+                let unpacked_size_provided = LittleEndian::read_u32(&unpacked_size_provided);
+                if unpacked_size_provided == 0xFFFF_FFFF {
+                    None
+                } else {
+                    Some(unpacked_size_provided as u64)
+                }
+            }
+            UnpackedSize::UseProvided(x) => x.into(),
         };
 
         lzma_info!("Unpacked size: {:?}", unpacked_size);
 
         let params = LzmaParams {
-            lc,
-            lp,
-            pb,
+            props,
             dict_size,
             unpacked_size,
         };
@@ -125,9 +254,72 @@ impl LzmaParams {
     }
 }
 
-pub struct DecoderState<LZB, const PROBS_MEM_LIMIT: usize>
+/// Renders the parameters with binary units, e.g.
+/// `lc=3 lp=0 pb=2 dict=64KiB size=1.2MiB`, or `size=unknown(eos)` for a
+/// stream ending with an end marker.
+///
+/// ```
+/// let header = b"\x5d\x00\x00\x01\x00\x00\x00\x13\x00\x00\x00\x00\x00";
+/// let params = lzma_rs::decompress::LzmaParams::read_header(
+///     &mut &header[..],
+///     &lzma_rs::decompress::Options::default(),
+/// )?;
+/// assert_eq!(params.to_string(), "lc=3 lp=0 pb=2 dict=64KiB size=1.2MiB");
+/// # Ok::<(), lzma_rs::error::Error>(())
+/// ```
+impl core::fmt::Display for LzmaParams {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} dict={}",
+            self.props,
+            HumanSize(self.dict_size as u64)
+        )?;
+        match self.unpacked_size {
+            Some(unpacked_size) => write!(f, " size={}", HumanSize(unpacked_size)),
+            None => write!(f, " size=unknown(eos)"),
+        }
+    }
+}
+
+/// Byte count rendered in the largest binary unit it reaches, rounded to one
+/// decimal which is left out when it is zero.
+struct HumanSize(u64);
+
+impl core::fmt::Display for HumanSize {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+        let mut unit = 1u64;
+        let mut name = "B";
+        for (i, &next) in UNITS.iter().enumerate() {
+            let size = 1u64 << (10 * (i + 1));
+            // Move up already when rounding would give `1024.0` of `unit`
+            if self.0 < size - unit / 20 {
+                break;
+            }
+            unit = size;
+            name = next;
+        }
+        let whole = self.0 / unit;
+        // `rest < unit <= 2^60`, so this cannot overflow
+        let tenths = (self.0 % unit * 10 + unit / 2) / unit;
+        match tenths {
+            0 => write!(f, "{}{}", whole, name),
+            10 => write!(f, "{}.0{}", whole + 1, name),
+            _ => write!(f, "{}.{}{}", whole, tenths, name),
+        }
+    }
+}
+
+/// Probabilities of one literal coder.
+pub type LiteralCoder = [u16; 0x300];
+
+// `PROBS` is the storage of the literal coders, either an array
+// (`[LiteralCoder; N]`) or a borrowed slice (`&mut [LiteralCoder]`)
+pub struct DecoderState<LZB, PROBS>
 where
     LZB: lzbuffer::LzBuffer,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
 {
     processing_status: ProcessingStatus,
     // Buffer input data here if we need more for decompression. Up to
@@ -135,7 +327,7 @@ where
     pub params: Option<LzmaParams>,
     partial_input_buf: io::Cursor<[u8; MAX_REQUIRED_INPUT]>,
     pub output: LZB,
-    literal_probs: [[u16; 0x300]; PROBS_MEM_LIMIT],
+    literal_probs: PROBS,
     pos_slot_decoder: [rangecoder::BitTree<64>; 4],
     align_decoder: rangecoder::BitTree<16>,
     pos_decoders: [u16; 115],
@@ -149,18 +341,33 @@ where
     rep: [usize; 4],
     len_decoder: rangecoder::LenDecoder,
     rep_len_decoder: rangecoder::LenDecoder,
+    // Number of loop iterations left before processing fails, if limited
+    iterations_left: Option<u64>,
+    // Whether input may follow the end marker
+    trailing_data: bool,
+    // Number of symbols left before `process_stream` or `process_bounded`
+    // returns early, if limited
+    symbols_left: Option<u32>,
+    #[cfg(feature = "stats")]
+    pub stats: DecodeStats,
+    // Timing hook, its tick interval and the symbols decoded since the reset
+    #[cfg(feature = "instrumentation")]
+    instrumentation: Option<(&'static (dyn Instrumentation + Sync), u32)>,
+    #[cfg(feature = "instrumentation")]
+    symbols: u64,
 }
 
-impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
-    DecoderState<lzbuffer::LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>
-{
-    pub const fn new() -> Self {
-        Self {
+/// Fresh `DecoderState` with the given buffers. The constructors expand it
+/// in place instead of calling each other, as buffers passed by value are
+/// copied on the stack in debug builds.
+macro_rules! decoder_state {
+    ($output:expr, $literal_probs:expr) => {
+        DecoderState {
             processing_status: ProcessingStatus::Uninitialized,
-            output: lzbuffer::LzCircularBuffer::new(),
+            output: $output,
             partial_input_buf: io::Cursor::new([0; MAX_REQUIRED_INPUT]),
             params: None,
-            literal_probs: [[0; 0x300]; PROBS_MEM_LIMIT],
+            literal_probs: $literal_probs,
             pos_slot_decoder: [rangecoder::BitTree::new(); 4],
             align_decoder: rangecoder::BitTree::new(),
             pos_decoders: [0; 115],
@@ -174,43 +381,322 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
             rep: [0; 4],
             len_decoder: rangecoder::LenDecoder::new(),
             rep_len_decoder: rangecoder::LenDecoder::new(),
+            iterations_left: None,
+            trailing_data: false,
+            symbols_left: None,
+            #[cfg(feature = "stats")]
+            stats: DecodeStats::new(),
+            #[cfg(feature = "instrumentation")]
+            instrumentation: None,
+            #[cfg(feature = "instrumentation")]
+            symbols: 0,
         }
+    };
+}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
+    DecoderState<lzbuffer::LzCircularBuffer<[u8; DICT_MEM_LIMIT]>, [LiteralCoder; PROBS_MEM_LIMIT]>
+{
+    pub const fn new() -> Self {
+        decoder_state!(
+            lzbuffer::LzCircularBuffer::new(),
+            [[0; 0x300]; PROBS_MEM_LIMIT]
+        )
     }
 }
 
-impl<LZB, const PROBS_MEM_LIMIT: usize> DecoderState<LZB, PROBS_MEM_LIMIT>
+impl<LZB, const PROBS_MEM_LIMIT: usize> DecoderState<LZB, [LiteralCoder; PROBS_MEM_LIMIT]>
 where
     LZB: lzbuffer::LzBuffer,
 {
+    pub const fn with_buffer(output: LZB) -> Self {
+        decoder_state!(output, [[0; 0x300]; PROBS_MEM_LIMIT])
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<LZB, PROBS> zeroize::Zeroize for DecoderState<LZB, PROBS>
+where
+    LZB: lzbuffer::LzBuffer + zeroize::Zeroize,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+{
+    fn zeroize(&mut self) {
+        self.output.zeroize();
+        self.zeroize_state();
+    }
+}
+
+// The output buffer wipes itself on drop if it owns the decompressed data,
+// e.g. `LzCircularBuffer`, but not `LzSliceBuffer` which borrows the output of
+// the caller
+#[cfg(feature = "zeroize")]
+impl<LZB, PROBS> Drop for DecoderState<LZB, PROBS>
+where
+    LZB: lzbuffer::LzBuffer,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+{
+    fn drop(&mut self) {
+        self.zeroize_state()
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<LZB, PROBS> zeroize::ZeroizeOnDrop for DecoderState<LZB, PROBS>
+where
+    LZB: lzbuffer::LzBuffer + zeroize::ZeroizeOnDrop,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+{
+}
+
+impl<LZB, PROBS> DecoderState<LZB, PROBS>
+where
+    LZB: lzbuffer::LzBuffer,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+{
+    // Wipe everything but the output buffer
+    #[cfg(feature = "zeroize")]
+    fn zeroize_state(&mut self) {
+        use zeroize::Zeroize;
+
+        self.processing_status = ProcessingStatus::Uninitialized;
+        self.params = None;
+        self.partial_input_buf.get_mut().zeroize();
+        self.partial_input_buf.set_position(0);
+        self.literal_probs
+            .as_mut()
+            .iter_mut()
+            .for_each(|v| v.zeroize());
+        self.pos_slot_decoder.zeroize();
+        self.align_decoder.zeroize();
+        self.pos_decoders.zeroize();
+        self.is_match.zeroize();
+        self.is_rep.zeroize();
+        self.is_rep_g0.zeroize();
+        self.is_rep_g1.zeroize();
+        self.is_rep_g2.zeroize();
+        self.is_rep_0long.zeroize();
+        self.state.zeroize();
+        self.rep.zeroize();
+        self.len_decoder.zeroize();
+        self.rep_len_decoder.zeroize();
+    }
+}
+
+impl<LZB, PROBS> DecoderState<LZB, PROBS>
+where
+    LZB: lzbuffer::LzBuffer,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+{
+    #[cfg(any(feature = "stream", feature = "std"))]
+    pub const fn from_parts(output: LZB, literal_probs: PROBS) -> Self {
+        decoder_state!(output, literal_probs)
+    }
+
+    /// Whether compressed data is held back, waiting for more input.
+    #[cfg(feature = "stream")]
+    pub(crate) fn has_partial_input(&self) -> bool {
+        self.partial_input_buf.position() > 0
+    }
+
+    /// Drop the compressed data held back, e.g. because it follows the end
+    /// of the stream, and return its length.
+    #[cfg(feature = "stream")]
+    pub(crate) fn take_partial_input(&mut self) -> usize {
+        let len = self.partial_input_buf.position() as usize;
+        self.partial_input_buf.set_position(0);
+        len
+    }
+
+    /// Whether the stream has ended, at its end marker or its unpacked size.
+    #[cfg(feature = "stream")]
+    pub(crate) fn is_finished(&self) -> bool {
+        match (self.processing_status, self.params) {
+            (ProcessingStatus::Finished, _) => true,
+            (
+                _,
+                Some(LzmaParams {
+                    unpacked_size: Some(unpacked_size),
+                    ..
+                }),
+            ) => self.output.len() >= unpacked_size,
+            _ => false,
+        }
+    }
+
+    /// Number of literal coders the probabilities buffer can hold.
+    #[allow(dead_code)]
+    pub(crate) fn literal_coders_available(&self) -> usize {
+        self.literal_probs.as_ref().len()
+    }
+
     #[allow(dead_code)]
     pub(crate) fn get_processing_status(&self) -> ProcessingStatus {
         self.processing_status
     }
 
-    pub fn set_params(&mut self, params: LzmaParams) -> error::Result<()> {
-        if let ProcessingStatus::Uninitialized = self.processing_status {
-            panic!("DecoderState is uninitialized; call `DecoderState::reset` first");
-        }
-        if (1 << (params.lc + params.lp)) > PROBS_MEM_LIMIT {
+    /// Check that a stream with `params` fits in the buffers, without
+    /// modifying the state.
+    pub fn validate_params(&self, params: &LzmaParams) -> error::Result<()> {
+        let literal_coders = params.props.literal_coders();
+        let available = self.literal_probs.as_ref().len();
+        if literal_coders > available {
             return Err(error::Error::ProbabilitiesBufferTooSmall {
-                needed: 1 << (params.lc + params.lp),
-                available: PROBS_MEM_LIMIT,
+                needed: literal_coders,
+                available,
             });
         }
-        self.output.set_dict_size(params.dict_size as usize)?;
+        self.output
+            .check_dict_size(util::to_usize(params.dict_size.into())?)
+    }
+
+    pub fn set_params(&mut self, params: LzmaParams) -> error::Result<()> {
+        if let ProcessingStatus::Uninitialized = self.processing_status {
+            lzma_panic!(
+                Err(error::Error::Uninitialized),
+                "DecoderState is uninitialized; call `DecoderState::reset` first"
+            );
+        }
+        self.validate_params(&params)?;
+        self.output
+            .set_dict_size(util::to_usize(params.dict_size.into())?)?;
+        // Only the literal coders selected by lc/lp are ever used; leave the
+        // rest of the buffer untouched
+        let literal_coders = params.props.literal_coders();
+        self.literal_probs
+            .as_mut()
+            .iter_mut()
+            .take(literal_coders)
+            .for_each(|v| v.iter_mut().for_each(|v| *v = 0x400));
         self.params = Some(params);
         Ok(())
     }
 
+    /// Fail with [`error::Error::IterationLimitExceeded`] after `limit`
+    /// iterations of the decoding loop. Not cleared by a reset.
+    #[allow(dead_code)]
+    pub(crate) fn set_iteration_limit(&mut self, limit: u64) {
+        self.iterations_left = Some(limit);
+    }
+
+    /// Accept input left after the end marker, for streams embedded in a
+    /// larger blob. Not cleared by a reset.
+    pub(crate) fn set_trailing_data(&mut self, allowed: bool) {
+        self.trailing_data = allowed;
+    }
+
+    /// Make [`DecoderState::process_stream`] return after decoding `limit`
+    /// symbols, even if input is left. Applies until changed.
+    #[cfg(feature = "stream")]
+    pub(crate) fn set_symbol_limit(&mut self, limit: Option<u32>) {
+        self.symbols_left = limit;
+    }
+
+    /// Whether the last call to [`DecoderState::process_stream`] used up the
+    /// symbol limit.
+    #[cfg(feature = "stream")]
+    pub(crate) fn symbol_limit_reached(&self) -> bool {
+        matches!(self.symbols_left, Some(0))
+    }
+
+    /// Install a timing hook, or remove it with `None`. Not cleared by a
+    /// reset.
+    #[cfg(feature = "instrumentation")]
+    pub(crate) fn set_instrumentation(
+        &mut self,
+        hook: core::option::Option<&'static (dyn Instrumentation + Sync)>,
+    ) {
+        self.instrumentation = hook.map(|hook| (hook, hook.tick_interval().max(1))).into();
+    }
+
+    #[cfg(feature = "instrumentation")]
+    pub(crate) fn instrumentation(
+        &self,
+    ) -> core::option::Option<&'static (dyn Instrumentation + Sync)> {
+        match self.instrumentation {
+            Some((hook, _)) => core::option::Option::Some(hook),
+            None => core::option::Option::None,
+        }
+    }
+
+    /// Count a decoded symbol, calling the timing hook every tick interval.
+    #[inline(always)]
+    fn record_symbol(&mut self) {
+        #[cfg(feature = "instrumentation")]
+        if let Some((hook, interval)) = self.instrumentation {
+            self.symbols += 1;
+            if self.symbols % u64::from(interval) == 0 {
+                hook.tick(self.symbols);
+            }
+        }
+    }
+
+    /// Probabilities in the order of a model snapshot, see
+    /// [`crate::decode::model`], with the first `literal_coders` literal
+    /// coders.
+    pub(crate) fn model(&self, literal_coders: usize) -> impl Iterator<Item = &[u16]> {
+        iter::once(&self.is_match[..])
+            .chain(iter::once(&self.is_rep[..]))
+            .chain(iter::once(&self.is_rep_g0[..]))
+            .chain(iter::once(&self.is_rep_g1[..]))
+            .chain(iter::once(&self.is_rep_g2[..]))
+            .chain(iter::once(&self.is_rep_0long[..]))
+            .chain(self.pos_slot_decoder.iter().map(rangecoder::BitTree::probs))
+            .chain(iter::once(&self.pos_decoders[..]))
+            .chain(iter::once(self.align_decoder.probs()))
+            .chain(self.len_decoder.model())
+            .chain(self.rep_len_decoder.model())
+            .chain(
+                self.literal_probs
+                    .as_ref()
+                    .iter()
+                    .take(literal_coders)
+                    .map(|v| &v[..]),
+            )
+    }
+
+    pub(crate) fn model_mut(&mut self, literal_coders: usize) -> impl Iterator<Item = &mut [u16]> {
+        iter::once(&mut self.is_match[..])
+            .chain(iter::once(&mut self.is_rep[..]))
+            .chain(iter::once(&mut self.is_rep_g0[..]))
+            .chain(iter::once(&mut self.is_rep_g1[..]))
+            .chain(iter::once(&mut self.is_rep_g2[..]))
+            .chain(iter::once(&mut self.is_rep_0long[..]))
+            .chain(
+                self.pos_slot_decoder
+                    .iter_mut()
+                    .map(rangecoder::BitTree::probs_mut),
+            )
+            .chain(iter::once(&mut self.pos_decoders[..]))
+            .chain(iter::once(self.align_decoder.probs_mut()))
+            .chain(self.len_decoder.model_mut())
+            .chain(self.rep_len_decoder.model_mut())
+            .chain(
+                self.literal_probs
+                    .as_mut()
+                    .iter_mut()
+                    .take(literal_coders)
+                    .map(|v| &mut v[..]),
+            )
+    }
+
     #[allow(dead_code)]
     pub(crate) fn reset(&mut self) {
-        self.processing_status = ProcessingStatus::Continue;
         self.output.reset();
+        self.reset_state();
+    }
+
+    /// Reset the decoder but keep the data decoded so far as the dictionary
+    /// of the next stream.
+    #[allow(dead_code)]
+    pub(crate) fn reset_keep_dict(&mut self) {
+        self.output.reset_keep_dict();
+        self.reset_state();
+    }
+
+    pub(crate) fn reset_state(&mut self) {
+        self.processing_status = ProcessingStatus::Continue;
         self.partial_input_buf = io::Cursor::new([0; MAX_REQUIRED_INPUT]);
         self.params = None;
-        self.literal_probs
-            .iter_mut()
-            .for_each(|v| v.iter_mut().for_each(|v| *v = 0x400));
         self.pos_slot_decoder.iter_mut().for_each(|v| v.reset());
         self.align_decoder.reset();
         self.pos_decoders.iter_mut().for_each(|v| *v = 0x400);
@@ -224,6 +710,10 @@ where
         self.rep = [0; 4];
         self.len_decoder.reset();
         self.rep_len_decoder.reset();
+        #[cfg(feature = "instrumentation")]
+        {
+            self.symbols = 0;
+        }
     }
 
     pub fn process<'a, R: io::BufRead>(
@@ -243,6 +733,23 @@ where
         self.process_mode(output, rangecoder, ProcessingMode::Partial)
     }
 
+    /// Same as [`DecoderState::process`], but return after decoding `limit`
+    /// symbols. Returns whether the end of the stream was reached; the
+    /// unpacked size is only checked then.
+    pub(crate) fn process_bounded<'a, R: io::BufRead>(
+        &mut self,
+        output: &mut dyn io::Write,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        limit: u32,
+    ) -> error::Result<bool> {
+        let saved = self.symbols_left.replace(limit);
+        let res = self.process_mode(output, rangecoder, ProcessingMode::Bounded);
+        // The limit check clears `symbols_left` when it stops the loop
+        let finished = matches!(self.symbols_left, Some(_));
+        self.symbols_left = saved;
+        res.map(|_| finished)
+    }
+
     /// Process the next iteration of the loop.
     ///
     /// If the update flag is true, the decoder's state will be updated.
@@ -256,24 +763,33 @@ where
         update: bool,
     ) -> error::Result<ProcessingStatus> {
         let params = match &self.params {
-            Some(v) => v.clone(),
-            None => panic!(
+            Some(v) => *v,
+            None => lzma_panic!(
+                Err(error::Error::Uninitialized),
                 "DecoderState::params is not initialized; call `DecoderState::set_params` first"
             ),
         };
-        let pos_state = self.output.len() & ((1 << params.pb) - 1);
+        // `pb` is at most 4
+        let pos_state = self.output.len() as usize & ((1 << params.props.pb) - 1) & 0xF;
+        // Checking the state once lets the compiler elide bounds checks on
+        // the state-indexed probabilities
+        let state = self.state;
+        if state >= 12 {
+            return Err(error::lzma::LzmaError::InvalidState { state }.into());
+        }
 
         // Literal
-        if !rangecoder.decode_bit(
-            // TODO: assumes pb = 2 ??
-            &mut self.is_match[(self.state << 4) + pos_state],
-            update,
-        )? {
+        if !rangecoder.decode_bit_raw(&mut self.is_match[(state << 4) + pos_state], update)? {
             let byte: u8 = self.decode_literal(rangecoder, update)?;
 
             if update {
                 lzma_debug!("Literal: {}", byte);
                 self.output.append_literal(output, byte)?;
+                #[cfg(feature = "stats")]
+                {
+                    self.stats.record_literal();
+                    self.record_dict_usage(params.dict_size);
+                }
 
                 self.state = if self.state < 4 {
                     0
@@ -289,28 +805,33 @@ where
         // LZ
         let mut len: usize;
         // Distance is repeated from LRU
-        if rangecoder.decode_bit(&mut self.is_rep[self.state], update)? {
+        let is_rep = rangecoder.decode_bit_raw(&mut self.is_rep[state], update)?;
+        if is_rep {
             // dist = rep[0]
-            if !rangecoder.decode_bit(&mut self.is_rep_g0[self.state], update)? {
+            if !rangecoder.decode_bit_raw(&mut self.is_rep_g0[state], update)? {
                 // len = 1
-                if !rangecoder.decode_bit(
-                    &mut self.is_rep_0long[(self.state << 4) + pos_state],
-                    update,
-                )? {
+                if !rangecoder
+                    .decode_bit_raw(&mut self.is_rep_0long[(state << 4) + pos_state], update)?
+                {
                     // update state (short rep)
                     if update {
                         self.state = if self.state < 7 { 9 } else { 11 };
-                        let dist = self.rep[0] + 1;
+                        let dist = rep_distance(self.rep[0])?;
                         self.output.append_lz(output, 1, dist)?;
+                        #[cfg(feature = "stats")]
+                        {
+                            self.stats.record_match(true, 1, dist);
+                            self.record_dict_usage(params.dict_size);
+                        }
                     }
                     return Ok(ProcessingStatus::Continue);
                 }
             // dist = rep[i]
             } else {
                 let idx: usize;
-                if !rangecoder.decode_bit(&mut self.is_rep_g1[self.state], update)? {
+                if !rangecoder.decode_bit_raw(&mut self.is_rep_g1[state], update)? {
                     idx = 1;
-                } else if !rangecoder.decode_bit(&mut self.is_rep_g2[self.state], update)? {
+                } else if !rangecoder.decode_bit_raw(&mut self.is_rep_g2[state], update)? {
                     idx = 2;
                 } else {
                     idx = 3;
@@ -350,9 +871,11 @@ where
             let rep_0 = self.decode_distance(rangecoder, len, update)?;
 
             if update {
-                self.rep[0] = rep_0;
-                if self.rep[0] == 0xFFFF_FFFF {
-                    if rangecoder.is_finished_ok()? {
+                // A distance not fitting in `usize` is beyond any dictionary;
+                // saturate it so that the match is rejected
+                self.rep[0] = usize::try_from(rep_0).unwrap_or(usize::MAX - 1);
+                if rep_0 == 0xFFFF_FFFF {
+                    if rangecoder.code == 0 && (self.trailing_data || rangecoder.is_eof()?) {
                         self.processing_status = ProcessingStatus::Finished;
                         return Ok(ProcessingStatus::Finished);
                     }
@@ -363,9 +886,19 @@ where
 
         if update {
             len += 2;
+            // Cannot be exceeded by a valid length decoder state; fail rather
+            // than smear the dictionary if the state is corrupted
+            if len > MATCH_LEN_MAX {
+                return Err(error::lzma::LzmaError::MatchLengthOutOfRange { len }.into());
+            }
 
-            let dist = self.rep[0] + 1;
+            let dist = rep_distance(self.rep[0])?;
             self.output.append_lz(output, len, dist)?;
+            #[cfg(feature = "stats")]
+            {
+                self.stats.record_match(is_rep, len, dist);
+                self.record_dict_usage(params.dict_size);
+            }
         }
 
         Ok(ProcessingStatus::Continue)
@@ -404,8 +937,12 @@ where
     ) -> error::Result<()> {
         // Fill as much of the tmp buffer as possible
         let start = self.partial_input_buf.position() as usize;
-        let bytes_read =
-            rangecoder.read_into(&mut self.partial_input_buf.get_mut()[start..])? as u64;
+        let buf = self
+            .partial_input_buf
+            .get_mut()
+            .get_mut(start..)
+            .ok_or_else(util::out_of_bounds)?;
+        let bytes_read = rangecoder.read_into(buf)? as u64;
         self.partial_input_buf
             .set_position(self.partial_input_buf.position() + bytes_read);
         Ok(())
@@ -414,72 +951,99 @@ where
     fn process_mode<'a, R: io::BufRead>(
         &mut self,
         output: &mut dyn io::Write,
-        mut rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
         mode: ProcessingMode,
     ) -> error::Result<()> {
         if let ProcessingStatus::Uninitialized = self.processing_status {
-            panic!("DecoderState is uninitialized; call `DecoderState::reset` first");
+            lzma_panic!(
+                Err(error::Error::Uninitialized),
+                "DecoderState is uninitialized; call `DecoderState::reset` first"
+            );
         }
         let params = match &self.params {
-            Some(v) => v.clone(),
-            None => panic!(
+            Some(v) => *v,
+            None => lzma_panic!(
+                Err(error::Error::Uninitialized),
                 "DecoderState::params is not initialized; call `DecoderState::set_params` first"
             ),
         };
         loop {
             if let Some(unpacked_size) = params.unpacked_size {
-                if self.output.len() as u64 >= unpacked_size {
+                if self.output.len() >= unpacked_size {
                     break;
                 }
             } else if match mode {
                 ProcessingMode::Partial => {
                     rangecoder.is_eof()? && self.partial_input_buf.position() as usize == 0
                 }
-                ProcessingMode::Finish => {
+                ProcessingMode::Finish | ProcessingMode::Bounded => {
                     rangecoder.is_finished_ok()? && self.partial_input_buf.position() as usize == 0
                 }
             } {
                 break;
             }
 
+            if mode != ProcessingMode::Finish {
+                if let Some(left) = self.symbols_left.as_mut() {
+                    if *left == 0 {
+                        if mode == ProcessingMode::Bounded {
+                            self.symbols_left = None;
+                        }
+                        return Ok(());
+                    }
+                    *left -= 1;
+                }
+            }
+
+            if let Some(left) = self.iterations_left.as_mut() {
+                if *left == 0 {
+                    return Err(error::Error::iteration_limit_exceeded());
+                }
+                *left -= 1;
+            }
+
             if self.partial_input_buf.position() as usize > 0 {
                 self.read_partial_input_buf(rangecoder)?;
                 let tmp = *self.partial_input_buf.get_ref();
+                let tmp = tmp
+                    .get(..self.partial_input_buf.position() as usize)
+                    .ok_or_else(util::out_of_bounds)?;
 
                 // Check if we need more data to advance the decompressor
                 if mode == ProcessingMode::Partial
                     && (self.partial_input_buf.position() as usize) < MAX_REQUIRED_INPUT
                     && self
-                        .try_process_next(
-                            output,
-                            &tmp[..self.partial_input_buf.position() as usize],
-                            rangecoder.range,
-                            rangecoder.code,
-                        )
+                        .try_process_next(output, tmp, rangecoder.range, rangecoder.code)
                         .is_err()
                 {
                     return Ok(());
                 }
 
                 // Run the decompressor on the tmp buffer
-                let mut tmp_reader =
-                    io::Cursor::new(&tmp[..self.partial_input_buf.position() as usize]);
+                let mut tmp_reader = io::Cursor::new(tmp);
                 let mut tmp_rangecoder = rangecoder::RangeDecoder::from_parts(
                     &mut tmp_reader,
                     rangecoder.range,
                     rangecoder.code,
                 );
                 let res = self.process_next(output, &mut tmp_rangecoder)?;
+                if res == ProcessingStatus::Continue {
+                    self.record_symbol();
+                }
 
                 // Update the actual rangecoder
                 rangecoder.set(tmp_rangecoder.range, tmp_rangecoder.code);
 
                 // Update tmp buffer
-                let end = self.partial_input_buf.position();
-                let new_len = end - tmp_reader.position();
-                self.partial_input_buf.get_mut()[..new_len as usize]
-                    .copy_from_slice(&tmp[tmp_reader.position() as usize..end as usize]);
-                self.partial_input_buf.set_position(new_len);
+                let remaining = tmp
+                    .get(tmp_reader.position() as usize..)
+                    .ok_or_else(util::out_of_bounds)?;
+                self.partial_input_buf
+                    .get_mut()
+                    .get_mut(..remaining.len())
+                    .ok_or_else(util::out_of_bounds)?
+                    .copy_from_slice(remaining);
+                self.partial_input_buf.set_position(remaining.len() as u64);
 
                 if res == ProcessingStatus::Finished {
                     break;
@@ -495,14 +1059,16 @@ where
                     return self.read_partial_input_buf(rangecoder);
                 }
 
-                if self.process_next(output, &mut rangecoder)? == ProcessingStatus::Finished {
+                if self.process_next(output, rangecoder)? == ProcessingStatus::Finished {
                     break;
                 };
+                self.record_symbol();
+                self.process_literal_run(output, rangecoder, &params)?;
             }
         }
 
         if let Some(unpacked_size) = params.unpacked_size {
-            if mode == ProcessingMode::Finish && unpacked_size != self.output.len() as u64 {
+            if mode != ProcessingMode::Partial && unpacked_size != self.output.len() {
                 return Err(
                     error::lzma::LzmaError::ProcessedDataDoesNotMatchUnpackedSize {
                         unpacked_size,
@@ -516,43 +1082,174 @@ where
         Ok(())
     }
 
+    /// Decode plain literals back to back, as long as the next symbol is one
+    /// and the main loop has nothing to check: the input holds a whole
+    /// symbol, the unpacked size is not reached and there is no iteration
+    /// or symbol limit. The position and previous byte are kept in locals
+    /// instead of being derived from the output buffer for each literal.
+    fn process_literal_run<'a, R: io::BufRead>(
+        &mut self,
+        output: &mut dyn io::Write,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        params: &LzmaParams,
+    ) -> error::Result<()> {
+        // Literals following a match are matched literals, left to the main
+        // loop
+        if self.state >= 7
+            || matches!(self.iterations_left, Some(_))
+            || matches!(self.symbols_left, Some(_))
+        {
+            return Ok(());
+        }
+        // Specialize for the common properties, as for `decode_literal`
+        if params.props.lc == 3 && params.props.lp == 0 {
+            self.process_literal_run_with(output, rangecoder, params, 3, 0)
+        } else {
+            let LzmaProperties { lc, lp, .. } = params.props;
+            self.process_literal_run_with(output, rangecoder, params, lc, lp)
+        }
+    }
+
+    #[inline(always)]
+    fn process_literal_run_with<'a, R: io::BufRead>(
+        &mut self,
+        output: &mut dyn io::Write,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        params: &LzmaParams,
+        lc: u32,
+        lp: u32,
+    ) -> error::Result<()> {
+        let pos_mask = (1_usize << params.props.pb) - 1;
+        let lp_mask = (1_usize << lp) - 1;
+        let end = match params.unpacked_size {
+            Some(v) => v,
+            None => u64::MAX,
+        };
+        let mut pos = self.output.len();
+        let mut prev_byte = self.output.last_or(0) as usize;
+        loop {
+            // The main loop handles partial input and the end of the stream
+            if pos >= end || rangecoder.stream.fill_buf()?.len() < MAX_REQUIRED_INPUT {
+                return Ok(());
+            }
+            // `state` stays below 7 while decoding literals
+            let state = self.state & 7;
+            let is_match = &mut self.is_match[(state << 4) + (pos as usize & pos_mask & 0xF)];
+            if rangecoder.peek_bit(*is_match) {
+                return Ok(());
+            }
+            rangecoder.decode_bit_raw(is_match, true)?;
+
+            let lit_state = ((pos as usize & lp_mask) << lc) + (prev_byte >> (8 - lc));
+            let literal_probs = self.literal_probs.as_mut();
+            let available = literal_probs.len();
+            let probs = literal_probs.get_mut(lit_state).ok_or_else(|| {
+                error::Error::probabilities_buffer_too_small(lit_state + 1, available)
+            })?;
+            let mut result: usize = 1;
+            for _ in 0..8 {
+                result = (result << 1)
+                    ^ (rangecoder.decode_bit_raw(&mut probs[result & 0xFF], true)? as usize);
+            }
+            let byte = (result - 0x100) as u8;
+
+            lzma_debug!("Literal: {}", byte);
+            self.output.append_literal(output, byte)?;
+            #[cfg(feature = "stats")]
+            {
+                self.stats.record_literal();
+                self.record_dict_usage(params.dict_size);
+            }
+            self.state = if state < 4 { 0 } else { state - 3 };
+            self.record_symbol();
+            pos += 1;
+            prev_byte = byte as usize;
+        }
+    }
+
+    /// Current state of the LZMA state machine and the last four match
+    /// distances (minus one), most recent first.
+    #[cfg(feature = "stream")]
+    pub(crate) fn symbol_state(&self) -> (usize, [usize; 4]) {
+        (self.state, self.rep)
+    }
+
+    #[cfg(feature = "stats")]
+    fn record_dict_usage(&mut self, dict_size: u32) {
+        // `dict_size` fits in `usize`, `set_params` checked it
+        let used = core::cmp::min(self.output.len(), dict_size.into());
+        self.stats.record_dict_usage(used as usize);
+    }
+
     fn decode_literal<'a, R: io::BufRead>(
         &mut self,
         rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
         update: bool,
     ) -> error::Result<u8> {
         let params = match &self.params {
-            Some(v) => v.clone(),
-            None => panic!(
+            Some(v) => *v,
+            None => lzma_panic!(
+                Err(error::Error::Uninitialized),
                 "DecoderState::params is not initialized; call `DecoderState::set_params` first"
             ),
         };
+        // Specialize for the overwhelmingly common lc=3, lp=0 properties;
+        // with constant arguments, the literal state computation folds away
+        if params.props.lc == 3 && params.props.lp == 0 {
+            self.decode_literal_with(rangecoder, 3, 0, update)
+        } else {
+            self.decode_literal_with(rangecoder, params.props.lc, params.props.lp, update)
+        }
+    }
+
+    #[inline(always)]
+    fn decode_literal_with<'a, R: io::BufRead>(
+        &mut self,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        lc: u32,
+        lp: u32,
+        update: bool,
+    ) -> error::Result<u8> {
         let def_prev_byte = 0u8;
         let prev_byte = self.output.last_or(def_prev_byte) as usize;
 
         let mut result: usize = 1;
-        let lit_state = ((self.output.len() & ((1 << params.lp) - 1)) << params.lc)
-            + (prev_byte >> (8 - params.lc));
-        let probs = &mut self.literal_probs[lit_state];
+        let lit_state =
+            ((self.output.len() as usize & ((1 << lp) - 1)) << lc) + (prev_byte >> (8 - lc));
+        let literal_probs = self.literal_probs.as_mut();
+        let available = literal_probs.len();
+        let probs = literal_probs.get_mut(lit_state).ok_or_else(|| {
+            error::Error::probabilities_buffer_too_small(lit_state + 1, available)
+        })?;
 
         if self.state >= 7 {
-            let mut match_byte = self.output.last_n(self.rep[0] + 1)? as usize;
+            let mut match_byte = self.output.last_n(rep_distance(self.rep[0])?)? as usize;
 
             while result < 0x100 {
                 let match_bit = (match_byte >> 7) & 1;
                 match_byte <<= 1;
+                // Masking `result`, which is below 0x100 here, lets the
+                // compiler elide the bounds checks
                 let bit = rangecoder
-                    .decode_bit(&mut probs[((1 + match_bit) << 8) + result], update)?
+                    .decode_bit_raw(&mut probs[((1 + match_bit) << 8) + (result & 0xFF)], update)?
                     as usize;
                 result = (result << 1) ^ bit;
                 if match_bit != bit {
                     break;
                 }
             }
-        }
 
-        while result < 0x100 {
-            result = (result << 1) ^ (rangecoder.decode_bit(&mut probs[result], update)? as usize);
+            while result < 0x100 {
+                result = (result << 1)
+                    ^ (rangecoder.decode_bit_raw(&mut probs[result & 0xFF], update)? as usize);
+            }
+        } else {
+            // Plain literal: the whole 8-bit tree is decoded, a fixed trip
+            // count lets the compiler unroll the loop
+            for _ in 0..8 {
+                result = (result << 1)
+                    ^ (rangecoder.decode_bit_raw(&mut probs[result & 0xFF], update)? as usize);
+            }
         }
 
         Ok((result - 0x100) as u8)
@@ -563,27 +1260,28 @@ where
         rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
         length: usize,
         update: bool,
-    ) -> error::Result<usize> {
+    ) -> error::Result<u32> {
         let len_state = if length > 3 { 3 } else { length };
 
-        let pos_slot = self.pos_slot_decoder[len_state].parse(rangecoder, update)? as usize;
+        // Distances are computed on 32 bits, they may not fit in `usize`
+        let pos_slot = self.pos_slot_decoder[len_state].parse(rangecoder, update)?;
         if pos_slot < 4 {
             return Ok(pos_slot);
         }
 
-        let num_direct_bits = (pos_slot >> 1) - 1;
+        let num_direct_bits = ((pos_slot >> 1) - 1) as usize;
         let mut result = (2 ^ (pos_slot & 1)) << num_direct_bits;
 
         if pos_slot < 14 {
             result += rangecoder.parse_reverse_bit_tree(
                 num_direct_bits,
                 &mut self.pos_decoders,
-                result - pos_slot,
+                (result - pos_slot) as usize,
                 update,
-            )? as usize;
+            )?;
         } else {
-            result += (rangecoder.get(num_direct_bits - 4)? as usize) << 4;
-            result += self.align_decoder.parse_reverse(rangecoder, update)? as usize;
+            result += rangecoder.get(num_direct_bits - 4)? << 4;
+            result += self.align_decoder.parse_reverse(rangecoder, update)?;
         }
 
         Ok(result)
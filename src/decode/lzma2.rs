@@ -0,0 +1,593 @@
+//! Streaming decoder for raw LZMA2 data.
+
+use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
+use crate::decode::lzma::{
+    DecoderState, LiteralCoder, LzmaParams, ProcessingStatus, MIN_DICT_SIZE,
+};
+use crate::decode::rangecoder::RangeDecoder;
+use crate::decode::stream::StreamStatus;
+use crate::decode::util;
+use crate::error::{self, lzma2::Lzma2Error};
+use crate::io::{self, Cursor};
+use crate::option::GuaranteedOption::*;
+use crate::properties::LzmaProperties;
+use core::convert::TryFrom;
+use core::fmt::Debug;
+
+/// Literal coders needed by any LZMA2 chunk, as LZMA2 requires `lc + lp <=
+/// 4`.
+const LZMA2_LITERAL_CODERS: usize = 16;
+
+/// Longest chunk header: control byte, unpacked size (2 bytes), packed size
+/// (2 bytes), properties byte, followed by the 5 bytes starting the range
+/// coder of an LZMA chunk.
+const MAX_CHUNK_HEADER_LEN: usize = 11;
+
+/// Bytes starting the range coder of an LZMA chunk, counted in its packed
+/// size.
+const RANGE_CODER_INIT_LEN: usize = 5;
+
+/// Internal state of the decoder, between two writes.
+#[derive(Clone, Copy, Debug)]
+enum State {
+    Uninitialized,
+    /// Waiting for the header of the next chunk.
+    ChunkStart,
+    /// Copying an uncompressed chunk, with `left` bytes to go.
+    Uncompressed {
+        left: usize,
+    },
+    /// Decoding an LZMA chunk, with `left` packed bytes to go.
+    Lzma {
+        left: usize,
+        range: u32,
+        code: u32,
+    },
+    /// The end-of-data chunk has been read.
+    Finished,
+    /// A chunk was invalid.
+    Invalid,
+}
+
+/// Streaming decoder for raw LZMA2 data, i.e. a sequence of chunks followed by
+/// the end-of-data chunk, as written by
+/// [`HeaderFormat::Lzma2`](crate::compress::HeaderFormat::Lzma2) or found in
+/// `.xz` and 7z containers.
+///
+/// The dictionary size is not part of raw LZMA2 data; it is given to
+/// [`Lzma2Stream::reset`]. The decoder uses a fixed amount of memory, about
+/// `DICT_MEM_LIMIT + 24 KiB`.
+///
+/// Chunks resetting the decoder state are sync points: everything before
+/// them has been decompressed and written to the output sink, and the rest
+/// of the data decodes the same whatever came before, apart from the
+/// dictionary if the chunk does not reset it too. [`Lzma2Stream::write`]
+/// returns early when it reaches one, with the stream status set to
+/// [`StreamStatus::AtSyncPoint`], so that the receiver can checkpoint its
+/// output.
+///
+/// ```
+/// # #[cfg(feature = "encoder")]
+/// # {
+/// use lzma_rs::compress::{HeaderFormat, Options};
+/// use lzma_rs::decompress::{Lzma2Stream, StreamStatus};
+///
+/// let data = vec![42_u8; 20000];
+/// let mut compressed = Vec::new();
+/// let options = Options {
+///     header: HeaderFormat::Lzma2,
+///     ..Options::default()
+/// };
+/// lzma_rs::lzma_compress_with_options(&mut &data[..], &mut compressed, &options).unwrap();
+///
+/// let mut stream = Lzma2Stream::<0x4000>::new();
+/// stream.reset(0x4000).unwrap();
+/// let mut output = Vec::new();
+/// let mut input = &compressed[..];
+/// let mut checkpoints = Vec::new();
+/// while !input.is_empty() {
+///     let n = stream.write(&mut output, input).unwrap();
+///     input = &input[n..];
+///     if let StreamStatus::AtSyncPoint {
+///         unpacked_data_processed,
+///     } = stream.get_stream_status()
+///     {
+///         assert_eq!(output.len() as u64, unpacked_data_processed);
+///         checkpoints.push(unpacked_data_processed);
+///     }
+/// }
+/// stream.finish(&mut output).unwrap();
+/// assert_eq!(output, data);
+/// assert_eq!(checkpoints, [0x2000, 0x4000]);
+/// # }
+/// ```
+pub struct Lzma2Stream<const DICT_MEM_LIMIT: usize> {
+    decoder:
+        DecoderState<LzCircularBuffer<[u8; DICT_MEM_LIMIT]>, [LiteralCoder; LZMA2_LITERAL_CODERS]>,
+    dict_size: u32,
+    state: State,
+    /// Header of the next chunk, accumulated until complete.
+    header: [u8; MAX_CHUNK_HEADER_LEN],
+    header_len: usize,
+    need_dict_reset: bool,
+    need_props: bool,
+    /// Bytes decompressed before the last dictionary reset, which cleared
+    /// the count of the buffer.
+    unpacked_before: u64,
+    /// Whether the last write stopped at the sync point starting the next
+    /// chunk.
+    sync_point: bool,
+}
+
+impl<const DICT_MEM_LIMIT: usize> Lzma2Stream<DICT_MEM_LIMIT> {
+    /// Create a decoder, to be initialized with [`Lzma2Stream::reset`].
+    pub const fn new() -> Self {
+        Self {
+            decoder: DecoderState::new(),
+            dict_size: MIN_DICT_SIZE,
+            state: State::Uninitialized,
+            header: [0; MAX_CHUNK_HEADER_LEN],
+            header_len: 0,
+            need_dict_reset: true,
+            need_props: true,
+            unpacked_before: 0,
+            sync_point: false,
+        }
+    }
+
+    /// Start decoding new data, compressed with a dictionary of `dict_size`
+    /// bytes (at least 4 KiB are used).
+    pub fn reset(&mut self, dict_size: u32) -> error::Result<()> {
+        let dict_size = core::cmp::max(dict_size, MIN_DICT_SIZE);
+        self.decoder.reset();
+        self.decoder
+            .output
+            .check_dict_size(util::to_usize(dict_size.into())?)?;
+        self.dict_size = dict_size;
+        self.state = State::ChunkStart;
+        self.header_len = 0;
+        self.need_dict_reset = true;
+        self.need_props = true;
+        self.unpacked_before = 0;
+        self.sync_point = false;
+        Ok(())
+    }
+
+    /// Process compressed data, writing decompressed data to `output`.
+    /// Returns the number of bytes consumed from `data`, which is less than
+    /// `data.len()` at a sync point or after the end-of-data chunk.
+    pub fn write(&mut self, output: &mut dyn io::Write, data: &[u8]) -> error::Result<usize> {
+        match self.state {
+            State::Uninitialized => lzma_panic!(
+                Err(error::Error::Uninitialized),
+                "Lzma2Stream is uninitialized; call `Lzma2Stream::reset` first"
+            ),
+            State::Invalid => return Err(error::Error::Uninitialized),
+            _ => {}
+        }
+        let result = self.write_chunks(output, data);
+        if result.is_err() {
+            self.state = State::Invalid;
+        }
+        result
+    }
+
+    /// Write the whole `buf` slice of compressed data into the stream by
+    /// repeatedly calling [`Lzma2Stream::write`], going past sync points.
+    pub fn write_all(&mut self, output: &mut dyn io::Write, mut buf: &[u8]) -> error::Result<()> {
+        while !buf.is_empty() {
+            match self.write(output, buf)? {
+                0 if !self.sync_point => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    )
+                    .into());
+                }
+                n => buf = buf.get(n..).ok_or_else(util::out_of_bounds)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that the end-of-data chunk has been read and flush the output.
+    /// The decoder must be reset before decoding new data.
+    pub fn finish(&mut self, output: &mut dyn io::Write) -> error::Result<()> {
+        let result = match self.state {
+            State::Finished => self
+                .decoder
+                .output
+                .finish(output)
+                .map_err(error::Error::from),
+            State::Uninitialized => lzma_panic!(
+                Err(error::Error::Uninitialized),
+                "Lzma2Stream is uninitialized; call `Lzma2Stream::reset` first"
+            ),
+            State::Invalid => Err(error::Error::Uninitialized),
+            _ => Err(Lzma2Error::EndMarkerMissing.into()),
+        };
+        self.state = State::Uninitialized;
+        result
+    }
+
+    /// Status of the decoder.
+    pub fn get_stream_status(&self) -> StreamStatus {
+        let unpacked_data_processed = self.unpacked_before + self.decoder.output.len();
+        match self.state {
+            State::Uninitialized => StreamStatus::Uninitialized,
+            State::Invalid => StreamStatus::InvalidState,
+            State::Finished => StreamStatus::EosReached,
+            State::ChunkStart if self.sync_point => StreamStatus::AtSyncPoint {
+                unpacked_data_processed,
+            },
+            _ => StreamStatus::ProcessingData {
+                unpacked_data_processed,
+                unpacked_size: core::option::Option::None,
+            },
+        }
+    }
+
+    fn write_chunks(&mut self, output: &mut dyn io::Write, data: &[u8]) -> error::Result<usize> {
+        let mut input = data;
+        while !input.is_empty() {
+            let consumed = match self.state {
+                State::ChunkStart => {
+                    if self.header_len == 0 && self.at_sync_point(output, input)? {
+                        break;
+                    }
+                    self.read_header(output, input)?
+                }
+                State::Uncompressed { left } => {
+                    let chunk = input.get(..left).unwrap_or(input);
+                    for &byte in chunk {
+                        self.decoder.output.append_literal(output, byte)?;
+                    }
+                    self.state = match left - chunk.len() {
+                        0 => State::ChunkStart,
+                        left => State::Uncompressed { left },
+                    };
+                    chunk.len()
+                }
+                State::Lzma { left, range, code } => {
+                    self.decode_lzma(output, input, left, range, code)?
+                }
+                State::Uninitialized | State::Finished | State::Invalid => break,
+            };
+            // Only an LZMA chunk with no packed bytes left ends without
+            // consuming input
+            if consumed == 0 && !matches!(self.state, State::ChunkStart) {
+                break;
+            }
+            input = input.get(consumed..).ok_or_else(util::out_of_bounds)?;
+        }
+        Ok(data.len() - input.len())
+    }
+
+    /// Stop before the chunk starting `input` if it resets the state, and
+    /// data has been decompressed since the last stop.
+    fn at_sync_point(&mut self, output: &mut dyn io::Write, input: &[u8]) -> error::Result<bool> {
+        let resets_state = match input.first() {
+            core::option::Option::Some(&control) => control == 0x01 || control >= 0xA0,
+            core::option::Option::None => false,
+        };
+        if !resets_state || self.sync_point || self.unpacked_before + self.decoder.output.len() == 0
+        {
+            return Ok(false);
+        }
+        self.decoder.output.finish(output)?;
+        self.sync_point = true;
+        Ok(true)
+    }
+
+    /// Take bytes of the chunk header from `input`, and start the chunk
+    /// once the header is complete. Returns the number of bytes taken.
+    fn read_header(&mut self, output: &mut dyn io::Write, input: &[u8]) -> error::Result<usize> {
+        let mut taken = 0;
+        if self.header_len == 0 {
+            let control = match input.first() {
+                core::option::Option::Some(&control) => control,
+                core::option::Option::None => return Ok(0),
+            };
+            self.check_control(control)?;
+            self.header[0] = control;
+            self.header_len = 1;
+            self.sync_point = false;
+            taken = 1;
+        }
+        let needed = match self.header[0] {
+            0x00 => 1,
+            0x01 | 0x02 => 3,
+            0x80..=0xBF => 5 + RANGE_CODER_INIT_LEN,
+            _ => 6 + RANGE_CODER_INIT_LEN,
+        };
+        let free = self
+            .header
+            .get_mut(self.header_len..needed)
+            .ok_or_else(util::out_of_bounds)?;
+        let rest = input.get(taken..).ok_or_else(util::out_of_bounds)?;
+        let n = core::cmp::min(free.len(), rest.len());
+        free.iter_mut().zip(rest).for_each(|(v, &byte)| *v = byte);
+        self.header_len += n;
+        taken += n;
+        if self.header_len == needed {
+            self.header_len = 0;
+            self.start_chunk(output)?;
+        }
+        Ok(taken)
+    }
+
+    /// Check the control byte starting a chunk against the chunks before it.
+    fn check_control(&mut self, control: u8) -> error::Result<()> {
+        if control == 0x00 {
+            return Ok(());
+        }
+        if (0x03..0x80).contains(&control) {
+            return Err(Lzma2Error::InvalidControl { control }.into());
+        }
+        if control == 0x01 || control >= 0xE0 {
+            self.need_dict_reset = false;
+            self.need_props = true;
+        } else if self.need_dict_reset {
+            return Err(Lzma2Error::DictionaryResetExpected { control }.into());
+        }
+        if control >= 0xC0 {
+            self.need_props = false;
+        } else if control >= 0x80 && self.need_props {
+            return Err(Lzma2Error::PropertiesExpected { control }.into());
+        }
+        Ok(())
+    }
+
+    /// Start the chunk whose header has been read.
+    fn start_chunk(&mut self, output: &mut dyn io::Write) -> error::Result<()> {
+        let header = self.header;
+        let control = header[0];
+        if control == 0x00 {
+            self.decoder.output.finish(output)?;
+            self.state = State::Finished;
+            return Ok(());
+        }
+        if control == 0x01 || control >= 0xE0 {
+            // Data before the reset is never referred to again
+            self.decoder.output.finish(output)?;
+            self.unpacked_before += self.decoder.output.len();
+            self.decoder.output.reset();
+            self.decoder
+                .output
+                .set_dict_size(util::to_usize(self.dict_size.into())?)?;
+        }
+        let unpacked = usize::from(u16::from_be_bytes([header[1], header[2]])) + 1;
+        if control < 0x80 {
+            self.state = State::Uncompressed { left: unpacked };
+            return Ok(());
+        }
+
+        let unpacked = ((usize::from(control & 0x1F)) << 16) + unpacked;
+        let packed = usize::from(u16::from_be_bytes([header[3], header[4]])) + 1;
+        let end = self.decoder.output.len() + unpacked as u64;
+        let init = if control >= 0xC0 {
+            let props = LzmaProperties::try_from(header[5])?;
+            if props.lc + props.lp > 4 {
+                return Err(Lzma2Error::LiteralContextTooLarge {
+                    lc: props.lc,
+                    lp: props.lp,
+                }
+                .into());
+            }
+            self.decoder.reset_state();
+            self.decoder.set_params(LzmaParams {
+                props,
+                dict_size: self.dict_size,
+                unpacked_size: Some(end),
+            })?;
+            header.get(6..).ok_or_else(util::out_of_bounds)?
+        } else {
+            let params = match self.decoder.params {
+                Some(params) => params,
+                None => return Err(Lzma2Error::PropertiesExpected { control }.into()),
+            };
+            let params = LzmaParams {
+                unpacked_size: Some(end),
+                ..params
+            };
+            if control >= 0xA0 {
+                self.decoder.reset_state();
+                self.decoder.set_params(params)?;
+            } else {
+                self.decoder.params = Some(params);
+            }
+            header.get(5..).ok_or_else(util::out_of_bounds)?
+        };
+        if packed < RANGE_CODER_INIT_LEN {
+            return Err(Lzma2Error::ChunkSizeMismatch.into());
+        }
+        let mut init = Cursor::new(init);
+        let rangecoder = RangeDecoder::new(&mut init)?;
+        self.state = State::Lzma {
+            left: packed - RANGE_CODER_INIT_LEN,
+            range: rangecoder.range(),
+            code: rangecoder.code(),
+        };
+        Ok(())
+    }
+
+    /// Decode the packed bytes of the current LZMA chunk found in `input`.
+    /// Returns the number of bytes consumed.
+    fn decode_lzma(
+        &mut self,
+        output: &mut dyn io::Write,
+        input: &[u8],
+        left: usize,
+        range: u32,
+        code: u32,
+    ) -> error::Result<usize> {
+        let end = match self.decoder.params {
+            Some(LzmaParams {
+                unpacked_size: Some(end),
+                ..
+            }) => end,
+            _ => return Err(error::Error::Uninitialized),
+        };
+        let chunk = input.get(..left).unwrap_or(input);
+        let whole = chunk.len() == left;
+        let mut chunk = Cursor::new(chunk);
+        let mut rangecoder = RangeDecoder::from_parts(&mut chunk, range, code);
+        if whole {
+            // The rest of the chunk is there: it must decode to exactly the
+            // unpacked size and leave the range coder finished
+            self.decoder.process(output, &mut rangecoder)?;
+            if !rangecoder.is_finished_ok()?
+                || self.decoder.has_partial_input()
+                || self.end_marker_found()
+            {
+                return Err(Lzma2Error::ChunkSizeMismatch.into());
+            }
+            self.state = State::ChunkStart;
+        } else {
+            self.decoder.process_stream(output, &mut rangecoder)?;
+            if self.decoder.output.len() >= end || self.end_marker_found() {
+                return Err(Lzma2Error::ChunkSizeMismatch.into());
+            }
+            let (range, code) = (rangecoder.range(), rangecoder.code());
+            let consumed = chunk.position() as usize;
+            self.state = State::Lzma {
+                left: left - consumed,
+                range,
+                code,
+            };
+            return Ok(consumed);
+        }
+        Ok(left)
+    }
+
+    /// Whether the current chunk contains an end marker, which LZMA2 does
+    /// not allow.
+    fn end_marker_found(&self) -> bool {
+        self.decoder.get_processing_status() == ProcessingStatus::Finished
+    }
+}
+
+impl<const DICT_MEM_LIMIT: usize> Default for Lzma2Stream<DICT_MEM_LIMIT> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const DICT_MEM_LIMIT: usize> Debug for Lzma2Stream<DICT_MEM_LIMIT> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("Lzma2Stream")
+            .field("dict_size", &self.dict_size)
+            .field("state", &self.state)
+            .field("sync_point", &self.sync_point)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    /// Decode `input` with writes of at most `step` bytes, returning the
+    /// output and the sync points met.
+    fn decode(input: &[u8], step: usize) -> (Vec<u8>, Vec<u64>) {
+        let mut stream = Lzma2Stream::<0x4000>::new();
+        stream.reset(0x4000).unwrap();
+        let mut output = Vec::new();
+        let mut sync_points = Vec::new();
+        let mut input = input;
+        while stream.get_stream_status() != StreamStatus::EosReached {
+            let n = stream
+                .write(&mut output, &input[..step.min(input.len())])
+                .unwrap();
+            input = &input[n..];
+            if let StreamStatus::AtSyncPoint {
+                unpacked_data_processed,
+            } = stream.get_stream_status()
+            {
+                assert_eq!(unpacked_data_processed, output.len() as u64);
+                sync_points.push(unpacked_data_processed);
+            }
+        }
+        stream.finish(&mut output).unwrap();
+        (output, sync_points)
+    }
+
+    #[test]
+    fn test_decode_xz_files() {
+        for i in 1..=4 {
+            let xz = std::fs::read(format!("tests/files/good-1-lzma2-{}.xz", i)).unwrap();
+            let expected = std::fs::read(format!("tests/files/good-1-lzma2-{}", i)).unwrap();
+            // Stream header (12 bytes) and block header (12 bytes)
+            let raw = &xz[24..];
+            for &step in &[1, 7, raw.len()] {
+                let (output, _) = decode(raw, step);
+                assert_eq!(output, expected, "file {}, step {}", i, step);
+            }
+        }
+    }
+
+    #[cfg(feature = "encoder")]
+    #[test]
+    fn test_sync_points() {
+        let data: Vec<u8> = (0..30000_u32)
+            .map(|i| (i % 251) as u8 ^ (i / 97) as u8)
+            .collect();
+        let mut compressed = Vec::new();
+        let options = crate::compress::Options {
+            header: crate::compress::HeaderFormat::Lzma2,
+            ..Default::default()
+        };
+        crate::lzma_compress_with_options(&mut &data[..], &mut compressed, &options).unwrap();
+        for &step in &[1, 100, compressed.len()] {
+            let (output, sync_points) = decode(&compressed, step);
+            assert_eq!(output, data);
+            assert_eq!(sync_points, [0x2000, 0x4000, 0x6000]);
+        }
+
+        let mut stream = Lzma2Stream::<0x4000>::new();
+        stream.reset(0x4000).unwrap();
+        let mut output = Vec::new();
+        stream.write_all(&mut output, &compressed).unwrap();
+        stream.finish(&mut output).unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn test_invalid_chunks() {
+        let cases: [(&[u8], Lzma2Error); 4] = [
+            (&[0x03], Lzma2Error::InvalidControl { control: 0x03 }),
+            (
+                &[0x02, 0, 0],
+                Lzma2Error::DictionaryResetExpected { control: 0x02 },
+            ),
+            (
+                &[0x01, 0, 0, 42, 0xA0],
+                Lzma2Error::PropertiesExpected { control: 0xA0 },
+            ),
+            (
+                &[0xE0, 0, 0, 0, 4, 0x0D, 0, 0, 0, 0, 0],
+                Lzma2Error::LiteralContextTooLarge { lc: 4, lp: 1 },
+            ),
+        ];
+        for (input, expected) in cases.iter() {
+            let mut stream = Lzma2Stream::<0x1000>::new();
+            stream.reset(0).unwrap();
+            let mut output = Vec::new();
+            let err = stream.write_all(&mut output, input).unwrap_err();
+            assert_eq!(err, error::Error::Lzma2Error(*expected), "{:?}", input);
+            assert_eq!(stream.get_stream_status(), StreamStatus::InvalidState);
+        }
+    }
+
+    #[test]
+    fn test_end_marker_missing() {
+        let mut stream = Lzma2Stream::<0x1000>::new();
+        stream.reset(0).unwrap();
+        let mut output = Vec::new();
+        stream.write_all(&mut output, &[0x01, 0, 1, 4, 2]).unwrap();
+        assert_eq!(
+            stream.finish(&mut output).unwrap_err(),
+            error::Error::Lzma2Error(Lzma2Error::EndMarkerMissing)
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! Memory accounting.
+
+use crate::decode::lzbuffer::LzCircularBuffer;
+use crate::decode::lzma::{DecoderState, LiteralCoder, MAX_REQUIRED_INPUT};
+use core::mem::size_of;
+
+/// Bytes used by a decoder object, see [`worst_case_memory`] and
+/// [`crate::decompress::Stream::memory_footprint`].
+///
+/// Sizes are those of the object itself, wherever it is placed (`static`,
+/// stack or heap). The decoder does not allocate; stack frames of the
+/// decoding functions are not included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryFootprint {
+    /// Dictionary buffer (`DICT_MEM_LIMIT`).
+    pub dict: usize,
+    /// Literal probabilities (`PROBS_MEM_LIMIT * 0x300` `u16` values).
+    pub probs: usize,
+    /// Buffers holding partial input: the range coder input buffer and, for
+    /// [`crate::decompress::Stream`], the header buffer.
+    pub input_buffers: usize,
+    /// Write combining buffer of [`crate::decompress::Stream`]
+    /// (`WRITE_BUF_LEN`).
+    pub write_buffer: usize,
+    /// Everything else: other probabilities, decoder state and padding.
+    pub overhead: usize,
+    /// Total size, the sum of all the other fields.
+    pub total: usize,
+}
+
+impl MemoryFootprint {
+    pub(crate) const fn new(
+        total: usize,
+        dict: usize,
+        probs: usize,
+        input_buffers: usize,
+        write_buffer: usize,
+    ) -> Self {
+        Self {
+            dict,
+            probs,
+            input_buffers,
+            write_buffer,
+            overhead: total - dict - probs - input_buffers - write_buffer,
+            total,
+        }
+    }
+}
+
+/// Memory used by the decoder of [`crate::lzma_decompress`] and
+/// [`crate::lzma_decompress_with_options`] with the same `DICT_MEM_LIMIT` and
+/// `PROBS_MEM_LIMIT`. The decoder is placed on the stack of these functions.
+pub const fn worst_case_memory<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>(
+) -> MemoryFootprint {
+    MemoryFootprint::new(
+        size_of::<
+            DecoderState<LzCircularBuffer<[u8; DICT_MEM_LIMIT]>, [LiteralCoder; PROBS_MEM_LIMIT]>,
+        >(),
+        DICT_MEM_LIMIT,
+        size_of::<[LiteralCoder; PROBS_MEM_LIMIT]>(),
+        MAX_REQUIRED_INPUT,
+        0,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_worst_case_memory() {
+        let footprint = worst_case_memory::<4096, 8>();
+        assert_eq!(footprint.dict, 4096);
+        assert_eq!(footprint.probs, 8 * 0x300 * 2);
+        assert_eq!(footprint.input_buffers, MAX_REQUIRED_INPUT);
+        assert_eq!(footprint.write_buffer, 0);
+        assert_eq!(
+            footprint.total,
+            footprint.dict
+                + footprint.probs
+                + footprint.input_buffers
+                + footprint.write_buffer
+                + footprint.overhead
+        );
+        // Other probabilities and the state are a few KiB at most
+        assert!(footprint.overhead < 4096);
+    }
+}
@@ -1,10 +1,32 @@
 //! Decoding logic.
 
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "futures-stream")]
+pub mod bytes_stream;
+pub mod chunks;
+#[cfg(feature = "stream")]
+pub mod feed;
+pub mod format;
+#[cfg(feature = "std")]
+pub mod fuzz;
+#[cfg(feature = "instrumentation")]
+pub mod instrumentation;
 pub mod lzbuffer;
 pub mod lzma;
+#[cfg(feature = "stream")]
+pub mod lzma2;
+pub mod memory;
+pub mod model;
 pub mod options;
 pub mod rangecoder;
+pub mod session;
+pub mod state;
+#[cfg(feature = "stats")]
+pub mod stats;
 pub mod util;
 
 #[cfg(feature = "stream")]
 pub mod stream;
+#[cfg(feature = "async")]
+pub mod yielding;
@@ -0,0 +1,67 @@
+//! Snapshots of the probability model, shared by the encoder and decoder.
+//!
+//! A snapshot holds every probability as a little-endian `u16`, in the
+//! order of the LZMA specification: `is_match`, `is_rep`, `is_rep_g0`,
+//! `is_rep_g1`, `is_rep_g2`, `is_rep_0long`, the 4 position slot trees, the
+//! special positions, the alignment tree, the length and rep length models
+//! (`choice`, `choice2`, 16 low trees, 16 mid trees and the high tree), and
+//! last the literal coders selected by `lc` and `lp`.
+
+use crate::error;
+use crate::error::model::ModelError;
+use crate::io;
+use crate::properties::LzmaProperties;
+
+/// Number of probabilities besides the literal coders.
+pub(crate) const FIXED_PROBS: usize = 192 + 4 * 12 + 192 + 4 * 64 + 115 + 16 + 2 * LEN_PROBS;
+
+/// Number of probabilities of a length model.
+const LEN_PROBS: usize = 2 + 2 * 16 * 8 + 256;
+
+/// Write the probabilities of `model` to `output`.
+pub(crate) fn save<'a>(
+    model: impl Iterator<Item = &'a [u16]>,
+    output: &mut dyn io::Write,
+) -> io::Result<()> {
+    for probs in model {
+        for prob in probs {
+            output.write_all(&prob.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Fill the probabilities of `model` from `snapshot`, a model for
+/// `properties`. Nothing is written unless the whole snapshot is valid.
+pub(crate) fn load<'a>(
+    model: impl Iterator<Item = &'a mut [u16]>,
+    properties: LzmaProperties,
+    snapshot: &[u8],
+) -> error::Result<()> {
+    let expected = properties.model_len();
+    if snapshot.len() != expected {
+        return Err(ModelError::LengthMismatch {
+            expected,
+            actual: snapshot.len(),
+        }
+        .into());
+    }
+    let mut values = snapshot.chunks_exact(2).map(|v| match *v {
+        [lo, hi] => u16::from_le_bytes([lo, hi]),
+        _ => 0,
+    });
+    // Probabilities out of range would make the range coder misbehave
+    if let Some((index, value)) = values
+        .clone()
+        .enumerate()
+        .find(|&(_, v)| v == 0 || v >= 0x800)
+    {
+        return Err(ModelError::ProbabilityOutOfRange { index, value }.into());
+    }
+    for probs in model {
+        for (prob, value) in probs.iter_mut().zip(&mut values) {
+            *prob = value;
+        }
+    }
+    Ok(())
+}
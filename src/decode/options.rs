@@ -1,18 +1,81 @@
-use crate::option::GuaranteedOption as Option;
-use crate::option::GuaranteedOption::*;
+use crate::decode::lzma::MIN_DICT_SIZE;
+use crate::error::options::{OptionsError, RawOptionsError};
+use core::convert::TryFrom;
+
 /// Options to tweak decompression behavior.
-#[derive(Clone, Copy, Debug, PartialEq, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Options {
     /// Defines whether the unpacked size should be read from the header or provided.
     ///
     /// The default is
     /// [`UnpackedSize::ReadFromHeader`](enum.UnpackedSize.html#variant.ReadFromHeader).
     pub unpacked_size: UnpackedSize,
+    /// Reject headers declaring a dictionary smaller than the 4 KiB minimum
+    /// with [`crate::error::lzma::LzmaError::DictionaryTooSmall`], instead of
+    /// treating them as 4 KiB as the specification and xz-utils do.
+    ///
+    /// The default is `false`.
+    pub reject_small_dict: bool,
+    /// Reject headers declaring a dictionary larger than this with
+    /// [`crate::error::lzma::LzmaError::DictionaryTooLarge`], before any
+    /// buffer is involved. This is a policy limit, independent of the size
+    /// of the dictionary buffer, which is checked later and fails with
+    /// [`crate::error::Error::DictionaryBufferTooSmall`].
+    ///
+    /// Dictionary sizes below 4 KiB are rounded up before the check. The
+    /// default is `None`, no limit.
+    pub max_dict_size: Option<u32>,
+    /// Make [`Stream::write`](crate::decompress::Stream::write) return after
+    /// decoding this many symbols (literals or matches), even if compressed
+    /// data is left, so that a bare-metal main loop can service a watchdog or
+    /// other tasks in between. A symbol decodes to at most 273 bytes.
+    ///
+    /// The call then consumes only part of the data and
+    /// [`Stream::symbol_limit_reached`](crate::decompress::Stream::symbol_limit_reached)
+    /// returns `true`; pass the rest of the data to the next call. Only the
+    /// streaming decoder honors this limit, and not in
+    /// [`Stream::finish`](crate::decompress::Stream::finish), which has at
+    /// most a few bytes of data left to decode. The default is `None`, no
+    /// limit.
+    pub max_symbols_per_write: Option<u32>,
+    /// What to do with input following the end of the stream, i.e. its
+    /// unpacked size or its end marker, such as the `0xFF` or `0x00` padding
+    /// of a flash image. The one-shot functions and
+    /// [`Stream`](crate::decompress::Stream) handle it the same way.
+    ///
+    /// The default is `None`, keeping the historical behavior: input after
+    /// an end marker fails with
+    /// [`crate::error::lzma::LzmaError::EosFoundButMoreBytesAvailable`],
+    /// while input after the unpacked size is left unread by the one-shot
+    /// functions and not consumed by `Stream::write`.
+    pub trailing_bytes: Option<TrailingBytes>,
+}
+
+/// Handling of input following the end of a stream, see
+/// [`Options::trailing_bytes`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrailingBytes {
+    /// Fail with [`crate::error::lzma::LzmaError::TrailingBytes`].
+    Error,
+    /// Accept the input and skip it. The one-shot functions leave it unread,
+    /// while `Stream::write` consumes it.
+    Ignore,
+    /// Like [`TrailingBytes::Ignore`], but read the input and report its
+    /// length, in [`DecodeSummary::trailing_bytes`](crate::decompress::DecodeSummary::trailing_bytes)
+    /// or `FinishSummary::trailing_bytes`.
+    ReturnCount,
 }
 
 /// Alternatives for defining the unpacked size of the decoded data.
 #[repr(C)]
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UnpackedSize {
     /// Assume that the 8 bytes used to specify the unpacked size are present in the header.
     /// If the bytes are `0xFFFF_FFFF_FFFF_FFFF`, assume that there is an end-of-payload marker in
@@ -26,6 +89,11 @@ pub enum UnpackedSize {
     /// but is used by certain libraries such as
     /// [OpenCTM](http://openctm.sourceforge.net/).
     ReadHeaderButUseProvided(Option<u64>),
+    /// Assume that only 4 bytes are used to specify the unpacked size in the header, as written
+    /// by some legacy tools.
+    /// If the bytes are `0xFFFF_FFFF`, assume that there is an end-of-payload marker in the file.
+    /// If not, read the 4 bytes as a little-endian encoded u32.
+    ReadFromHeader32,
     /// Assume that the 8 bytes typically used to represent the unpacked size are *not* present in
     /// the header. Use the provided value.
     /// If the provided value is `None`, assume that there is an end-of-payload marker in the file.
@@ -43,6 +111,96 @@ impl Options {
     pub const fn default() -> Self {
         Self {
             unpacked_size: UnpackedSize::default(),
+            reject_small_dict: false,
+            max_dict_size: None,
+            max_symbols_per_write: None,
+            trailing_bytes: None,
+        }
+    }
+
+    /// Start building options from the defaults, checking them with
+    /// [`Options::validate`] at the end.
+    ///
+    /// ```
+    /// use lzma_rs::decompress::{Options, UnpackedSize};
+    ///
+    /// const OPTIONS: Options = match Options::builder()
+    ///     .unpacked_size(UnpackedSize::UseProvided(Some(4096)))
+    ///     .max_dict_size(1 << 20)
+    ///     .build()
+    /// {
+    ///     Ok(options) => options,
+    ///     Err(_) => panic!("invalid options"),
+    /// };
+    /// assert_eq!(OPTIONS.max_dict_size, Some(1 << 20));
+    ///
+    /// assert!(Options::builder().max_symbols_per_write(0).build().is_err());
+    /// ```
+    pub const fn builder() -> OptionsBuilder {
+        OptionsBuilder {
+            options: Self::default(),
+        }
+    }
+
+    /// Check that the options are consistent: `max_dict_size` must not be
+    /// below the 4 KiB minimum dictionary size, and `max_symbols_per_write`
+    /// must not be zero.
+    pub const fn validate(&self) -> Result<(), OptionsError> {
+        if let Some(max_dict_size) = self.max_dict_size {
+            if max_dict_size < MIN_DICT_SIZE {
+                return Err(OptionsError::MaxDictSizeBelowMinimum { max_dict_size });
+            }
+        }
+        if let Some(0) = self.max_symbols_per_write {
+            return Err(OptionsError::ZeroSymbolsPerWrite);
+        }
+        Ok(())
+    }
+}
+
+/// Builder for [`Options`], see [`Options::builder`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+impl OptionsBuilder {
+    /// Set [`Options::unpacked_size`].
+    pub const fn unpacked_size(mut self, unpacked_size: UnpackedSize) -> Self {
+        self.options.unpacked_size = unpacked_size;
+        self
+    }
+
+    /// Set [`Options::reject_small_dict`].
+    pub const fn reject_small_dict(mut self, reject_small_dict: bool) -> Self {
+        self.options.reject_small_dict = reject_small_dict;
+        self
+    }
+
+    /// Set [`Options::max_dict_size`] to `Some(max_dict_size)`.
+    pub const fn max_dict_size(mut self, max_dict_size: u32) -> Self {
+        self.options.max_dict_size = Some(max_dict_size);
+        self
+    }
+
+    /// Set [`Options::max_symbols_per_write`] to
+    /// `Some(max_symbols_per_write)`.
+    pub const fn max_symbols_per_write(mut self, max_symbols_per_write: u32) -> Self {
+        self.options.max_symbols_per_write = Some(max_symbols_per_write);
+        self
+    }
+
+    /// Set [`Options::trailing_bytes`] to `Some(trailing_bytes)`.
+    pub const fn trailing_bytes(mut self, trailing_bytes: TrailingBytes) -> Self {
+        self.options.trailing_bytes = Some(trailing_bytes);
+        self
+    }
+
+    /// Validate and return the options.
+    pub const fn build(self) -> Result<Options, OptionsError> {
+        match self.options.validate() {
+            Ok(()) => Ok(self.options),
+            Err(e) => Err(e),
         }
     }
 }
@@ -54,6 +212,188 @@ impl UnpackedSize {
     }
 }
 
+/// [`Options`] with a stable `#[repr(C)]` layout, for configuration shared
+/// with C code or patched into flash after linking.
+///
+/// The layout is fixed (24 bytes, 8-byte aligned, native endianness):
+///
+/// ```c
+/// struct lzma_raw_options {
+///     uint32_t unpacked_size_mode; /* offset 0, RawOptions::UNPACKED_SIZE_* */
+///     uint32_t flags;              /* offset 4, RawOptions::FLAG_* and
+///                                     RawOptions::TRAILING_BYTES_* */
+///     uint64_t unpacked_size;      /* offset 8 */
+///     uint32_t max_dict_size;      /* offset 16, 0 for no limit */
+///     uint32_t max_symbols_per_write; /* offset 20, 0 for no limit */
+/// };
+/// ```
+///
+/// All zeroes is the default configuration. Convert to [`Options`] with
+/// [`TryFrom`], which rejects unknown modes and flags:
+///
+/// ```
+/// use core::convert::TryFrom;
+/// use lzma_rs::decompress::{Options, RawOptions, UnpackedSize};
+///
+/// #[no_mangle]
+/// static LZMA_OPTIONS: RawOptions = RawOptions::new(&Options {
+///     unpacked_size: UnpackedSize::UseProvided(Some(4096)),
+///     reject_small_dict: true,
+///     max_dict_size: Some(1 << 20),
+///     max_symbols_per_write: None,
+///     trailing_bytes: None,
+/// });
+///
+/// let options = Options::try_from(LZMA_OPTIONS).unwrap();
+/// assert_eq!(options.unpacked_size, UnpackedSize::UseProvided(Some(4096)));
+/// ```
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub struct RawOptions {
+    /// Variant of [`UnpackedSize`], one of the `UNPACKED_SIZE_*` constants.
+    pub unpacked_size_mode: u32,
+    /// Bitwise or of the `FLAG_*` constants and one of the `TRAILING_BYTES_*`
+    /// constants.
+    pub flags: u32,
+    /// Value of the provided unpacked size, if [`RawOptions::FLAG_HAS_UNPACKED_SIZE`]
+    /// is set.
+    pub unpacked_size: u64,
+    /// [`Options::max_dict_size`], `0` meaning `None`.
+    pub max_dict_size: u32,
+    /// [`Options::max_symbols_per_write`], `0` meaning `None`.
+    pub max_symbols_per_write: u32,
+}
+
+const _: () = {
+    use core::mem::{align_of, offset_of, size_of};
+    assert!(size_of::<RawOptions>() == 24);
+    assert!(align_of::<RawOptions>() == 8);
+    assert!(offset_of!(RawOptions, unpacked_size_mode) == 0);
+    assert!(offset_of!(RawOptions, flags) == 4);
+    assert!(offset_of!(RawOptions, unpacked_size) == 8);
+    assert!(offset_of!(RawOptions, max_dict_size) == 16);
+    assert!(offset_of!(RawOptions, max_symbols_per_write) == 20);
+};
+
+impl RawOptions {
+    /// [`UnpackedSize::ReadFromHeader`]
+    pub const UNPACKED_SIZE_READ_FROM_HEADER: u32 = 0;
+    /// [`UnpackedSize::ReadHeaderButUseProvided`]
+    pub const UNPACKED_SIZE_READ_HEADER_BUT_USE_PROVIDED: u32 = 1;
+    /// [`UnpackedSize::ReadFromHeader32`]
+    pub const UNPACKED_SIZE_READ_FROM_HEADER_32: u32 = 2;
+    /// [`UnpackedSize::UseProvided`]
+    pub const UNPACKED_SIZE_USE_PROVIDED: u32 = 3;
+
+    /// The provided unpacked size is `Some(unpacked_size)`, `None` otherwise.
+    pub const FLAG_HAS_UNPACKED_SIZE: u32 = 1 << 0;
+    /// [`Options::reject_small_dict`]
+    pub const FLAG_REJECT_SMALL_DICT: u32 = 1 << 1;
+
+    /// Bits of the flags holding [`Options::trailing_bytes`], one of the
+    /// `TRAILING_BYTES_*` constants.
+    pub const TRAILING_BYTES_MASK: u32 = 3 << 2;
+    /// [`Options::trailing_bytes`] is `None`
+    pub const TRAILING_BYTES_DEFAULT: u32 = 0;
+    /// [`TrailingBytes::Error`]
+    pub const TRAILING_BYTES_ERROR: u32 = 1 << 2;
+    /// [`TrailingBytes::Ignore`]
+    pub const TRAILING_BYTES_IGNORE: u32 = 2 << 2;
+    /// [`TrailingBytes::ReturnCount`]
+    pub const TRAILING_BYTES_RETURN_COUNT: u32 = 3 << 2;
+
+    const KNOWN_FLAGS: u32 =
+        Self::FLAG_HAS_UNPACKED_SIZE | Self::FLAG_REJECT_SMALL_DICT | Self::TRAILING_BYTES_MASK;
+
+    /// Convert `options`, usable to initialize a `static`.
+    pub const fn new(options: &Options) -> Self {
+        let (unpacked_size_mode, provided) = match options.unpacked_size {
+            UnpackedSize::ReadFromHeader => (Self::UNPACKED_SIZE_READ_FROM_HEADER, None),
+            UnpackedSize::ReadHeaderButUseProvided(x) => {
+                (Self::UNPACKED_SIZE_READ_HEADER_BUT_USE_PROVIDED, x)
+            }
+            UnpackedSize::ReadFromHeader32 => (Self::UNPACKED_SIZE_READ_FROM_HEADER_32, None),
+            UnpackedSize::UseProvided(x) => (Self::UNPACKED_SIZE_USE_PROVIDED, x),
+        };
+        let (mut flags, unpacked_size) = match provided {
+            Some(x) => (Self::FLAG_HAS_UNPACKED_SIZE, x),
+            None => (0, 0),
+        };
+        if options.reject_small_dict {
+            flags |= Self::FLAG_REJECT_SMALL_DICT;
+        }
+        flags |= match options.trailing_bytes {
+            None => Self::TRAILING_BYTES_DEFAULT,
+            Some(TrailingBytes::Error) => Self::TRAILING_BYTES_ERROR,
+            Some(TrailingBytes::Ignore) => Self::TRAILING_BYTES_IGNORE,
+            Some(TrailingBytes::ReturnCount) => Self::TRAILING_BYTES_RETURN_COUNT,
+        };
+        let max_dict_size = match options.max_dict_size {
+            Some(x) => x,
+            None => 0,
+        };
+        let max_symbols_per_write = match options.max_symbols_per_write {
+            Some(x) => x,
+            None => 0,
+        };
+        Self {
+            unpacked_size_mode,
+            flags,
+            unpacked_size,
+            max_dict_size,
+            max_symbols_per_write,
+        }
+    }
+}
+
+impl From<Options> for RawOptions {
+    fn from(options: Options) -> Self {
+        Self::new(&options)
+    }
+}
+
+impl TryFrom<RawOptions> for Options {
+    type Error = RawOptionsError;
+
+    fn try_from(raw: RawOptions) -> Result<Self, Self::Error> {
+        if raw.flags & !RawOptions::KNOWN_FLAGS != 0 {
+            return Err(RawOptionsError::UnknownFlags { flags: raw.flags });
+        }
+        let provided = if raw.flags & RawOptions::FLAG_HAS_UNPACKED_SIZE != 0 {
+            Some(raw.unpacked_size)
+        } else {
+            None
+        };
+        let unpacked_size = match raw.unpacked_size_mode {
+            RawOptions::UNPACKED_SIZE_READ_FROM_HEADER => UnpackedSize::ReadFromHeader,
+            RawOptions::UNPACKED_SIZE_READ_HEADER_BUT_USE_PROVIDED => {
+                UnpackedSize::ReadHeaderButUseProvided(provided)
+            }
+            RawOptions::UNPACKED_SIZE_READ_FROM_HEADER_32 => UnpackedSize::ReadFromHeader32,
+            RawOptions::UNPACKED_SIZE_USE_PROVIDED => UnpackedSize::UseProvided(provided),
+            mode => return Err(RawOptionsError::UnknownUnpackedSizeMode { mode }),
+        };
+        Ok(Options {
+            unpacked_size,
+            reject_small_dict: raw.flags & RawOptions::FLAG_REJECT_SMALL_DICT != 0,
+            max_dict_size: match raw.max_dict_size {
+                0 => None,
+                x => Some(x),
+            },
+            max_symbols_per_write: match raw.max_symbols_per_write {
+                0 => None,
+                x => Some(x),
+            },
+            trailing_bytes: match raw.flags & RawOptions::TRAILING_BYTES_MASK {
+                RawOptions::TRAILING_BYTES_ERROR => Some(TrailingBytes::Error),
+                RawOptions::TRAILING_BYTES_IGNORE => Some(TrailingBytes::Ignore),
+                RawOptions::TRAILING_BYTES_RETURN_COUNT => Some(TrailingBytes::ReturnCount),
+                _ => None,
+            },
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -62,8 +402,95 @@ mod test {
         assert_eq!(
             Options {
                 unpacked_size: UnpackedSize::ReadFromHeader,
+                reject_small_dict: false,
+                max_dict_size: None,
+                max_symbols_per_write: None,
+                trailing_bytes: None,
             },
             Options::default()
         );
     }
+
+    #[test]
+    fn test_options_builder() {
+        assert_eq!(Options::builder().build(), Ok(Options::default()));
+        assert_eq!(
+            Options::builder()
+                .unpacked_size(UnpackedSize::ReadFromHeader32)
+                .reject_small_dict(true)
+                .max_dict_size(MIN_DICT_SIZE)
+                .max_symbols_per_write(1)
+                .trailing_bytes(TrailingBytes::Ignore)
+                .build(),
+            Ok(Options {
+                unpacked_size: UnpackedSize::ReadFromHeader32,
+                reject_small_dict: true,
+                max_dict_size: Some(MIN_DICT_SIZE),
+                max_symbols_per_write: Some(1),
+                trailing_bytes: Some(TrailingBytes::Ignore),
+            })
+        );
+        assert_eq!(
+            Options::builder().max_dict_size(MIN_DICT_SIZE - 1).build(),
+            Err(OptionsError::MaxDictSizeBelowMinimum {
+                max_dict_size: MIN_DICT_SIZE - 1
+            })
+        );
+        assert_eq!(
+            Options::builder().max_symbols_per_write(0).build(),
+            Err(OptionsError::ZeroSymbolsPerWrite)
+        );
+    }
+
+    #[test]
+    fn test_raw_options() {
+        assert_eq!(RawOptions::default(), RawOptions::new(&Options::default()));
+        for unpacked_size in [
+            UnpackedSize::ReadFromHeader,
+            UnpackedSize::ReadHeaderButUseProvided(None),
+            UnpackedSize::ReadHeaderButUseProvided(Some(0)),
+            UnpackedSize::ReadFromHeader32,
+            UnpackedSize::UseProvided(None),
+            UnpackedSize::UseProvided(Some(u64::MAX)),
+        ] {
+            for reject_small_dict in [false, true] {
+                for max_dict_size in [None, Some(1), Some(u32::MAX)] {
+                    for max_symbols_per_write in [None, Some(1), Some(u32::MAX)] {
+                        for trailing_bytes in [
+                            None,
+                            Some(TrailingBytes::Error),
+                            Some(TrailingBytes::Ignore),
+                            Some(TrailingBytes::ReturnCount),
+                        ] {
+                            let options = Options {
+                                unpacked_size,
+                                reject_small_dict,
+                                max_dict_size,
+                                max_symbols_per_write,
+                                trailing_bytes,
+                            };
+                            assert_eq!(Options::try_from(RawOptions::from(options)), Ok(options));
+                        }
+                    }
+                }
+            }
+        }
+
+        let raw = RawOptions {
+            unpacked_size_mode: 4,
+            ..RawOptions::default()
+        };
+        assert_eq!(
+            Options::try_from(raw),
+            Err(RawOptionsError::UnknownUnpackedSizeMode { mode: 4 })
+        );
+        let raw = RawOptions {
+            flags: 1 << 4,
+            ..RawOptions::default()
+        };
+        assert_eq!(
+            Options::try_from(raw),
+            Err(RawOptionsError::UnknownFlags { flags: 1 << 4 })
+        );
+    }
 }
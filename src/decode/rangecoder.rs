@@ -1,35 +1,46 @@
 use crate::decode::util;
+#[cfg(any(feature = "encoder", feature = "telemetry-encoder"))]
+use crate::encode::rangecoder::RangeEncoder;
 use crate::error;
 use crate::io;
-use byteorder::BigEndian;
-use io::ReadBytesExt;
+use core::{iter, slice};
 
 // TODO: Replace generic RangeDecoder over `R` into `dyn io::BufRead`?
+/// Binary range decoder reading from `stream`, as used by LZMA.
+///
+/// Bits are decoded either with an adaptive probability (a `u16` out of
+/// `0x800`, initially `0x400`) or with a fixed one-half probability.
 pub struct RangeDecoder<'a, R>
 where
     R: 'a + io::BufRead,
 {
-    pub stream: &'a mut R,
-    pub range: u32,
-    pub code: u32,
+    pub(crate) stream: &'a mut R,
+    pub(crate) range: u32,
+    pub(crate) code: u32,
 }
 
 impl<'a, R> RangeDecoder<'a, R>
 where
     R: io::BufRead,
 {
+    /// Start decoding `stream`, reading the 5 initialization bytes written
+    /// by the range encoder.
     pub fn new(stream: &'a mut R) -> io::Result<Self> {
         let mut dec = Self {
             stream,
             range: 0xFFFF_FFFF,
             code: 0,
         };
-        let _ = dec.stream.read_u8()?;
-        dec.code = dec.stream.read_u32::<BigEndian>()?;
+        let _ = util::read_byte(dec.stream)?;
+        for _ in 0..4 {
+            dec.code = (dec.code << 8) ^ u32::from(util::read_byte(dec.stream)?);
+        }
         lzma_debug!("0 {{ range: {:08x}, code: {:08x} }}", dec.range, dec.code);
         Ok(dec)
     }
 
+    /// Resume decoding `stream` from a saved `range` and `code`, see
+    /// [`RangeDecoder::range`] and [`RangeDecoder::code`].
     pub fn from_parts(stream: &'a mut R, range: u32, code: u32) -> Self {
         Self {
             stream,
@@ -38,22 +49,41 @@ where
         }
     }
 
-    pub fn set(&mut self, range: u32, code: u32) {
+    /// Current range of the decoder.
+    pub fn range(&self) -> u32 {
+        self.range
+    }
+
+    /// Current code of the decoder.
+    pub fn code(&self) -> u32 {
+        self.code
+    }
+
+    pub(crate) fn set(&mut self, range: u32, code: u32) {
         self.range = range;
         self.code = code;
     }
 
-    pub fn read_into(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+    /// Value of the next bit decoded with probability `prob`, without
+    /// decoding it.
+    #[inline]
+    pub(crate) fn peek_bit(&self, prob: u16) -> bool {
+        self.code >= (self.range >> 11) * (prob as u32)
+    }
+
+    pub(crate) fn read_into(&mut self, dst: &mut [u8]) -> io::Result<usize> {
         self.stream.read(dst)
     }
 
+    /// Whether the encoded data ended properly: the code is zero and the
+    /// input is exhausted.
     #[inline]
     pub fn is_finished_ok(&mut self) -> io::Result<bool> {
         Ok(self.code == 0 && self.is_eof()?)
     }
 
     #[inline]
-    pub fn is_eof(&mut self) -> io::Result<bool> {
+    pub(crate) fn is_eof(&mut self) -> io::Result<bool> {
         util::is_eof(self.stream)
     }
 
@@ -62,7 +92,7 @@ where
         lzma_trace!("  {{ range: {:08x}, code: {:08x} }}", self.range, self.code);
         if self.range < 0x0100_0000 {
             self.range <<= 8;
-            self.code = (self.code << 8) ^ (self.stream.read_u8()? as u32);
+            self.code = (self.code << 8) ^ (util::read_byte(self.stream)? as u32);
 
             lzma_debug!("+ {{ range: {:08x}, code: {:08x} }}", self.range, self.code);
         }
@@ -82,6 +112,8 @@ where
         Ok(bit)
     }
 
+    /// Decode `count` bits with a fixed one-half probability, most
+    /// significant bit first.
     pub fn get(&mut self, count: usize) -> error::Result<u32> {
         let mut result = 0u32;
         for _ in 0..count {
@@ -90,8 +122,24 @@ where
         Ok(result)
     }
 
-    #[inline]
+    /// Decode a bit with the adaptive probability `prob` of it being `0`,
+    /// updating `prob` if `update` is set.
+    ///
+    /// `prob` must be in `1..0x800`, otherwise an error of kind
+    /// [`InvalidInput`](io::ErrorKind::InvalidInput) is returned.
     pub fn decode_bit(&mut self, prob: &mut u16, update: bool) -> io::Result<bool> {
+        if *prob == 0 || *prob >= 0x800 {
+            return Err(prob_out_of_range());
+        }
+        self.decode_bit_raw(prob, update)
+    }
+
+    /// Same as [`RangeDecoder::decode_bit`], for probabilities kept in range
+    /// by the decoder itself.
+    // Written without data-dependent branches so that the compiler can use
+    // conditional moves; the decoded bit is unpredictable by nature.
+    #[inline]
+    pub(crate) fn decode_bit_raw(&mut self, prob: &mut u16, update: bool) -> io::Result<bool> {
         let bound: u32 = (self.range >> 11) * (*prob as u32);
 
         lzma_trace!(
@@ -100,40 +148,46 @@ where
             prob,
             (self.code > bound) as u8
         );
-        if self.code < bound {
-            if update {
-                *prob += (0x800_u16 - *prob) >> 5;
-            }
-            self.range = bound;
-
-            self.normalize()?;
-            Ok(false)
-        } else {
-            if update {
-                *prob -= *prob >> 5;
-            }
-            self.code -= bound;
-            self.range -= bound;
-
-            self.normalize()?;
-            Ok(true)
+        let bit = self.code >= bound;
+        // All ones if the bit is set, zero otherwise
+        let mask = 0_u32.wrapping_sub(bit as u32);
+        self.code -= bound & mask;
+        self.range = (bound & !mask) | (self.range.wrapping_sub(bound) & mask);
+        if update {
+            // Both candidates are computed upfront; the select compiles into
+            // a conditional move
+            let p = *prob;
+            let p0 = p + ((0x800_u16 - p) >> 5);
+            let p1 = p - (p >> 5);
+            *prob = if bit { p1 } else { p0 };
         }
+
+        self.normalize()?;
+        Ok(bit)
     }
 
-    fn parse_bit_tree(
+    fn parse_bit_tree<const SIZE: usize>(
         &mut self,
         num_bits: usize,
-        probs: &mut [u16],
+        probs: &mut [u16; SIZE],
         update: bool,
     ) -> io::Result<u32> {
         let mut tmp: u32 = 1;
         for _ in 0..num_bits {
-            let bit = self.decode_bit(&mut probs[tmp as usize], update)?;
+            // `SIZE` is a power of 2, masking lets the compiler elide the
+            // bounds check
+            let bit = self.decode_bit_raw(&mut probs[tmp as usize & (SIZE - 1)], update)?;
             tmp = (tmp << 1) ^ (bit as u32);
         }
         Ok(tmp - (1 << num_bits))
     }
 
+    /// Decode `num_bits` bits, least significant bit first, with the bit
+    /// tree of probabilities starting at `probs[offset]`. The tree uses the
+    /// `1 << num_bits` entries after `offset`, the first one being unused.
+    ///
+    /// `num_bits` must be 32 at most and the probabilities in the range
+    /// accepted by [`RangeDecoder::decode_bit`].
     pub fn parse_reverse_bit_tree(
         &mut self,
         num_bits: usize,
@@ -141,10 +195,20 @@ where
         offset: usize,
         update: bool,
     ) -> io::Result<u32> {
+        if num_bits > 32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "more than 32 bits requested",
+            ));
+        }
         let mut result = 0u32;
         let mut tmp: usize = 1;
         for i in 0..num_bits {
-            let bit = self.decode_bit(&mut probs[offset + tmp], update)?;
+            let prob = offset
+                .checked_add(tmp)
+                .and_then(|index| probs.get_mut(index))
+                .ok_or_else(util::out_of_bounds)?;
+            let bit = self.decode_bit(prob, update)?;
             tmp = (tmp << 1) ^ (bit as usize);
             result ^= (bit as u32) << i;
         }
@@ -152,43 +216,108 @@ where
     }
 }
 
-#[derive(Clone, Copy)]
+#[cold]
+#[inline(never)]
+fn prob_out_of_range() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "probability out of range")
+}
+
+impl<'a, R> core::fmt::Debug for RangeDecoder<'a, R>
+where
+    R: io::BufRead,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("RangeDecoder")
+            .field("range", &self.range)
+            .field("code", &self.code)
+            .finish()
+    }
+}
+
+/// Adaptive model of values of `log2(SIZE)` bits, decoded bit by bit along a
+/// binary tree of `SIZE` probabilities. `SIZE` must be a power of 2.
+///
+/// The probabilities are zero until [`BitTree::reset`] is called.
+#[derive(Clone, Copy, Debug)]
 pub struct BitTree<const SIZE: usize> {
-    num_bits: usize,
     probs: [u16; SIZE],
 }
 
 impl<const SIZE: usize> BitTree<SIZE> {
+    // Evaluated at compile time, so a bad `SIZE` never panics at runtime
+    const NUM_BITS: usize = match util::exact_log2(SIZE) {
+        Some(v) => v,
+        None => panic!("BitTree<SIZE> where SIZE is not power of 2"),
+    };
+
+    /// Create a bit tree; call [`BitTree::reset`] before using it.
     pub const fn new() -> Self {
-        Self {
-            num_bits: 0,
-            probs: [0; SIZE],
-        }
+        Self { probs: [0; SIZE] }
     }
+    /// Set all probabilities to one half.
     pub fn reset(&mut self) {
-        self.num_bits = match util::exact_log2(SIZE) {
-            Some(v) => v,
-            None => panic!("BitTree<SIZE> where SIZE is not power of 2"),
-        };
         self.probs.iter_mut().for_each(|v| *v = 0x400);
     }
+
+    pub(crate) fn probs(&self) -> &[u16] {
+        &self.probs
+    }
+
+    pub(crate) fn probs_mut(&mut self) -> &mut [u16] {
+        &mut self.probs
+    }
+    /// Decode a value, most significant bit first.
     pub fn parse<R: io::BufRead>(
         &mut self,
         rangecoder: &mut RangeDecoder<R>,
         update: bool,
     ) -> io::Result<u32> {
-        rangecoder.parse_bit_tree(self.num_bits, &mut self.probs, update)
+        rangecoder.parse_bit_tree(Self::NUM_BITS, &mut self.probs, update)
     }
 
+    /// Decode a value, least significant bit first.
     pub fn parse_reverse<R: io::BufRead>(
         &mut self,
         rangecoder: &mut RangeDecoder<R>,
         update: bool,
     ) -> io::Result<u32> {
-        rangecoder.parse_reverse_bit_tree(self.num_bits, &mut self.probs, 0, update)
+        rangecoder.parse_reverse_bit_tree(Self::NUM_BITS, &mut self.probs, 0, update)
+    }
+
+    /// Encode `value`, most significant bit first, the counterpart of
+    /// [`BitTree::parse`].
+    #[cfg(any(feature = "encoder", feature = "telemetry-encoder"))]
+    pub fn encode<W: io::Write + ?Sized>(
+        &mut self,
+        rangecoder: &mut RangeEncoder<W>,
+        value: u32,
+    ) -> io::Result<()> {
+        rangecoder.encode_bit_tree(&mut self.probs, Self::NUM_BITS, value)
+    }
+
+    /// Encode `value`, least significant bit first, the counterpart of
+    /// [`BitTree::parse_reverse`].
+    #[cfg(any(feature = "encoder", feature = "telemetry-encoder"))]
+    pub fn encode_reverse<W: io::Write + ?Sized>(
+        &mut self,
+        rangecoder: &mut RangeEncoder<W>,
+        value: u32,
+    ) -> io::Result<()> {
+        rangecoder.encode_reverse_bit_tree(&mut self.probs, 0, Self::NUM_BITS, value)
     }
 }
 
+impl<const SIZE: usize> Default for BitTree<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Adaptive model of LZMA match lengths minus 2 (0 to 271), with separate
+/// models for short lengths in each of 16 position states.
+///
+/// The probabilities are zero until [`LenDecoder::reset`] is called.
+#[derive(Clone, Copy, Debug)]
 pub struct LenDecoder {
     choice: u16,
     choice2: u16,
@@ -198,6 +327,7 @@ pub struct LenDecoder {
 }
 
 impl LenDecoder {
+    /// Create a length decoder; call [`LenDecoder::reset`] before using it.
     pub const fn new() -> Self {
         Self {
             choice: 0,
@@ -207,25 +337,155 @@ impl LenDecoder {
             high_coder: BitTree::new(),
         }
     }
+    /// Set all probabilities to one half.
     pub fn reset(&mut self) {
-            self.choice = 0x400;
-            self.choice2 = 0x400;
-            self.low_coder.iter_mut().for_each(|v| v.reset());
-            self.mid_coder.iter_mut().for_each(|v| v.reset());
-            self.high_coder.reset();
+        self.choice = 0x400;
+        self.choice2 = 0x400;
+        self.low_coder.iter_mut().for_each(|v| v.reset());
+        self.mid_coder.iter_mut().for_each(|v| v.reset());
+        self.high_coder.reset();
+    }
+
+    /// Probabilities in the order of a model snapshot, see
+    /// [`crate::decode::model`].
+    pub(crate) fn model(&self) -> impl Iterator<Item = &[u16]> {
+        iter::once(slice::from_ref(&self.choice))
+            .chain(iter::once(slice::from_ref(&self.choice2)))
+            .chain(self.low_coder.iter().map(BitTree::probs))
+            .chain(self.mid_coder.iter().map(BitTree::probs))
+            .chain(iter::once(self.high_coder.probs()))
+    }
+
+    pub(crate) fn model_mut(&mut self) -> impl Iterator<Item = &mut [u16]> {
+        iter::once(slice::from_mut(&mut self.choice))
+            .chain(iter::once(slice::from_mut(&mut self.choice2)))
+            .chain(self.low_coder.iter_mut().map(BitTree::probs_mut))
+            .chain(self.mid_coder.iter_mut().map(BitTree::probs_mut))
+            .chain(iter::once(self.high_coder.probs_mut()))
     }
+    /// Decode a length in position state `pos_state` (only its 4 low bits
+    /// are used).
     pub fn decode<R: io::BufRead>(
         &mut self,
         rangecoder: &mut RangeDecoder<R>,
         pos_state: usize,
         update: bool,
     ) -> io::Result<usize> {
-        if !rangecoder.decode_bit(&mut self.choice, update)? {
+        // `pos_state` is below 16, masking lets the compiler elide the bounds
+        // checks
+        let pos_state = pos_state & 0xF;
+        if !rangecoder.decode_bit_raw(&mut self.choice, update)? {
             Ok(self.low_coder[pos_state].parse(rangecoder, update)? as usize)
-        } else if !rangecoder.decode_bit(&mut self.choice2, update)? {
+        } else if !rangecoder.decode_bit_raw(&mut self.choice2, update)? {
             Ok(self.mid_coder[pos_state].parse(rangecoder, update)? as usize + 8)
         } else {
             Ok(self.high_coder.parse(rangecoder, update)? as usize + 16)
         }
     }
 }
+
+impl Default for LenDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<const SIZE: usize> zeroize::Zeroize for BitTree<SIZE> {
+    fn zeroize(&mut self) {
+        self.probs.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for LenDecoder {
+    fn zeroize(&mut self) {
+        self.choice.zeroize();
+        self.choice2.zeroize();
+        self.low_coder.zeroize();
+        self.mid_coder.zeroize();
+        self.high_coder.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_len_decoder_max() {
+        // `code` just below `range` decodes every bit as 1, the longest length
+        let input = [0xFF; 16];
+        let mut reader = io::Cursor::new(&input[..]);
+        let mut rangecoder = RangeDecoder::from_parts(&mut reader, 0xFFFF_FFFF, 0xFFFF_FFFE);
+        let mut decoder = LenDecoder::new();
+        let len = decoder.decode(&mut rangecoder, 0, true).unwrap();
+        assert_eq!(len + 2, crate::decode::lzma::MATCH_LEN_MAX);
+    }
+
+    #[test]
+    fn test_out_of_range_inputs() {
+        let input = [0; 16];
+        let mut reader = io::Cursor::new(&input[..]);
+        let mut rangecoder = RangeDecoder::from_parts(&mut reader, 0xFFFF_FFFF, 0);
+        for &prob in &[0, 0x800, 0x801, 0xFFFF] {
+            let mut prob = prob;
+            let err = rangecoder.decode_bit(&mut prob, true).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        let mut probs = [0x400; 64];
+        let err = rangecoder
+            .parse_reverse_bit_tree(33, &mut probs, 0, true)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(rangecoder
+            .parse_reverse_bit_tree(4, &mut probs, usize::MAX, true)
+            .is_err());
+        probs[1] = 0x900;
+        let err = rangecoder
+            .parse_reverse_bit_tree(4, &mut probs, 0, true)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // Nothing was decoded
+        assert_eq!((rangecoder.range(), rangecoder.code()), (0xFFFF_FFFF, 0));
+    }
+
+    #[cfg(feature = "encoder")]
+    #[test]
+    fn test_round_trip_with_encoder() {
+        use crate::encode::rangecoder::LenEncoder;
+
+        let mut encoded = Vec::new();
+        let mut enc = RangeEncoder::new(&mut encoded);
+        let mut tree = BitTree::<64>::new();
+        tree.reset();
+        let mut len_encoder = LenEncoder::new();
+        for i in 0..272 {
+            tree.encode(&mut enc, i % 64).unwrap();
+            tree.encode_reverse(&mut enc, (i * 7) % 64).unwrap();
+            enc.encode_direct_bits(i, 9).unwrap();
+            len_encoder
+                .encode(&mut enc, i as usize, i as usize)
+                .unwrap();
+        }
+        enc.finish().unwrap();
+
+        let mut input = &encoded[..];
+        let mut dec = RangeDecoder::new(&mut input).unwrap();
+        let mut tree = BitTree::<64>::new();
+        tree.reset();
+        let mut len_decoder = LenDecoder::new();
+        len_decoder.reset();
+        for i in 0..272 {
+            assert_eq!(tree.parse(&mut dec, true).unwrap(), i % 64);
+            assert_eq!(tree.parse_reverse(&mut dec, true).unwrap(), (i * 7) % 64);
+            assert_eq!(dec.get(9).unwrap(), i);
+            assert_eq!(
+                len_decoder.decode(&mut dec, i as usize, true).unwrap(),
+                i as usize
+            );
+        }
+        assert!(dec.is_finished_ok().unwrap());
+    }
+}
@@ -0,0 +1,354 @@
+//! Decompression of a sequence of messages sharing one LZMA stream.
+
+use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
+use crate::decode::lzma::{self, LiteralCoder, LzmaParams};
+use crate::decode::model;
+use crate::decode::rangecoder::RangeDecoder;
+use crate::error;
+use crate::io;
+use crate::option::GuaranteedOption;
+use crate::properties::LzmaProperties;
+use core::fmt::Debug;
+
+/// Decompresses the messages of a
+/// [`CompressSession`](crate::compress::CompressSession), keeping the
+/// dictionary and probabilities from one message to the next.
+///
+/// The session uses a fixed amount of memory, about `DICT_MEM_LIMIT +
+/// PROBS_MEM_LIMIT * 1536` bytes. Messages must be given in the order they
+/// were compressed. After an error, e.g. a lost or corrupted message, the
+/// session must be started again, and so must the compressing side.
+pub struct DecompressSession<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> {
+    decoder:
+        lzma::DecoderState<LzCircularBuffer<[u8; DICT_MEM_LIMIT]>, [LiteralCoder; PROBS_MEM_LIMIT]>,
+    /// Whether `start` succeeded since the last error.
+    started: bool,
+}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
+    DecompressSession<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+{
+    /// Create a session, to be started with [`DecompressSession::start`].
+    pub const fn new() -> Self {
+        Self {
+            decoder: lzma::DecoderState::new(),
+            started: false,
+        }
+    }
+
+    /// Start a new session with the properties and dictionary size of the
+    /// compressing side, forgetting previous messages.
+    ///
+    /// Fails with [`error::Error::DictionaryBufferTooSmall`] or
+    /// [`error::Error::ProbabilitiesBufferTooSmall`] if the session does not
+    /// fit in the buffers.
+    pub fn start(&mut self, properties: LzmaProperties, dict_size: u32) -> error::Result<()> {
+        self.started = false;
+        let params = LzmaParams {
+            props: properties,
+            dict_size: core::cmp::max(dict_size, lzma::MIN_DICT_SIZE),
+            unpacked_size: GuaranteedOption::Some(0),
+        };
+        self.decoder.validate_params(&params)?;
+        self.decoder.reset();
+        self.decoder.set_params(params)?;
+        self.started = true;
+        Ok(())
+    }
+
+    /// Decompress `message`, a whole message written by
+    /// [`CompressSession::compress`](crate::compress::CompressSession::compress),
+    /// to `output`. Returns the length of the decompressed message.
+    ///
+    /// Fails with [`error::Error::Uninitialized`] if the session was not
+    /// started, or after an error.
+    pub fn decompress(&mut self, message: &[u8], output: &mut dyn io::Write) -> error::Result<u64> {
+        if !core::mem::replace(&mut self.started, false) {
+            return Err(error::Error::Uninitialized);
+        }
+        let (len, mut data) = read_len(message)?;
+        let params = match self.decoder.params {
+            GuaranteedOption::Some(params) => params,
+            GuaranteedOption::None => return Err(error::Error::Uninitialized),
+        };
+        let end = self
+            .decoder
+            .output
+            .len()
+            .checked_add(len)
+            .ok_or(error::Error::SizeOverflowsTarget { size: len })?;
+        self.decoder.params = GuaranteedOption::Some(LzmaParams {
+            unpacked_size: GuaranteedOption::Some(end),
+            ..params
+        });
+
+        let mut rangecoder = RangeDecoder::new(&mut data)
+            .map_err(|_| error::lzma::LzmaError::DataStreamIsTooShort)?;
+        self.decoder.process(output, &mut rangecoder)?;
+        self.decoder.output.finish(output)?;
+        self.started = true;
+        Ok(len)
+    }
+
+    /// Write the probability model of the session to `output`, e.g. after
+    /// decoding representative messages, to warm start other sessions with
+    /// [`DecompressSession::load_model`] or
+    /// [`CompressSession::load_model`](crate::compress::CompressSession::load_model).
+    ///
+    /// The snapshot holds the [`LzmaProperties::model_len`] bytes of the
+    /// adaptive probabilities only; the dictionary and the recent match
+    /// distances are not part of it.
+    ///
+    /// Fails with [`error::Error::Uninitialized`] if the session was not
+    /// started, or after an error.
+    pub fn save_model(&self, output: &mut dyn io::Write) -> error::Result<()> {
+        let params = match (self.started, self.decoder.params) {
+            (true, GuaranteedOption::Some(params)) => params,
+            _ => return Err(error::Error::Uninitialized),
+        };
+        let literal_coders = params.props.literal_coders();
+        model::save(self.decoder.model(literal_coders), output)?;
+        Ok(())
+    }
+
+    /// Start the session again, with the probabilities of `snapshot`
+    /// instead of the default ones. The compressing side must start from
+    /// the same snapshot.
+    ///
+    /// Fails with [`error::Error::Uninitialized`] if the session was not
+    /// started, and with [`error::Error::ModelError`] if `snapshot` is not a
+    /// model for the properties of the session. The session is stopped
+    /// after an error.
+    pub fn load_model(&mut self, snapshot: &[u8]) -> error::Result<()> {
+        let params = match (self.started, self.decoder.params) {
+            (true, GuaranteedOption::Some(params)) => params,
+            _ => return Err(error::Error::Uninitialized),
+        };
+        self.start(params.props, params.dict_size)?;
+        self.started = false;
+        let literal_coders = params.props.literal_coders();
+        model::load(
+            self.decoder.model_mut(literal_coders),
+            params.props,
+            snapshot,
+        )?;
+        self.started = true;
+        Ok(())
+    }
+}
+
+/// Split the LEB128 length prefix off `message`.
+fn read_len(message: &[u8]) -> error::Result<(u64, &[u8])> {
+    let mut len = 0_u64;
+    for (i, byte) in message.iter().enumerate().take(10) {
+        len |= u64::from(byte & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((len, message.get(i + 1..).unwrap_or(&[])));
+        }
+    }
+    Err(error::lzma::LzmaError::DataStreamIsTooShort.into())
+}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> Default
+    for DecompressSession<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> Debug
+    for DecompressSession<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("DecompressSession")
+            .field("started", &self.started)
+            .field("decompressed", &self.decoder.output.len())
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "std", feature = "telemetry-encoder"))]
+mod test {
+    use super::*;
+    use crate::compress::CompressSession;
+
+    fn records(count: u32) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|i| format!("id=7 seq={} temp={} status=OK", i, 200 + i % 13).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let mut encoder = CompressSession::<2048, 8>::new();
+        let mut decoder = DecompressSession::<4096, 8>::new();
+        let mut records = records(500);
+        // Empty and long messages, longer than the window
+        records.insert(10, Vec::new());
+        records.insert(20, records[..200].concat());
+
+        for _ in 0..2 {
+            encoder.reset();
+            decoder
+                .start(encoder.properties(), encoder.dict_size())
+                .unwrap();
+            let mut session_len = 0;
+            let mut standalone_len = 0;
+            for record in &records {
+                let mut message = Vec::new();
+                encoder.compress(&mut message, record).unwrap();
+                session_len += message.len();
+
+                let mut decompressed = Vec::new();
+                let len = decoder.decompress(&message, &mut decompressed).unwrap();
+                assert_eq!(len, record.len() as u64);
+                assert_eq!(&decompressed, record);
+
+                let mut standalone = Vec::new();
+                let mut single = crate::compress::TelemetryEncoder::<2048, 8>::new();
+                single.write(&mut standalone, record).unwrap();
+                single.finish(&mut standalone).unwrap();
+                standalone_len += standalone.len();
+            }
+            assert!(session_len * 3 < standalone_len);
+        }
+    }
+
+    #[test]
+    fn test_session_errors() {
+        let mut encoder = CompressSession::<2048, 8>::new();
+        let mut decoder = DecompressSession::<4096, 8>::new();
+        let mut message = Vec::new();
+        encoder.compress(&mut message, b"first").unwrap();
+        assert!(matches!(
+            decoder.decompress(&message, &mut Vec::new()),
+            Err(error::Error::Uninitialized)
+        ));
+
+        assert!(matches!(
+            DecompressSession::<2048, 8>::new().start(encoder.properties(), 8192),
+            Err(error::Error::DictionaryBufferTooSmall {
+                needed: 8192,
+                available: 2048
+            })
+        ));
+        assert!(matches!(
+            DecompressSession::<4096, 1>::new().start(encoder.properties(), 4096),
+            Err(error::Error::ProbabilitiesBufferTooSmall {
+                needed: 8,
+                available: 1
+            })
+        ));
+
+        // A truncated message fails and stops the session until restarted
+        decoder
+            .start(encoder.properties(), encoder.dict_size())
+            .unwrap();
+        for truncated in [&[][..], &[0x80], &message[..3]] {
+            assert!(decoder.decompress(truncated, &mut Vec::new()).is_err());
+            assert!(matches!(
+                decoder.decompress(&message, &mut Vec::new()),
+                Err(error::Error::Uninitialized)
+            ));
+            decoder
+                .start(encoder.properties(), encoder.dict_size())
+                .unwrap();
+        }
+        let mut decompressed = Vec::new();
+        decoder.decompress(&message, &mut decompressed).unwrap();
+        assert_eq!(decompressed, b"first");
+    }
+
+    #[test]
+    fn test_session_model() {
+        let records = records(300);
+        let (training, messages) = records.split_at(200);
+
+        // Both sides of a session end up with the same model
+        let mut encoder = CompressSession::<2048, 8>::new();
+        let mut decoder = DecompressSession::<4096, 8>::new();
+        decoder
+            .start(encoder.properties(), encoder.dict_size())
+            .unwrap();
+        for record in training {
+            let mut message = Vec::new();
+            encoder.compress(&mut message, record).unwrap();
+            decoder.decompress(&message, &mut Vec::new()).unwrap();
+        }
+        let mut model = Vec::new();
+        encoder.save_model(&mut model).unwrap();
+        assert_eq!(model.len(), encoder.properties().model_len());
+        let mut decoder_model = Vec::new();
+        decoder.save_model(&mut decoder_model).unwrap();
+        assert_eq!(decoder_model, model);
+
+        // Warm and cold sessions decode the same messages, the warm one
+        // with less data
+        let mut lens = [0; 2];
+        for (warm, len) in [true, false].iter().zip(lens.iter_mut()) {
+            let mut encoder = CompressSession::<2048, 8>::new();
+            let mut decoder = DecompressSession::<4096, 8>::new();
+            decoder
+                .start(encoder.properties(), encoder.dict_size())
+                .unwrap();
+            if *warm {
+                encoder.load_model(&model).unwrap();
+                decoder.load_model(&model).unwrap();
+            }
+            for record in messages {
+                let mut message = Vec::new();
+                encoder.compress(&mut message, record).unwrap();
+                *len += message.len();
+                let mut decompressed = Vec::new();
+                decoder.decompress(&message, &mut decompressed).unwrap();
+                assert_eq!(&decompressed, record);
+            }
+        }
+        assert!(lens[0] < lens[1], "{:?}", lens);
+    }
+
+    #[test]
+    fn test_session_model_errors() {
+        let mut encoder = CompressSession::<2048, 8>::new();
+        let mut decoder = DecompressSession::<4096, 8>::new();
+        let mut model = Vec::new();
+        encoder.save_model(&mut model).unwrap();
+        assert!(matches!(
+            decoder.load_model(&model),
+            Err(error::Error::Uninitialized)
+        ));
+        decoder
+            .start(encoder.properties(), encoder.dict_size())
+            .unwrap();
+
+        let len = model.len();
+        for err in [
+            decoder.load_model(&model[..len - 2]).unwrap_err(),
+            encoder.load_model(&model[..len - 2]).unwrap_err(),
+        ] {
+            assert_eq!(
+                err,
+                error::Error::ModelError(error::model::ModelError::LengthMismatch {
+                    expected: len,
+                    actual: len - 2,
+                })
+            );
+        }
+        decoder
+            .start(encoder.properties(), encoder.dict_size())
+            .unwrap();
+        model[6..8].copy_from_slice(&0x800_u16.to_le_bytes());
+        assert_eq!(
+            decoder.load_model(&model).unwrap_err(),
+            error::Error::ModelError(error::model::ModelError::ProbabilityOutOfRange {
+                index: 3,
+                value: 0x800,
+            })
+        );
+        assert!(matches!(
+            decoder.save_model(&mut Vec::new()),
+            Err(error::Error::Uninitialized)
+        ));
+    }
+}
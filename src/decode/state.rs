@@ -0,0 +1,190 @@
+//! Decoder state reusable across one-shot decompressions.
+
+use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
+use crate::decode::lzma::{self, LiteralCoder, LzmaParams};
+use crate::decode::options::{Options, TrailingBytes};
+use crate::decode::rangecoder::RangeDecoder;
+use crate::decode::util;
+use crate::error;
+use crate::io;
+use core::fmt::Debug;
+
+/// Outcome of [`crate::lzma_decompress_with_summary`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecodeSummary {
+    /// Number of bytes of compressed data read, header included. The input
+    /// is left right after the end of the stream, unless
+    /// [`Options::trailing_bytes`] is [`TrailingBytes::ReturnCount`].
+    pub consumed: u64,
+    /// Number of bytes decompressed.
+    pub produced: u64,
+    /// Number of bytes following the end of the stream, read up to the end
+    /// of the input if [`Options::trailing_bytes`] is
+    /// [`TrailingBytes::ReturnCount`], `0` otherwise.
+    pub trailing_bytes: u64,
+}
+
+/// Decoder state for [`crate::lzma_decompress_with_state`].
+///
+/// The state is large (about `DICT_MEM_LIMIT + PROBS_MEM_LIMIT * 1536`
+/// bytes); keeping one around, e.g. in a `static`, avoids building a new one
+/// for every message. Only the parts used by a stream are reset before
+/// decoding it.
+///
+/// [`crate::lzma_decompress_with_state`] reads the header, resets the state
+/// and decodes in one go. The steps are also available separately, to
+/// decide whether to decode a stream once its header is known, before
+/// paying for the reset:
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # {
+/// use lzma_rs::decompress::DecoderState;
+///
+/// let mut input: &[u8] = include_bytes!("../../tests/files/foo.txt.lzma");
+/// let mut state = DecoderState::<4096, 8>::new();
+///
+/// let params = state.read_header(&mut input)?;
+/// state.validate(&params)?;
+/// if params.unpacked_size().is_some_and(|size| size > 1 << 20) {
+///     // Not enough room to store the output
+///     return Ok(());
+/// }
+/// state.prepare(params)?;
+/// let mut output = Vec::new();
+/// state.decompress_prepared(&mut input, &mut output)?;
+/// # }
+/// # Ok::<(), lzma_rs::error::Error>(())
+/// ```
+pub struct DecoderState<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> {
+    pub(crate) decoder:
+        lzma::DecoderState<LzCircularBuffer<[u8; DICT_MEM_LIMIT]>, [LiteralCoder; PROBS_MEM_LIMIT]>,
+    pub(crate) options: Options,
+    /// Whether `prepare` succeeded since the last decompression.
+    prepared: bool,
+    /// Bytes found after the end of the last stream, see
+    /// [`DecoderState::trailing_bytes`].
+    trailing_bytes: u64,
+}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
+    DecoderState<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+{
+    /// Create a state decoding with default [`Options`].
+    pub const fn new() -> Self {
+        Self::new_with_options(&Options::default())
+    }
+
+    /// Create a state decoding with the given `options`.
+    pub const fn new_with_options(options: &Options) -> Self {
+        Self {
+            decoder: lzma::DecoderState::new(),
+            options: *options,
+            prepared: false,
+            trailing_bytes: 0,
+        }
+    }
+
+    /// Read the header of a stream from `input`, with the options of the
+    /// state.
+    pub fn read_header<R: io::BufRead>(&self, input: &mut R) -> error::Result<LzmaParams> {
+        #[cfg(feature = "instrumentation")]
+        let hook = self.decoder.instrumentation();
+        #[cfg(feature = "instrumentation")]
+        if let Some(hook) = hook {
+            hook.header_enter();
+        }
+        let res = LzmaParams::read_header(input, &self.options);
+        #[cfg(feature = "instrumentation")]
+        if let Some(hook) = hook {
+            hook.header_exit();
+        }
+        res
+    }
+
+    /// Install a timing hook called while decoding, or remove it with
+    /// `None`. The hook is kept across decompressions.
+    #[cfg(feature = "instrumentation")]
+    pub fn set_instrumentation(
+        &mut self,
+        hook: Option<&'static (dyn crate::decompress::Instrumentation + Sync)>,
+    ) {
+        self.decoder.set_instrumentation(hook);
+    }
+
+    /// Check that a stream with `params` can be decoded with this state,
+    /// without modifying it.
+    pub fn validate(&self, params: &LzmaParams) -> error::Result<()> {
+        self.decoder.validate_params(params)
+    }
+
+    /// Reset the state to decode a stream with `params`. This is the
+    /// expensive step, proportional to the number of literal coders used.
+    pub fn prepare(&mut self, params: LzmaParams) -> error::Result<()> {
+        self.prepared = false;
+        self.decoder.validate_params(&params)?;
+        self.decoder.reset();
+        self.decoder.set_params(params)?;
+        self.prepared = true;
+        Ok(())
+    }
+
+    /// Decode the compressed data following the header of the stream given
+    /// to [`DecoderState::prepare`] and return the number of bytes
+    /// decompressed. Fails with [`error::Error::Uninitialized`] if `prepare`
+    /// was not called since the last decompression.
+    pub fn decompress_prepared<R: io::BufRead, W: io::Write>(
+        &mut self,
+        input: &mut R,
+        output: &mut W,
+    ) -> error::Result<u64> {
+        if !core::mem::replace(&mut self.prepared, false) {
+            return Err(error::Error::Uninitialized);
+        }
+        let decoder = &mut self.decoder;
+        if self.options.trailing_bytes.is_some() {
+            decoder.set_trailing_data(true);
+        }
+        self.trailing_bytes = 0;
+        let mut rangecoder =
+            RangeDecoder::new(input).map_err(|_| error::lzma::LzmaError::DataStreamIsTooShort)?;
+        decoder.process(output, &mut rangecoder)?;
+        match self.options.trailing_bytes {
+            Some(TrailingBytes::Error) if !util::is_eof(rangecoder.stream)? => {
+                return Err(error::lzma::LzmaError::TrailingBytes.into());
+            }
+            Some(TrailingBytes::ReturnCount) => {
+                self.trailing_bytes = util::skip_to_end(rangecoder.stream)?;
+            }
+            _ => {}
+        }
+        decoder.output.finish(output)?;
+        Ok(decoder.output.len())
+    }
+
+    /// Number of bytes following the end of the last stream decoded, read up
+    /// to the end of the input if [`Options::trailing_bytes`] is
+    /// [`TrailingBytes::ReturnCount`], `0` otherwise.
+    pub fn trailing_bytes(&self) -> u64 {
+        self.trailing_bytes
+    }
+}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> Default
+    for DecoderState<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> Debug
+    for DecoderState<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("DecoderState")
+            .field("options", &self.options)
+            .finish()
+    }
+}
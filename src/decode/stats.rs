@@ -0,0 +1,80 @@
+/// Statistics about decoded LZMA symbols, useful to tune dictionary sizes.
+///
+/// Collected only when the `stats` feature is enabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecodeStats {
+    /// Number of decoded literals.
+    pub literals: u64,
+    /// Number of decoded matches with a new distance.
+    pub matches: u64,
+    /// Number of decoded matches reusing one of the last four distances
+    /// (including short reps).
+    pub rep_matches: u64,
+    /// Total number of bytes produced by matches and rep matches.
+    pub match_bytes: u64,
+    /// Largest match distance seen.
+    pub max_distance: usize,
+    /// Largest number of bytes held in the dictionary.
+    pub dict_high_water: usize,
+}
+
+impl DecodeStats {
+    /// Create empty statistics.
+    pub const fn new() -> Self {
+        Self {
+            literals: 0,
+            matches: 0,
+            rep_matches: 0,
+            match_bytes: 0,
+            max_distance: 0,
+            dict_high_water: 0,
+        }
+    }
+
+    /// Average length of matches and rep matches, or 0 if there were none.
+    pub fn average_match_len(&self) -> f32 {
+        let count = self.matches + self.rep_matches;
+        if count == 0 {
+            0.0
+        } else {
+            self.match_bytes as f32 / count as f32
+        }
+    }
+
+    pub(crate) fn record_literal(&mut self) {
+        self.literals += 1;
+    }
+
+    pub(crate) fn record_match(&mut self, rep: bool, len: usize, distance: usize) {
+        if rep {
+            self.rep_matches += 1;
+        } else {
+            self.matches += 1;
+        }
+        self.match_bytes += len as u64;
+        self.max_distance = core::cmp::max(self.max_distance, distance);
+    }
+
+    pub(crate) fn record_dict_usage(&mut self, used: usize) {
+        self.dict_high_water = core::cmp::max(self.dict_high_water, used);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn verify_average_match_len() {
+        let mut stats = DecodeStats::new();
+        assert_eq!(stats.average_match_len(), 0.0);
+        stats.record_match(false, 10, 100);
+        stats.record_match(true, 1, 5);
+        stats.record_match(true, 4, 5);
+        assert_eq!(stats.matches, 1);
+        assert_eq!(stats.rep_matches, 2);
+        assert_eq!(stats.max_distance, 100);
+        assert_eq!(stats.average_match_len(), 5.0);
+    }
+}
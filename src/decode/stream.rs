@@ -1,21 +1,24 @@
 use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
-use crate::decode::lzma::{DecoderState, LzmaParams};
+use crate::decode::lzma::{
+    rep_distance, DecoderState, LiteralCoder, LzmaParams, MAX_REQUIRED_INPUT, MIN_DICT_SIZE,
+};
+use crate::decode::memory::MemoryFootprint;
 use crate::decode::rangecoder::RangeDecoder;
-use crate::decompress::Options;
+use crate::decode::util;
+use crate::decompress::{Options, TrailingBytes, UnpackedSize};
 use crate::error;
-use crate::io::{self, BufRead, Cursor, Read, Write};
-use crate::option::GuaranteedOption as Option;
+use crate::io::{self, BufRead, Cursor, Write};
 use crate::option::GuaranteedOption::*;
+use crate::properties::LzmaProperties;
+use core::convert::TryFrom;
 use core::fmt::Debug;
 
-/// Minimum header length to be read.
+/// Max header length to be read, with [`UnpackedSize::ReadFromHeader`]. The
+/// length for given options is [`LzmaParams::header_len`].
 /// - props: u8 (1 byte)
 /// - dict_size: u32 (4 bytes)
-const MIN_HEADER_LEN: usize = 5;
-
-/// Max header length to be read.
 /// - unpacked_size: u64 (8 bytes)
-const MAX_HEADER_LEN: usize = MIN_HEADER_LEN + 8;
+const MAX_HEADER_LEN: usize = LzmaParams::header_len_for(&UnpackedSize::ReadFromHeader);
 
 /// Required bytes after the header.
 /// - ignore: u8 (1 byte)
@@ -23,25 +26,65 @@ const MAX_HEADER_LEN: usize = MIN_HEADER_LEN + 8;
 const START_BYTES: usize = 5;
 
 /// Maximum number of bytes to buffer while reading the header.
-const MAX_TMP_LEN: usize = MAX_HEADER_LEN + START_BYTES;
+pub(crate) const MAX_TMP_LEN: usize = MAX_HEADER_LEN + START_BYTES;
+
+/// Header and first range coder bytes of a stream, accumulated as they
+/// arrive so that they can be split across any number of writes. Only the
+/// bytes still needed are taken from each write, so the rest of the input
+/// is decoded in place.
+#[derive(Clone, Debug)]
+struct HeaderAccumulator {
+    buf: [u8; MAX_TMP_LEN],
+    len: usize,
+}
+
+impl HeaderAccumulator {
+    const fn new() -> Self {
+        Self {
+            buf: [0; MAX_TMP_LEN],
+            len: 0,
+        }
+    }
+
+    /// Bytes accumulated so far.
+    fn bytes(&self) -> &[u8] {
+        self.buf.get(..self.len).unwrap_or(&[])
+    }
+
+    /// Take bytes from `data` until `needed` bytes are accumulated, and
+    /// return the number of bytes taken.
+    fn fill(&mut self, data: &[u8], needed: usize) -> usize {
+        let free = self
+            .buf
+            .get_mut(self.len..core::cmp::min(needed, MAX_TMP_LEN))
+            .unwrap_or(&mut []);
+        let n = core::cmp::min(free.len(), data.len());
+        free.iter_mut().zip(data).for_each(|(v, &byte)| *v = byte);
+        self.len += n;
+        n
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
 
 /// Internal state of this streaming decoder. This is needed because we have to
 /// initialize the stream before processing any data.
 #[repr(C)]
 #[derive(Debug)]
-enum State<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> {
+#[allow(clippy::enum_variant_names)]
+enum State {
     Uninitialized,
     InvalidState,
     /// Stream is initialized but header values have not yet been read.
     Header,
     /// Header values have been read and the stream is ready to process more
     /// data.
-    Data(RunState<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>),
+    Data(RunState),
 }
 
-impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
-    State<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
-{
+impl State {
     fn take(&mut self) -> Self {
         core::mem::replace(self, Self::InvalidState)
     }
@@ -51,14 +94,14 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
 }
 
 /// Structures needed while decoding data.
-#[derive(Debug)]
-struct RunState<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> {
+#[derive(Clone, Copy, Debug)]
+struct RunState {
     range: u32,
     code: u32,
 }
 
 /// Enum describing current state of a stream
-#[derive(PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum StreamStatus {
     /// Stream has not been initialized; call [`Stream::reset`]
     Uninitialized,
@@ -74,6 +117,15 @@ pub enum StreamStatus {
         /// [`crate::decode::options::Options::unpacked_size`] setting)
         unpacked_size: core::option::Option<u64>,
     },
+    /// Decoding stopped right before an LZMA2 chunk resetting the
+    /// dictionary, see [`Lzma2Stream`](crate::decompress::Lzma2Stream). All
+    /// the data decompressed so far has been written to the output sink, and
+    /// decoding could restart from the next chunk without it. Never returned
+    /// by [`Stream`].
+    AtSyncPoint {
+        /// Data decompressed and written to the output sink so far, in bytes.
+        unpacked_data_processed: u64,
+    },
     /// Stream entered undefined state. Happens if one calls `Stream::finish`
     /// after faulty `Stream::write` call
     InvalidState,
@@ -81,27 +133,202 @@ pub enum StreamStatus {
     EosReached,
 }
 
+/// Outcome of [`Stream::resync`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResyncStatus {
+    /// A plausible LZMA header starts `skipped` bytes into the data. The
+    /// stream has been reset; write the data starting from that offset.
+    Found {
+        /// Number of bytes to discard before the header.
+        skipped: usize,
+    },
+    /// No plausible header was found. The first `skipped` bytes can be
+    /// discarded; the remaining ones may hold the beginning of a header and
+    /// must be scanned again together with more data.
+    NotFound {
+        /// Number of bytes that can be discarded.
+        skipped: usize,
+    },
+}
+
+/// Outcome of [`Stream::finish_with_summary`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FinishSummary {
+    /// The range coder ended in its final state (`code == 0`) with no
+    /// compressed data left over.
+    ///
+    /// Streams with an unpacked size in the header end once that many bytes
+    /// are decompressed, which does not involve the range coder; this flag is
+    /// `false` if its final flush is missing or corrupted. Streams terminated
+    /// by an end marker are always clean.
+    pub clean_termination: bool,
+    /// Number of bytes written after the end of the stream and skipped, if
+    /// [`Options::trailing_bytes`] is [`TrailingBytes::ReturnCount`], `0`
+    /// otherwise.
+    pub trailing_bytes: u64,
+}
+
+/// Outcome of [`Stream::write_returning_output`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WriteOutcome {
+    /// Number of bytes of compressed data read, as returned by
+    /// [`Stream::write`].
+    pub consumed: usize,
+    /// Number of bytes decompressed. With a non-zero `WRITE_BUF_LEN`, some of
+    /// them may still be held in the write buffer rather than written to the
+    /// sink.
+    pub produced: u64,
+}
+
+/// Options overriding the stream's [`Options`] for a single call to
+/// [`Stream::write_with`], e.g. to decode records with different unpacked
+/// sizes with a pooled stream without reconfiguring it. `None` fields keep
+/// the stream's option.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct WriteOverrides {
+    /// Overrides [`Options::unpacked_size`]. It only matters to the call
+    /// completing the header, so pass it to every call until the header is
+    /// read.
+    pub unpacked_size: core::option::Option<UnpackedSize>,
+    /// Overrides [`Options::max_symbols_per_write`].
+    pub max_symbols_per_write: core::option::Option<u32>,
+}
+
 /// Lzma decompressor that can process multiple chunks of data using the
 /// `io::Write` interface.
 ///
 /// - `DICT_MEM_LIMIT` must be equal or larger than dictionary size of
 ///   compressed data streams that will be processed
-/// - `PROBS_MEM_LIMIT` must be equal or larger than (1 << LC + PB)
-///   parametrization of compressed data streams that will be processed
-pub struct Stream<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> {
-    decoder: DecoderState<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>,
+/// - `PROBS_MEM_LIMIT` must be equal or larger than `1 << (lc + lp)` of
+///   compressed data streams that will be processed; it is the number of
+///   literal coders (`0x300` probabilities each) to reserve memory for
+/// - `WRITE_BUF_LEN`, if non-zero, is the size of the chunks written to the
+///   output sink. Decompressed data is combined into `WRITE_BUF_LEN` sized
+///   writes, only the last write issued by [`Stream::finish`] may be shorter.
+///   This is useful for slow sinks such as flash page writers
+///
+/// Each combination of parameters gets its own copy of the decoder code; use
+/// [`DynStream`] to share a single copy between streams of different sizes.
+///
+/// [`Stream::new`] and [`Stream::new_with_options`] are `const`, so a stream
+/// can be placed in a `static` without lazy initialization. The dictionary
+/// is stored inline, so `#[link_section]` places it together with the rest
+/// of the stream, e.g. in a dedicated RAM bank:
+///
+/// ```
+/// use lzma_rs::decompress::Stream;
+///
+/// #[cfg_attr(target_os = "none", link_section = ".ram_d2")]
+/// static mut STREAM: Stream<0x10000, 8> = Stream::new();
+/// ```
+pub type Stream<
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+    const WRITE_BUF_LEN: usize = 0,
+> = GenericStream<[u8; DICT_MEM_LIMIT], [LiteralCoder; PROBS_MEM_LIMIT], [u8; WRITE_BUF_LEN]>;
+
+/// Lzma decompressor with a dictionary borrowed from the caller and the other
+/// buffers stored inline, see [`Stream`] for the parameters.
+///
+/// The dictionary does not need to be initialized to any particular value:
+/// its content is never read before being written by the decoder. This
+/// allows using a large buffer from a section that is not zeroed at startup
+/// (e.g. `.noinit`) without paying for clearing it. Its size is checked
+/// against the dictionary size of each stream when the header is read,
+/// failing with [`error::Error::DictionaryBufferTooSmall`].
+///
+/// ```
+/// use lzma_rs::decompress::BorrowedDictStream;
+///
+/// let dict: &'static mut [u8] = Box::leak(vec![0; 0x10000].into_boxed_slice());
+/// let mut stream = BorrowedDictStream::<8>::with_dict(dict);
+/// stream.reset();
+/// ```
+pub type BorrowedDictStream<'a, const PROBS_MEM_LIMIT: usize, const WRITE_BUF_LEN: usize = 0> =
+    GenericStream<&'a mut [u8], [LiteralCoder; PROBS_MEM_LIMIT], [u8; WRITE_BUF_LEN]>;
+
+/// Lzma decompressor with the probabilities borrowed from the caller and the
+/// other buffers stored inline, see [`Stream`] for the parameters.
+///
+/// The probabilities are accessed for every decoded bit, the dictionary
+/// mostly for matches; on targets with a small tightly coupled memory, it
+/// may hold the probabilities while the dictionary stays in regular RAM.
+/// `probs` must hold at least `1 << (lc + lp)` literal coders, else
+/// processing fails with [`error::Error::ProbabilitiesBufferTooSmall`]. Its
+/// content does not matter, it is reset for each stream.
+///
+/// ```
+/// use lzma_rs::decompress::{BorrowedProbsStream, LiteralCoder};
+///
+/// let probs: &'static mut [LiteralCoder] = Box::leak(vec![[0; 0x300]; 8].into_boxed_slice());
+/// let mut stream = BorrowedProbsStream::<0x10000>::with_probs(probs);
+/// stream.reset();
+/// ```
+///
+/// Any other storage implementing `AsRef<[LiteralCoder]>` and
+/// `AsMut<[LiteralCoder]>`, e.g. a `Box`, can be used with
+/// [`GenericStream::with_buffers`].
+pub type BorrowedProbsStream<'a, const DICT_MEM_LIMIT: usize, const WRITE_BUF_LEN: usize = 0> =
+    GenericStream<[u8; DICT_MEM_LIMIT], &'a mut [LiteralCoder], [u8; WRITE_BUF_LEN]>;
+
+/// Lzma decompressor using buffers borrowed from the caller, sized at
+/// runtime:
+///
+/// - `dict` must be equal or larger than dictionary size of compressed data
+///   streams that will be processed
+/// - `probs` must hold at least `1 << (lc + lp)` literal coders
+/// - `write_buf`, if not empty, sets the size of the chunks written to the
+///   output sink, see [`Stream`]
+///
+/// As for [`BorrowedDictStream`], `dict` does not need to be zeroed.
+///
+/// All `DynStream`s share the same code regardless of their buffer sizes,
+/// which keeps flash usage down when several decoders are linked in, at the
+/// cost of slightly slower decoding than [`Stream`].
+pub type DynStream<'a> = GenericStream<&'a mut [u8], &'a mut [LiteralCoder], &'a mut [u8]>;
+
+/// Lzma decompressor generic over the storage of its buffers; see [`Stream`]
+/// and [`DynStream`].
+pub struct GenericStream<DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Decoder state and buffers, kept apart so that the stream can be taken
+    /// apart with [`GenericStream::into_parts`].
+    inner: StreamDecoder<DICT, PROBS, WBUF>,
     /// Temporary buffer to hold data while the header is being read.
-    tmp: Cursor<[u8; MAX_TMP_LEN]>,
-    /// Whether the stream is initialized and ready to process data.
-    /// An `Option` is used to avoid interior mutability when updating the
-    /// state.
-    state: State<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>,
+    tmp: StreamTmp,
     /// Options given when a stream is created.
-    options: Options,
+    pub(crate) options: Options,
+}
+
+/// Snapshot of the internal state of a [`Stream`], see
+/// [`Stream::debug_snapshot`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DebugSnapshot {
+    /// Range of the range decoder after the last processed chunk.
+    pub range: u32,
+    /// Code of the range decoder after the last processed chunk.
+    pub code: u32,
+    /// State of the LZMA state machine (0 to 11).
+    pub lzma_state: usize,
+    /// Last four match distances, most recent first.
+    pub rep_distances: [usize; 4],
+    /// Number of bytes decompressed from the current stream.
+    pub unpacked_len: u64,
+    /// Number of dictionary bytes copied into the caller-provided buffer.
+    pub dict_len: usize,
+    /// Symbol statistics of the decoded data.
+    #[cfg(feature = "stats")]
+    pub stats: crate::decompress::DecodeStats,
 }
 
-impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
-    Stream<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize, const WRITE_BUF_LEN: usize>
+    Stream<DICT_MEM_LIMIT, PROBS_MEM_LIMIT, WRITE_BUF_LEN>
 {
     /// Initialize the stream. This will consume the `output` which is the sink
     /// implementing `io::Write` that will receive decompressed bytes.
@@ -113,48 +340,440 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
     /// `output` which is the sink implementing `io::Write` that will
     /// receive decompressed bytes.
     pub const fn new_with_options(options: &Options) -> Self {
+        // Built in place rather than with `with_buffers`, which would copy
+        // the buffers on the stack in debug builds
         Self {
-            decoder: DecoderState::new(),
-            tmp: Cursor::new([0; MAX_TMP_LEN]),
-            state: State::Uninitialized,
+            inner: StreamDecoder {
+                decoder: DecoderState::new(),
+                write_buf: [0; WRITE_BUF_LEN],
+                write_buf_len: 0,
+                state: State::Uninitialized,
+                last_run: RunState { range: 0, code: 0 },
+                trailing_bytes: 0,
+                bytes_flushed: 0,
+            },
+            tmp: StreamTmp(HeaderAccumulator::new()),
             options: *options,
         }
     }
 
-    /// Reset the state of the stream. All internal buffers and fields are
-    /// cleared and set to initial values.
+    /// Memory used by a stream with these parameters. Streams are usually
+    /// placed in a `static`, in which case this is their whole footprint.
+    pub const fn memory_footprint() -> MemoryFootprint {
+        MemoryFootprint::new(
+            core::mem::size_of::<Self>(),
+            DICT_MEM_LIMIT,
+            core::mem::size_of::<[LiteralCoder; PROBS_MEM_LIMIT]>(),
+            MAX_REQUIRED_INPUT + MAX_TMP_LEN,
+            WRITE_BUF_LEN,
+        )
+    }
+}
+
+impl<'a, const PROBS_MEM_LIMIT: usize, const WRITE_BUF_LEN: usize>
+    BorrowedDictStream<'a, PROBS_MEM_LIMIT, WRITE_BUF_LEN>
+{
+    /// Initialize the stream with the given dictionary buffer.
+    pub const fn with_dict(dict: &'a mut [u8]) -> Self {
+        Self::with_dict_and_options(dict, &Options::default())
+    }
+
+    /// Initialize the stream with the given dictionary buffer and `options`.
+    pub const fn with_dict_and_options(dict: &'a mut [u8], options: &Options) -> Self {
+        Self::with_buffers(
+            dict,
+            [[0; 0x300]; PROBS_MEM_LIMIT],
+            [0; WRITE_BUF_LEN],
+            options,
+        )
+    }
+}
+
+impl<'a, const DICT_MEM_LIMIT: usize, const WRITE_BUF_LEN: usize>
+    BorrowedProbsStream<'a, DICT_MEM_LIMIT, WRITE_BUF_LEN>
+{
+    /// Initialize the stream with the given probabilities buffer.
+    pub const fn with_probs(probs: &'a mut [LiteralCoder]) -> Self {
+        Self::with_probs_and_options(probs, &Options::default())
+    }
+
+    /// Initialize the stream with the given probabilities buffer and
+    /// `options`.
+    pub const fn with_probs_and_options(probs: &'a mut [LiteralCoder], options: &Options) -> Self {
+        Self::with_buffers([0; DICT_MEM_LIMIT], probs, [0; WRITE_BUF_LEN], options)
+    }
+}
+
+impl<'a> DynStream<'a> {
+    /// Initialize the stream with the given buffers.
+    pub const fn new(
+        dict: &'a mut [u8],
+        probs: &'a mut [LiteralCoder],
+        write_buf: &'a mut [u8],
+    ) -> Self {
+        Self::new_with_options(dict, probs, write_buf, &Options::default())
+    }
+
+    /// Initialize the stream with the given buffers and `options`.
+    pub const fn new_with_options(
+        dict: &'a mut [u8],
+        probs: &'a mut [LiteralCoder],
+        write_buf: &'a mut [u8],
+        options: &Options,
+    ) -> Self {
+        Self::with_buffers(dict, probs, write_buf, options)
+    }
+}
+
+impl<DICT, PROBS, WBUF> GenericStream<DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Initialize the stream with buffers of any type giving access to a
+    /// slice, with the same requirements as for [`DynStream`].
+    ///
+    /// The decoder never allocates, so this is how buffers from a specific
+    /// heap or memory pool are used: wrap the pool's handle, or use e.g.
+    /// `Box<[u8], A>` with a custom allocator `A` on nightly. The buffers
+    /// are returned to their owner when the stream is dropped.
+    ///
+    /// ```
+    /// use lzma_rs::decompress::{GenericStream, Options};
+    ///
+    /// /// Block taken from a pool, given back when dropped.
+    /// struct PoolBlock(Box<[u8]>);
+    ///
+    /// impl AsRef<[u8]> for PoolBlock {
+    ///     fn as_ref(&self) -> &[u8] {
+    ///         &self.0
+    ///     }
+    /// }
+    ///
+    /// impl AsMut<[u8]> for PoolBlock {
+    ///     fn as_mut(&mut self) -> &mut [u8] {
+    ///         &mut self.0
+    ///     }
+    /// }
+    ///
+    /// let dict = PoolBlock(vec![0; 0x10000].into_boxed_slice());
+    /// let probs = vec![[0; 0x300]; 8];
+    /// let mut stream = GenericStream::with_buffers(dict, probs, [0; 0], &Options::default());
+    /// stream.reset();
+    /// ```
+    pub const fn with_buffers(
+        dict: DICT,
+        probs: PROBS,
+        write_buf: WBUF,
+        options: &Options,
+    ) -> Self {
+        Self {
+            inner: StreamDecoder {
+                decoder: DecoderState::from_parts(LzCircularBuffer::from_buf(dict), probs),
+                write_buf,
+                write_buf_len: 0,
+                state: State::Uninitialized,
+                last_run: RunState { range: 0, code: 0 },
+                trailing_bytes: 0,
+                bytes_flushed: 0,
+            },
+            tmp: StreamTmp(HeaderAccumulator::new()),
+            options: *options,
+        }
+    }
+
+    /// Number of bytes the stream consumes before it can produce any output:
+    /// the header, whose length depends on [`Options::unpacked_size`], and
+    /// the 5 bytes starting the range coder. Useful to size the first fetch
+    /// of compressed data.
+    ///
+    /// ```
+    /// use lzma_rs::decompress::{Options, Stream, UnpackedSize};
+    ///
+    /// assert_eq!(Stream::<4096, 8>::initial_bytes_required(&Options::default()), 18);
+    /// let options = Options {
+    ///     unpacked_size: UnpackedSize::UseProvided(None),
+    ///     ..Options::default()
+    /// };
+    /// assert_eq!(Stream::<4096, 8>::initial_bytes_required(&options), 10);
+    /// ```
+    pub const fn initial_bytes_required(options: &Options) -> usize {
+        LzmaParams::header_len(options) + START_BYTES
+    }
+
+    /// Take the stream apart into its decoder state, its options and the
+    /// input bytes it buffered, e.g. to move the decoder into a task or a
+    /// cell holding only the decoder state while the options live elsewhere.
+    /// Put it back together with [`GenericStream::from_parts`].
+    ///
+    /// ```
+    /// use lzma_rs::decompress::{Options, Stream};
+    ///
+    /// let mut stream = Stream::<4096, 8>::new();
+    /// stream.reset();
+    /// let (decoder, options, tmp) = stream.into_parts();
+    /// let options = Options {
+    ///     max_dict_size: Some(4096),
+    ///     ..options
+    /// };
+    /// let stream = Stream::from_parts(decoder, options, tmp);
+    /// ```
+    pub fn into_parts(self) -> (StreamDecoder<DICT, PROBS, WBUF>, Options, StreamTmp) {
+        (self.inner, self.options, self.tmp)
+    }
+
+    /// Rebuild a stream from the parts returned by
+    /// [`GenericStream::into_parts`]. Decoding continues where it stopped if
+    /// `decoder` and `tmp` come from the same stream; `options` may differ
+    /// and apply from the next header.
+    pub fn from_parts(
+        decoder: StreamDecoder<DICT, PROBS, WBUF>,
+        options: Options,
+        tmp: StreamTmp,
+    ) -> Self {
+        Self {
+            inner: decoder,
+            tmp,
+            options,
+        }
+    }
+}
+
+/// Decoder state and buffers of a [`GenericStream`], see
+/// [`GenericStream::into_parts`].
+pub struct StreamDecoder<DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    decoder: DecoderState<LzCircularBuffer<DICT>, PROBS>,
+    /// Decompressed data not yet written to the output sink.
+    write_buf: WBUF,
+    write_buf_len: usize,
+    /// Whether the stream is initialized and ready to process data.
+    /// An `Option` is used to avoid interior mutability when updating the
+    /// state.
+    state: State,
+    /// Range coder values after the last processed chunk, kept even if
+    /// processing failed.
+    last_run: RunState,
+    /// Bytes written after the end of the stream, see
+    /// [`FinishSummary::trailing_bytes`].
+    trailing_bytes: u64,
+    /// Decompressed bytes accepted by the output sink, see
+    /// [`GenericStream::bytes_flushed`].
+    bytes_flushed: u64,
+}
+
+impl<DICT, PROBS, WBUF> StreamDecoder<DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    #[cfg(feature = "zeroize")]
+    fn zeroize(&mut self) {
+        use zeroize::Zeroize;
+        self.decoder.zeroize();
+        self.write_buf.as_mut().zeroize();
+        self.write_buf_len.zeroize();
+        self.state = State::Uninitialized;
+    }
+}
+
+impl<DICT, PROBS, WBUF> Debug for StreamDecoder<DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("StreamDecoder")
+            .field("write_buf_len", &self.write_buf_len)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+/// Input bytes buffered by a [`GenericStream`] while the header or the
+/// range coder input was incomplete, see [`GenericStream::into_parts`].
+#[derive(Clone, Debug)]
+pub struct StreamTmp(HeaderAccumulator);
+
+impl StreamTmp {
+    /// Number of buffered bytes.
+    pub fn len(&self) -> usize {
+        self.0.len
+    }
+
+    /// Whether no bytes are buffered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[cfg(feature = "zeroize")]
+    fn zeroize(&mut self) {
+        use zeroize::Zeroize;
+        self.0.buf.zeroize();
+        self.0.clear();
+    }
+}
+
+impl<'a, PROBS, WBUF> GenericStream<&'a mut [u8], PROBS, WBUF>
+where
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Reset the stream and return its dictionary buffer, e.g. to lend it to
+    /// a `BorrowedWindowEncoder`.
+    /// Until it gets a buffer back with [`GenericStream::give_buffer`], the
+    /// stream fails with [`error::Error::DictionaryBufferTooSmall`].
+    pub fn take_buffer(&mut self) -> &'a mut [u8] {
+        self.reset();
+        self.inner.decoder.output.replace_buf(&mut [])
+    }
+
+    /// Reset the stream and use `dict` as its dictionary buffer.
+    pub fn give_buffer(&mut self, dict: &'a mut [u8]) {
+        self.reset();
+        self.inner.decoder.output.replace_buf(dict);
+    }
+}
+
+impl<DICT, PROBS, WBUF> GenericStream<DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Reset the state of the stream. All internal fields are set to initial
+    /// values. The dictionary buffer is not cleared as its stale content is
+    /// never read.
     pub fn reset(&mut self) {
-        self.decoder.reset();
-        self.tmp = Cursor::new([0; MAX_TMP_LEN]);
-        self.state = State::Header;
+        self.inner.decoder.reset();
+        self.tmp.0.clear();
+        self.inner.write_buf_len = 0;
+        self.inner.state = State::Header;
+        self.inner.last_run = RunState { range: 0, code: 0 };
+        self.inner.trailing_bytes = 0;
+        self.inner.bytes_flushed = 0;
+    }
+
+    /// Reset the state of the stream like [`Stream::reset`], but keep the data
+    /// decompressed so far as the dictionary of the next stream, which can then
+    /// reference it with back-references.
+    ///
+    /// The dictionary is only kept if the next stream header declares the same
+    /// dictionary size; otherwise the next stream starts with an empty one.
+    /// Only streams encoded against that data can be decoded this way; to end
+    /// a stream and keep its data, use [`Stream::finish_keep_dict`], as
+    /// [`Stream::finish`] forgets it.
+    ///
+    /// If the current stream was not finished, its data not yet written to
+    /// the output sink is discarded, as with [`Stream::reset`].
+    pub fn reset_keep_dict(&mut self) {
+        self.inner.decoder.reset_keep_dict();
+        self.tmp.0.clear();
+        self.inner.write_buf_len = 0;
+        self.inner.state = State::Header;
+        self.inner.last_run = RunState { range: 0, code: 0 };
+        self.inner.trailing_bytes = 0;
+        self.inner.bytes_flushed = 0;
     }
 
-    /// Consumes the stream and returns the output sink. This also makes sure
+    /// Flushes remaining data into the `output` sink. This also makes sure
     /// we have properly reached the end of the stream.
+    ///
+    /// The stream is reset afterwards, like with [`Stream::reset`].
     pub fn finish(&mut self, output: &mut dyn Write) -> crate::error::Result<()> {
-        let finish_status = match self.state.take() {
+        self.finish_with_summary(output).map(|_| ())
+    }
+
+    /// Like [`Stream::finish`], but on success the stream is reset like with
+    /// [`Stream::reset_keep_dict`], keeping the decompressed data as the
+    /// dictionary of the next stream.
+    pub fn finish_keep_dict(&mut self, output: &mut dyn Write) -> crate::error::Result<()> {
+        self.finish_inner(output, true).map(|_| ())
+    }
+
+    /// Like [`Stream::finish`], also reporting whether the range coder was
+    /// properly terminated. Validators can use it to reject streams that
+    /// decode fine but were not flushed properly or carry trailing data.
+    pub fn finish_with_summary(
+        &mut self,
+        output: &mut dyn Write,
+    ) -> crate::error::Result<FinishSummary> {
+        self.finish_inner(output, false)
+    }
+
+    fn finish_inner(
+        &mut self,
+        output: &mut dyn Write,
+        keep_dict: bool,
+    ) -> crate::error::Result<FinishSummary> {
+        let mut combiner = WriteCombiner::new(
+            self.inner.write_buf.as_mut(),
+            &mut self.inner.write_buf_len,
+            &mut self.inner.bytes_flushed,
+            output,
+        );
+        let output: &mut dyn Write = &mut combiner;
+        let finish_status = match self.inner.state.take() {
             State::Header => {
-                if self.tmp.position() > 0 {
+                if !self.tmp.is_empty() {
                     Err(error::stream::StreamError::FailedToReadLzmaHeader.into())
                 } else {
-                    Ok(())
+                    // Nothing was written, there is no range coder to check
+                    Ok(FinishSummary {
+                        clean_termination: true,
+                        trailing_bytes: 0,
+                    })
                 }
             }
             State::Data(state) => {
                 // Process one last time with empty input to force end of
                 // stream checks
-                let mut stream = Cursor::new(&self.tmp.get_ref()[0..self.tmp.position() as usize]);
+                let mut stream: &[u8] = &[];
                 let mut range_decoder =
                     RangeDecoder::from_parts(&mut stream, state.range, state.code);
-                self.decoder
+                self.inner
+                    .decoder
                     .process(output, &mut range_decoder)
-                    .and(self.decoder.output.finish(output).map_err(|e| e.into()))
-                    .and(Ok(()))
+                    .and(
+                        self.inner
+                            .decoder
+                            .output
+                            .finish(output)
+                            .map_err(|e| e.into()),
+                    )
+                    .and(combiner.finish().map_err(|e| e.into()))
+                    .and_then(|()| {
+                        let clean_termination = range_decoder.is_finished_ok()?
+                            && !self.inner.decoder.has_partial_input();
+                        Ok(FinishSummary {
+                            clean_termination,
+                            trailing_bytes: self.inner.trailing_bytes,
+                        })
+                    })
             }
             State::InvalidState => Err(error::stream::StreamError::InvalidState.into()),
-            State::Uninitialized => panic!("Stream is uninitialized; call `Stream::reset` first"),
+            State::Uninitialized => {
+                lzma_panic!(
+                    Err(error::Error::Uninitialized),
+                    "Stream is uninitialized; call `Stream::reset` first"
+                )
+            }
         };
-        self.reset();
+        // Kept to tell how much of the output is valid if finishing failed
+        let bytes_flushed = self.inner.bytes_flushed;
+        if keep_dict && finish_status.is_ok() {
+            self.reset_keep_dict();
+        } else {
+            self.reset();
+        }
+        self.inner.bytes_flushed = bytes_flushed;
         finish_status
     }
 
@@ -163,16 +782,27 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
     /// This function will consume the state, returning the next state on both
     /// error and success.
     fn read_header<R: BufRead>(
-        decoder: &mut DecoderState<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>,
+        decoder: &mut DecoderState<LzCircularBuffer<DICT>, PROBS>,
         mut input: &mut R,
         options: &Options,
-    ) -> crate::error::Result<State<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>> {
-        match LzmaParams::read_header(&mut input, options) {
+        last_run: &mut RunState,
+    ) -> crate::error::Result<State> {
+        #[cfg(feature = "instrumentation")]
+        let hook = decoder.instrumentation();
+        #[cfg(feature = "instrumentation")]
+        if let core::option::Option::Some(hook) = hook {
+            hook.header_enter();
+        }
+        let res = match LzmaParams::read_header(&mut input, options) {
             Ok(params) => {
                 // The RangeDecoder is only kept temporarily as we are processing
                 // chunks of data.
                 if let Ok(rangecoder) = RangeDecoder::new(&mut input) {
                     decoder.set_params(params)?;
+                    *last_run = RunState {
+                        range: rangecoder.range,
+                        code: rangecoder.code,
+                    };
                     Ok(State::Data(RunState {
                         range: rangecoder.range,
                         code: rangecoder.code,
@@ -184,30 +814,36 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
                 }
             }
             // Failed to read_header() because we need more data, try again later.
-            Err(error::Error::HeaderTooShort(_)) => Ok(State::Header),
+            Err(error::Error::HeaderTooShort { .. }) => Ok(State::Header),
             // Fatal error. Don't retry.
             Err(e) => Err(e),
+        };
+        #[cfg(feature = "instrumentation")]
+        if let core::option::Option::Some(hook) = hook {
+            hook.header_exit();
         }
+        res
     }
 
     /// Process compressed data
     fn read_data<R: BufRead>(
-        decoder: &mut DecoderState<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>,
-        state: RunState<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>,
+        decoder: &mut DecoderState<LzCircularBuffer<DICT>, PROBS>,
+        state: RunState,
         output: &mut dyn Write,
         mut input: &mut R,
-    ) -> crate::error::Result<RunState<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>> {
+        last_run: &mut RunState,
+    ) -> crate::error::Result<RunState> {
         // Construct our RangeDecoder from the previous range and code
         // values.
         let mut rangecoder = RangeDecoder::from_parts(&mut input, state.range, state.code);
 
         // Try to process all bytes of data.
-        decoder.process_stream(output, &mut rangecoder)?;
-
-        Ok(RunState {
+        let res = decoder.process_stream(output, &mut rangecoder);
+        *last_run = RunState {
             range: rangecoder.range,
             code: rangecoder.code,
-        })
+        };
+        res.map(|_| *last_run)
     }
 
     /// Write slice of compressed `data` into the stream. Decompressed data will
@@ -216,114 +852,218 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
     /// This function reads between 0 and `data.len()` of bytes. To read all the
     /// data from `data` slice, use [`Stream::write_all`] function.
     pub fn write(&mut self, output: &mut dyn Write, data: &[u8]) -> crate::error::Result<usize> {
+        let options = self.options;
+        self.write_with_options(output, data, &options)
+    }
+
+    /// Same as [`Stream::write`], with the stream's options replaced by
+    /// `overrides` for this call only.
+    ///
+    /// ```
+    /// # #[cfg(feature = "encoder")]
+    /// # {
+    /// use lzma_rs::compress::{self, UnpackedSize as CompressUnpackedSize};
+    /// use lzma_rs::decompress::{Stream, UnpackedSize, WriteOverrides};
+    ///
+    /// // A record stored without its unpacked size
+    /// let mut record = Vec::new();
+    /// let options = compress::Options {
+    ///     unpacked_size: CompressUnpackedSize::SkipWritingToHeader,
+    ///     ..compress::Options::default()
+    /// };
+    /// lzma_rs::lzma_compress_with_options(&mut &b"record"[..], &mut record, &options).unwrap();
+    ///
+    /// let mut stream = Stream::<4096, 8>::new();
+    /// stream.reset();
+    /// let overrides = WriteOverrides {
+    ///     unpacked_size: Some(UnpackedSize::UseProvided(None)),
+    ///     ..WriteOverrides::default()
+    /// };
+    /// let mut output = Vec::new();
+    /// let mut data = &record[..];
+    /// while !data.is_empty() {
+    ///     let n = stream.write_with(&mut output, data, &overrides).unwrap();
+    ///     data = &data[n..];
+    /// }
+    /// stream.finish(&mut output).unwrap();
+    /// assert_eq!(output, b"record");
+    /// # }
+    /// ```
+    pub fn write_with(
+        &mut self,
+        output: &mut dyn Write,
+        data: &[u8],
+        overrides: &WriteOverrides,
+    ) -> crate::error::Result<usize> {
+        let mut options = self.options;
+        if let core::option::Option::Some(unpacked_size) = overrides.unpacked_size {
+            options.unpacked_size = unpacked_size;
+        }
+        if let core::option::Option::Some(limit) = overrides.max_symbols_per_write {
+            options.max_symbols_per_write = core::option::Option::Some(limit);
+        }
+        self.write_with_options(output, data, &options)
+    }
+
+    /// [`Stream::write`] with `options` instead of the stream's options.
+    pub(crate) fn write_with_options(
+        &mut self,
+        output: &mut dyn Write,
+        data: &[u8],
+        options: &Options,
+    ) -> crate::error::Result<usize> {
         if let StreamStatus::Uninitialized = self.get_stream_status() {
-            panic!("Stream is uninitialized; call `Stream::reset` first");
+            lzma_panic!(
+                Err(error::Error::Uninitialized),
+                "Stream is uninitialized; call `Stream::reset` first"
+            );
         }
+        let mut combiner = WriteCombiner::new(
+            self.inner.write_buf.as_mut(),
+            &mut self.inner.write_buf_len,
+            &mut self.inner.bytes_flushed,
+            output,
+        );
+        let output: &mut dyn Write = &mut combiner;
         let mut input = Cursor::new(data);
+        // Data after an end marker is handled below if a policy is set
+        self.inner
+            .decoder
+            .set_trailing_data(options.trailing_bytes.is_some());
+        // A limit of 0 would make no progress
+        self.inner.decoder.set_symbol_limit(
+            options
+                .max_symbols_per_write
+                .map(|limit| limit.max(1))
+                .into(),
+        );
 
-        let state = match self.state.take() {
-            // Read the header values and transition into a running state.
+        let state = match self.inner.state.take() {
+            // Accumulate the header and the bytes starting the range coder,
+            // then transition into a running state.
             State::Header => {
-                let res = if self.tmp.position() > 0 {
-                    // attempt to fill the tmp buffer
-                    let position = self.tmp.position();
-                    let bytes_read = input.read(&mut self.tmp.get_mut()[position as usize..])?;
-                    let bytes_read = if bytes_read < u64::MAX as usize {
-                        bytes_read as u64
-                    } else {
-                        return Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            "Failed to convert integer to u64.",
-                        )
-                        .into());
-                    };
-                    self.tmp.set_position(position + bytes_read);
-
-                    // attempt to read the header from our tmp buffer
-                    let (position, res) = {
-                        let mut tmp_input =
-                            Cursor::new(&self.tmp.get_ref()[0..self.tmp.position() as usize]);
-                        let res =
-                            Stream::read_header(&mut self.decoder, &mut tmp_input, &self.options);
-                        (tmp_input.position(), res)
-                    };
-
-                    // discard all bytes up to position if reading the header
-                    // was successful
-                    if let Ok(State::Data(_)) = &res {
-                        let tmp = *self.tmp.get_ref();
-                        let end = self.tmp.position();
-                        let new_len = end - position;
-                        (&mut self.tmp.get_mut()[0..new_len as usize])
-                            .copy_from_slice(&tmp[position as usize..end as usize]);
-                        self.tmp.set_position(new_len);
-                    }
-                    res
-                } else {
-                    Stream::read_header(&mut self.decoder, &mut input, &self.options)
-                }?;
-
-                match res {
-                    // occurs when not enough input bytes were provided to
-                    // read the entire header
-                    State::Header => {
-                        if self.tmp.position() == 0 {
-                            // reset the cursor because we may have partial reads
-                            input.set_position(0);
-                            let bytes_read = input.read(&mut self.tmp.get_mut()[..])?;
-                            let bytes_read = if bytes_read < u64::MAX as usize {
-                                bytes_read as u64
-                            } else {
-                                return Err(io::Error::new(
-                                    io::ErrorKind::Other,
-                                    "Failed to convert integer to u64.",
-                                )
-                                .into());
-                            };
-                            self.tmp.set_position(bytes_read);
-                        }
-                        State::Header
+                let needed = Self::initial_bytes_required(options);
+                let consumed = self.tmp.0.fill(data, needed);
+                input.set_position(consumed as u64);
+                let state = Self::read_header(
+                    &mut self.inner.decoder,
+                    &mut self.tmp.0.bytes(),
+                    options,
+                    &mut self.inner.last_run,
+                )?;
+                match state {
+                    // Not enough bytes yet
+                    State::Header => State::Header,
+                    State::Data(val) => {
+                        self.tmp.0.clear();
+                        State::Data(val)
                     }
-
-                    // occurs when the header was successfully read and we
-                    // move on to the next state
-                    State::Data(val) => State::Data(val),
                     State::InvalidState => {
                         return Err(error::stream::StreamError::InvalidState.into())
                     }
                     State::Uninitialized => {
-                        panic!("Stream is uninitialized; call `Stream::reset` first")
+                        lzma_panic!(
+                            Err(error::Error::Uninitialized),
+                            "Stream is uninitialized; call `Stream::reset` first"
+                        )
                     }
                 }
             }
 
-            // Process another chunk of data.
-            State::Data(state) => {
-                let state = if self.tmp.position() > 0 {
-                    let mut tmp_input =
-                        Cursor::new(&self.tmp.get_ref()[0..self.tmp.position() as usize]);
-                    let res = Stream::read_data(&mut self.decoder, state, output, &mut tmp_input)?;
-                    self.tmp.set_position(0);
-                    res
-                } else {
-                    state
-                };
-                State::Data(Stream::read_data(
-                    &mut self.decoder,
-                    state,
-                    output,
-                    &mut input,
-                )?)
-            }
+            // Process another chunk of data, unless the stream has ended and
+            // the data follows it.
+            State::Data(state) => match options.trailing_bytes {
+                core::option::Option::Some(policy) if self.inner.decoder.is_finished() => {
+                    self.skip_trailing_bytes(policy, &mut input)?;
+                    State::Data(state)
+                }
+                policy => {
+                    let state = Self::read_data(
+                        &mut self.inner.decoder,
+                        state,
+                        output,
+                        &mut input,
+                        &mut self.inner.last_run,
+                    )?;
+                    if let core::option::Option::Some(policy) = policy {
+                        if self.inner.decoder.is_finished() {
+                            self.skip_trailing_bytes(policy, &mut input)?;
+                        }
+                    }
+                    State::Data(state)
+                }
+            },
             State::InvalidState => return Err(error::stream::StreamError::InvalidState.into()),
-            State::Uninitialized => panic!("Stream is uninitialized; call `Stream::reset` first"),
+            State::Uninitialized => {
+                lzma_panic!(
+                    Err(error::Error::Uninitialized),
+                    "Stream is uninitialized; call `Stream::reset` first"
+                )
+            }
         };
-        self.state.replace(state);
+        self.inner.state.replace(state);
 
         Ok(input.position() as usize)
     }
 
-    ///
+    /// Handle the data following the end of the stream, i.e. the rest of
+    /// `input` and the bytes held back by the decoder, according to
+    /// `policy`.
+    fn skip_trailing_bytes(
+        &mut self,
+        policy: TrailingBytes,
+        input: &mut Cursor<&[u8]>,
+    ) -> crate::error::Result<()> {
+        let rest = input.get_ref().len() as u64 - input.position();
+        let trailing = self.inner.decoder.take_partial_input() as u64 + rest;
+        if trailing == 0 {
+            return Ok(());
+        }
+        if policy == TrailingBytes::Error {
+            return Err(error::lzma::LzmaError::TrailingBytes.into());
+        }
+        input.set_position(input.get_ref().len() as u64);
+        if policy == TrailingBytes::ReturnCount {
+            self.inner.trailing_bytes += trailing;
+        }
+        Ok(())
+    }
+
+    /// Whether the last [`Stream::write`] stopped after decoding
+    /// [`Options::max_symbols_per_write`] symbols. Data it did not consume
+    /// is still to be written.
+    pub fn symbol_limit_reached(&self) -> bool {
+        self.inner.decoder.symbol_limit_reached()
+    }
+
+    /// Install a timing hook called while decoding, or remove it with
+    /// `None`. The hook is kept across resets.
+    #[cfg(feature = "instrumentation")]
+    pub fn set_instrumentation(
+        &mut self,
+        hook: core::option::Option<&'static (dyn crate::decompress::Instrumentation + Sync)>,
+    ) {
+        self.inner.decoder.set_instrumentation(hook);
+    }
+
+    /// Same as [`Stream::write`], but also return the number of bytes
+    /// decompressed by this call, to track output progress without wrapping
+    /// the sink.
+    pub fn write_returning_output(
+        &mut self,
+        output: &mut dyn Write,
+        data: &[u8],
+    ) -> crate::error::Result<WriteOutcome> {
+        let before = self.inner.decoder.output.len();
+        let consumed = self.write(output, data)?;
+        Ok(WriteOutcome {
+            consumed,
+            produced: self.inner.decoder.output.len().saturating_sub(before),
+        })
+    }
+
+    /// Write the whole `buf` slice of compressed data into the stream by
+    /// repeatedly calling [`Stream::write`].
     pub fn write_all(
         &mut self,
         output: &mut dyn Write,
@@ -331,20 +1071,208 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
     ) -> crate::error::Result<()> {
         while !buf.is_empty() {
             match self.write(output, buf) {
-                Ok(0) => {
+                Ok(0) if !self.inner.decoder.symbol_limit_reached() => {
                     return Err(io::Error::new(
                         io::ErrorKind::WriteZero,
                         "failed to write whole buffer",
                     )
                     .into());
                 }
-                Ok(n) => buf = &buf[n..],
+                Ok(n) => buf = buf.get(n..).ok_or_else(util::out_of_bounds)?,
                 Err(e) => return Err(e),
             }
         }
         Ok(())
     }
 
+    /// Write a sequence of non-contiguous slices of compressed data into the
+    /// stream, as if they were concatenated. Decompressed data will be written
+    /// to the `output` sink.
+    ///
+    /// Slices are processed in order by repeatedly calling [`Stream::write`];
+    /// processing stops early if the stream does not accept any more data
+    /// (e.g. the end of the stream has been reached). Returns the total number
+    /// of bytes read from `bufs`.
+    pub fn write_vectored(
+        &mut self,
+        output: &mut dyn Write,
+        bufs: &[&[u8]],
+    ) -> crate::error::Result<usize> {
+        let mut total = 0;
+        for &(mut buf) in bufs {
+            while !buf.is_empty() {
+                let n = self.write(output, buf)?;
+                if n == 0 && !self.inner.decoder.symbol_limit_reached() {
+                    return Ok(total);
+                }
+                total += n;
+                buf = buf.get(n..).ok_or_else(util::out_of_bounds)?;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Wipe all internal buffers, including the dictionary holding
+    /// decompressed data, so that no plaintext lingers in memory. The stream
+    /// is left uninitialized; call [`Stream::reset`] before using it again.
+    ///
+    /// This also happens when the stream is dropped.
+    #[cfg(feature = "zeroize")]
+    pub fn zeroize(&mut self) {
+        self.inner.zeroize();
+        self.tmp.zeroize();
+    }
+
+    /// Best-effort recovery from corrupted or truncated input: look for the
+    /// first offset in `data` at which a plausible LZMA header starts and, if
+    /// found, reset the stream so decoding can restart from there.
+    ///
+    /// A header is plausible if its properties byte is valid, the dictionary
+    /// size and literal coders fit in `DICT_MEM_LIMIT` and `PROBS_MEM_LIMIT`,
+    /// and the first byte of the range coder data is zero. This is a
+    /// heuristic: a match may be a false positive, in which case decoding
+    /// will likely fail again and `resync` can be called on the data
+    /// following the previous match.
+    ///
+    /// Data already buffered by the stream is dropped. Statistics are kept.
+    pub fn resync(&mut self, data: &[u8]) -> ResyncStatus {
+        let header_len = LzmaParams::header_len(&self.options);
+        // The header is followed by the zero byte starting the range coder
+        let window = header_len + 1;
+        if data.len() < window {
+            return ResyncStatus::NotFound { skipped: 0 };
+        }
+        let candidates = data.len() - window + 1;
+        match (0..candidates).find(|&i| {
+            data.get(i..i + window)
+                .is_some_and(|data| self.is_plausible_header(data))
+        }) {
+            core::option::Option::Some(skipped) => {
+                self.reset();
+                ResyncStatus::Found { skipped }
+            }
+            core::option::Option::None => ResyncStatus::NotFound {
+                skipped: candidates,
+            },
+        }
+    }
+
+    /// Check the header at the start of `data`, followed by the first byte of
+    /// the range coder.
+    fn is_plausible_header(&self, data: &[u8]) -> bool {
+        let (props, dict_size, start) = match data {
+            [props, d0, d1, d2, d3, .., start] => (*props, [*d0, *d1, *d2, *d3], *start),
+            _ => return false,
+        };
+        match LzmaProperties::try_from(props) {
+            Ok(props)
+                if props.literal_coders() <= self.inner.decoder.literal_coders_available() => {}
+            _ => return false,
+        }
+        // Dictionary sizes below the minimum are rounded up by the decoder
+        let dict_size = u32::from_le_bytes(dict_size);
+        match usize::try_from(core::cmp::max(dict_size, MIN_DICT_SIZE)) {
+            Ok(dict_size) if dict_size <= self.inner.decoder.output.mem_limit() => {}
+            _ => return false,
+        }
+        start == 0
+    }
+
+    /// Copy the oldest decompressed bytes that have not reached the output
+    /// sink yet into `buf`, and return the number of bytes copied.
+    ///
+    /// Data is only written out when the dictionary wraps around or the
+    /// stream is finished, so the beginning of a payload smaller than the
+    /// dictionary can be sniffed (e.g. an ELF or UF2 magic) before choosing
+    /// the sink to pass to the next [`Stream::write`] or [`Stream::finish`].
+    ///
+    /// ```
+    /// # #[cfg(feature = "encoder")]
+    /// # {
+    /// use lzma_rs::decompress::Stream;
+    ///
+    /// let mut compressed = Vec::new();
+    /// lzma_rs::lzma_compress(&mut &b"\x7fELF..."[..], &mut compressed).unwrap();
+    ///
+    /// let mut stream = Stream::<4096, 8>::new();
+    /// stream.reset();
+    /// let mut sink = Vec::new();
+    /// stream.write_all(&mut sink, &compressed).unwrap();
+    /// let mut magic = [0; 4];
+    /// assert_eq!(stream.peek_output(&mut magic), 4);
+    /// assert_eq!(&magic, b"\x7fELF");
+    /// assert!(sink.is_empty());
+    /// # }
+    /// ```
+    pub fn peek_output(&self, buf: &mut [u8]) -> usize {
+        let combined = self
+            .inner
+            .write_buf
+            .as_ref()
+            .get(..self.inner.write_buf_len)
+            .unwrap_or(&[]);
+        let pending = combined.iter().chain(self.inner.decoder.output.unflushed());
+        let mut n = 0;
+        for (dst, src) in buf.iter_mut().zip(pending) {
+            *dst = *src;
+            n += 1;
+        }
+        n
+    }
+
+    /// Number of decompressed bytes of the current stream accepted by the
+    /// output sink, counting every byte the sink took even if a write then
+    /// failed: after an error, the first `bytes_flushed` bytes of the output
+    /// are valid, e.g. to resume programming flash from there.
+    ///
+    /// Kept by [`Stream::finish`] for the finished stream, whether it
+    /// succeeded or not, and cleared by [`Stream::reset`].
+    pub fn bytes_flushed(&self) -> u64 {
+        self.inner.bytes_flushed
+    }
+
+    /// Capture the internal decoder state, e.g. to log it after a decoding
+    /// failure. The last (up to `dict.len()`) decompressed bytes still held in
+    /// the dictionary are copied into `dict`, oldest first.
+    ///
+    /// The state is still available after [`Stream::write`] has failed, but
+    /// not after [`Stream::finish`] as it resets the stream.
+    ///
+    /// Fails with [`error::Error::SizeOverflowsTarget`] if a match distance
+    /// decoded from crafted input does not fit in `usize`.
+    pub fn debug_snapshot(&self, dict: &mut [u8]) -> error::Result<DebugSnapshot> {
+        let (lzma_state, rep) = self.inner.decoder.symbol_state();
+        let mut rep_distances = [0; 4];
+        for (distance, &rep) in rep_distances.iter_mut().zip(rep.iter()) {
+            *distance = rep_distance(rep)?;
+        }
+        Ok(DebugSnapshot {
+            range: self.inner.last_run.range,
+            code: self.inner.last_run.code,
+            lzma_state,
+            rep_distances,
+            unpacked_len: self.inner.decoder.output.len(),
+            dict_len: self.inner.decoder.output.copy_last(dict),
+            #[cfg(feature = "stats")]
+            stats: self.inner.decoder.stats,
+        })
+    }
+
+    /// Statistics about the data decoded so far.
+    ///
+    /// Statistics accumulate across streams and are not affected by
+    /// [`Stream::reset`]; use [`Stream::reset_stats`] to clear them.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> crate::decompress::DecodeStats {
+        self.inner.decoder.stats
+    }
+
+    /// Clear the statistics returned by [`Stream::stats`].
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.inner.decoder.stats = crate::decompress::DecodeStats::new();
+    }
+
     /// Retrieve the stream state.
     ///
     /// If [`StreamStatus::EosReached`] is returned, [`Stream::finish`] call is
@@ -353,14 +1281,15 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
         use crate::decode::lzma::ProcessingStatus;
         use State::*;
         use StreamStatus::*;
-        match &self.state {
+        match &self.inner.state {
             Header => ProcessingHeader,
             Data(_) => {
-                let params = match &self.decoder.params {
-                    Some(v) => v.clone(),
-                    None => panic!(
-                "DecoderState::params is not initialized; call `DecoderState::set_params` first"
-            ),
+                let params = match &self.inner.decoder.params {
+                    Some(v) => *v,
+                    None => lzma_panic!(
+                        StreamStatus::Uninitialized,
+                        "DecoderState::params is not initialized; call `DecoderState::set_params` first"
+                    ),
                 };
                 let unpacked_size = params.unpacked_size;
                 // Temporary buffer in `Stream` must be checked; without `Stream::finish` call,
@@ -371,16 +1300,14 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
                 // 3. Decoder reads all the data bytes but not the marker?
                 // Status will then indicate that unpacked_size == unpacked_data_processed but
                 // Eos is not reached yet. Should one call `finish` then?
-                // Should tmp position be added to unpacked_data_processed?
-                let unpacked_data_processed =
-                    self.decoder.output.len() as u64 + self.tmp.position();
+                let unpacked_data_processed = self.inner.decoder.output.len();
                 // TODO: Add tests stressing this; especially considering different decoding
                 // options in `decode::Options::UnpackedSize` If unpacked_size
 
                 // If EOS marker is found, return proper status
                 // Note: reaching unpacked_size == unpacked_data_processed does not mean that
                 // stream has been terminated
-                match self.decoder.get_processing_status() {
+                match self.inner.decoder.get_processing_status() {
                     ProcessingStatus::Uninitialized => StreamStatus::Uninitialized,
                     ProcessingStatus::Continue => ProcessingData {
                         unpacked_size: unpacked_size.into(),
@@ -395,19 +1322,193 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
     }
 }
 
-impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> Debug
-    for Stream<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
-{
-    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
-        fmt.debug_struct("Stream")
-            .field("tmp", &self.tmp.position())
-            .field("state", &self.state)
-            .field("options", &self.options)
-            .finish()
+#[cfg(feature = "zeroize")]
+impl<DICT, PROBS, WBUF> zeroize::Zeroize for GenericStream<DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fn zeroize(&mut self) {
+        GenericStream::zeroize(self)
+    }
+}
+
+// The stream is wiped on drop by its parts, so that it can still be taken
+// apart with `into_parts`.
+#[cfg(feature = "zeroize")]
+impl<DICT, PROBS, WBUF> Drop for StreamDecoder<DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fn drop(&mut self) {
+        self.zeroize()
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for StreamTmp {
+    fn drop(&mut self) {
+        self.zeroize()
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<DICT, PROBS, WBUF> zeroize::ZeroizeOnDrop for GenericStream<DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+}
+
+#[cfg(feature = "zeroize")]
+impl<DICT, PROBS, WBUF> zeroize::ZeroizeOnDrop for StreamDecoder<DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::ZeroizeOnDrop for StreamTmp {}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize, const WRITE_BUF_LEN: usize> Default
+    for Stream<DICT_MEM_LIMIT, PROBS_MEM_LIMIT, WRITE_BUF_LEN>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<DICT, PROBS, WBUF> Debug for GenericStream<DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("Stream")
+            .field("tmp", &self.tmp.len())
+            .field("write_buf_len", &self.inner.write_buf_len)
+            .field("state", &self.inner.state)
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+/// Writer combining data written to `inner` into chunks the size of `buf`,
+/// a buffer owned by the [`Stream`]. Pass-through if `buf` is empty.
+struct WriteCombiner<'a> {
+    buf: &'a mut [u8],
+    len: &'a mut usize,
+    /// Bytes accepted by `inner`, counted as they are written so that the
+    /// count is exact when a write fails.
+    flushed: &'a mut u64,
+    inner: &'a mut dyn Write,
+}
+
+impl<'a> WriteCombiner<'a> {
+    fn new(
+        buf: &'a mut [u8],
+        len: &'a mut usize,
+        flushed: &'a mut u64,
+        inner: &'a mut dyn Write,
+    ) -> Self {
+        Self {
+            buf,
+            len,
+            flushed,
+            inner,
+        }
+    }
+
+    /// Write out the remaining, possibly incomplete, chunk.
+    fn finish(&mut self) -> io::Result<()> {
+        if *self.len > 0 {
+            let chunk = self.buf.get(..*self.len).ok_or_else(util::out_of_bounds)?;
+            write_all_counted(self.inner, chunk, self.flushed)?;
+            *self.len = 0;
+        }
+        self.inner.flush()
+    }
+}
+
+/// Like [`util::write_all`], adding the bytes accepted by `inner` to
+/// `flushed` as they are written.
+fn write_all_counted(inner: &mut dyn Write, mut data: &[u8], flushed: &mut u64) -> io::Result<()> {
+    while !data.is_empty() {
+        match inner.write(data) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(n) => {
+                *flushed += n as u64;
+                data = data.get(n..).ok_or_else(util::out_of_bounds)?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Checked `<[u8]>::split_at`.
+fn split_at(data: &[u8], mid: usize) -> io::Result<(&[u8], &[u8])> {
+    match (data.get(..mid), data.get(mid..)) {
+        (core::option::Option::Some(head), core::option::Option::Some(tail)) => Ok((head, tail)),
+        _ => Err(util::out_of_bounds()),
+    }
+}
+
+impl<'a> Write for WriteCombiner<'a> {
+    fn write(&mut self, mut data: &[u8]) -> io::Result<usize> {
+        let chunk_len = self.buf.len();
+        if chunk_len == 0 {
+            let n = self.inner.write(data)?;
+            *self.flushed += n as u64;
+            return Ok(n);
+        }
+        let written = data.len();
+        while !data.is_empty() {
+            if *self.len == 0 && data.len() >= chunk_len {
+                // Nothing buffered; write straight from `data`
+                let (chunk, rest) = split_at(data, chunk_len)?;
+                write_all_counted(self.inner, chunk, self.flushed)?;
+                data = rest;
+                continue;
+            }
+            let free = self
+                .buf
+                .get_mut(*self.len..)
+                .ok_or_else(util::out_of_bounds)?;
+            let amt = core::cmp::min(free.len(), data.len());
+            for (dst, src) in free.iter_mut().zip(data) {
+                *dst = *src;
+            }
+            *self.len += amt;
+            data = data.get(amt..).ok_or_else(util::out_of_bounds)?;
+            if *self.len == chunk_len {
+                write_all_counted(self.inner, self.buf, self.flushed)?;
+                *self.len = 0;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Incomplete chunks are kept until `finish`
+        self.inner.flush()
     }
 }
 
-#[cfg(all(test, feature = "std"))]
+#[cfg(all(test, feature = "encoder"))]
 mod test {
     // TODO: Write a test that checks if resetting is always equal to newly
     // construted object
@@ -424,6 +1525,239 @@ mod test {
         assert!(sink.is_empty());
     }
 
+    #[test]
+    fn test_peek_output() {
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut compressed = Vec::new();
+        crate::lzma_compress(&mut &data[..], &mut compressed).unwrap();
+
+        let mut sink = Vec::new();
+        let mut stream = Stream::<4096, 8, 3000>::new();
+        stream.reset();
+        stream.write_all(&mut sink, &compressed).unwrap();
+        // The dictionary wrapped once: 3000 bytes reached the sink, 1096 are
+        // in the write buffer and the rest is still in the dictionary
+        assert_eq!(sink, &data[..3000]);
+        let mut peeked = [0; 2500];
+        assert_eq!(stream.peek_output(&mut peeked), 2000);
+        assert_eq!(&peeked[..2000], &data[3000..]);
+
+        stream.finish(&mut sink).unwrap();
+        assert_eq!(sink, data);
+        assert_eq!(stream.peek_output(&mut peeked), 0);
+    }
+
+    #[cfg(feature = "instrumentation")]
+    #[test]
+    fn test_instrumentation() {
+        use crate::decompress::{DecoderState, Instrumentation};
+        use core::sync::atomic::{AtomicU64, Ordering};
+
+        struct Counter;
+        static HEADERS: AtomicU64 = AtomicU64::new(0);
+        static SYMBOLS: AtomicU64 = AtomicU64::new(0);
+        impl Instrumentation for Counter {
+            fn header_exit(&self) {
+                HEADERS.fetch_add(1, Ordering::Relaxed);
+            }
+            fn tick_interval(&self) -> u32 {
+                0
+            }
+            fn tick(&self, symbols: u64) {
+                SYMBOLS.store(symbols, Ordering::Relaxed);
+            }
+        }
+
+        let compressed = include_bytes!("../../tests/files/foo.txt.lzma");
+        let mut state = DecoderState::<4096, 8>::new();
+        state.set_instrumentation(core::option::Option::Some(&Counter));
+        crate::lzma_decompress_with_state(&mut state, &mut &compressed[..], &mut Vec::new())
+            .unwrap();
+        let symbols = SYMBOLS.swap(0, Ordering::Relaxed);
+        assert_eq!(HEADERS.swap(0, Ordering::Relaxed), 1);
+        assert!(symbols > 1000);
+
+        // Same count in streaming mode, with the header split across writes
+        let mut stream = Stream::<4096, 8>::new();
+        stream.set_instrumentation(core::option::Option::Some(&Counter));
+        stream.reset();
+        let mut sink = Vec::new();
+        for chunk in compressed.chunks(7) {
+            stream.write_all(&mut sink, chunk).unwrap();
+        }
+        stream.finish(&mut sink).unwrap();
+        assert_eq!(SYMBOLS.load(Ordering::Relaxed), symbols);
+        // The header and the 5 bytes starting the range coder span three
+        // chunks, each retrying the parse
+        assert_eq!(HEADERS.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_header_split_anywhere() {
+        let expected = b"Header and range coder bytes split across writes";
+        let mut input = Vec::new();
+        crate::lzma_compress(&mut &expected[..], &mut input).unwrap();
+        let mut stream = Stream::<4096, 8>::new();
+        stream.reset();
+        // Only the header and the bytes starting the range coder are taken
+        assert_eq!(
+            stream.write(&mut Vec::new(), &input).unwrap(),
+            MAX_HEADER_LEN + START_BYTES
+        );
+
+        for i in 0..=MAX_TMP_LEN {
+            for j in i..=MAX_TMP_LEN + 2 {
+                stream.reset();
+                let mut sink = Vec::new();
+                for chunk in [&input[..i], &input[i..j], &input[j..]] {
+                    stream.write_all(&mut sink, chunk).unwrap();
+                }
+                stream.finish(&mut sink).unwrap();
+                assert_eq!(&sink[..], &expected[..], "split at {} and {}", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn test_initial_bytes_required() {
+        type S = Stream<4096, 8>;
+        let with = |unpacked_size| Options {
+            unpacked_size,
+            ..Options::default()
+        };
+        assert_eq!(S::initial_bytes_required(&Options::default()), 18);
+        assert_eq!(
+            S::initial_bytes_required(&with(UnpackedSize::ReadFromHeader32)),
+            14
+        );
+        assert_eq!(
+            S::initial_bytes_required(&with(UnpackedSize::UseProvided(
+                core::option::Option::Some(3)
+            ))),
+            10
+        );
+
+        // Matches what the stream actually consumes before any output
+        let mut input = Vec::new();
+        crate::lzma_compress(&mut &b"abc"[..], &mut input).unwrap();
+        let mut stream = S::new();
+        stream.reset();
+        assert_eq!(
+            stream.write(&mut Vec::new(), &input).unwrap(),
+            S::initial_bytes_required(&Options::default())
+        );
+    }
+
+    #[test]
+    fn test_unpacked_size_modes() {
+        use crate::compress::UnpackedSize as Write;
+        use core::option::Option as Opt;
+        use UnpackedSize as Read;
+        let expected = b"Each unpacked size mode reads its own header length. ".repeat(8);
+        let len = expected.len() as u64;
+        let compress = |unpacked_size| {
+            let mut compressed = Vec::new();
+            let options = crate::compress::Options {
+                unpacked_size,
+                ..crate::compress::Options::default()
+            };
+            crate::lzma_compress_with_options(&mut &expected[..], &mut compressed, &options)
+                .unwrap();
+            compressed
+        };
+        // With an end marker and no size field, which the encoder doesn't write
+        let mut no_size_field = compress(Write::WriteToHeader(Opt::None));
+        no_size_field.drain(5..13);
+
+        let cases = [
+            (
+                Read::ReadFromHeader,
+                compress(Write::WriteToHeader(Opt::None)),
+            ),
+            (
+                Read::ReadFromHeader,
+                compress(Write::WriteToHeader(Opt::Some(len))),
+            ),
+            (
+                Read::ReadHeaderButUseProvided(Opt::None),
+                compress(Write::WriteToHeader(Opt::None)),
+            ),
+            (
+                Read::ReadHeaderButUseProvided(Opt::Some(len)),
+                compress(Write::WriteToHeader(Opt::Some(len))),
+            ),
+            (
+                Read::ReadFromHeader32,
+                compress(Write::WriteToHeader32(Opt::None)),
+            ),
+            (
+                Read::ReadFromHeader32,
+                compress(Write::WriteToHeader32(Opt::Some(len as u32))),
+            ),
+            (Read::UseProvided(Opt::None), no_size_field),
+            (
+                Read::UseProvided(Opt::Some(len)),
+                compress(Write::SkipWritingToHeader),
+            ),
+        ];
+        for (unpacked_size, input) in cases.iter() {
+            let options = Options {
+                unpacked_size: *unpacked_size,
+                ..Options::default()
+            };
+            let mut stream = Stream::<4096, 8>::new_with_options(&options);
+            // Nothing is decoded before the whole header has been read
+            let initial = Stream::<4096, 8>::initial_bytes_required(&options);
+            stream.reset();
+            assert_eq!(
+                stream
+                    .write(&mut Vec::new(), &input[..initial - 1])
+                    .unwrap(),
+                initial - 1
+            );
+            assert_eq!(stream.get_stream_status(), StreamStatus::ProcessingHeader);
+
+            for chunk_size in &[1, 2, 5, 13, input.len()] {
+                stream.reset();
+                let mut sink = Vec::new();
+                for chunk in input.chunks(*chunk_size) {
+                    stream.write_all(&mut sink, chunk).unwrap();
+                }
+                stream.finish(&mut sink).unwrap();
+                assert_eq!(
+                    &sink[..],
+                    &expected[..],
+                    "{:?} in chunks of {}",
+                    unpacked_size,
+                    chunk_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_into_parts() {
+        let input = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+        let (head, tail) = input.split_at(7);
+
+        let mut sink = Vec::new();
+        let mut stream = Stream::<0x10000, 8>::new();
+        stream.reset();
+        stream.write_all(&mut sink, head).unwrap();
+        let (decoder, options, tmp) = stream.into_parts();
+        assert_eq!(tmp.len(), 7);
+
+        // Parts can be moved independently, e.g. to another thread
+        let handle = std::thread::spawn(move || {
+            let mut stream = Stream::from_parts(decoder, options, tmp);
+            stream.write_all(&mut sink, tail).unwrap();
+            stream.finish(&mut sink).unwrap();
+            sink
+        });
+        assert_eq!(handle.join().unwrap(), &expected[..]);
+    }
+
     /// Test writing an empty slice
     #[test]
     fn test_stream_zero() {
@@ -449,8 +1783,9 @@ mod test {
         stream.reset();
 
         match stream.write_all(&mut sink, &input[..]).unwrap_err() {
-            error::Error::LzmaError(error::lzma::LzmaError::InvalidHeader {
-                invalid_properties: 255,
+            error::Error::LzmaError(error::lzma::LzmaError::InvalidProperties {
+                value: 255,
+                reason: error::lzma::InvalidPropertiesReason::PbTooLarge,
             }) => {}
             err => panic!("Unexpected error: {:#?}", err),
         }
@@ -537,11 +1872,11 @@ mod test {
         stream.write_all(&mut sink, &input[..]).unwrap();
         stream.finish(&mut sink).unwrap();
         assert_eq!(expected, &sink[..]);
-        sink.truncate(0);
+        sink.clear();
         stream.write_all(&mut sink, &input[..]).unwrap();
         stream.finish(&mut sink).unwrap();
         assert_eq!(expected, &sink[..]);
-        sink.truncate(0);
+        sink.clear();
         let (first_half, second_half) = input.split_at(input.len() / 2);
         stream.write_all(&mut sink, first_half).unwrap();
         stream.write_all(&mut sink, second_half).unwrap();
@@ -549,6 +1884,405 @@ mod test {
         assert_eq!(expected, &sink[..]);
     }
 
+    #[test]
+    fn test_stream_reset_keep_dict() {
+        let input = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+        let mut sink = Vec::new();
+        let mut stream = Stream::<4096, 8>::new();
+        stream.reset();
+        stream.write_all(&mut sink, &input[..]).unwrap();
+        stream.finish_keep_dict(&mut sink).unwrap();
+        assert_eq!(expected, &sink[..]);
+
+        // The data is kept for the next stream, with the same dictionary size
+        let params = LzmaParams::read_header(&mut &input[..], &Options::default()).unwrap();
+        let output = &mut stream.inner.decoder.output;
+        assert_eq!(output.len(), 0);
+        output.set_dict_size(params.dict_size as usize).unwrap();
+        assert_eq!(output.last_n(1).unwrap(), expected[expected.len() - 1]);
+        assert_eq!(
+            output.last_n(4096).unwrap(),
+            expected[expected.len() - 4096]
+        );
+
+        // `reset` and `finish` forget it
+        stream.reset_keep_dict();
+        stream.reset();
+        stream.write_all(&mut sink, &input[..]).unwrap();
+        stream.finish(&mut sink).unwrap();
+        let output = &mut stream.inner.decoder.output;
+        output.set_dict_size(params.dict_size as usize).unwrap();
+        assert!(output.last_n(1).is_err());
+    }
+
+    #[test]
+    fn test_stream_reset_keep_dict_unfinished() {
+        let mut first = Vec::new();
+        crate::lzma_compress(&mut &[b'A'; 3000][..], &mut first).unwrap();
+        let mut second = Vec::new();
+        crate::lzma_compress(&mut &b"hello world"[..], &mut second).unwrap();
+
+        let mut stream = Stream::<4096, 8>::new();
+        stream.reset();
+        let mut sink = Vec::new();
+        stream
+            .write_all(&mut sink, &first[..first.len() - 2])
+            .unwrap();
+        stream.reset_keep_dict();
+
+        // Only the output of the new stream is written
+        let mut sink = Vec::new();
+        stream.write_all(&mut sink, &second).unwrap();
+        stream.finish(&mut sink).unwrap();
+        assert_eq!(&sink[..], b"hello world");
+    }
+
+    #[test]
+    fn test_stream_decodes_streams_in_a_row() {
+        // Literals are decoded in the context of the previous byte, so a
+        // stream ending with a letter would change how the next one starts if
+        // its data were kept
+        let foo = include_bytes!("../../tests/files/foo.txt");
+        let data: [&[u8]; 4] = [b"Ends with a letter", foo, b"Second", b"Third one"];
+        let mut stream = Stream::<65536, 8>::new();
+        stream.reset();
+        for expected in data.iter() {
+            let mut input = Vec::new();
+            crate::lzma_compress(&mut &expected[..], &mut input).unwrap();
+            let mut sink = Vec::new();
+            stream.write_all(&mut sink, &input).unwrap();
+            stream.finish(&mut sink).unwrap();
+            assert_eq!(*expected, &sink[..]);
+        }
+    }
+
+    #[test]
+    fn test_stream_memory_footprint() {
+        let footprint = Stream::<4096, 8, 64>::memory_footprint();
+        assert_eq!(footprint.dict, 4096);
+        assert_eq!(footprint.probs, 8 * 0x300 * 2);
+        assert_eq!(footprint.write_buffer, 64);
+        assert_eq!(footprint.total, core::mem::size_of::<Stream<4096, 8, 64>>());
+        assert!(
+            footprint.total
+                > crate::decompress::worst_case_memory::<4096, 8>().total + footprint.write_buffer
+        );
+    }
+
+    #[test]
+    fn test_stream_debug_snapshot() {
+        let input = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+        let mut sink = Vec::new();
+        let mut stream = Stream::<4096, 8>::new();
+        stream.reset();
+        stream.write_all(&mut sink, &input[..]).unwrap();
+
+        let mut dict = [0; 16];
+        let snapshot = stream.debug_snapshot(&mut dict).unwrap();
+        let unpacked_len = snapshot.unpacked_len as usize;
+        assert!(unpacked_len > 4096);
+        assert_eq!(snapshot.dict_len, 16);
+        assert_eq!(&dict[..], &expected[unpacked_len - 16..unpacked_len]);
+        assert!(snapshot.lzma_state < 12);
+        assert_ne!(snapshot.range, 0);
+
+        // Nothing to copy before the header is read
+        stream.reset();
+        let snapshot = stream.debug_snapshot(&mut dict).unwrap();
+        assert_eq!(snapshot.dict_len, 0);
+        assert_eq!(snapshot.unpacked_len, 0);
+
+        // The end marker distance of a crafted stream overflows on 32-bit
+        // targets
+        match rep_distance(usize::MAX) {
+            Err(error::Error::SizeOverflowsTarget { .. }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stream_bytes_flushed() {
+        fn check<const WRITE_BUF_LEN: usize>(stream: &mut Stream<4096, 8, WRITE_BUF_LEN>) {
+            let input = include_bytes!("../../tests/files/foo.txt.lzma");
+            let expected = include_bytes!("../../tests/files/foo.txt");
+
+            // The sink fills up in the middle of a flush
+            let mut output = [0; 10001];
+            stream.reset();
+            assert!(stream.write_all(&mut &mut output[..], &input[..]).is_err());
+            assert_eq!(stream.bytes_flushed(), 10001);
+            assert_eq!(&output[..], &expected[..10001]);
+
+            // The end of the data is flushed by `finish`, which fails too but
+            // keeps the count
+            let mut output = vec![0; expected.len() - 5];
+            let mut sink = &mut output[..];
+            stream.reset();
+            stream.write_all(&mut sink, &input[..]).unwrap();
+            assert!(stream.bytes_flushed() < expected.len() as u64 - 5);
+            assert!(stream.finish(&mut sink).is_err());
+            assert_eq!(stream.bytes_flushed(), expected.len() as u64 - 5);
+            assert_eq!(&output[..], &expected[..expected.len() - 5]);
+            stream.reset();
+            assert_eq!(stream.bytes_flushed(), 0);
+
+            let mut sink = Vec::new();
+            stream.write_all(&mut sink, &input[..]).unwrap();
+            stream.finish(&mut sink).unwrap();
+            assert_eq!(stream.bytes_flushed(), expected.len() as u64);
+        }
+
+        check(&mut Stream::<4096, 8>::new());
+        check(&mut Stream::<4096, 8, 64>::new());
+    }
+
+    #[test]
+    fn test_stream_resync() {
+        let input = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+        let mut garbage = vec![0xFF; 37];
+        garbage.extend_from_slice(&input[..]);
+
+        let mut sink = Vec::new();
+        let mut stream = Stream::<4096, 8>::new();
+        stream.reset();
+        stream.write_all(&mut sink, &garbage).unwrap_err();
+
+        assert_eq!(
+            stream.resync(&garbage[..10]),
+            ResyncStatus::NotFound { skipped: 0 }
+        );
+        assert_eq!(
+            stream.resync(&garbage[..30]),
+            ResyncStatus::NotFound { skipped: 17 }
+        );
+        assert_eq!(stream.resync(&garbage), ResyncStatus::Found { skipped: 37 });
+        sink.clear();
+        stream.write_all(&mut sink, &garbage[37..]).unwrap();
+        stream.finish(&mut sink).unwrap();
+        assert_eq!(&sink[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_stream_write_combining() {
+        /// Sink recording the size of every write
+        #[derive(Default)]
+        struct RecordingSink {
+            data: Vec<u8>,
+            writes: Vec<usize>,
+        }
+        impl Write for RecordingSink {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.data.extend_from_slice(buf);
+                self.writes.push(buf.len());
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let input = include_bytes!("../../tests/files/small.txt");
+        let mut compressed = Vec::new();
+        crate::lzma_compress(&mut &input[..], &mut compressed).unwrap();
+
+        for chunk in &[1, 7, compressed.len()] {
+            let mut sink = RecordingSink::default();
+            let mut stream = Stream::<4096, 8, 16>::new();
+            stream.reset();
+            for data in compressed.chunks(*chunk) {
+                stream.write_all(&mut sink, data).unwrap();
+            }
+            stream.finish(&mut sink).unwrap();
+            assert_eq!(&sink.data[..], &input[..]);
+            let (last, full) = sink.writes.split_last().unwrap();
+            assert!(full.iter().all(|&len| len == 16));
+            assert!(*last > 0 && *last <= 16);
+        }
+    }
+
+    #[test]
+    fn test_borrowed_dict_stream() {
+        let input = include_bytes!("../../tests/files/small.txt");
+        let mut compressed = Vec::new();
+        crate::lzma_compress(&mut &input[..], &mut compressed).unwrap();
+
+        // Stale dictionary content must not matter
+        let mut dict = vec![0xAA; 4096];
+        let mut stream = BorrowedDictStream::<8>::with_dict(&mut dict);
+        stream.reset();
+        let mut sink = Vec::new();
+        stream.write_all(&mut sink, &compressed).unwrap();
+        stream.finish(&mut sink).unwrap();
+        assert_eq!(&sink[..], &input[..]);
+
+        let mut dict = vec![0xAA; 4095];
+        let mut stream = BorrowedDictStream::<8>::with_dict(&mut dict);
+        stream.reset();
+        match stream.write_all(&mut Vec::new(), &compressed) {
+            Err(error::Error::DictionaryBufferTooSmall {
+                needed: 4096,
+                available: 4095,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_write_with_overrides() {
+        let compressed = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+        // Same data without the unpacked size in the header
+        let mut record = compressed[..5].to_vec();
+        record.extend_from_slice(&compressed[13..]);
+        let overrides = WriteOverrides {
+            unpacked_size: core::option::Option::Some(UnpackedSize::UseProvided(
+                core::option::Option::None,
+            )),
+            max_symbols_per_write: core::option::Option::Some(100),
+        };
+
+        let mut stream = Stream::<4096, 8>::new();
+        for _ in 0..2 {
+            stream.reset();
+            let mut sink = Vec::new();
+            let mut data = &record[..];
+            while !data.is_empty() {
+                let n = stream.write_with(&mut sink, data, &overrides).unwrap();
+                data = &data[n..];
+            }
+            stream.finish(&mut sink).unwrap();
+            assert_eq!(&sink[..], &expected[..]);
+
+            // The stream's own options still apply to the next record
+            stream.reset();
+            let mut sink = Vec::new();
+            stream.write_all(&mut sink, compressed).unwrap();
+            stream.finish(&mut sink).unwrap();
+            assert_eq!(&sink[..], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_max_symbols_per_write() {
+        let compressed = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+        for limit in &[0, 1, 7, 1000] {
+            let options = Options {
+                max_symbols_per_write: core::option::Option::Some(*limit),
+                ..Options::default()
+            };
+            let mut stream = Stream::<4096, 8>::new_with_options(&options);
+            stream.reset();
+            let mut sink = Vec::new();
+            let mut data = &compressed[..];
+            let mut calls = 0;
+            while !data.is_empty() {
+                let outcome = stream.write_returning_output(&mut sink, data).unwrap();
+                let n = outcome.consumed;
+                // Each symbol decodes to at most 273 bytes
+                assert!(outcome.produced <= 273 * (*limit).max(1) as u64);
+                // The first call only reads the header
+                assert!(n == data.len() || stream.symbol_limit_reached() || calls == 0);
+                data = &data[n..];
+                calls += 1;
+            }
+            stream.finish(&mut sink).unwrap();
+            assert_eq!(&sink[..], &expected[..]);
+            assert!(*limit == 1000 || calls > 10, "{} calls", calls);
+
+            let mut stream = Stream::<4096, 8>::new_with_options(&options);
+            stream.reset();
+            let mut sink = Vec::new();
+            stream.write_all(&mut sink, compressed).unwrap();
+            stream.finish(&mut sink).unwrap();
+            assert_eq!(&sink[..], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_borrowed_probs_stream() {
+        let input = include_bytes!("../../tests/files/small.txt");
+        let mut compressed = Vec::new();
+        crate::lzma_compress(&mut &input[..], &mut compressed).unwrap();
+
+        // Stale probabilities must not matter
+        let mut probs = vec![[0xAAAA; 0x300]; 8];
+        let mut stream = BorrowedProbsStream::<4096>::with_probs(&mut probs);
+        stream.reset();
+        let mut sink = Vec::new();
+        stream.write_all(&mut sink, &compressed).unwrap();
+        stream.finish(&mut sink).unwrap();
+        assert_eq!(&sink[..], &input[..]);
+
+        let mut probs = vec![[0; 0x300]; 4];
+        let mut stream = BorrowedProbsStream::<4096>::with_probs(&mut probs);
+        stream.reset();
+        match stream.write_all(&mut Vec::new(), &compressed) {
+            Err(error::Error::ProbabilitiesBufferTooSmall {
+                needed: 8,
+                available: 4,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dyn_stream() {
+        let input = include_bytes!("../../tests/files/small.txt");
+        let mut compressed = Vec::new();
+        crate::lzma_compress(&mut &input[..], &mut compressed).unwrap();
+
+        for write_buf_len in &[0, 16] {
+            let mut dict = vec![0; 4096];
+            let mut probs = vec![[0; 0x300]; 8];
+            let mut write_buf = vec![0; *write_buf_len];
+            let mut stream = DynStream::new(&mut dict, &mut probs, &mut write_buf);
+            stream.reset();
+            let mut sink = Vec::new();
+            for data in compressed.chunks(7) {
+                stream.write_all(&mut sink, data).unwrap();
+            }
+            stream.finish(&mut sink).unwrap();
+            assert_eq!(&sink[..], &input[..]);
+        }
+
+        // Buffers are checked against the stream at runtime
+        let mut dict = vec![0; 1024];
+        let mut probs = vec![[0; 0x300]; 8];
+        let mut stream = DynStream::new(&mut dict, &mut probs, &mut []);
+        stream.reset();
+        match stream.write_all(&mut Vec::new(), &compressed) {
+            Err(error::Error::DictionaryBufferTooSmall {
+                needed: 4096,
+                available: 1024,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_stream_zeroize() {
+        let input = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+        let mut sink = Vec::new();
+        let mut stream = Stream::<4096, 8>::new();
+        stream.reset();
+        stream.write_all(&mut sink, &input[..10]).unwrap();
+        stream.zeroize();
+        assert_eq!(stream.get_stream_status(), StreamStatus::Uninitialized);
+        assert_eq!(stream.tmp.len(), 0);
+        assert!(stream.tmp.0.buf.iter().all(|&v| v == 0));
+
+        stream.reset();
+        stream.write_all(&mut sink, &input[..]).unwrap();
+        stream.finish(&mut sink).unwrap();
+        assert_eq!(expected, &sink[..]);
+    }
+
     /// Test processing only partial data
     #[test]
     fn test_stream_incomplete() {
@@ -566,7 +2300,7 @@ mod test {
             let mut stream = Stream::<4096, 8>::new();
             stream.reset();
             stream.write_all(&mut sink, &input[..end as usize]).unwrap();
-            assert_eq!(stream.tmp.position(), end);
+            assert_eq!(stream.tmp.len() as u64, end);
             assert_eq!(stream.get_stream_status(), ProcessingHeader);
 
             match stream.finish(&mut sink).unwrap_err() {
@@ -661,4 +2395,62 @@ mod test {
             err => panic!("Unexpected error: {:#?}", err),
         }
     }
+
+    #[test]
+    fn test_stream_finish_summary() {
+        let input = include_bytes!("../../tests/files/small.txt");
+        let decode = |compressed: &[u8]| {
+            let mut stream = Stream::<4096, 8>::new();
+            stream.reset();
+            let mut sink = Vec::new();
+            stream.write_all(&mut sink, compressed).unwrap();
+            let summary = stream.finish_with_summary(&mut sink).unwrap();
+            assert_eq!(&sink[..], &input[..]);
+            summary.clean_termination
+        };
+
+        // End marker
+        let mut compressed = Vec::new();
+        crate::lzma_compress(&mut &input[..], &mut compressed).unwrap();
+        assert!(decode(&compressed));
+
+        // Unpacked size in the header
+        let options = crate::compress::Options {
+            unpacked_size: crate::compress::UnpackedSize::WriteToHeader(
+                core::option::Option::Some(input.len() as u64),
+            ),
+            ..Default::default()
+        };
+        let mut compressed = Vec::new();
+        crate::lzma_compress_with_options(&mut &input[..], &mut compressed, &options).unwrap();
+        assert!(decode(&compressed));
+
+        // Range coder not flushed
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        assert!(!decode(&compressed));
+    }
+
+    #[test]
+    fn test_stream_write_returning_output() {
+        let input = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+        let mut stream = Stream::<4096, 8, 64>::new();
+        stream.reset();
+        let mut sink = Vec::new();
+        let mut produced = 0;
+        for mut chunk in input.chunks(17) {
+            while !chunk.is_empty() {
+                let outcome = stream.write_returning_output(&mut sink, chunk).unwrap();
+                chunk = &chunk[outcome.consumed..];
+                produced += outcome.produced;
+                // Buffered output is counted before it reaches the sink
+                assert!(sink.len() as u64 <= produced);
+            }
+        }
+        assert!(produced > 0);
+        stream.finish(&mut sink).unwrap();
+        assert_eq!(&sink[..], &expected[..]);
+        assert!(produced <= expected.len() as u64);
+    }
 }
@@ -1,10 +1,131 @@
+use crate::error;
 use crate::io;
+use core::convert::TryFrom;
 
 pub fn is_eof<R: io::BufRead>(input: &mut R) -> io::Result<bool> {
     let buf = input.fill_buf()?;
     Ok(buf.is_empty())
 }
 
+/// Consume `input` up to its end and return the number of bytes skipped.
+pub fn skip_to_end<R: io::BufRead>(input: &mut R) -> io::Result<u64> {
+    let mut skipped = 0;
+    loop {
+        let len = input.fill_buf()?.len();
+        if len == 0 {
+            return Ok(skipped);
+        }
+        input.consume(len);
+        skipped += len as u64;
+    }
+}
+
+/// Copy `input` up to its end into `output` and return the number of bytes
+/// copied.
+#[cfg(feature = "encoder")]
+pub fn copy_to_end<R: io::BufRead, W: io::Write + ?Sized>(
+    input: &mut R,
+    output: &mut W,
+) -> io::Result<u64> {
+    let mut copied = 0;
+    loop {
+        let buf = input.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(copied);
+        }
+        output.write_all(buf)?;
+        let len = buf.len();
+        input.consume(len);
+        copied += len as u64;
+    }
+}
+
+/// Reader counting the bytes consumed from `inner`.
+pub struct CountingReader<'a, R> {
+    inner: &'a mut R,
+    pub count: u64,
+}
+
+impl<'a, R> CountingReader<'a, R> {
+    pub fn new(inner: &'a mut R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<'a, R: io::Read> io::Read for CountingReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a, R: io::BufRead> io::BufRead for CountingReader<'a, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.count += amt as u64;
+    }
+}
+
+/// Read one byte straight from the buffer of `input`. For a `&[u8]` input
+/// this compiles down to indexing the slice, without the copy of
+/// `Read::read_exact`.
+#[inline(always)]
+pub fn read_byte<R: io::BufRead + ?Sized>(input: &mut R) -> io::Result<u8> {
+    let byte = match input.fill_buf()?.first() {
+        Some(&byte) => byte,
+        // At the end of the input, `read_u8` builds the usual error
+        None => return read_byte_slow(input),
+    };
+    input.consume(1);
+    Ok(byte)
+}
+
+#[cold]
+#[inline(never)]
+fn read_byte_slow<R: io::BufRead + ?Sized>(input: &mut R) -> io::Result<u8> {
+    use io::ReadBytesExt;
+    input.read_u8()
+}
+
+/// Error reported for an out of bounds index. Indices are in bounds by
+/// construction; reporting an error instead of panicking keeps panic paths out
+/// of the decoder.
+#[cold]
+#[inline(never)]
+pub fn out_of_bounds() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "index out of bounds")
+}
+
+/// Same as `Write::write_all`, without the panic path of the default
+/// implementation.
+pub fn write_all(stream: &mut dyn io::Write, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match stream.write(buf) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(n) => buf = buf.get(n..).ok_or_else(out_of_bounds)?,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Convert a size read from a header to `usize`, failing on targets where it
+/// does not fit (e.g. 16-bit ones).
+pub fn to_usize(value: u64) -> error::Result<usize> {
+    usize::try_from(value).map_err(|_| error::Error::SizeOverflowsTarget { size: value })
+}
+
 pub const fn exact_log2(mut value: usize) -> Option<usize> {
     if value == 0 {
         return None;
@@ -27,6 +148,27 @@ pub const fn exact_log2(mut value: usize) -> Option<usize> {
 #[cfg(test)]
 mod test {
     use super::*;
+    #[test]
+    fn verify_to_usize() {
+        assert_eq!(to_usize(0x1000).unwrap(), 0x1000);
+        match to_usize(u64::MAX) {
+            Ok(v) => assert_eq!(v as u64, u64::MAX),
+            Err(error::Error::SizeOverflowsTarget { size }) => assert_eq!(size, u64::MAX),
+            Err(e) => panic!("Unexpected error: {:#?}", e),
+        }
+    }
+
+    #[test]
+    fn verify_read_byte() {
+        let mut input: &[u8] = &[1, 2];
+        assert_eq!(read_byte(&mut input).unwrap(), 1);
+        assert_eq!(read_byte(&mut input).unwrap(), 2);
+        assert_eq!(
+            read_byte(&mut input).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
     #[test]
     fn verify_exact_log2() {
         assert_eq!(Some(0), exact_log2(1 << 0));
@@ -45,4 +187,3 @@ mod test {
         assert_eq!(None, exact_log2((1 << 31) + 1));
     }
 }
-
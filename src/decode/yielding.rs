@@ -0,0 +1,220 @@
+//! Async decoding yielding to the executor between slices of work.
+
+use crate::decode::lzma::LiteralCoder;
+use crate::decode::stream::GenericStream;
+use crate::decode::util;
+use crate::decompress::Options;
+use crate::error;
+use crate::io::{self, Write};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Number of symbols decoded between calls to the [`YieldHook`] when
+/// [`Options::max_symbols_per_write`](crate::decompress::Options::max_symbols_per_write)
+/// is not set.
+const DEFAULT_SLICE_SYMBOLS: u32 = 1024;
+
+/// Decides when [`GenericStream::write_all_async`] yields to the executor.
+///
+/// Closures returning `bool` are hooks; [`YieldAfter`] yields based on
+/// elapsed time.
+pub trait YieldHook {
+    /// Called after each slice of decoding work; returning `true` makes the
+    /// decoder yield before the next slice.
+    fn should_yield(&mut self) -> bool;
+}
+
+impl<F: FnMut() -> bool> YieldHook for F {
+    fn should_yield(&mut self) -> bool {
+        self()
+    }
+}
+
+/// [`YieldHook`] yielding once `period` ticks have elapsed since the last
+/// yield, reading the time from an `Instant`-like clock returning ticks.
+///
+/// ```ignore
+/// use embassy_time::{Duration, Instant};
+/// use lzma_rs::decompress::YieldAfter;
+///
+/// // Let other tasks run at least every millisecond
+/// let mut hook = YieldAfter::new(
+///     || Instant::now().as_ticks(),
+///     Duration::from_millis(1).as_ticks(),
+/// );
+/// stream.write_all_async(&mut output, data, &mut hook).await?;
+/// ```
+#[derive(Debug)]
+pub struct YieldAfter<C: FnMut() -> u64> {
+    now: C,
+    period: u64,
+    last: u64,
+}
+
+impl<C: FnMut() -> u64> YieldAfter<C> {
+    /// Create a hook yielding every `period` ticks of the `now` clock,
+    /// counted from now.
+    pub fn new(mut now: C, period: u64) -> Self {
+        let last = now();
+        Self { now, period, last }
+    }
+}
+
+impl<C: FnMut() -> u64> YieldHook for YieldAfter<C> {
+    fn should_yield(&mut self) -> bool {
+        let now = (self.now)();
+        if now.wrapping_sub(self.last) < self.period {
+            return false;
+        }
+        self.last = now;
+        true
+    }
+}
+
+/// Future returning `Pending` once, letting the executor run other tasks,
+/// like `embassy_futures::yield_now`.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless awaited"]
+pub struct YieldNow {
+    yielded: bool,
+}
+
+/// Yield to the executor once.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl<DICT, PROBS, WBUF> GenericStream<DICT, PROBS, WBUF>
+where
+    DICT: AsRef<[u8]> + AsMut<[u8]>,
+    PROBS: AsRef<[LiteralCoder]> + AsMut<[LiteralCoder]>,
+    WBUF: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Same as [`GenericStream::write_all`], but asking `hook` whether to
+    /// yield to the executor after every slice of
+    /// [`Options::max_symbols_per_write`](crate::decompress::Options::max_symbols_per_write)
+    /// symbols (1024 if not set), so that decompressing a large buffer does
+    /// not starve other tasks.
+    ///
+    /// ```
+    /// # #[cfg(feature = "std")]
+    /// # async fn run(data: &[u8]) -> lzma_rs::error::Result<Vec<u8>> {
+    /// use lzma_rs::decompress::Stream;
+    ///
+    /// let mut stream = Stream::<4096, 8>::new();
+    /// stream.reset();
+    /// let mut output = Vec::new();
+    /// // Yield after every slice
+    /// stream.write_all_async(&mut output, data, &mut || true).await?;
+    /// stream.finish(&mut output)?;
+    /// # Ok(output)
+    /// # }
+    /// ```
+    pub async fn write_all_async<H: YieldHook>(
+        &mut self,
+        output: &mut dyn Write,
+        mut buf: &[u8],
+        hook: &mut H,
+    ) -> error::Result<()> {
+        let options = Options {
+            max_symbols_per_write: Some(
+                self.options
+                    .max_symbols_per_write
+                    .unwrap_or(DEFAULT_SLICE_SYMBOLS),
+            ),
+            ..self.options
+        };
+        while !buf.is_empty() {
+            let n = self.write_with_options(output, buf, &options)?;
+            if n == 0 && !self.symbol_limit_reached() {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                )
+                .into());
+            }
+            buf = buf.get(n..).ok_or_else(util::out_of_bounds)?;
+            if hook.should_yield() {
+                yield_now().await;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+    use crate::decompress::Stream;
+    use std::task::Waker;
+
+    /// Run `future` to completion, returning its output and the number of
+    /// times it yielded.
+    fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+        let mut future = core::pin::pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut yields = 0;
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return (output, yields),
+                Poll::Pending => yields += 1,
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_all_async() {
+        let compressed = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+
+        let mut stream = Stream::<4096, 8>::new();
+        stream.reset();
+        let mut output = Vec::new();
+        let (res, yields) = block_on(stream.write_all_async(&mut output, compressed, &mut || true));
+        res.unwrap();
+        stream.finish(&mut output).unwrap();
+        assert_eq!(&output[..], &expected[..]);
+        assert!(yields > 10, "{} yields", yields);
+
+        stream.reset();
+        let mut output = Vec::new();
+        let (res, yields) =
+            block_on(stream.write_all_async(&mut output, compressed, &mut || false));
+        res.unwrap();
+        stream.finish(&mut output).unwrap();
+        assert_eq!(&output[..], &expected[..]);
+        assert_eq!(yields, 0);
+    }
+
+    #[test]
+    fn test_yield_after() {
+        let mut ticks = 0;
+        let mut hook = YieldAfter::new(
+            || {
+                ticks += 3;
+                ticks
+            },
+            10,
+        );
+        let yields: Vec<bool> = (0..8).map(|_| hook.should_yield()).collect();
+        // Clock at 6, 9, 12, 15 (yield), 18, 21, 24, 27 (yield)
+        assert_eq!(
+            yields,
+            [false, false, false, true, false, false, false, true]
+        );
+    }
+}
@@ -1,41 +1,83 @@
-use crate::compress::{Options, UnpackedSize};
+use crate::compress::{HeaderFormat, Options, Progress, UnpackedSize};
 use crate::encode::rangecoder;
 use crate::io;
+use crate::properties::LzmaProperties;
 use byteorder::LittleEndian;
 use io::WriteBytesExt;
 
+/// Number of input bytes between calls to the progress and cancel callbacks.
+const CALLBACK_INTERVAL: u64 = 1 << 16;
+
 pub struct Encoder<'a, W>
 where
     W: 'a + io::Write,
 {
     rangecoder: rangecoder::RangeEncoder<'a, W>,
-    literal_probs: [[u16; 0x300]; 8],
-    is_match: [u16; 4], // true = LZ, false = literal
+    literal_probs: Vec<[u16; 0x300]>,
+    is_match: [u16; 16], // true = LZ, false = literal
+    properties: LzmaProperties,
     unpacked_size: UnpackedSize,
+    header_len: u64,
+    progress: Option<&'a dyn Fn(Progress)>,
+    cancel: Option<&'a dyn Fn() -> bool>,
 }
 
-const LC: u32 = 3;
-const LP: u32 = 0;
-const PB: u32 = 2;
-
 impl<'a, W> Encoder<'a, W>
 where
     W: io::Write,
 {
-    pub fn from_stream(stream: &'a mut W, options: &Options) -> io::Result<Self> {
-        let dict_size = 4096;
+    pub fn from_stream(stream: &'a mut W, options: &Options<'a>) -> io::Result<Self> {
+        let properties = options.properties;
+        let header_len = match options.header {
+            HeaderFormat::Alone => Self::write_header(stream, options)?,
+            // Chunk headers of LZMA2 are written by the caller
+            HeaderFormat::Raw | HeaderFormat::Lzma2 => 0,
+        };
+
+        let encoder = Encoder {
+            rangecoder: rangecoder::RangeEncoder::new(stream),
+            literal_probs: vec![[0x400; 0x300]; properties.literal_coders()],
+            is_match: [0x400; 16],
+            properties,
+            unpacked_size: match options.unpacked_size {
+                // These sizes are written as the code of an unknown size, so
+                // the decoder needs the end marker
+                UnpackedSize::WriteToHeader(Some(0xFFFF_FFFF_FFFF_FFFF)) => {
+                    UnpackedSize::WriteToHeader(None)
+                }
+                UnpackedSize::WriteToHeader32(Some(0xFFFF_FFFF)) => {
+                    UnpackedSize::WriteToHeader32(None)
+                }
+                unpacked_size => unpacked_size,
+            },
+            header_len,
+            progress: options.progress,
+            cancel: options.cancel,
+        };
+
+        Ok(encoder)
+    }
+
+    /// Write the `.lzma` header and return its length.
+    fn write_header(stream: &mut W, options: &Options) -> io::Result<u64> {
+        let unpacked_size = match options.unpacked_size {
+            UnpackedSize::WriteToHeader(unpacked_size) => unpacked_size,
+            UnpackedSize::WriteToHeader32(unpacked_size) => unpacked_size.map(u64::from),
+            UnpackedSize::SkipWritingToHeader => None,
+        };
+        let dict_size = options.dict_size.resolve(unpacked_size);
 
         // Properties
-        let props = (LC + 9 * (LP + 5 * PB)) as u8;
-        lzma_info!("Properties {{ lc: {}, lp: {}, pb: {} }}", LC, LP, PB);
-        stream.write_u8(props)?;
+        let properties = options.properties;
+        lzma_info!("Properties {:?}", properties);
+        stream.write_u8(properties.to_byte())?;
 
         // Dictionary
         lzma_info!("Dict size: {}", dict_size);
         stream.write_u32::<LittleEndian>(dict_size)?;
 
         // Unpacked size
-        match &options.unpacked_size {
+        let header_len = match &options.unpacked_size {
             UnpackedSize::WriteToHeader(unpacked_size) => {
                 let value: u64 = match unpacked_size {
                     None => {
@@ -48,49 +90,82 @@ where
                     }
                 };
                 stream.write_u64::<LittleEndian>(value)?;
+                13
             }
-            UnpackedSize::SkipWritingToHeader => {}
-        };
-
-        let encoder = Encoder {
-            rangecoder: rangecoder::RangeEncoder::new(stream),
-            literal_probs: [[0x400; 0x300]; 8],
-            is_match: [0x400; 4],
-            unpacked_size: options.unpacked_size,
+            UnpackedSize::WriteToHeader32(unpacked_size) => {
+                let value: u32 = match unpacked_size {
+                    None => {
+                        lzma_info!("Unpacked size: unknown");
+                        0xFFFF_FFFF
+                    }
+                    Some(x) => {
+                        lzma_info!("Unpacked size: {}", x);
+                        *x
+                    }
+                };
+                stream.write_u32::<LittleEndian>(value)?;
+                9
+            }
+            UnpackedSize::SkipWritingToHeader => 5,
         };
 
-        Ok(encoder)
+        Ok(header_len)
     }
 
+    #[allow(clippy::unbuffered_bytes)]
     pub fn process<R>(mut self, input: R) -> io::Result<()>
     where
         R: io::Read,
     {
         let mut prev_byte = 0u8;
         let mut input_len = 0;
+        let mut consumed = 0;
 
-        for (out_len, byte_result) in input.bytes().enumerate() {
+        // Counted in u64 as the input may exceed 4 GiB on 32-bit targets
+        for (out_len, byte_result) in (0_u64..).zip(input.bytes()) {
             let byte = byte_result?;
-            let pos_state = out_len & 3;
+            if out_len % CALLBACK_INTERVAL == 0 && out_len > 0 {
+                self.report_progress(out_len);
+                self.check_cancel()?;
+            }
+            let pos_state = out_len as usize & ((1 << self.properties.pb) - 1);
             input_len = out_len;
+            consumed = out_len + 1;
 
             // Literal
             self.rangecoder
                 .encode_bit(&mut self.is_match[pos_state], false)?;
 
-            self.encode_literal(byte, prev_byte)?;
+            self.encode_literal(byte, prev_byte, out_len)?;
             prev_byte = byte;
         }
 
-        self.finish(input_len + 1)
+        self.finish(input_len + 1)?;
+        self.report_progress(consumed);
+        Ok(())
     }
 
-    fn finish(&mut self, input_len: usize) -> io::Result<()> {
+    fn report_progress(&self, input_len: u64) {
+        if let Some(progress) = self.progress {
+            progress(Progress {
+                input_bytes: input_len,
+                output_bytes: self.header_len + self.rangecoder.written(),
+            });
+        }
+    }
+
+    fn check_cancel(&self) -> io::Result<()> {
+        check_cancel(self.cancel)
+    }
+
+    fn finish(&mut self, input_len: u64) -> io::Result<()> {
         match self.unpacked_size {
-            UnpackedSize::SkipWritingToHeader | UnpackedSize::WriteToHeader(Some(_)) => {}
-            UnpackedSize::WriteToHeader(None) => {
+            UnpackedSize::SkipWritingToHeader
+            | UnpackedSize::WriteToHeader(Some(_))
+            | UnpackedSize::WriteToHeader32(Some(_)) => {}
+            UnpackedSize::WriteToHeader(None) | UnpackedSize::WriteToHeader32(None) => {
                 // Write end-of-stream marker
-                let pos_state = input_len & 3;
+                let pos_state = input_len as usize & ((1 << self.properties.pb) - 1);
 
                 // Match
                 self.rangecoder
@@ -123,19 +198,27 @@ where
         self.rangecoder.finish()
     }
 
-    fn encode_literal(&mut self, byte: u8, prev_byte: u8) -> io::Result<()> {
+    fn encode_literal(&mut self, byte: u8, prev_byte: u8, pos: u64) -> io::Result<()> {
+        let pos = pos as usize;
         let prev_byte = prev_byte as usize;
+        let lc = self.properties.lc;
+        let lp = self.properties.lp;
 
-        let mut result: usize = 1;
-        let lit_state = prev_byte >> 5;
+        let lit_state = ((pos & ((1 << lp) - 1)) << lc) + (prev_byte >> (8 - lc));
         let probs = &mut self.literal_probs[lit_state];
+        self.rangecoder.encode_bit_tree(probs, 8, byte as u32)
+    }
+}
 
-        for i in 0..8 {
-            let bit = ((byte >> (7 - i)) & 1) != 0;
-            self.rangecoder.encode_bit(&mut probs[result], bit)?;
-            result = (result << 1) ^ (bit as usize);
+/// Poll the cancel callback of [`Options`].
+pub fn check_cancel(cancel: Option<&dyn Fn() -> bool>) -> io::Result<()> {
+    if let Some(cancel) = cancel {
+        if cancel() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "compression cancelled",
+            ));
         }
-
-        Ok(())
     }
+    Ok(())
 }
@@ -0,0 +1,66 @@
+use crate::decode::lzma::LzmaParams;
+use crate::decode::util;
+use crate::error;
+use crate::error::lzma::HeaderField;
+use crate::io;
+use crate::properties::LzmaProperties;
+use byteorder::{ByteOrder, LittleEndian};
+use core::convert::TryFrom;
+
+/// Copy the `.lzma` stream of `input` to `output`, patching the unpacked size
+/// and dictionary size of its 13-byte header without recompressing.
+///
+/// `None` keeps the value of the header. An unpacked size of `u64::MAX`
+/// marks it as unknown, which is only valid for a stream ending with an end
+/// marker. The compressed data is copied as is and is not checked against
+/// the new values: a dictionary smaller than the largest match distance or
+/// a wrong unpacked size make the output fail to decode.
+///
+/// ```
+/// let mut compressed = Vec::new();
+/// lzma_rs::lzma_compress(&b"Hello world"[..], &mut compressed)?;
+///
+/// let mut patched = Vec::new();
+/// lzma_rs::compress::rewrite_header(&compressed[..], &mut patched, Some(11), Some(4096))?;
+/// let params = lzma_rs::decompress::LzmaParams::read_header(
+///     &mut &patched[..],
+///     &lzma_rs::decompress::Options::default(),
+/// )?;
+/// assert_eq!(params.unpacked_size(), Some(11));
+/// assert_eq!(params.dict_size(), 4096);
+/// # Ok::<(), lzma_rs::error::Error>(())
+/// ```
+pub fn rewrite_header<R: io::BufRead, W: io::Write>(
+    mut input: R,
+    output: &mut W,
+    new_unpacked_size: Option<u64>,
+    new_dict_size: Option<u32>,
+) -> error::Result<()> {
+    let mut header = [0; 13];
+    let (props, rest) = header.split_at_mut(1);
+    let (dict_size, unpacked_size) = rest.split_at_mut(4);
+    let mut have = 0;
+    LzmaParams::read_header_field(&mut input, props, &mut have, 13, HeaderField::Properties)?;
+    LzmaProperties::try_from(props[0])?;
+    LzmaParams::read_header_field(&mut input, dict_size, &mut have, 13, HeaderField::DictSize)?;
+    LzmaParams::read_header_field(
+        &mut input,
+        unpacked_size,
+        &mut have,
+        13,
+        HeaderField::UnpackedSize,
+    )?;
+
+    if let Some(new_dict_size) = new_dict_size {
+        lzma_info!("Dict size: {}", new_dict_size);
+        LittleEndian::write_u32(dict_size, new_dict_size);
+    }
+    if let Some(new_unpacked_size) = new_unpacked_size {
+        lzma_info!("Unpacked size: {}", new_unpacked_size);
+        LittleEndian::write_u64(unpacked_size, new_unpacked_size);
+    }
+
+    output.write_all(&header)?;
+    util::copy_to_end(&mut input, output)?;
+    Ok(())
+}
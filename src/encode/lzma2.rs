@@ -0,0 +1,83 @@
+//! LZMA2 chunk writer on top of the LZMA encoder.
+
+use crate::compress::{HeaderFormat, Options, Progress, UnpackedSize};
+use crate::encode::dumbencoder::{self, Encoder};
+use crate::io;
+use byteorder::BigEndian;
+use io::{Read, WriteBytesExt};
+
+/// Uncompressed bytes per chunk. Small enough for the compressed data to fit
+/// in a chunk (64 KiB) even if each literal costs the maximum of ~55 bits.
+const CHUNK_SIZE: usize = 0x2000;
+
+/// Encode `input` as LZMA2 chunks. Every chunk resets the dictionary and
+/// the state, and is stored uncompressed if LZMA does not make it smaller.
+pub fn encode<R, W>(mut input: R, output: &mut W, options: &Options) -> io::Result<()>
+where
+    R: io::BufRead,
+    W: io::Write,
+{
+    let properties = options.properties;
+    if properties.lc + properties.lp > 4 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "LZMA2 requires lc + lp <= 4",
+        ));
+    }
+    let chunk_options = Options {
+        unpacked_size: UnpackedSize::SkipWritingToHeader,
+        header: HeaderFormat::Raw,
+        progress: None,
+        cancel: None,
+        ..*options
+    };
+
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    let mut packed = Vec::new();
+    let mut progress = Progress {
+        input_bytes: 0,
+        output_bytes: 0,
+    };
+    loop {
+        dumbencoder::check_cancel(options.cancel)?;
+        chunk.clear();
+        (&mut input)
+            .take(CHUNK_SIZE as u64)
+            .read_to_end(&mut chunk)?;
+        if chunk.is_empty() {
+            break;
+        }
+
+        packed.clear();
+        Encoder::from_stream(&mut packed, &chunk_options)?.process(chunk.as_slice())?;
+        let unpacked_len = chunk.len() - 1;
+        if packed.len() < chunk.len() {
+            // LZMA chunk resetting the dictionary, the state and setting new
+            // properties
+            output.write_u8(0xE0 | (unpacked_len >> 16) as u8)?;
+            output.write_u16::<BigEndian>(unpacked_len as u16)?;
+            output.write_u16::<BigEndian>((packed.len() - 1) as u16)?;
+            output.write_u8(properties.to_byte())?;
+            output.write_all(&packed)?;
+            progress.output_bytes += 6 + packed.len() as u64;
+        } else {
+            // Uncompressed chunk resetting the dictionary
+            output.write_u8(0x01)?;
+            output.write_u16::<BigEndian>(unpacked_len as u16)?;
+            output.write_all(&chunk)?;
+            progress.output_bytes += 3 + chunk.len() as u64;
+        }
+        progress.input_bytes += chunk.len() as u64;
+        if let Some(callback) = options.progress {
+            callback(progress);
+        }
+    }
+
+    // End of data
+    output.write_u8(0x00)?;
+    progress.output_bytes += 1;
+    if let Some(callback) = options.progress {
+        callback(progress);
+    }
+    Ok(())
+}
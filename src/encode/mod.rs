@@ -1,5 +1,15 @@
 //! Encoding logic.
 
+#[cfg(feature = "encoder")]
 pub mod dumbencoder;
+#[cfg(feature = "encoder")]
+pub mod header;
+#[cfg(feature = "encoder")]
+pub mod lzma2;
+#[cfg(feature = "encoder")]
 pub mod options;
-mod rangecoder;
+pub mod rangecoder;
+#[cfg(feature = "telemetry-encoder")]
+pub mod session;
+#[cfg(feature = "telemetry-encoder")]
+pub mod telemetry;
@@ -1,21 +1,164 @@
+use crate::properties::LzmaProperties;
+use core::fmt;
+
 /// Options for the `lzma_compress` function
-#[derive(Clone, Copy, Debug, Default)]
-pub struct Options {
+///
+/// The compressed output only depends on the input data and these options:
+/// compressing the same data twice gives bit-for-bit identical results.
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options<'a> {
     /// Defines whether the unpacked size should be written to the header.
     /// The default is
     /// [`UnpackedSize::WriteToHeader(None)`](enum.encode.UnpackedSize.html#variant.WriteValueToHeader)
     pub unpacked_size: UnpackedSize,
+    /// Properties written to the header and used for encoding.
+    /// The default is [`LzmaProperties::DEFAULT`].
+    pub properties: LzmaProperties,
+    /// Format of the output. The default is [`HeaderFormat::Alone`].
+    pub header: HeaderFormat,
+    /// Dictionary size written to the `.lzma` header, i.e. the window that
+    /// decoders reserve for the data. The default is
+    /// [`DictSize::Fixed(4096)`](DictSize::Fixed).
+    pub dict_size: DictSize,
+    /// Keep the output reproducible across versions of this crate, for
+    /// reproducible builds. Disables any heuristic that would make the output
+    /// depend on more than the input and options, or change it between
+    /// releases. The current encoder uses no such heuristic.
+    pub deterministic: bool,
+    /// Called regularly during compression, and once at the end, with the
+    /// number of input bytes consumed and output bytes written so far.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub progress: Option<&'a dyn Fn(Progress)>,
+    /// Polled regularly during compression; returning `true` aborts it with
+    /// an [`crate::io::ErrorKind::Other`] error. The output is then incomplete.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cancel: Option<&'a dyn Fn() -> bool>,
+}
+
+impl<'a> fmt::Debug for Options<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Options")
+            .field("unpacked_size", &self.unpacked_size)
+            .field("properties", &self.properties)
+            .field("header", &self.header)
+            .field("dict_size", &self.dict_size)
+            .field("deterministic", &self.deterministic)
+            .field("progress", &self.progress.is_some())
+            .field("cancel", &self.cancel.is_some())
+            .finish()
+    }
+}
+
+/// Container format of the compressed data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HeaderFormat {
+    /// LZMA data preceded by the `.lzma` ("LZMA alone") header: properties
+    /// byte, dictionary size and unpacked size (see [`UnpackedSize`]).
+    #[default]
+    Alone,
+    /// Raw LZMA data without any header, for containers storing the
+    /// properties themselves (e.g. zip method 14, 7z). The
+    /// [`Options::unpacked_size`] is not written; it only decides whether an
+    /// end-of-stream marker is written (it is for an unknown size).
+    Raw,
+    /// Raw LZMA2 data, i.e. a sequence of chunks followed by the end marker,
+    /// without the dictionary size property byte which is stored by the
+    /// container (e.g. `.xz`, 7z). The [`Options::unpacked_size`] is ignored.
+    Lzma2,
+}
+
+/// Dictionary size written to the header, see [`Options::dict_size`].
+///
+/// The encoder never refers to data further back than the dictionary size, so
+/// any value is valid; smaller ones let decoders get away with smaller
+/// windows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DictSize {
+    /// Write the given value.
+    Fixed(u32),
+    /// Write the unpacked size rounded up to the next power of two, at least
+    /// 4096 and at most `max` (or 4096 if `max` is smaller). When the
+    /// unpacked size isn't written to the header (see [`UnpackedSize`]),
+    /// `max` is written.
+    Auto {
+        /// Upper bound of the dictionary size.
+        max: u32,
+    },
+}
+
+impl DictSize {
+    /// Dictionary size to write for data of `unpacked_size` bytes, if known.
+    ///
+    /// ```
+    /// use lzma_rs::compress::DictSize;
+    ///
+    /// let auto = DictSize::Auto { max: 1 << 24 };
+    /// assert_eq!(auto.resolve(Some(10)), 4096);
+    /// assert_eq!(auto.resolve(Some(100_000)), 1 << 17);
+    /// assert_eq!(auto.resolve(Some(1 << 30)), 1 << 24);
+    /// assert_eq!(auto.resolve(None), 1 << 24);
+    /// ```
+    pub fn resolve(&self, unpacked_size: Option<u64>) -> u32 {
+        match *self {
+            DictSize::Fixed(dict_size) => dict_size,
+            DictSize::Auto { max } => {
+                let max = u64::from(max).max(MIN_DICT_SIZE);
+                let dict_size = match unpacked_size {
+                    Some(unpacked_size) => unpacked_size
+                        .clamp(MIN_DICT_SIZE, max)
+                        .next_power_of_two()
+                        .min(max),
+                    None => max,
+                };
+                // At most `max`, or MIN_DICT_SIZE, so it fits
+                dict_size as u32
+            }
+        }
+    }
+}
+
+impl Default for DictSize {
+    fn default() -> DictSize {
+        DictSize::Fixed(MIN_DICT_SIZE as u32)
+    }
+}
+
+/// Smallest dictionary size picked by [`DictSize::Auto`], which is also the
+/// smallest one decoders use.
+const MIN_DICT_SIZE: u64 = 4096;
+
+/// Compression progress, see [`Options::progress`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Progress {
+    /// Number of bytes read from the input.
+    pub input_bytes: u64,
+    /// Number of bytes written to the output, header included.
+    pub output_bytes: u64,
 }
 
 /// Alternatives for handling unpacked size
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UnpackedSize {
     /// If the value is `Some(u64)`, write the provided u64 value to the header.
     /// There is currently no check in place that verifies that this is the actual number of bytes
     /// provided by the input stream.
     /// If the value is `None`, write the special `0xFFFF_FFFF_FFFF_FFFF` code to the header,
-    /// indicating that the unpacked size is unknown.
+    /// indicating that the unpacked size is unknown. `Some(0xFFFF_FFFF_FFFF_FFFF)` is the same
+    /// code, so it is handled like `None`.
     WriteToHeader(Option<u64>),
+    /// Like [`UnpackedSize::WriteToHeader`], but write the value on 4 bytes only, as expected by
+    /// some legacy tools. If the value is `None`, write the special `0xFFFF_FFFF` code to the
+    /// header. `Some(0xFFFF_FFFF)` is the same code, so it is handled like `None`.
+    WriteToHeader32(Option<u32>),
     /// Do not write anything to the header. The unpacked size needs to be stored elsewhere and
     /// provided when reading the file. Note that this is a non-standard way of writing LZMA data,
     /// but is used by certain libraries such as
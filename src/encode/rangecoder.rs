@@ -1,40 +1,95 @@
 use crate::io;
+#[cfg(feature = "telemetry-encoder")]
+use core::{iter, slice};
 use io::WriteBytesExt;
 
+/// Binary range encoder writing to `stream`, the counterpart of
+/// [`RangeDecoder`](crate::rangecoder::RangeDecoder).
+///
+/// Call [`RangeEncoder::finish`] once done to write out the last bytes.
 pub struct RangeEncoder<'a, W>
 where
-    W: 'a + io::Write,
+    W: 'a + io::Write + ?Sized,
 {
     stream: &'a mut W,
     range: u32,
     low: u64,
     cache: u8,
     cachesz: u32,
+    written: u64,
+}
+
+/// State of a [`RangeEncoder`] between two borrows of its output stream.
+#[derive(Clone, Copy, Debug)]
+pub struct RangeEncoderState {
+    range: u32,
+    low: u64,
+    cache: u8,
+    cachesz: u32,
+    written: u64,
+}
+
+impl RangeEncoderState {
+    /// State of a new encoder.
+    pub const fn new() -> Self {
+        Self {
+            range: 0xFFFF_FFFF,
+            low: 0,
+            cache: 0,
+            cachesz: 1,
+            written: 0,
+        }
+    }
+}
+
+impl Default for RangeEncoderState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a, W> RangeEncoder<'a, W>
 where
-    W: io::Write,
+    W: io::Write + ?Sized,
 {
-    #[allow(clippy::let_and_return)]
+    /// Start encoding to `stream`.
     pub fn new(stream: &'a mut W) -> Self {
+        Self::from_state(stream, RangeEncoderState::new())
+    }
+
+    /// Resume encoding to `stream` from a saved `state`.
+    #[allow(clippy::let_and_return)]
+    pub fn from_state(stream: &'a mut W, state: RangeEncoderState) -> Self {
         let enc = Self {
             stream,
-            range: 0xFFFF_FFFF,
-            low: 0,
-            cache: 0,
-            cachesz: 1,
+            range: state.range,
+            low: state.low,
+            cache: state.cache,
+            cachesz: state.cachesz,
+            written: state.written,
         };
-        lzma_debug!("0 {{ range: {:08x}, low: {:010x} }}", enc.range, enc.low);
+        lzma_debug!("0 {{ range: {:08x}, low: {:010x} }}", enc.range, enc.low);
         enc
     }
 
+    /// Save the state, to resume with [`RangeEncoder::from_state`].
+    pub fn state(&self) -> RangeEncoderState {
+        RangeEncoderState {
+            range: self.range,
+            low: self.low,
+            cache: self.cache,
+            cachesz: self.cachesz,
+            written: self.written,
+        }
+    }
+
     fn write_low(&mut self) -> io::Result<()> {
         if self.low < 0xFF00_0000 || self.low > 0xFFFF_FFFF {
             let mut tmp = self.cache;
             loop {
                 let byte = tmp.wrapping_add((self.low >> 32) as u8);
                 self.stream.write_u8(byte)?;
+                self.written += 1;
                 lzma_debug!("> byte: {:02x}", byte);
                 tmp = 0xFF;
                 self.cachesz -= 1;
@@ -50,6 +105,12 @@ where
         Ok(())
     }
 
+    /// Number of bytes written to the stream so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Write out the pending bytes. Further bits must not be encoded.
     pub fn finish(&mut self) -> io::Result<()> {
         for _ in 0..5 {
             self.write_low()?;
@@ -82,6 +143,8 @@ where
         Ok(())
     }
 
+    /// Encode `bit` with the adaptive probability `prob` of it being `0`,
+    /// and update `prob`.
     pub fn encode_bit(&mut self, prob: &mut u16, bit: bool) -> io::Result<()> {
         let bound: u32 = (self.range >> 11) * (*prob as u32);
         lzma_trace!(
@@ -102,4 +165,137 @@ where
 
         self.normalize()
     }
+
+    /// Encode the `count` low bits of `value` with fixed probabilities, most
+    /// significant bit first.
+    pub fn encode_direct_bits(&mut self, value: u32, count: usize) -> io::Result<()> {
+        for i in (0..count).rev() {
+            self.range >>= 1;
+            if (value >> i) & 1 != 0 {
+                self.low += self.range as u64;
+            }
+            self.normalize()?;
+        }
+        Ok(())
+    }
+
+    /// Encode the `num_bits` low bits of `value` with the bit tree `probs`,
+    /// most significant bit first.
+    pub fn encode_bit_tree(
+        &mut self,
+        probs: &mut [u16],
+        num_bits: usize,
+        value: u32,
+    ) -> io::Result<()> {
+        let mut tmp = 1;
+        for i in (0..num_bits).rev() {
+            let bit = (value >> i) & 1 != 0;
+            self.encode_bit(&mut probs[tmp], bit)?;
+            tmp = (tmp << 1) | bit as usize;
+        }
+        Ok(())
+    }
+
+    /// Encode the `num_bits` low bits of `value` with the bit tree starting
+    /// at `probs[offset]`, least significant bit first.
+    pub fn encode_reverse_bit_tree(
+        &mut self,
+        probs: &mut [u16],
+        offset: usize,
+        num_bits: usize,
+        value: u32,
+    ) -> io::Result<()> {
+        let mut tmp = 1;
+        for i in 0..num_bits {
+            let bit = (value >> i) & 1 != 0;
+            self.encode_bit(&mut probs[offset + tmp], bit)?;
+            tmp = (tmp << 1) | bit as usize;
+        }
+        Ok(())
+    }
+}
+
+/// Encoder for match lengths, see
+/// [`LenDecoder`](crate::rangecoder::LenDecoder).
+#[derive(Clone, Copy, Debug)]
+pub struct LenEncoder {
+    choice: u16,
+    choice2: u16,
+    low_coder: [[u16; 8]; 16],
+    mid_coder: [[u16; 8]; 16],
+    high_coder: [u16; 256],
+}
+
+impl<'a, W> core::fmt::Debug for RangeEncoder<'a, W>
+where
+    W: io::Write + ?Sized,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("RangeEncoder")
+            .field("range", &self.range)
+            .field("low", &self.low)
+            .field("written", &self.written)
+            .finish()
+    }
+}
+
+impl LenEncoder {
+    /// Create a length encoder with all probabilities set to one half.
+    pub const fn new() -> Self {
+        Self {
+            choice: 0x400,
+            choice2: 0x400,
+            low_coder: [[0x400; 8]; 16],
+            mid_coder: [[0x400; 8]; 16],
+            high_coder: [0x400; 256],
+        }
+    }
+
+    /// Probabilities in the order of a model snapshot, see
+    /// [`crate::decode::model`].
+    #[cfg(feature = "telemetry-encoder")]
+    pub(crate) fn model(&self) -> impl Iterator<Item = &[u16]> {
+        iter::once(slice::from_ref(&self.choice))
+            .chain(iter::once(slice::from_ref(&self.choice2)))
+            .chain(self.low_coder.iter().map(|v| &v[..]))
+            .chain(self.mid_coder.iter().map(|v| &v[..]))
+            .chain(iter::once(&self.high_coder[..]))
+    }
+
+    #[cfg(feature = "telemetry-encoder")]
+    pub(crate) fn model_mut(&mut self) -> impl Iterator<Item = &mut [u16]> {
+        iter::once(slice::from_mut(&mut self.choice))
+            .chain(iter::once(slice::from_mut(&mut self.choice2)))
+            .chain(self.low_coder.iter_mut().map(|v| &mut v[..]))
+            .chain(self.mid_coder.iter_mut().map(|v| &mut v[..]))
+            .chain(iter::once(&mut self.high_coder[..]))
+    }
+
+    /// Encode `len`, the match length minus 2, which must be below 272.
+    pub fn encode<W: io::Write + ?Sized>(
+        &mut self,
+        rangecoder: &mut RangeEncoder<W>,
+        pos_state: usize,
+        len: usize,
+    ) -> io::Result<()> {
+        let pos_state = pos_state & 0xF;
+        if len < 8 {
+            rangecoder.encode_bit(&mut self.choice, false)?;
+            rangecoder.encode_bit_tree(&mut self.low_coder[pos_state], 3, len as u32)
+        } else if len < 16 {
+            rangecoder.encode_bit(&mut self.choice, true)?;
+            rangecoder.encode_bit(&mut self.choice2, false)?;
+            rangecoder.encode_bit_tree(&mut self.mid_coder[pos_state], 3, (len - 8) as u32)
+        } else {
+            rangecoder.encode_bit(&mut self.choice, true)?;
+            rangecoder.encode_bit(&mut self.choice2, true)?;
+            rangecoder.encode_bit_tree(&mut self.high_coder, 8, (len - 16) as u32)
+        }
+    }
+}
+
+impl Default for LenEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -0,0 +1,147 @@
+//! Compression of a sequence of messages sharing one LZMA stream.
+
+use crate::decode::model;
+use crate::encode::telemetry::TelemetryEncoder;
+use crate::error;
+use crate::io;
+use crate::properties::LzmaProperties;
+use core::fmt;
+
+/// Compresses a sequence of short messages, e.g. telemetry records sent one
+/// by one, keeping the window and probabilities of the encoder from one
+/// message to the next, like zlib's `Z_SYNC_FLUSH`. Messages similar to the
+/// previous ones compress much better than on their own, and each one can
+/// be decoded as soon as it is received, with a
+/// [`DecompressSession`](crate::decompress::DecompressSession).
+///
+/// Each compressed message holds the length of the message as a LEB128
+/// varint (one byte below 128 bytes) followed by its range-coded data; there
+/// is no `.lzma` header, so both ends must agree on
+/// [`CompressSession::properties`] and [`CompressSession::dict_size`]. The
+/// messages must be decoded in order and none may be lost: after a lost
+/// message, both ends need a [`CompressSession::reset`].
+///
+/// `WINDOW` and `PROBS_MEM_LIMIT` are those of the underlying
+/// [`TelemetryEncoder`].
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # {
+/// use lzma_rs::compress::CompressSession;
+/// use lzma_rs::decompress::DecompressSession;
+///
+/// let mut encoder = CompressSession::<2048, 8>::new();
+/// let mut decoder = DecompressSession::<4096, 8>::new();
+/// decoder.start(encoder.properties(), encoder.dict_size())?;
+///
+/// for seq in 0..10 {
+///     let record = format!("{{\"seq\":{},\"temp\":21.5,\"status\":\"ok\"}}", seq);
+///     let mut message = Vec::new();
+///     encoder.compress(&mut message, record.as_bytes())?;
+///     if seq > 0 {
+///         assert!(message.len() < record.len() / 2);
+///     }
+///
+///     let mut decompressed = Vec::new();
+///     decoder.decompress(&message, &mut decompressed)?;
+///     assert_eq!(decompressed, record.as_bytes());
+/// }
+/// # }
+/// # Ok::<(), lzma_rs::error::Error>(())
+/// ```
+pub struct CompressSession<const WINDOW: usize, const PROBS_MEM_LIMIT: usize> {
+    encoder: TelemetryEncoder<WINDOW, PROBS_MEM_LIMIT>,
+}
+
+impl<const WINDOW: usize, const PROBS_MEM_LIMIT: usize> CompressSession<WINDOW, PROBS_MEM_LIMIT> {
+    /// Create a session with the properties of [`TelemetryEncoder::new`].
+    pub const fn new() -> Self {
+        Self {
+            encoder: TelemetryEncoder::new(),
+        }
+    }
+
+    /// Use `properties` and start a new session.
+    ///
+    /// Fails if they are invalid, or with
+    /// [`error::Error::ProbabilitiesBufferTooSmall`] if they need more
+    /// literal coders than `PROBS_MEM_LIMIT`.
+    pub fn set_properties(&mut self, properties: LzmaProperties) -> error::Result<()> {
+        self.encoder.set_properties(properties)
+    }
+
+    /// Properties used for encoding, to give to
+    /// [`DecompressSession::start`](crate::decompress::DecompressSession::start).
+    pub fn properties(&self) -> LzmaProperties {
+        self.encoder.properties()
+    }
+
+    /// Dictionary size needed to decode the session, to give to
+    /// [`DecompressSession::start`](crate::decompress::DecompressSession::start).
+    pub fn dict_size(&self) -> u32 {
+        self.encoder.dict_size() as u32
+    }
+
+    /// Forget the previous messages and start a new session. Needed after an
+    /// error, as the output may then be partially written.
+    pub fn reset(&mut self) {
+        self.encoder.reset();
+    }
+
+    /// Compress `message` and write it to `output`.
+    pub fn compress(&mut self, output: &mut dyn io::Write, message: &[u8]) -> io::Result<()> {
+        let mut len = message.len() as u64;
+        loop {
+            let byte = (len & 0x7F) as u8;
+            len >>= 7;
+            if len == 0 {
+                output.write_all(&[byte])?;
+                break;
+            }
+            output.write_all(&[byte | 0x80])?;
+        }
+        self.encoder.write_data(output, message)?;
+        self.encoder.sync_flush(output)
+    }
+
+    /// Write the probability model of the session to `output`, e.g. after
+    /// compressing representative messages on a host, to warm start
+    /// sessions on devices. See
+    /// [`DecompressSession::save_model`](crate::decompress::DecompressSession::save_model)
+    /// for the content of the snapshot.
+    pub fn save_model(&self, output: &mut dyn io::Write) -> io::Result<()> {
+        model::save(self.encoder.model(), output)
+    }
+
+    /// Start a new session, with the probabilities of `snapshot` instead of
+    /// the default ones. The decompressing side must start from the same
+    /// snapshot, with
+    /// [`DecompressSession::load_model`](crate::decompress::DecompressSession::load_model).
+    ///
+    /// Fails with [`error::Error::ModelError`] if `snapshot` is not a model
+    /// for [`CompressSession::properties`], leaving the default
+    /// probabilities.
+    pub fn load_model(&mut self, snapshot: &[u8]) -> error::Result<()> {
+        self.encoder.reset();
+        let properties = self.encoder.properties();
+        model::load(self.encoder.model_mut(), properties, snapshot)
+    }
+}
+
+impl<const WINDOW: usize, const PROBS_MEM_LIMIT: usize> Default
+    for CompressSession<WINDOW, PROBS_MEM_LIMIT>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const WINDOW: usize, const PROBS_MEM_LIMIT: usize> fmt::Debug
+    for CompressSession<WINDOW, PROBS_MEM_LIMIT>
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("CompressSession")
+            .field("encoder", &self.encoder)
+            .finish()
+    }
+}
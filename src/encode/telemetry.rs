@@ -0,0 +1,834 @@
+//! Bounded-memory streaming encoder.
+
+use crate::encode::rangecoder::{LenEncoder, RangeEncoder, RangeEncoderState};
+use crate::error;
+use crate::io;
+use crate::properties::LzmaProperties;
+use byteorder::LittleEndian;
+use core::fmt;
+use core::iter;
+use io::WriteBytesExt;
+
+const MATCH_LEN_MIN: usize = 2;
+const MATCH_LEN_MAX: usize = 273;
+/// Smallest window: the history kept when sliding the window plus the
+/// lookahead needed for the longest match must fit in it.
+const MIN_WINDOW: usize = 1024;
+/// Smallest dictionary size allowed in the header.
+const MIN_DICT_SIZE: usize = 4096;
+const HASH_SIZE: usize = 256;
+
+/// Streaming LZMA encoder using a fixed amount of memory, for `no_std`
+/// targets compressing their own data (logs, telemetry) before sending it.
+///
+/// - `WINDOW` is the size of the input buffer, at least 1024 bytes. Matches
+///   are searched in the last `WINDOW / 2` bytes
+/// - `PROBS_MEM_LIMIT` is the number of literal coders (`0x300`
+///   probabilities each) to reserve, `1 << (lc + lp)` must not exceed it
+///
+/// Each position is checked against the four most recent match distances
+/// and against a single candidate from a small hash table, so compression
+/// is much weaker than the host-side encoders of xz or 7-Zip, but much
+/// better than literals only on repetitive data. The output is a standard
+/// `.lzma` stream with an end marker and an unknown unpacked size, which any
+/// LZMA decoder accepts.
+///
+/// `TelemetryEncoder<2048, 1>` uses under 8 KiB, and [`TelemetryEncoder::new`]
+/// is `const` so it can be placed in a `static`:
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # {
+/// use lzma_rs::compress::TelemetryEncoder;
+///
+/// assert!(core::mem::size_of::<TelemetryEncoder<2048, 1>>() < 8 * 1024);
+///
+/// let mut encoder = TelemetryEncoder::<2048, 1>::new();
+/// let mut compressed = Vec::new();
+/// for sample in 0..100_u32 {
+///     let record = [b'T', (sample % 4) as u8, 0, 0x2A];
+///     encoder.write(&mut compressed, &record).unwrap();
+/// }
+/// encoder.finish(&mut compressed).unwrap();
+/// assert!(compressed.len() < 100);
+/// # }
+/// ```
+pub type TelemetryEncoder<const WINDOW: usize, const PROBS_MEM_LIMIT: usize> =
+    GenericTelemetryEncoder<[u8; WINDOW], PROBS_MEM_LIMIT>;
+
+/// [`TelemetryEncoder`] with a window borrowed from the caller, of at least
+/// 1024 bytes; smaller windows make [`GenericTelemetryEncoder::write`] fail.
+///
+/// The window can be handed over to a
+/// [`BorrowedDictStream`](crate::decompress::BorrowedDictStream) or a
+/// [`DynStream`](crate::decompress::DynStream) and back with `take_buffer`
+/// and `give_buffer`, so that a device compressing data and later decoding
+/// it to check it needs a single buffer. The decoder needs a buffer of at
+/// least [`GenericTelemetryEncoder::dict_size`] bytes, which is the case for
+/// windows of 4096 bytes or more:
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # {
+/// use lzma_rs::compress::BorrowedWindowEncoder;
+///
+/// let mut buffer = [0; 4096];
+/// let mut encoder = BorrowedWindowEncoder::<1>::with_window(&mut buffer);
+/// assert_eq!(encoder.dict_size(), 4096);
+///
+/// let mut compressed = Vec::new();
+/// encoder.write(&mut compressed, b"boot ok\nboot ok\n").unwrap();
+/// encoder.finish(&mut compressed).unwrap();
+///
+/// // Lend the buffer to a decoder with `give_buffer`, then get it back
+/// let buffer = encoder.take_buffer();
+/// encoder.give_buffer(buffer);
+/// # }
+/// ```
+pub type BorrowedWindowEncoder<'a, const PROBS_MEM_LIMIT: usize> =
+    GenericTelemetryEncoder<&'a mut [u8], PROBS_MEM_LIMIT>;
+
+/// Bounded-memory encoder generic over the storage of its window; see
+/// [`TelemetryEncoder`] and [`BorrowedWindowEncoder`].
+pub struct GenericTelemetryEncoder<WIN, const PROBS_MEM_LIMIT: usize>
+where
+    WIN: AsRef<[u8]> + AsMut<[u8]>,
+{
+    window: WIN,
+    /// Number of bytes in `window`.
+    len: usize,
+    /// Position in `window` of the next byte to encode.
+    pos: usize,
+    /// Absolute position of `window[0]` in the stream.
+    base: u64,
+    /// Low 16 bits of the last position of each hash of 3 bytes.
+    hash: [u16; HASH_SIZE],
+    rangecoder: RangeEncoderState,
+    properties: LzmaProperties,
+    header_written: bool,
+    state: usize,
+    rep: [usize; 4],
+    literal_probs: [[u16; 0x300]; PROBS_MEM_LIMIT],
+    is_match: [u16; 192],
+    is_rep: [u16; 12],
+    is_rep_g0: [u16; 12],
+    is_rep_g1: [u16; 12],
+    is_rep_g2: [u16; 12],
+    is_rep_0long: [u16; 192],
+    pos_slot: [[u16; 64]; 4],
+    pos_special: [u16; 115],
+    align: [u16; 16],
+    len_encoder: LenEncoder,
+    rep_len_encoder: LenEncoder,
+}
+
+/// What to encode at the current position.
+#[derive(Clone, Copy)]
+enum Symbol {
+    Literal,
+    /// Match reusing `rep[index]`
+    Rep {
+        index: usize,
+        len: usize,
+    },
+    Match {
+        dist: usize,
+        len: usize,
+    },
+}
+
+impl<const WINDOW: usize, const PROBS_MEM_LIMIT: usize> TelemetryEncoder<WINDOW, PROBS_MEM_LIMIT> {
+    // Evaluated at compile time, so a bad `WINDOW` never fails at runtime
+    const WINDOW_OK: () = assert!(
+        WINDOW >= MIN_WINDOW,
+        "TelemetryEncoder needs WINDOW >= 1024"
+    );
+
+    /// Create an encoder using the largest `lc` allowed by `PROBS_MEM_LIMIT`,
+    /// `lp = 0` and `pb = 2`.
+    pub const fn new() -> Self {
+        let () = Self::WINDOW_OK;
+        Self::from_window([0; WINDOW])
+    }
+}
+
+impl<'a, const PROBS_MEM_LIMIT: usize> BorrowedWindowEncoder<'a, PROBS_MEM_LIMIT> {
+    /// Create an encoder using `window`, with the same properties as
+    /// [`TelemetryEncoder::new`]. The window does not need to be zeroed.
+    pub const fn with_window(window: &'a mut [u8]) -> Self {
+        Self::from_window(window)
+    }
+
+    /// Reset the encoder and return its window, e.g. to pass it to a
+    /// decoder. Data not yet given to [`GenericTelemetryEncoder::finish`] is
+    /// dropped. The encoder fails to write until it gets a window back with
+    /// [`BorrowedWindowEncoder::give_buffer`].
+    pub fn take_buffer(&mut self) -> &'a mut [u8] {
+        self.reset();
+        core::mem::take(&mut self.window)
+    }
+
+    /// Reset the encoder and use `window` for the next stream.
+    pub fn give_buffer(&mut self, window: &'a mut [u8]) {
+        self.reset();
+        self.window = window;
+    }
+}
+
+impl<WIN, const PROBS_MEM_LIMIT: usize> GenericTelemetryEncoder<WIN, PROBS_MEM_LIMIT>
+where
+    WIN: AsRef<[u8]> + AsMut<[u8]>,
+{
+    const PROBS_OK: () = assert!(
+        PROBS_MEM_LIMIT > 0,
+        "TelemetryEncoder needs PROBS_MEM_LIMIT > 0"
+    );
+
+    const fn from_window(window: WIN) -> Self {
+        let () = Self::PROBS_OK;
+        let mut lc = 0;
+        while lc < crate::properties::MAX_LC && (2 << lc) <= PROBS_MEM_LIMIT {
+            lc += 1;
+        }
+        Self {
+            window,
+            len: 0,
+            pos: 0,
+            base: 0,
+            hash: [0; HASH_SIZE],
+            rangecoder: RangeEncoderState::new(),
+            properties: LzmaProperties { lc, lp: 0, pb: 2 },
+            header_written: false,
+            state: 0,
+            rep: [0; 4],
+            literal_probs: [[0x400; 0x300]; PROBS_MEM_LIMIT],
+            is_match: [0x400; 192],
+            is_rep: [0x400; 12],
+            is_rep_g0: [0x400; 12],
+            is_rep_g1: [0x400; 12],
+            is_rep_g2: [0x400; 12],
+            is_rep_0long: [0x400; 192],
+            pos_slot: [[0x400; 64]; 4],
+            pos_special: [0x400; 115],
+            align: [0x400; 16],
+            len_encoder: LenEncoder::new(),
+            rep_len_encoder: LenEncoder::new(),
+        }
+    }
+
+    /// Use `properties` for the next stream, and reset the encoder.
+    ///
+    /// Fails if they are invalid, or with
+    /// [`error::Error::ProbabilitiesBufferTooSmall`] if they need more
+    /// literal coders than `PROBS_MEM_LIMIT`.
+    pub fn set_properties(&mut self, properties: LzmaProperties) -> error::Result<()> {
+        let properties = LzmaProperties::new(properties.lc, properties.lp, properties.pb)?;
+        let needed = properties.literal_coders();
+        if needed > PROBS_MEM_LIMIT {
+            return Err(error::Error::ProbabilitiesBufferTooSmall {
+                needed,
+                available: PROBS_MEM_LIMIT,
+            });
+        }
+        self.properties = properties;
+        self.reset();
+        Ok(())
+    }
+
+    /// Drop any buffered data and start a new stream. Needed after an error.
+    pub fn reset(&mut self) {
+        // Reset in place rather than building a new encoder on the stack
+        self.len = 0;
+        self.pos = 0;
+        self.base = 0;
+        self.hash = [0; HASH_SIZE];
+        self.rangecoder = RangeEncoderState::new();
+        self.header_written = false;
+        self.state = 0;
+        self.rep = [0; 4];
+        for probs in self.literal_probs.iter_mut() {
+            probs.fill(0x400);
+        }
+        self.is_match.fill(0x400);
+        self.is_rep.fill(0x400);
+        self.is_rep_g0.fill(0x400);
+        self.is_rep_g1.fill(0x400);
+        self.is_rep_g2.fill(0x400);
+        self.is_rep_0long.fill(0x400);
+        for probs in self.pos_slot.iter_mut() {
+            probs.fill(0x400);
+        }
+        self.pos_special.fill(0x400);
+        self.align.fill(0x400);
+        self.len_encoder = LenEncoder::new();
+        self.rep_len_encoder = LenEncoder::new();
+    }
+
+    /// Compress `data`, writing compressed data to `output` as the internal
+    /// buffer fills up. The `.lzma` header is written by the first call.
+    pub fn write(&mut self, output: &mut dyn io::Write, data: &[u8]) -> io::Result<()> {
+        self.write_header(output)?;
+        self.write_data(output, data)
+    }
+
+    /// Buffer `data`, encoding previous data to `output` to make room, without
+    /// writing the header.
+    pub(crate) fn write_data(
+        &mut self,
+        output: &mut dyn io::Write,
+        mut data: &[u8],
+    ) -> io::Result<()> {
+        if self.window.as_ref().len() < MIN_WINDOW {
+            return Err(window_too_small());
+        }
+        while !data.is_empty() {
+            let window_len = self.window.as_ref().len();
+            if self.len == window_len {
+                self.encode(output, false)?;
+                self.slide();
+            }
+            let n = core::cmp::min(window_len - self.len, data.len());
+            let (head, tail) = data.split_at(n);
+            self.window.as_mut()[self.len..self.len + n].copy_from_slice(head);
+            self.len += n;
+            data = tail;
+        }
+        Ok(())
+    }
+
+    /// Compress the buffered data and terminate the stream. The encoder is
+    /// reset afterwards, ready for a new stream.
+    pub fn finish(&mut self, output: &mut dyn io::Write) -> io::Result<()> {
+        self.write_header(output)?;
+        self.encode(output, true)?;
+        self.reset();
+        Ok(())
+    }
+
+    /// Encode the buffered data and flush the range coder, so that a decoder
+    /// given everything written so far can decode all of it. Unlike
+    /// [`GenericTelemetryEncoder::finish`], no end marker is written and the
+    /// window and probabilities are kept: data written next is still
+    /// compressed against the previous data, in a new range-coded block.
+    pub(crate) fn sync_flush(&mut self, output: &mut dyn io::Write) -> io::Result<()> {
+        let mut rangecoder = RangeEncoder::from_state(output, self.rangecoder);
+        let result = self
+            .encode_symbols(&mut rangecoder, true)
+            .and_then(|()| rangecoder.finish());
+        self.rangecoder = RangeEncoderState::new();
+        result
+    }
+
+    /// Probabilities in the order of a model snapshot, see
+    /// [`crate::decode::model`].
+    pub(crate) fn model(&self) -> impl Iterator<Item = &[u16]> {
+        let literal_coders = self.properties.literal_coders();
+        iter::once(&self.is_match[..])
+            .chain(iter::once(&self.is_rep[..]))
+            .chain(iter::once(&self.is_rep_g0[..]))
+            .chain(iter::once(&self.is_rep_g1[..]))
+            .chain(iter::once(&self.is_rep_g2[..]))
+            .chain(iter::once(&self.is_rep_0long[..]))
+            .chain(self.pos_slot.iter().map(|v| &v[..]))
+            .chain(iter::once(&self.pos_special[..]))
+            .chain(iter::once(&self.align[..]))
+            .chain(self.len_encoder.model())
+            .chain(self.rep_len_encoder.model())
+            .chain(
+                self.literal_probs
+                    .iter()
+                    .take(literal_coders)
+                    .map(|v| &v[..]),
+            )
+    }
+
+    pub(crate) fn model_mut(&mut self) -> impl Iterator<Item = &mut [u16]> {
+        let literal_coders = self.properties.literal_coders();
+        iter::once(&mut self.is_match[..])
+            .chain(iter::once(&mut self.is_rep[..]))
+            .chain(iter::once(&mut self.is_rep_g0[..]))
+            .chain(iter::once(&mut self.is_rep_g1[..]))
+            .chain(iter::once(&mut self.is_rep_g2[..]))
+            .chain(iter::once(&mut self.is_rep_0long[..]))
+            .chain(self.pos_slot.iter_mut().map(|v| &mut v[..]))
+            .chain(iter::once(&mut self.pos_special[..]))
+            .chain(iter::once(&mut self.align[..]))
+            .chain(self.len_encoder.model_mut())
+            .chain(self.rep_len_encoder.model_mut())
+            .chain(
+                self.literal_probs
+                    .iter_mut()
+                    .take(literal_coders)
+                    .map(|v| &mut v[..]),
+            )
+    }
+
+    /// Properties used for encoding.
+    pub(crate) fn properties(&self) -> LzmaProperties {
+        self.properties
+    }
+
+    /// Dictionary size written in the header, i.e. the dictionary buffer
+    /// needed to decode the output.
+    pub fn dict_size(&self) -> usize {
+        core::cmp::max(self.history(), MIN_DICT_SIZE)
+    }
+
+    fn history(&self) -> usize {
+        self.window.as_ref().len() / 2
+    }
+
+    fn write_header(&mut self, output: &mut dyn io::Write) -> io::Result<()> {
+        if self.header_written {
+            return Ok(());
+        }
+        if self.window.as_ref().len() < MIN_WINDOW {
+            return Err(window_too_small());
+        }
+        output.write_u8(self.properties.to_byte())?;
+        output.write_u32::<LittleEndian>(self.dict_size() as u32)?;
+        output.write_u64::<LittleEndian>(0xFFFF_FFFF_FFFF_FFFF)?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    /// Encode buffered data, keeping enough lookahead for the longest match
+    /// unless `flush` is set, in which case everything is encoded and the
+    /// stream is terminated.
+    fn encode(&mut self, output: &mut dyn io::Write, flush: bool) -> io::Result<()> {
+        let mut rangecoder = RangeEncoder::from_state(output, self.rangecoder);
+        let result = self.encode_with(&mut rangecoder, flush);
+        self.rangecoder = rangecoder.state();
+        result
+    }
+
+    fn encode_with(
+        &mut self,
+        rangecoder: &mut RangeEncoder<dyn io::Write + '_>,
+        flush: bool,
+    ) -> io::Result<()> {
+        self.encode_symbols(rangecoder, flush)?;
+        if flush {
+            self.encode_end_marker(rangecoder)?;
+            rangecoder.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Encode buffered data, all of it if `flush` is set.
+    fn encode_symbols(
+        &mut self,
+        rangecoder: &mut RangeEncoder<dyn io::Write + '_>,
+        flush: bool,
+    ) -> io::Result<()> {
+        while self.pos < self.len && (flush || self.len - self.pos >= MATCH_LEN_MAX) {
+            let advance = match self.find_symbol() {
+                Symbol::Literal => {
+                    self.encode_literal(rangecoder)?;
+                    1
+                }
+                Symbol::Rep { index, len } => {
+                    self.encode_rep(rangecoder, index, len)?;
+                    len
+                }
+                Symbol::Match { dist, len } => {
+                    self.encode_match(rangecoder, dist, len)?;
+                    len
+                }
+            };
+            for _ in 0..advance {
+                self.insert_hash();
+                self.pos += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep the last `history()` encoded bytes and the lookahead, to make room
+    /// for more input.
+    fn slide(&mut self) {
+        let shift = self.pos.saturating_sub(self.history());
+        self.window.as_mut().copy_within(shift..self.len, 0);
+        self.pos -= shift;
+        self.len -= shift;
+        self.base += shift as u64;
+    }
+
+    fn abs_pos(&self) -> u64 {
+        self.base + self.pos as u64
+    }
+
+    fn pos_state(&self) -> usize {
+        self.abs_pos() as usize & ((1 << self.properties.pb) - 1)
+    }
+
+    fn hash_index(&self) -> Option<usize> {
+        let bytes = self.window.as_ref()[..self.len].get(self.pos..self.pos + 3)?;
+        let value = u32::from(bytes[0]) | u32::from(bytes[1]) << 8 | u32::from(bytes[2]) << 16;
+        Some((value.wrapping_mul(0x9E37_79B1) >> 24) as usize % HASH_SIZE)
+    }
+
+    fn insert_hash(&mut self) {
+        if let Some(index) = self.hash_index() {
+            self.hash[index] = self.abs_pos() as u16;
+        }
+    }
+
+    /// Length of the match at distance `dist`, at most `max_len`.
+    fn match_len(&self, dist: usize, max_len: usize) -> usize {
+        let current = &self.window.as_ref()[self.pos..self.pos + max_len];
+        let previous = &self.window.as_ref()[self.pos - dist..];
+        current
+            .iter()
+            .zip(previous)
+            .take_while(|(a, b)| a == b)
+            .count()
+    }
+
+    fn find_symbol(&self) -> Symbol {
+        let max_len = core::cmp::min(self.len - self.pos, MATCH_LEN_MAX);
+        let mut best = Symbol::Literal;
+        let mut best_len = 1;
+        if max_len < MATCH_LEN_MIN {
+            return best;
+        }
+
+        for (index, &rep) in self.rep.iter().enumerate() {
+            let dist = rep + 1;
+            if dist <= self.pos {
+                let len = self.match_len(dist, max_len);
+                if len >= MATCH_LEN_MIN && len > best_len {
+                    best = Symbol::Rep { index, len };
+                    best_len = len;
+                }
+            }
+        }
+
+        if let Some(index) = self.hash_index() {
+            let dist = (self.abs_pos() as u16).wrapping_sub(self.hash[index]) as usize;
+            if dist > 0 && dist <= core::cmp::min(self.pos, self.history()) {
+                let len = self.match_len(dist, max_len);
+                // A new distance costs more than a repeated one
+                if len >= 3 && len > best_len + 1 {
+                    best = Symbol::Match { dist, len };
+                }
+            }
+        }
+        best
+    }
+
+    fn encode_literal(
+        &mut self,
+        rangecoder: &mut RangeEncoder<dyn io::Write + '_>,
+    ) -> io::Result<()> {
+        let pos_state = self.pos_state();
+        let LzmaProperties { lc, lp, .. } = self.properties;
+        rangecoder.encode_bit(&mut self.is_match[(self.state << 4) + pos_state], false)?;
+
+        let byte = self.window.as_ref()[self.pos];
+        let prev_byte = if self.pos > 0 {
+            self.window.as_ref()[self.pos - 1]
+        } else {
+            0
+        } as usize;
+        let lit_state =
+            ((self.abs_pos() as usize & ((1 << lp) - 1)) << lc) + (prev_byte >> (8 - lc));
+        let probs = &mut self.literal_probs[lit_state];
+
+        if self.state >= 7 {
+            let match_byte = self.window.as_ref()[self.pos - self.rep[0] - 1];
+            let mut result = 1;
+            let mut matched = true;
+            for i in (0..8).rev() {
+                let bit = (byte >> i) & 1;
+                if matched {
+                    let match_bit = (match_byte >> i) & 1;
+                    let index = ((1 + match_bit as usize) << 8) + result;
+                    rangecoder.encode_bit(&mut probs[index], bit != 0)?;
+                    matched = match_bit == bit;
+                } else {
+                    rangecoder.encode_bit(&mut probs[result], bit != 0)?;
+                }
+                result = (result << 1) | bit as usize;
+            }
+        } else {
+            rangecoder.encode_bit_tree(probs, 8, byte as u32)?;
+        }
+
+        self.state = if self.state < 4 {
+            0
+        } else if self.state < 10 {
+            self.state - 3
+        } else {
+            self.state - 6
+        };
+        Ok(())
+    }
+
+    fn encode_rep(
+        &mut self,
+        rangecoder: &mut RangeEncoder<dyn io::Write + '_>,
+        index: usize,
+        len: usize,
+    ) -> io::Result<()> {
+        let pos_state = self.pos_state();
+        let state = self.state;
+        rangecoder.encode_bit(&mut self.is_match[(state << 4) + pos_state], true)?;
+        rangecoder.encode_bit(&mut self.is_rep[state], true)?;
+        if index == 0 {
+            rangecoder.encode_bit(&mut self.is_rep_g0[state], false)?;
+            rangecoder.encode_bit(&mut self.is_rep_0long[(state << 4) + pos_state], true)?;
+        } else {
+            rangecoder.encode_bit(&mut self.is_rep_g0[state], true)?;
+            if index == 1 {
+                rangecoder.encode_bit(&mut self.is_rep_g1[state], false)?;
+            } else {
+                rangecoder.encode_bit(&mut self.is_rep_g1[state], true)?;
+                rangecoder.encode_bit(&mut self.is_rep_g2[state], index == 3)?;
+            }
+            // Move the distance to the front
+            self.rep[..=index].rotate_right(1);
+        }
+        self.rep_len_encoder
+            .encode(rangecoder, pos_state, len - MATCH_LEN_MIN)?;
+        self.state = if state < 7 { 8 } else { 11 };
+        Ok(())
+    }
+
+    fn encode_match(
+        &mut self,
+        rangecoder: &mut RangeEncoder<dyn io::Write + '_>,
+        dist: usize,
+        len: usize,
+    ) -> io::Result<()> {
+        let pos_state = self.pos_state();
+        self.encode_match_header(rangecoder, pos_state, len)?;
+        self.encode_distance(rangecoder, (dist - 1) as u32, len)?;
+        self.rep.rotate_right(1);
+        self.rep[0] = dist - 1;
+        Ok(())
+    }
+
+    /// The end marker is a match with distance `0xFFFF_FFFF`.
+    fn encode_end_marker(
+        &mut self,
+        rangecoder: &mut RangeEncoder<dyn io::Write + '_>,
+    ) -> io::Result<()> {
+        let pos_state = self.pos_state();
+        self.encode_match_header(rangecoder, pos_state, MATCH_LEN_MIN)?;
+        self.encode_distance(rangecoder, 0xFFFF_FFFF, MATCH_LEN_MIN)
+    }
+
+    fn encode_match_header(
+        &mut self,
+        rangecoder: &mut RangeEncoder<dyn io::Write + '_>,
+        pos_state: usize,
+        len: usize,
+    ) -> io::Result<()> {
+        let state = self.state;
+        rangecoder.encode_bit(&mut self.is_match[(state << 4) + pos_state], true)?;
+        rangecoder.encode_bit(&mut self.is_rep[state], false)?;
+        self.len_encoder
+            .encode(rangecoder, pos_state, len - MATCH_LEN_MIN)?;
+        self.state = if state < 7 { 7 } else { 10 };
+        Ok(())
+    }
+
+    /// Encode `dist`, the match distance minus 1.
+    fn encode_distance(
+        &mut self,
+        rangecoder: &mut RangeEncoder<dyn io::Write + '_>,
+        dist: u32,
+        len: usize,
+    ) -> io::Result<()> {
+        let len_state = core::cmp::min(len - MATCH_LEN_MIN, 3);
+        let pos_slot_probs = &mut self.pos_slot[len_state];
+        if dist < 4 {
+            return rangecoder.encode_bit_tree(pos_slot_probs, 6, dist);
+        }
+
+        let log2 = 31 - dist.leading_zeros();
+        let pos_slot = (log2 << 1) | ((dist >> (log2 - 1)) & 1);
+        rangecoder.encode_bit_tree(pos_slot_probs, 6, pos_slot)?;
+
+        let num_direct_bits = ((pos_slot >> 1) - 1) as usize;
+        let base = (2 | (pos_slot & 1)) << num_direct_bits;
+        let reduced = dist - base;
+        if pos_slot < 14 {
+            rangecoder.encode_reverse_bit_tree(
+                &mut self.pos_special,
+                (base - pos_slot) as usize,
+                num_direct_bits,
+                reduced,
+            )
+        } else {
+            rangecoder.encode_direct_bits(reduced >> 4, num_direct_bits - 4)?;
+            rangecoder.encode_reverse_bit_tree(&mut self.align, 0, 4, reduced & 0xF)
+        }
+    }
+}
+
+fn window_too_small() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "window buffer too small")
+}
+
+impl<const WINDOW: usize, const PROBS_MEM_LIMIT: usize> Default
+    for TelemetryEncoder<WINDOW, PROBS_MEM_LIMIT>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<WIN, const PROBS_MEM_LIMIT: usize> fmt::Debug for GenericTelemetryEncoder<WIN, PROBS_MEM_LIMIT>
+where
+    WIN: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("TelemetryEncoder")
+            .field("properties", &self.properties)
+            .field("buffered", &(self.len - self.pos))
+            .field("position", &self.abs_pos())
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    fn decompress(compressed: &[u8]) -> Vec<u8> {
+        let mut decomp = Vec::new();
+        crate::lzma_decompress::<_, _, 4096, 16>(&mut &compressed[..], &mut decomp).unwrap();
+        decomp
+    }
+
+    /// Sensor readings, repetitive but not trivially so.
+    fn telemetry(records: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..records {
+            data.extend_from_slice(
+                format!("id=7 seq={} temp={} status=OK\n", i, 200 + i % 13).as_bytes(),
+            );
+        }
+        data
+    }
+
+    fn round_trip<const WINDOW: usize, const PROBS_MEM_LIMIT: usize>(
+        encoder: &mut TelemetryEncoder<WINDOW, PROBS_MEM_LIMIT>,
+        data: &[u8],
+        chunk_size: usize,
+    ) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        for chunk in data.chunks(chunk_size) {
+            encoder.write(&mut compressed, chunk).unwrap();
+        }
+        encoder.finish(&mut compressed).unwrap();
+        assert_eq!(decompress(&compressed), data);
+        compressed
+    }
+
+    #[test]
+    fn test_telemetry_size() {
+        assert!(core::mem::size_of::<TelemetryEncoder<2048, 1>>() < 8 * 1024);
+    }
+
+    #[test]
+    fn test_telemetry_empty() {
+        let mut encoder = TelemetryEncoder::<1024, 1>::new();
+        round_trip(&mut encoder, b"", 1);
+    }
+
+    #[test]
+    fn test_telemetry_round_trip() {
+        let mut encoder = TelemetryEncoder::<2048, 1>::new();
+        let data = telemetry(1000);
+        assert!(data.len() > 8 * 2048);
+        for chunk_size in [1, 7, 300, 4096, data.len()] {
+            let compressed = round_trip(&mut encoder, &data, chunk_size);
+            assert!(compressed.len() < data.len() / 3);
+        }
+    }
+
+    #[test]
+    fn test_telemetry_incompressible() {
+        let mut seed = 1_u32;
+        let data: Vec<u8> = (0..10_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let mut encoder = TelemetryEncoder::<1024, 1>::new();
+        round_trip(&mut encoder, &data, 100);
+        let zeros = vec![0; 10_000];
+        round_trip(&mut encoder, &zeros, 100);
+    }
+
+    #[test]
+    fn test_telemetry_properties() {
+        let data = telemetry(200);
+        let mut encoder = TelemetryEncoder::<4096, 8>::new();
+        assert_eq!(encoder.properties, LzmaProperties::DEFAULT);
+        for (lc, lp, pb) in [(0, 0, 0), (3, 0, 2), (1, 2, 4), (0, 3, 1)] {
+            let properties = LzmaProperties { lc, lp, pb };
+            encoder.set_properties(properties).unwrap();
+            let compressed = round_trip(&mut encoder, &data, 64);
+            assert_eq!(compressed[0], properties.to_byte());
+        }
+        assert_eq!(
+            encoder.set_properties(LzmaProperties {
+                lc: 4,
+                lp: 0,
+                pb: 2
+            }),
+            Err(error::Error::ProbabilitiesBufferTooSmall {
+                needed: 16,
+                available: 8
+            })
+        );
+    }
+
+    #[test]
+    fn test_telemetry_borrowed_window_too_small() {
+        let mut buffer = [0; 1023];
+        let mut encoder = BorrowedWindowEncoder::<1>::with_window(&mut buffer);
+        let err = encoder.write(&mut Vec::new(), b"x").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        encoder.take_buffer();
+        assert!(encoder.finish(&mut Vec::new()).is_err());
+    }
+
+    /// Compress and verify with a single buffer shared by the encoder and
+    /// the decoder
+    #[cfg(feature = "stream")]
+    #[test]
+    fn test_telemetry_shared_buffer() {
+        use crate::decompress::BorrowedDictStream;
+
+        let mut buffer = vec![0; 4096];
+        let mut encoder = BorrowedWindowEncoder::<1>::with_window(&mut buffer);
+        let mut decoder = BorrowedDictStream::<1>::with_dict(&mut []);
+        let data = telemetry(500);
+        for _ in 0..2 {
+            let mut compressed = Vec::new();
+            encoder.write(&mut compressed, &data).unwrap();
+            encoder.finish(&mut compressed).unwrap();
+
+            decoder.give_buffer(encoder.take_buffer());
+            let mut decompressed = Vec::new();
+            decoder.write_all(&mut decompressed, &compressed).unwrap();
+            decoder.finish(&mut decompressed).unwrap();
+            assert_eq!(decompressed, data);
+            encoder.give_buffer(decoder.take_buffer());
+        }
+
+        // Without its buffer, the decoder rejects the stream
+        let mut compressed = Vec::new();
+        encoder.finish(&mut compressed).unwrap();
+        assert!(matches!(
+            decoder.write_all(&mut Vec::new(), &compressed),
+            Err(error::Error::DictionaryBufferTooSmall { .. })
+        ));
+    }
+}
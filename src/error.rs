@@ -5,7 +5,7 @@ use crate::io;
 use core::result;
 
 pub mod lzma {
-    #[derive(PartialEq, Debug)]
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
     pub enum LzmaError {
         MatchDistanceIsBeyondDictionarySize {
             distance: usize,
@@ -23,22 +23,76 @@ pub mod lzma {
             distance: usize,
             output_len: usize,
         },
-        /// `properties` must be < 255
-        InvalidHeader {
-            invalid_properties: u32,
+        /// Decoded match length is above the 273 bytes allowed by the format
+        MatchLengthOutOfRange {
+            len: usize,
+        },
+        /// Invalid LZMA properties; `value` is either the properties byte or
+        /// the out-of-range `lc`, `lp` or `pb` value
+        InvalidProperties {
+            value: u32,
+            reason: InvalidPropertiesReason,
+        },
+        /// Dictionary size in the header is below the 4 KiB minimum and
+        /// [`crate::decompress::Options::reject_small_dict`] is set
+        DictionaryTooSmall {
+            dict_size: u32,
+        },
+        /// Dictionary size in the header is above
+        /// [`crate::decompress::Options::max_dict_size`]
+        DictionaryTooLarge {
+            dict_size: u32,
+            max_dict_size: u32,
+        },
+        /// Unpacked size in the header is above the `available` bytes of the
+        /// output slice
+        UnpackedSizeTooLarge {
+            unpacked_size: u64,
+            available: usize,
         },
         EosFoundButMoreBytesAvailable,
+        /// Input follows the end of the stream and
+        /// [`crate::decompress::Options::trailing_bytes`] is
+        /// [`crate::decompress::TrailingBytes::Error`]
+        TrailingBytes,
         ProcessedDataDoesNotMatchUnpackedSize {
             unpacked_size: u64,
-            decompressed_data: usize,
+            decompressed_data: u64,
         },
-        /// When processing is done in `Finish`, standalone mode and `RangeDecoder` 
+        /// When processing is done in `Finish`, standalone mode and `RangeDecoder`
         DataStreamIsTooShort,
+        /// Internal state machine is out of range (`state` must be < 12)
+        InvalidState {
+            state: usize,
+        },
+    }
+
+    /// Which of the LZMA properties is out of range
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    pub enum InvalidPropertiesReason {
+        /// `lc` must be <= 8
+        LcTooLarge,
+        /// `lp` must be <= 4
+        LpTooLarge,
+        /// `pb` must be <= 4, i.e. the properties byte must be < 225
+        PbTooLarge,
+    }
+
+    /// Field of the LZMA header
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    pub enum HeaderField {
+        /// Properties byte holding `lc`, `lp` and `pb`
+        Properties,
+        /// Dictionary size
+        DictSize,
+        /// Unpacked size, 8 bytes or 4 with
+        /// [`crate::decompress::UnpackedSize::ReadFromHeader32`]
+        UnpackedSize,
     }
 }
 
 pub mod stream {
-    #[derive(PartialEq, Debug)]
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
     pub enum StreamError {
         /// When `finish` is called and header parsing was never completed
         FailedToReadLzmaHeader,
@@ -48,6 +102,80 @@ pub mod stream {
     }
 }
 
+pub mod options {
+    /// Invalid [`crate::decompress::RawOptions`]
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    pub enum RawOptionsError {
+        /// `unpacked_size_mode` is not one of the `UNPACKED_SIZE_*` constants
+        UnknownUnpackedSizeMode { mode: u32 },
+        /// `flags` has bits set besides the `FLAG_*` and `TRAILING_BYTES_*`
+        /// constants
+        UnknownFlags { flags: u32 },
+    }
+
+    /// Inconsistent [`crate::decompress::Options`], see
+    /// [`crate::decompress::Options::validate`]
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    pub enum OptionsError {
+        /// `max_dict_size` is below the 4 KiB minimum dictionary size, so
+        /// that every header would be rejected
+        MaxDictSizeBelowMinimum { max_dict_size: u32 },
+        /// `max_symbols_per_write` is `Some(0)`, which would make no progress
+        ZeroSymbolsPerWrite,
+    }
+}
+
+pub mod lzma2 {
+    /// Invalid chunk in raw LZMA2 data, see `decompress::Lzma2Stream`
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    pub enum Lzma2Error {
+        /// The control byte starting a chunk is not a valid chunk type
+        InvalidControl { control: u8 },
+        /// The first chunk does not reset the dictionary
+        DictionaryResetExpected { control: u8 },
+        /// An LZMA chunk following a dictionary reset does not set the
+        /// properties
+        PropertiesExpected { control: u8 },
+        /// The properties of an LZMA chunk have `lc + lp > 4`
+        LiteralContextTooLarge { lc: u32, lp: u32 },
+        /// The compressed data of an LZMA chunk does not decode to exactly
+        /// the unpacked size of the chunk
+        ChunkSizeMismatch,
+        /// The data ends without the end-of-data chunk
+        EndMarkerMissing,
+    }
+}
+
+pub mod frame {
+    /// Invalid frame in [`crate::container::simple_frame`] or
+    /// `container::mini_frame`
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    pub enum FrameError {
+        /// The magic value of the frame is not the expected one
+        MagicMismatch { magic: u32 },
+        /// The input ends before the header or the payload, after `have`
+        /// bytes
+        Truncated { have: u64 },
+        /// The CRC-32 of the payload does not match the header
+        ChecksumMismatch { expected: u32, actual: u32 },
+        /// The payload does not fit in the length field of the frame
+        PayloadTooLarge { len: u64 },
+    }
+}
+
+pub mod model {
+    /// Invalid probability model snapshot, see
+    /// `decompress::DecompressSession::load_model`
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    pub enum ModelError {
+        /// The snapshot is `actual` bytes long instead of the `expected`
+        /// bytes of a model for the properties of the session
+        LengthMismatch { expected: usize, actual: usize },
+        /// The probability at `index` is 0 or above 2047
+        ProbabilityOutOfRange { index: usize, value: u16 },
+    }
+}
+
 /// Library errors.
 #[derive(Debug)]
 pub enum Error {
@@ -55,36 +183,290 @@ pub enum Error {
         needed: usize,
         available: usize,
     },
+    /// Counts are in literal coders (`0x300` probabilities each), i.e.
+    /// `needed` is `1 << (lc + lp)` and `available` is `PROBS_MEM_LIMIT`.
     ProbabilitiesBufferTooSmall {
         needed: usize,
         available: usize,
     },
+    /// Output slice given to [`crate::lzma_decompress_to_slice`] or
+    /// [`crate::lzma_compress_to_slice`] cannot hold the resulting data.
+    OutputBufferTooSmall {
+        available: usize,
+    },
+    /// Digest of the decompressed data does not match the expected one.
+    DigestMismatch,
+    /// A size declared in the input does not fit in `usize` on this target.
+    SizeOverflowsTarget {
+        size: u64,
+    },
+    /// Decoding loop ran for more iterations than allowed by
+    /// [`crate::decompress::fuzz_decode`].
+    IterationLimitExceeded,
+    /// Decoder used before being initialized. Only returned with the
+    /// `no-panics` feature; the decoder panics otherwise.
+    Uninitialized,
     /// I/O error.
     IoError(io::Error),
-    /// Not enough bytes to complete header
-    HeaderTooShort(io::Error),
+    /// Not enough bytes to complete the header: only `have` of the `need`
+    /// header bytes were available, the input ending within `field`.
+    HeaderTooShort {
+        have: usize,
+        need: usize,
+        field: lzma::HeaderField,
+    },
     /// LZMA error.
     LzmaError(lzma::LzmaError),
     StreamError(stream::StreamError),
+    FrameError(frame::FrameError),
+    Lzma2Error(lzma2::Lzma2Error),
+    ModelError(model::ModelError),
 }
 
 /// Library result alias.
 pub type Result<T> = result::Result<T, Error>;
 
+/// I/O errors are cloned with their kind and message, the original source
+/// error (if any) is not kept.
+impl Clone for Error {
+    fn clone(&self) -> Self {
+        match self {
+            Error::DictionaryBufferTooSmall { needed, available } => {
+                Error::DictionaryBufferTooSmall {
+                    needed: *needed,
+                    available: *available,
+                }
+            }
+            Error::ProbabilitiesBufferTooSmall { needed, available } => {
+                Error::ProbabilitiesBufferTooSmall {
+                    needed: *needed,
+                    available: *available,
+                }
+            }
+            Error::OutputBufferTooSmall { available } => Error::OutputBufferTooSmall {
+                available: *available,
+            },
+            Error::DigestMismatch => Error::DigestMismatch,
+            Error::SizeOverflowsTarget { size } => Error::SizeOverflowsTarget { size: *size },
+            Error::IterationLimitExceeded => Error::IterationLimitExceeded,
+            Error::Uninitialized => Error::Uninitialized,
+            Error::IoError(e) => Error::IoError(clone_io_error(e)),
+            Error::HeaderTooShort { have, need, field } => Error::HeaderTooShort {
+                have: *have,
+                need: *need,
+                field: *field,
+            },
+            Error::LzmaError(e) => Error::LzmaError(*e),
+            Error::StreamError(e) => Error::StreamError(*e),
+            Error::FrameError(e) => Error::FrameError(*e),
+            Error::Lzma2Error(e) => Error::Lzma2Error(*e),
+            Error::ModelError(e) => Error::ModelError(*e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn clone_io_error(e: &io::Error) -> io::Error {
+    match e.get_ref() {
+        Some(inner) => io::Error::new(e.kind(), inner.to_string()),
+        None => io::Error::from(e.kind()),
+    }
+}
+
+#[cfg(not(feature = "std"))]
+fn clone_io_error(e: &io::Error) -> io::Error {
+    match e.get_ref() {
+        Some(inner) => io::Error::new(e.kind(), inner),
+        None => io::Error::from(e.kind()),
+    }
+}
+
+/// I/O errors are compared by [`io::ErrorKind`] only.
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        use Error::*;
+        match (self, other) {
+            (
+                DictionaryBufferTooSmall { needed, available },
+                DictionaryBufferTooSmall {
+                    needed: other_needed,
+                    available: other_available,
+                },
+            )
+            | (
+                ProbabilitiesBufferTooSmall { needed, available },
+                ProbabilitiesBufferTooSmall {
+                    needed: other_needed,
+                    available: other_available,
+                },
+            ) => needed == other_needed && available == other_available,
+            (OutputBufferTooSmall { available }, OutputBufferTooSmall { available: other }) => {
+                available == other
+            }
+            (DigestMismatch, DigestMismatch)
+            | (IterationLimitExceeded, IterationLimitExceeded)
+            | (Uninitialized, Uninitialized) => true,
+            (SizeOverflowsTarget { size }, SizeOverflowsTarget { size: other }) => size == other,
+            (IoError(e), IoError(other)) => e.kind() == other.kind(),
+            (
+                HeaderTooShort { have, need, field },
+                HeaderTooShort {
+                    have: other_have,
+                    need: other_need,
+                    field: other_field,
+                },
+            ) => have == other_have && need == other_need && field == other_field,
+            (LzmaError(e), LzmaError(other)) => e == other,
+            (StreamError(e), StreamError(other)) => e == other,
+            (FrameError(e), FrameError(other)) => e == other,
+            (Lzma2Error(e), Lzma2Error(other)) => e == other,
+            (ModelError(e), ModelError(other)) => e == other,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Error {}
+
+// Errors built directly on the hot decoding paths, kept out of line like the
+// `From` conversions below
+impl Error {
+    #[cold]
+    #[inline(never)]
+    pub(crate) fn output_buffer_too_small(available: usize) -> Self {
+        Error::OutputBufferTooSmall { available }
+    }
+
+    #[cold]
+    #[inline(never)]
+    pub(crate) fn probabilities_buffer_too_small(needed: usize, available: usize) -> Self {
+        Error::ProbabilitiesBufferTooSmall { needed, available }
+    }
+
+    #[cold]
+    #[inline(never)]
+    pub(crate) fn size_overflows_target(size: u64) -> Self {
+        Error::SizeOverflowsTarget { size }
+    }
+
+    #[cold]
+    #[inline(never)]
+    pub(crate) fn iteration_limit_exceeded() -> Self {
+        Error::IterationLimitExceeded
+    }
+}
+
 impl From<lzma::LzmaError> for Error {
+    #[cold]
+    #[inline(never)]
     fn from(e: lzma::LzmaError) -> Self {
         Error::LzmaError(e)
     }
 }
 
 impl From<stream::StreamError> for Error {
+    #[cold]
+    #[inline(never)]
     fn from(e: stream::StreamError) -> Self {
         Error::StreamError(e)
     }
 }
 
+impl From<frame::FrameError> for Error {
+    #[cold]
+    #[inline(never)]
+    fn from(e: frame::FrameError) -> Self {
+        Error::FrameError(e)
+    }
+}
+
+impl From<lzma2::Lzma2Error> for Error {
+    #[cold]
+    #[inline(never)]
+    fn from(e: lzma2::Lzma2Error) -> Self {
+        Error::Lzma2Error(e)
+    }
+}
+
+impl From<model::ModelError> for Error {
+    #[cold]
+    #[inline(never)]
+    fn from(e: model::ModelError) -> Self {
+        Error::ModelError(e)
+    }
+}
+
 impl From<io::Error> for Error {
+    #[cold]
+    #[inline(never)]
     fn from(e: io::Error) -> Self {
         Error::IoError(e)
     }
 }
+
+/// I/O errors are displayed as such, other errors as their [`Debug`](core::fmt::Debug)
+/// representation.
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::IoError(e) => write!(f, "I/O error: {}", e),
+            e => write!(f, "{:?}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_error_display() {
+        let e = Error::IoError(io::Error::new(io::ErrorKind::WriteZero, "sink full"));
+        assert_eq!(e.to_string(), "I/O error: sink full");
+        assert!(std::error::Error::source(&e).is_some());
+        let e = Error::LzmaError(lzma::LzmaError::DataStreamIsTooShort);
+        assert_eq!(e.to_string(), "LzmaError(DataStreamIsTooShort)");
+        assert!(std::error::Error::source(&e).is_none());
+    }
+
+    #[test]
+    fn test_error_clone_eq() {
+        let errors = [
+            Error::DictionaryBufferTooSmall {
+                needed: 2,
+                available: 1,
+            },
+            Error::HeaderTooShort {
+                have: 1,
+                need: 13,
+                field: lzma::HeaderField::DictSize,
+            },
+            Error::LzmaError(lzma::LzmaError::DataStreamIsTooShort),
+            Error::StreamError(stream::StreamError::InvalidState),
+            Error::FrameError(frame::FrameError::Truncated { have: 0 }),
+            Error::Lzma2Error(lzma2::Lzma2Error::EndMarkerMissing),
+            Error::ModelError(model::ModelError::LengthMismatch {
+                expected: 2,
+                actual: 0,
+            }),
+            Error::IoError(io::Error::new(io::ErrorKind::WriteZero, "sink full")),
+            Error::IoError(io::ErrorKind::UnexpectedEof.into()),
+        ];
+        for (i, a) in errors.iter().enumerate() {
+            for (j, b) in errors.iter().enumerate() {
+                assert_eq!(i == j, *a == b.clone(), "{:?} vs {:?}", a, b);
+            }
+        }
+    }
+}
@@ -1,5 +1,41 @@
-mod io_ext;
+mod chain;
+mod chunked;
+#[cfg(feature = "core2")]
+mod core2_compat;
+#[cfg(not(feature = "std"))]
+mod core_io;
 mod cursor;
-pub use io_ext::*;
+#[cfg(feature = "digest")]
+mod digest_writer;
+mod double_buffer;
+#[cfg(feature = "heapless")]
+mod heapless_ext;
+mod io_ext;
+#[cfg(feature = "embedded-storage")]
+mod nor_flash;
+mod page;
+mod ring;
+mod take;
+mod tee;
+mod transform;
+pub use chain::ChainedSliceReader;
+#[cfg(feature = "core2")]
+pub use core2_compat::Core2;
+#[cfg(not(feature = "std"))]
+pub use core_io::{BufRead, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
 pub use cursor::Cursor;
-pub use core2::io::*;
+#[cfg(feature = "digest")]
+pub use digest_writer::DigestWriter;
+pub use double_buffer::{DoubleBufferWriter, SwapSink};
+#[cfg(feature = "heapless")]
+pub use heapless_ext::{HeaplessQueueReader, HeaplessVecWriter};
+pub use io_ext::*;
+#[cfg(feature = "embedded-storage")]
+pub use nor_flash::{FlashReader, NorFlashWriter};
+pub use page::PageAlignedWriter;
+pub use ring::{RingBuffer, RingBufferProducer, RingBufferReader};
+#[cfg(feature = "std")]
+pub use std::io::{BufRead, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+pub use take::TakeReader;
+pub use tee::TeeWriter;
+pub use transform::{OutputTransform, TransformWriter};
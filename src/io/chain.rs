@@ -0,0 +1,92 @@
+use super::{BufRead, Read, Result};
+
+/// A reader over a list of non-contiguous slices, presenting them as a single
+/// continuous input.
+///
+/// This allows to feed fragmented input (e.g. packets received from a radio
+/// stack) to the decoder without copying them into a contiguous staging
+/// buffer first.
+///
+/// ```
+/// use lzma_rs::io::{ChainedSliceReader, Read};
+///
+/// let fragments: &[&[u8]] = &[b"Hello", b"", b" world"];
+/// let mut reader = ChainedSliceReader::new(fragments);
+/// let mut buf = [0u8; 11];
+/// reader.read_exact(&mut buf).unwrap();
+/// assert_eq!(&buf, b"Hello world");
+/// ```
+#[derive(Clone, Debug)]
+pub struct ChainedSliceReader<'a, 'b> {
+    slices: &'b [&'a [u8]],
+    /// Position within the first slice of `slices`.
+    pos: usize,
+}
+
+impl<'a, 'b> ChainedSliceReader<'a, 'b> {
+    /// Create a reader yielding bytes of `slices` in order.
+    pub const fn new(slices: &'b [&'a [u8]]) -> Self {
+        Self { slices, pos: 0 }
+    }
+
+    /// Number of bytes that have not been read yet.
+    pub fn remaining(&self) -> usize {
+        self.slices.iter().map(|s| s.len()).sum::<usize>() - self.pos
+    }
+
+    /// Drop slices that have been read entirely.
+    fn skip_exhausted(&mut self) {
+        while let Some((first, rest)) = self.slices.split_first() {
+            if self.pos < first.len() {
+                break;
+            }
+            self.slices = rest;
+            self.pos = 0;
+        }
+    }
+}
+
+impl<'a, 'b> Read for ChainedSliceReader<'a, 'b> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = Read::read(&mut self.fill_buf()?, buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'a, 'b> BufRead for ChainedSliceReader<'a, 'b> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        self.skip_exhausted();
+        Ok(match self.slices.first() {
+            Some(first) => &first[self.pos..],
+            None => &[],
+        })
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+        self.skip_exhausted();
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chained_slice_reader() {
+        let fragments: &[&[u8]] = &[b"", b"ab", b"", b"c", b"def", b""];
+        let mut reader = ChainedSliceReader::new(fragments);
+        assert_eq!(reader.remaining(), 6);
+        assert_eq!(reader.fill_buf().unwrap(), b"ab");
+        reader.consume(1);
+        assert_eq!(reader.fill_buf().unwrap(), b"b");
+        reader.consume(1);
+        assert_eq!(reader.fill_buf().unwrap(), b"c");
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"cdef");
+        assert_eq!(reader.remaining(), 0);
+        assert!(reader.fill_buf().unwrap().is_empty());
+    }
+}
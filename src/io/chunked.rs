@@ -0,0 +1,43 @@
+use super::Result;
+
+/// A writer buffering data into fixed size chunks, handed over once full.
+pub(super) trait ChunkedWrite {
+    /// The chunk being filled and the number of bytes written to it.
+    fn chunk(&mut self) -> (&mut [u8], &mut usize);
+
+    /// Hand over the full chunk, emptying it on success.
+    fn hand_over(&mut self) -> Result<()>;
+
+    /// Whether the chunk is full, i.e. a hand-over failed.
+    fn is_full(&mut self) -> bool {
+        let (chunk, len) = self.chunk();
+        *len == chunk.len()
+    }
+}
+
+/// Implementation of `Write::write` for a [`ChunkedWrite`].
+pub(super) fn write<W: ChunkedWrite>(writer: &mut W, buf: &[u8]) -> Result<usize> {
+    // A full chunk left by a failed hand-over is retried first, so that its
+    // error is reported before taking more data
+    if writer.is_full() {
+        writer.hand_over()?;
+    }
+    let (chunk, len) = writer.chunk();
+    let amt = core::cmp::min(chunk.len() - *len, buf.len());
+    chunk[*len..*len + amt].copy_from_slice(&buf[..amt]);
+    *len += amt;
+    if writer.is_full() {
+        // The bytes are taken either way: on error, the chunk stays full and
+        // the next call fails
+        let _ = writer.hand_over();
+    }
+    Ok(amt)
+}
+
+/// Hand over a full chunk left by a failed write, if any.
+pub(super) fn flush<W: ChunkedWrite>(writer: &mut W) -> Result<()> {
+    if writer.is_full() {
+        writer.hand_over()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use super::{BufRead, Error, Read, Result, Write};
+
+/// Adapts a [`core2::io`] reader or writer to the traits of [`crate::io`].
+///
+/// With the `std` feature `core2` re-exports `std::io`, and this wrapper only
+/// forwards calls. Without it, `core2` errors are converted by kind, keeping
+/// their message.
+///
+/// ```
+/// # #[cfg(feature = "core2")]
+/// # {
+/// use lzma_rs::io::{Core2, Read};
+///
+/// let mut reader = Core2(core2::io::Cursor::new([1u8, 2, 3]));
+/// let mut buf = [0u8; 2];
+/// reader.read_exact(&mut buf).unwrap();
+/// assert_eq!(buf, [1, 2]);
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Core2<T>(pub T);
+
+impl<T> Core2<T> {
+    /// Unwraps the `core2` reader or writer.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+#[cfg(feature = "std")]
+#[inline]
+fn convert(e: core2::io::Error) -> Error {
+    e
+}
+
+#[cfg(not(feature = "std"))]
+fn convert(e: core2::io::Error) -> Error {
+    use super::ErrorKind;
+    use core2::io::ErrorKind as Core2Kind;
+
+    let kind = match e.kind() {
+        Core2Kind::NotFound => ErrorKind::NotFound,
+        Core2Kind::PermissionDenied => ErrorKind::PermissionDenied,
+        Core2Kind::ConnectionRefused => ErrorKind::ConnectionRefused,
+        Core2Kind::ConnectionReset => ErrorKind::ConnectionReset,
+        Core2Kind::ConnectionAborted => ErrorKind::ConnectionAborted,
+        Core2Kind::NotConnected => ErrorKind::NotConnected,
+        Core2Kind::AddrInUse => ErrorKind::AddrInUse,
+        Core2Kind::AddrNotAvailable => ErrorKind::AddrNotAvailable,
+        Core2Kind::BrokenPipe => ErrorKind::BrokenPipe,
+        Core2Kind::AlreadyExists => ErrorKind::AlreadyExists,
+        Core2Kind::WouldBlock => ErrorKind::WouldBlock,
+        Core2Kind::InvalidInput => ErrorKind::InvalidInput,
+        Core2Kind::InvalidData => ErrorKind::InvalidData,
+        Core2Kind::TimedOut => ErrorKind::TimedOut,
+        Core2Kind::WriteZero => ErrorKind::WriteZero,
+        Core2Kind::Interrupted => ErrorKind::Interrupted,
+        Core2Kind::UnexpectedEof => ErrorKind::UnexpectedEof,
+        _ => ErrorKind::Other,
+    };
+    match e.into_inner() {
+        Some(message) => Error::new(kind, message),
+        None => Error::from(kind),
+    }
+}
+
+impl<T: core2::io::Read> Read for Core2<T> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.0.read(buf).map_err(convert)
+    }
+}
+
+impl<T: core2::io::BufRead> BufRead for Core2<T> {
+    #[inline]
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        self.0.fill_buf().map_err(convert)
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        self.0.consume(amt)
+    }
+}
+
+impl<T: core2::io::Write> Write for Core2<T> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.write(buf).map_err(convert)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        self.0.flush().map_err(convert)
+    }
+}
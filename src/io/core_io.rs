@@ -0,0 +1,371 @@
+//! Minimal `no_std` counterparts of the `std::io` items used by the crate.
+//!
+//! Only the subset of the `std::io` API the codecs rely on is provided, so
+//! that code written against [`crate::io`] compiles unchanged with and
+//! without the `std` feature.
+
+use core::cmp;
+use core::fmt;
+
+/// A specialized [`Result`](core::result::Result) type for I/O operations.
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// A list specifying general categories of I/O error, mirroring
+/// `std::io::ErrorKind`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// An entity was not found.
+    NotFound,
+    /// The operation lacked the necessary privileges to complete.
+    PermissionDenied,
+    /// The connection was refused by the remote server.
+    ConnectionRefused,
+    /// The connection was reset by the remote server.
+    ConnectionReset,
+    /// The connection was aborted by the remote server.
+    ConnectionAborted,
+    /// The operation failed because the connection is not connected yet.
+    NotConnected,
+    /// A socket address could not be bound because it is in use elsewhere.
+    AddrInUse,
+    /// A nonexistent interface was requested or the address was not local.
+    AddrNotAvailable,
+    /// The operation failed because a pipe was closed.
+    BrokenPipe,
+    /// An entity already exists.
+    AlreadyExists,
+    /// The operation needs to block to complete, but blocking was not
+    /// requested.
+    WouldBlock,
+    /// A parameter was incorrect.
+    InvalidInput,
+    /// Data not valid for the operation were encountered.
+    InvalidData,
+    /// The I/O operation's timeout expired.
+    TimedOut,
+    /// A call to `write` returned `Ok(0)`.
+    WriteZero,
+    /// The operation was interrupted and can typically be retried.
+    Interrupted,
+    /// A custom error that does not fall under any other I/O error kind.
+    Other,
+    /// The operation could not complete because an "end of file" was reached
+    /// prematurely.
+    UnexpectedEof,
+}
+
+impl ErrorKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::NotFound => "entity not found",
+            ErrorKind::PermissionDenied => "permission denied",
+            ErrorKind::ConnectionRefused => "connection refused",
+            ErrorKind::ConnectionReset => "connection reset",
+            ErrorKind::ConnectionAborted => "connection aborted",
+            ErrorKind::NotConnected => "not connected",
+            ErrorKind::AddrInUse => "address in use",
+            ErrorKind::AddrNotAvailable => "address not available",
+            ErrorKind::BrokenPipe => "broken pipe",
+            ErrorKind::AlreadyExists => "entity already exists",
+            ErrorKind::WouldBlock => "operation would block",
+            ErrorKind::InvalidInput => "invalid input parameter",
+            ErrorKind::InvalidData => "invalid data",
+            ErrorKind::TimedOut => "timed out",
+            ErrorKind::WriteZero => "write zero",
+            ErrorKind::Interrupted => "operation interrupted",
+            ErrorKind::Other => "other os error",
+            ErrorKind::UnexpectedEof => "unexpected end of file",
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error type for I/O operations: an [`ErrorKind`] and an optional
+/// static message.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Error {
+    kind: ErrorKind,
+    message: Option<&'static str>,
+}
+
+impl Error {
+    /// Creates a new I/O error from a known kind of error and a message.
+    pub const fn new(kind: ErrorKind, message: &'static str) -> Error {
+        Error {
+            kind,
+            message: Some(message),
+        }
+    }
+
+    /// Returns the message this error was created with, if any.
+    pub fn get_ref(&self) -> Option<&'static str> {
+        self.message
+    }
+
+    /// Returns the corresponding [`ErrorKind`] for this error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error {
+            kind,
+            message: None,
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.message {
+            Some(message) => f
+                .debug_struct("Error")
+                .field("kind", &self.kind)
+                .field("message", &message)
+                .finish(),
+            None => f.debug_tuple("Kind").field(&self.kind).finish(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.message {
+            Some(message) => f.write_str(message),
+            None => f.write_str(self.kind.as_str()),
+        }
+    }
+}
+
+/// Enumeration of possible methods to seek within an I/O object.
+#[derive(Copy, PartialEq, Eq, Clone, Debug)]
+pub enum SeekFrom {
+    /// Sets the offset to the provided number of bytes.
+    Start(u64),
+    /// Sets the offset to the size of this object plus the specified number
+    /// of bytes.
+    End(i64),
+    /// Sets the offset to the current position plus the specified number of
+    /// bytes.
+    Current(i64),
+}
+
+/// The `Read` trait allows for reading bytes from a source.
+pub trait Read {
+    /// Pulls some bytes from this source into the specified buffer,
+    /// returning how many bytes were read.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Reads the exact number of bytes required to fill `buf`.
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.read(buf) {
+                Ok(0) => break,
+                Ok(n) => buf = &mut buf[n..],
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if buf.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ))
+        }
+    }
+}
+
+/// A `BufRead` is a type of [`Read`]er which has an internal buffer.
+pub trait BufRead: Read {
+    /// Returns the contents of the internal buffer, filling it with more
+    /// data from the inner reader if it is empty.
+    fn fill_buf(&mut self) -> Result<&[u8]>;
+
+    /// Tells this buffer that `amt` bytes have been consumed from the
+    /// buffer, so they should no longer be returned in calls to `read`.
+    fn consume(&mut self, amt: usize);
+}
+
+/// A trait for objects which are byte-oriented sinks.
+pub trait Write {
+    /// Writes a buffer into this writer, returning how many bytes were
+    /// written.
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    /// Flushes this output stream, ensuring that all intermediately buffered
+    /// contents reach their destination.
+    fn flush(&mut self) -> Result<()>;
+
+    /// Attempts to write an entire buffer into this writer.
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf) {
+                Ok(0) => {
+                    return Err(Error::new(
+                        ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ));
+                }
+                Ok(n) => buf = &buf[n..],
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `Seek` trait provides a cursor which can be moved within a stream of
+/// bytes.
+pub trait Seek {
+    /// Seeks to an offset, in bytes, in a stream, returning the new position
+    /// from the start of the stream.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64>;
+}
+
+impl<R: Read + ?Sized> Read for &mut R {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (**self).read(buf)
+    }
+
+    #[inline]
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        (**self).read_exact(buf)
+    }
+}
+
+impl<B: BufRead + ?Sized> BufRead for &mut B {
+    #[inline]
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        (**self).fill_buf()
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        (**self).consume(amt)
+    }
+}
+
+impl<W: Write + ?Sized> Write for &mut W {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (**self).write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+
+    #[inline]
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        (**self).write_all(buf)
+    }
+}
+
+impl<S: Seek + ?Sized> Seek for &mut S {
+    #[inline]
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        (**self).seek(pos)
+    }
+}
+
+impl Read for &[u8] {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let amt = cmp::min(buf.len(), self.len());
+        let (a, b) = self.split_at(amt);
+        buf[..amt].copy_from_slice(a);
+        *self = b;
+        Ok(amt)
+    }
+
+    #[inline]
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        if buf.len() > self.len() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ));
+        }
+        let (a, b) = self.split_at(buf.len());
+        buf.copy_from_slice(a);
+        *self = b;
+        Ok(())
+    }
+}
+
+impl BufRead for &[u8] {
+    #[inline]
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        Ok(*self)
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        *self = &self[amt..];
+    }
+}
+
+impl Write for &mut [u8] {
+    #[inline]
+    fn write(&mut self, data: &[u8]) -> Result<usize> {
+        let amt = cmp::min(data.len(), self.len());
+        let (a, b) = core::mem::take(self).split_at_mut(amt);
+        a.copy_from_slice(&data[..amt]);
+        *self = b;
+        Ok(amt)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slice_read_write() {
+        let mut input: &[u8] = b"hello";
+        let mut buf = [0u8; 3];
+        assert_eq!(input.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"hel");
+        assert_eq!(input.fill_buf().unwrap(), b"lo");
+        input.consume(1);
+        assert_eq!(
+            input.read_exact(&mut buf).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+
+        let mut out = [0u8; 4];
+        let mut sink: &mut [u8] = &mut out;
+        sink.write_all(b"ab").unwrap();
+        assert_eq!(
+            sink.write_all(b"cde").unwrap_err().kind(),
+            ErrorKind::WriteZero
+        );
+        assert_eq!(&out, b"abcd");
+    }
+
+    #[test]
+    fn error_display() {
+        let e = Error::new(ErrorKind::InvalidData, "bad magic");
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        assert_eq!(e.get_ref(), Some("bad magic"));
+        assert_eq!(Error::from(ErrorKind::WriteZero).get_ref(), None);
+    }
+}
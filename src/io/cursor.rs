@@ -1,4 +1,4 @@
-/// TODO: stolen from core2::io::Cursor
+/// TODO: stolen from core2::io::Cursor (itself a copy of `std::io::Cursor`)
 /// - [`Cursor::new`] is const; required to make all other abstractions const constructible
 use super::{BufRead, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
 use core::cmp;
@@ -26,7 +26,7 @@ use core::cmp;
 ///
 /// ```
 /// use std::io::prelude::*;
-/// use core2::io::{self, Seek, SeekFrom, Write};
+/// use lzma_rs::io::{self, Seek, SeekFrom, Write};
 /// use std::fs::File;
 ///
 /// // a library function we've written
@@ -58,7 +58,7 @@ use core::cmp;
 /// fn test_writes_bytes() {
 ///     // setting up a real File is much slower than an in-memory buffer,
 ///     // let's use a cursor instead
-///     use core2::io::Cursor;
+///     use lzma_rs::io::Cursor;
 ///     let mut buff = Cursor::new(vec![0; 15]);
 ///
 ///     write_ten_bytes_at_end(&mut buff).unwrap();
@@ -66,6 +66,7 @@ use core::cmp;
 ///     assert_eq!(&buff.get_ref()[5..15], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
 /// }
 /// ```
+#[allow(clippy::test_attr_in_doctest)]
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct Cursor<T> {
     inner: T,
@@ -82,7 +83,7 @@ impl<T> Cursor<T> {
     /// # Examples
     ///
     /// ```
-    /// use core2::io::Cursor;
+    /// use lzma_rs::io::Cursor;
     ///
     /// let buff = Cursor::new(Vec::new());
     /// # fn force_inference(_: &Cursor<Vec<u8>>) {}
@@ -97,7 +98,7 @@ impl<T> Cursor<T> {
     /// # Examples
     ///
     /// ```
-    /// use core2::io::Cursor;
+    /// use lzma_rs::io::Cursor;
     ///
     /// let buff = Cursor::new(Vec::new());
     /// # fn force_inference(_: &Cursor<Vec<u8>>) {}
@@ -114,7 +115,7 @@ impl<T> Cursor<T> {
     /// # Examples
     ///
     /// ```
-    /// use core2::io::Cursor;
+    /// use lzma_rs::io::Cursor;
     ///
     /// let buff = Cursor::new(Vec::new());
     /// # fn force_inference(_: &Cursor<Vec<u8>>) {}
@@ -134,7 +135,7 @@ impl<T> Cursor<T> {
     /// # Examples
     ///
     /// ```
-    /// use core2::io::Cursor;
+    /// use lzma_rs::io::Cursor;
     ///
     /// let mut buff = Cursor::new(Vec::new());
     /// # fn force_inference(_: &Cursor<Vec<u8>>) {}
@@ -151,7 +152,7 @@ impl<T> Cursor<T> {
     /// # Examples
     ///
     /// ```
-    /// use core2::io::{Cursor, Seek, SeekFrom};
+    /// use lzma_rs::io::{Cursor, Seek, SeekFrom};
     /// use std::io::prelude::*;
     ///
     /// let mut buff = Cursor::new(vec![1, 2, 3, 4, 5]);
@@ -173,7 +174,7 @@ impl<T> Cursor<T> {
     /// # Examples
     ///
     /// ```
-    /// use core2::io::Cursor;
+    /// use lzma_rs::io::Cursor;
     ///
     /// let mut buff = Cursor::new(vec![1, 2, 3, 4, 5]);
     ///
@@ -0,0 +1,76 @@
+use super::{Result, Write};
+use core::fmt;
+use digest::{Digest, Output};
+
+/// A writer hashing everything written to it with `D` while forwarding it to
+/// the inner sink.
+///
+/// Only data accepted by the inner sink is hashed, so the digest always
+/// matches what has actually been written.
+pub struct DigestWriter<W, D> {
+    inner: W,
+    digest: D,
+}
+
+impl<W: Write, D: Digest> DigestWriter<W, D> {
+    /// Create a writer forwarding data to `inner`, with a fresh digest.
+    pub fn new(inner: W) -> Self {
+        Self::with_digest(inner, D::new())
+    }
+
+    /// Create a writer forwarding data to `inner` and updating `digest`.
+    pub fn with_digest(inner: W, digest: D) -> Self {
+        Self { inner, digest }
+    }
+
+    /// Get a reference to the inner sink.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner sink.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consume the writer and return the inner sink and the digest of the
+    /// written data.
+    pub fn finalize(self) -> (W, Output<D>) {
+        (self.inner, self.digest.finalize())
+    }
+}
+
+impl<W: Write, D: Digest> Write for DigestWriter<W, D> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.digest.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: fmt::Debug, D> fmt::Debug for DigestWriter<W, D> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("DigestWriter")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "std", feature = "sha2"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_digest_writer() {
+        let mut writer = DigestWriter::<_, sha2::Sha256>::new(Vec::new());
+        writer.write_all(b"Hello ").unwrap();
+        writer.write_all(b"world").unwrap();
+        let (data, digest) = writer.finalize();
+        assert_eq!(data, b"Hello world");
+        assert_eq!(digest, sha2::Sha256::digest(b"Hello world"));
+    }
+}
@@ -0,0 +1,228 @@
+use super::chunked::{self, ChunkedWrite};
+use super::{Result, Write};
+
+/// Destination of the buffers filled by a [`DoubleBufferWriter`], e.g. a DMA
+/// channel programming flash.
+pub trait SwapSink {
+    /// Start transferring `buf`, a full buffer or the last, partial one, and
+    /// return without waiting for the transfer to complete. The writer keeps
+    /// `buf` untouched until the next call to [`SwapSink::wait`], so a DMA
+    /// transfer may keep reading it after this returns.
+    fn swap(&mut self, buf: &[u8]) -> Result<()>;
+
+    /// Wait for the transfer started by the last call to
+    /// [`SwapSink::swap`] to complete.
+    fn wait(&mut self) -> Result<()>;
+}
+
+/// A writer filling two `N` byte buffers alternately: once a buffer is full,
+/// it is handed to the sink and writing continues into the other one while
+/// the sink transfers it.
+///
+/// Before handing over a buffer, the writer waits for the transfer of the
+/// previous one, so at most one transfer is in flight and a buffer is never
+/// written while being transferred. Used as the output of a decoder, e.g. a
+/// [`Stream`](crate::decompress::Stream), decoding overlaps with the
+/// programming of the previous buffer.
+///
+/// ```
+/// # #[cfg(all(feature = "std", feature = "stream"))]
+/// # {
+/// use lzma_rs::decompress::Stream;
+/// use lzma_rs::io::{DoubleBufferWriter, Result, SwapSink};
+///
+/// struct Flash(Vec<u8>);
+///
+/// impl SwapSink for Flash {
+///     fn swap(&mut self, buf: &[u8]) -> Result<()> {
+///         // Start a DMA transfer of `buf` on a target
+///         self.0.extend_from_slice(buf);
+///         Ok(())
+///     }
+///
+///     fn wait(&mut self) -> Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// let compressed: &[u8] = include_bytes!("../../tests/files/foo.txt.lzma");
+/// let mut output = DoubleBufferWriter::<_, 256>::new(Flash(Vec::new()));
+/// let mut stream = Stream::<4096, 8>::new();
+/// stream.reset();
+/// stream.write_all(&mut output, compressed)?;
+/// stream.finish(&mut output)?;
+/// output.finish()?;
+/// assert_eq!(output.get_ref().0, include_bytes!("../../tests/files/foo.txt"));
+/// # }
+/// # Ok::<(), lzma_rs::error::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct DoubleBufferWriter<S, const N: usize> {
+    sink: S,
+    bufs: [[u8; N]; 2],
+    /// Index in `bufs` of the buffer being filled.
+    active: usize,
+    /// Number of bytes written to the active buffer.
+    len: usize,
+    /// Whether the sink may still be transferring the other buffer.
+    in_flight: bool,
+    swaps: usize,
+}
+
+impl<S: SwapSink, const N: usize> DoubleBufferWriter<S, N> {
+    /// Create a writer handing its buffers to `sink`.
+    pub const fn new(sink: S) -> Self {
+        Self {
+            sink,
+            bufs: [[0; N]; 2],
+            active: 0,
+            len: 0,
+            in_flight: false,
+            swaps: 0,
+        }
+    }
+
+    /// Number of buffers handed to the sink so far.
+    pub fn swaps(&self) -> usize {
+        self.swaps
+    }
+
+    /// Number of bytes waiting for the active buffer to be completed.
+    pub fn pending(&self) -> usize {
+        self.len
+    }
+
+    /// Hand the partial active buffer, if any, to the sink and wait for all
+    /// transfers to complete.
+    pub fn finish(&mut self) -> Result<()> {
+        if self.len > 0 {
+            self.swap()?;
+        }
+        if self.in_flight {
+            self.sink.wait()?;
+            self.in_flight = false;
+        }
+        Ok(())
+    }
+
+    /// Get a reference to the sink.
+    pub fn get_ref(&self) -> &S {
+        &self.sink
+    }
+
+    /// Get a mutable reference to the sink.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Consume the writer and return the sink. Data not handed over yet is
+    /// discarded; call [`DoubleBufferWriter::finish`] first to write it out.
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+
+    fn swap(&mut self) -> Result<()> {
+        if self.in_flight {
+            self.sink.wait()?;
+            self.in_flight = false;
+        }
+        self.sink.swap(&self.bufs[self.active][..self.len])?;
+        self.in_flight = true;
+        self.active ^= 1;
+        self.len = 0;
+        self.swaps += 1;
+        Ok(())
+    }
+}
+
+impl<S: SwapSink, const N: usize> ChunkedWrite for DoubleBufferWriter<S, N> {
+    fn chunk(&mut self) -> (&mut [u8], &mut usize) {
+        (&mut self.bufs[self.active], &mut self.len)
+    }
+
+    fn hand_over(&mut self) -> Result<()> {
+        self.swap()
+    }
+}
+
+impl<S: SwapSink, const N: usize> Write for DoubleBufferWriter<S, N> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        chunked::write(self, buf)
+    }
+
+    /// Hand over a full buffer left by a failed write, if any. An incomplete
+    /// buffer is kept, and transfers in flight are not waited for.
+    fn flush(&mut self) -> Result<()> {
+        chunked::flush(self)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    /// Sink recording the calls, failing the first `failures` swaps.
+    #[derive(Default)]
+    struct Recorder {
+        calls: Vec<String>,
+        failures: usize,
+        in_flight: bool,
+    }
+
+    impl SwapSink for Recorder {
+        fn swap(&mut self, buf: &[u8]) -> Result<()> {
+            assert!(!self.in_flight, "swap with a transfer in flight");
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(std::io::ErrorKind::Other.into());
+            }
+            self.in_flight = true;
+            self.calls
+                .push(format!("swap {}", std::str::from_utf8(buf).unwrap()));
+            Ok(())
+        }
+
+        fn wait(&mut self) -> Result<()> {
+            self.in_flight = false;
+            self.calls.push("wait".into());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_double_buffer_writer() {
+        let mut writer = DoubleBufferWriter::<_, 4>::new(Recorder::default());
+        writer.write_all(b"abcdefghij").unwrap();
+        assert_eq!(writer.swaps(), 2);
+        assert_eq!(writer.pending(), 2);
+        writer.finish().unwrap();
+        assert_eq!(writer.swaps(), 3);
+        assert_eq!(writer.pending(), 0);
+        assert_eq!(
+            writer.into_inner().calls,
+            ["swap abcd", "wait", "swap efgh", "wait", "swap ij", "wait"]
+        );
+    }
+
+    #[test]
+    fn test_double_buffer_writer_error() {
+        let mut writer = DoubleBufferWriter::<_, 4>::new(Recorder {
+            failures: 2,
+            ..Default::default()
+        });
+        // The bytes completing the buffer are taken even though handing it
+        // over fails, and the error is reported by the next call
+        assert_eq!(writer.write(b"abcdef").unwrap(), 4);
+        assert_eq!(writer.pending(), 4);
+        assert!(writer.write(b"ef").is_err());
+        assert_eq!(writer.swaps(), 0);
+
+        assert_eq!(writer.write(b"ef").unwrap(), 2);
+        writer.finish().unwrap();
+        assert_eq!(writer.swaps(), 2);
+        assert_eq!(
+            writer.into_inner().calls,
+            ["swap abcd", "wait", "swap ef", "wait"]
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use super::ring::Staging;
+use super::{BufRead, Read, Result, Write};
+use core::fmt;
+
+/// A [`Write`] sink appending data to a [`heapless::Vec`].
+///
+/// Once the vector is full, writes return `Ok(0)`, which makes
+/// [`Write::write_all`] fail with [`super::ErrorKind::WriteZero`].
+#[derive(Debug)]
+pub struct HeaplessVecWriter<'a, const N: usize> {
+    vec: &'a mut heapless::Vec<u8, N>,
+}
+
+impl<'a, const N: usize> HeaplessVecWriter<'a, N> {
+    /// Create a writer appending to `vec`.
+    pub fn new(vec: &'a mut heapless::Vec<u8, N>) -> Self {
+        Self { vec }
+    }
+}
+
+impl<'a, const N: usize> Write for HeaplessVecWriter<'a, N> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let amt = core::cmp::min(N - self.vec.len(), buf.len());
+        // Cannot fail, `amt` bytes are available
+        let _ = self.vec.extend_from_slice(&buf[..amt]);
+        Ok(amt)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A [`BufRead`] source draining a [`heapless::spsc::Consumer`].
+///
+/// Reads never block: end of input is reported whenever the queue is empty.
+/// Bytes are moved out of the queue into a small staging buffer on demand,
+/// as by [`RingBufferReader`](super::RingBufferReader).
+pub struct HeaplessQueueReader<'a, const N: usize> {
+    consumer: heapless::spsc::Consumer<'a, u8, N>,
+    staging: Staging,
+}
+
+impl<'a, const N: usize> HeaplessQueueReader<'a, N> {
+    /// Create a reader dequeuing bytes from `consumer`.
+    pub fn new(consumer: heapless::spsc::Consumer<'a, u8, N>) -> Self {
+        Self {
+            consumer,
+            staging: Staging::new(),
+        }
+    }
+
+    /// Consume the reader and return the queue consumer. Bytes already moved
+    /// into the staging buffer are lost.
+    pub fn into_inner(self) -> heapless::spsc::Consumer<'a, u8, N> {
+        self.consumer
+    }
+}
+
+impl<'a, const N: usize> fmt::Debug for HeaplessQueueReader<'a, N> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("HeaplessQueueReader")
+            .field("queued", &self.consumer.len())
+            .field("staged", &self.staging.remaining())
+            .finish()
+    }
+}
+
+impl<'a, const N: usize> Read for HeaplessQueueReader<'a, N> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = Read::read(&mut self.fill_buf()?, buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'a, const N: usize> BufRead for HeaplessQueueReader<'a, N> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        if self.staging.remaining() == 0 {
+            let consumer = &mut self.consumer;
+            self.staging.refill(|| consumer.dequeue());
+        }
+        Ok(self.staging.data())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.staging.consume(amt);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_heapless_vec_writer() {
+        let mut vec = heapless::Vec::<u8, 4>::new();
+        let mut writer = HeaplessVecWriter::new(&mut vec);
+        writer.write_all(b"abc").unwrap();
+        assert_eq!(writer.write(b"de").unwrap(), 1);
+        assert_eq!(writer.write(b"e").unwrap(), 0);
+        assert_eq!(&vec[..], b"abcd");
+    }
+
+    #[cfg(feature = "encoder")]
+    #[test]
+    fn test_heapless_round_trip() {
+        let data = b"Hello heapless world! ".repeat(16);
+        let mut compressed = Vec::new();
+        crate::lzma_compress(&mut &data[..], &mut compressed).unwrap();
+
+        let mut queue = heapless::spsc::Queue::<u8, 1024>::new();
+        let (mut producer, consumer) = queue.split();
+        for &byte in &compressed {
+            producer.enqueue(byte).unwrap();
+        }
+        let mut input = HeaplessQueueReader::new(consumer);
+        let mut vec = heapless::Vec::<u8, 512>::new();
+        crate::lzma_decompress::<_, _, 4096, 8>(&mut input, &mut HeaplessVecWriter::new(&mut vec))
+            .unwrap();
+        assert_eq!(&vec[..], &data[..]);
+    }
+}
@@ -1,10 +1,4 @@
-#![allow(unsafe_code)]
-
-use core2::{
-    io::{self, Result},
-};
-
-use core::slice;
+use super::{self as io, Result};
 
 use byteorder::ByteOrder;
 
@@ -563,12 +557,7 @@ pub trait ReadBytesExt: io::Read {
     /// ```
     #[inline]
     fn read_u16_into<T: ByteOrder>(&mut self, dst: &mut [u16]) -> Result<()> {
-        {
-            let buf = unsafe { slice_to_u8_mut(dst) };
-            self.read_exact(buf)?;
-        }
-        T::from_slice_u16(dst);
-        Ok(())
+        read_into(self, dst, T::read_u16_into)
     }
 
     /// Reads a sequence of unsigned 32 bit integers from the underlying
@@ -598,12 +587,7 @@ pub trait ReadBytesExt: io::Read {
     /// ```
     #[inline]
     fn read_u32_into<T: ByteOrder>(&mut self, dst: &mut [u32]) -> Result<()> {
-        {
-            let buf = unsafe { slice_to_u8_mut(dst) };
-            self.read_exact(buf)?;
-        }
-        T::from_slice_u32(dst);
-        Ok(())
+        read_into(self, dst, T::read_u32_into)
     }
 
     /// Reads a sequence of unsigned 64 bit integers from the underlying
@@ -636,12 +620,7 @@ pub trait ReadBytesExt: io::Read {
     /// ```
     #[inline]
     fn read_u64_into<T: ByteOrder>(&mut self, dst: &mut [u64]) -> Result<()> {
-        {
-            let buf = unsafe { slice_to_u8_mut(dst) };
-            self.read_exact(buf)?;
-        }
-        T::from_slice_u64(dst);
-        Ok(())
+        read_into(self, dst, T::read_u64_into)
     }
 
     /// Reads a sequence of unsigned 128 bit integers from the underlying
@@ -673,16 +652,8 @@ pub trait ReadBytesExt: io::Read {
     /// assert_eq!([517, 768], dst);
     /// ```
     #[inline]
-    fn read_u128_into<T: ByteOrder>(
-        &mut self,
-        dst: &mut [u128],
-    ) -> Result<()> {
-        {
-            let buf = unsafe { slice_to_u8_mut(dst) };
-            self.read_exact(buf)?;
-        }
-        T::from_slice_u128(dst);
-        Ok(())
+    fn read_u128_into<T: ByteOrder>(&mut self, dst: &mut [u128]) -> Result<()> {
+        read_into(self, dst, T::read_u128_into)
     }
 
     /// Reads a sequence of signed 8 bit integers from the underlying reader.
@@ -717,8 +688,11 @@ pub trait ReadBytesExt: io::Read {
     /// ```
     #[inline]
     fn read_i8_into(&mut self, dst: &mut [i8]) -> Result<()> {
-        let buf = unsafe { slice_to_u8_mut(dst) };
-        self.read_exact(buf)
+        read_into(self, dst, |src, dst| {
+            for (d, &s) in dst.iter_mut().zip(src) {
+                *d = s as i8;
+            }
+        })
     }
 
     /// Reads a sequence of signed 16 bit integers from the underlying
@@ -748,12 +722,7 @@ pub trait ReadBytesExt: io::Read {
     /// ```
     #[inline]
     fn read_i16_into<T: ByteOrder>(&mut self, dst: &mut [i16]) -> Result<()> {
-        {
-            let buf = unsafe { slice_to_u8_mut(dst) };
-            self.read_exact(buf)?;
-        }
-        T::from_slice_i16(dst);
-        Ok(())
+        read_into(self, dst, T::read_i16_into)
     }
 
     /// Reads a sequence of signed 32 bit integers from the underlying
@@ -783,12 +752,7 @@ pub trait ReadBytesExt: io::Read {
     /// ```
     #[inline]
     fn read_i32_into<T: ByteOrder>(&mut self, dst: &mut [i32]) -> Result<()> {
-        {
-            let buf = unsafe { slice_to_u8_mut(dst) };
-            self.read_exact(buf)?;
-        }
-        T::from_slice_i32(dst);
-        Ok(())
+        read_into(self, dst, T::read_i32_into)
     }
 
     /// Reads a sequence of signed 64 bit integers from the underlying
@@ -821,12 +785,7 @@ pub trait ReadBytesExt: io::Read {
     /// ```
     #[inline]
     fn read_i64_into<T: ByteOrder>(&mut self, dst: &mut [i64]) -> Result<()> {
-        {
-            let buf = unsafe { slice_to_u8_mut(dst) };
-            self.read_exact(buf)?;
-        }
-        T::from_slice_i64(dst);
-        Ok(())
+        read_into(self, dst, T::read_i64_into)
     }
 
     /// Reads a sequence of signed 128 bit integers from the underlying
@@ -858,16 +817,8 @@ pub trait ReadBytesExt: io::Read {
     /// assert_eq!([517, 768], dst);
     /// ```
     #[inline]
-    fn read_i128_into<T: ByteOrder>(
-        &mut self,
-        dst: &mut [i128],
-    ) -> Result<()> {
-        {
-            let buf = unsafe { slice_to_u8_mut(dst) };
-            self.read_exact(buf)?;
-        }
-        T::from_slice_i128(dst);
-        Ok(())
+    fn read_i128_into<T: ByteOrder>(&mut self, dst: &mut [i128]) -> Result<()> {
+        read_into(self, dst, T::read_i128_into)
     }
 
     /// Reads a sequence of IEEE754 single-precision (4 bytes) floating
@@ -903,12 +854,7 @@ pub trait ReadBytesExt: io::Read {
     /// ```
     #[inline]
     fn read_f32_into<T: ByteOrder>(&mut self, dst: &mut [f32]) -> Result<()> {
-        {
-            let buf = unsafe { slice_to_u8_mut(dst) };
-            self.read_exact(buf)?;
-        }
-        T::from_slice_f32(dst);
-        Ok(())
+        read_into(self, dst, T::read_f32_into)
     }
 
     /// **DEPRECATED**.
@@ -948,10 +894,7 @@ pub trait ReadBytesExt: io::Read {
     /// ```
     #[inline]
     #[deprecated(since = "1.2.0", note = "please use `read_f32_into` instead")]
-    fn read_f32_into_unchecked<T: ByteOrder>(
-        &mut self,
-        dst: &mut [f32],
-    ) -> Result<()> {
+    fn read_f32_into_unchecked<T: ByteOrder>(&mut self, dst: &mut [f32]) -> Result<()> {
         self.read_f32_into::<T>(dst)
     }
 
@@ -988,12 +931,7 @@ pub trait ReadBytesExt: io::Read {
     /// ```
     #[inline]
     fn read_f64_into<T: ByteOrder>(&mut self, dst: &mut [f64]) -> Result<()> {
-        {
-            let buf = unsafe { slice_to_u8_mut(dst) };
-            self.read_exact(buf)?;
-        }
-        T::from_slice_f64(dst);
-        Ok(())
+        read_into(self, dst, T::read_f64_into)
     }
 
     /// **DEPRECATED**.
@@ -1039,10 +977,7 @@ pub trait ReadBytesExt: io::Read {
     /// ```
     #[inline]
     #[deprecated(since = "1.2.0", note = "please use `read_f64_into` instead")]
-    fn read_f64_into_unchecked<T: ByteOrder>(
-        &mut self,
-        dst: &mut [f64],
-    ) -> Result<()> {
+    fn read_f64_into_unchecked<T: ByteOrder>(&mut self, dst: &mut [f64]) -> Result<()> {
         self.read_f64_into::<T>(dst)
     }
 }
@@ -1442,11 +1377,7 @@ pub trait WriteBytesExt: io::Write {
     /// assert_eq!(wtr, b"\x48\xc5\x74\x62\xe9\x00\x00\x00\x00\x2b");
     /// ```
     #[inline]
-    fn write_uint<T: ByteOrder>(
-        &mut self,
-        n: u64,
-        nbytes: usize,
-    ) -> Result<()> {
+    fn write_uint<T: ByteOrder>(&mut self, n: u64, nbytes: usize) -> Result<()> {
         let mut buf = [0; 8];
         T::write_uint(&mut buf, n, nbytes);
         self.write_all(&buf[0..nbytes])
@@ -1478,11 +1409,7 @@ pub trait WriteBytesExt: io::Write {
     /// assert_eq!(wtr, b"\xf3\x64\xf4\xd1\xfd\xb0\x81\x00\x00\x00\x00\x00\x00\x2b");
     /// ```
     #[inline]
-    fn write_int<T: ByteOrder>(
-        &mut self,
-        n: i64,
-        nbytes: usize,
-    ) -> Result<()> {
+    fn write_int<T: ByteOrder>(&mut self, n: i64, nbytes: usize) -> Result<()> {
         let mut buf = [0; 8];
         T::write_int(&mut buf, n, nbytes);
         self.write_all(&buf[0..nbytes])
@@ -1493,11 +1420,7 @@ pub trait WriteBytesExt: io::Write {
     /// If the given integer is not representable in the given number of bytes,
     /// this method panics. If `nbytes > 16`, this method panics.
     #[inline]
-    fn write_uint128<T: ByteOrder>(
-        &mut self,
-        n: u128,
-        nbytes: usize,
-    ) -> Result<()> {
+    fn write_uint128<T: ByteOrder>(&mut self, n: u128, nbytes: usize) -> Result<()> {
         let mut buf = [0; 16];
         T::write_uint128(&mut buf, n, nbytes);
         self.write_all(&buf[0..nbytes])
@@ -1508,11 +1431,7 @@ pub trait WriteBytesExt: io::Write {
     /// If the given integer is not representable in the given number of bytes,
     /// this method panics. If `nbytes > 16`, this method panics.
     #[inline]
-    fn write_int128<T: ByteOrder>(
-        &mut self,
-        n: i128,
-        nbytes: usize,
-    ) -> Result<()> {
+    fn write_int128<T: ByteOrder>(&mut self, n: i128, nbytes: usize) -> Result<()> {
         let mut buf = [0; 16];
         T::write_int128(&mut buf, n, nbytes);
         self.write_all(&buf[0..nbytes])
@@ -1581,15 +1500,53 @@ pub trait WriteBytesExt: io::Write {
 /// for free.
 impl<W: io::Write + ?Sized> WriteBytesExt for W {}
 
-/// Convert a slice of T (where T is plain old data) to its mutable binary
-/// representation.
-///
-/// This function is wildly unsafe because it permits arbitrary modification of
-/// the binary representation of any `Copy` type. Use with care. It's intended
-/// to be called only where `T` is a numeric type.
-unsafe fn slice_to_u8_mut<T: Copy>(slice: &mut [T]) -> &mut [u8] {
-    use core::mem::size_of;
+/// Number of bytes read at once by [`read_into`].
+const READ_INTO_CHUNK_LEN: usize = 256;
+
+/// Fill `dst` with values read from `rdr`, going through a stack buffer so
+/// that no `unsafe` reinterpretation of `dst` as bytes is needed. `decode`
+/// converts a chunk of bytes into as many values as fit in its destination.
+fn read_into<R, V>(rdr: &mut R, dst: &mut [V], decode: impl Fn(&[u8], &mut [V])) -> Result<()>
+where
+    R: io::Read + ?Sized,
+{
+    let mut buf = [0; READ_INTO_CHUNK_LEN];
+    for chunk in dst.chunks_mut(READ_INTO_CHUNK_LEN / core::mem::size_of::<V>()) {
+        let bytes = &mut buf[..core::mem::size_of_val(chunk)];
+        rdr.read_exact(bytes)?;
+        decode(bytes, chunk);
+    }
+    Ok(())
+}
 
-    let len = size_of::<T>() * slice.len();
-    slice::from_raw_parts_mut(slice.as_mut_ptr() as *mut u8, len)
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+    use byteorder::{BigEndian, LittleEndian};
+
+    #[test]
+    fn test_read_into_chunks() {
+        // More values than fit in one chunk of the stack buffer
+        let values: Vec<u32> = (0..200).map(|i| i * 0x0101_0101).collect();
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let mut dst = vec![0; values.len()];
+        (&bytes[..]).read_u32_into::<BigEndian>(&mut dst).unwrap();
+        assert_eq!(dst, values);
+
+        let mut dst = [0; 2];
+        (&[0xFE, 0xFF, 0x02, 0x00][..])
+            .read_i16_into::<LittleEndian>(&mut dst)
+            .unwrap();
+        assert_eq!(dst, [-2, 2]);
+
+        let mut dst = [0; 3];
+        (&[2, 251, 3][..]).read_i8_into(&mut dst).unwrap();
+        assert_eq!(dst, [2, -5, 3]);
+
+        let mut dst = [0u64; 2];
+        let err = (&[0; 12][..])
+            .read_u64_into::<LittleEndian>(&mut dst)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
 }
@@ -0,0 +1,418 @@
+use super::{BufRead, Error, ErrorKind, Read, Result, Write};
+use embedded_storage::nor_flash::{NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+/// A writer programming data into a region of a [`NorFlash`].
+///
+/// Sectors are erased lazily, right before the first write into them, and
+/// data is programmed in `BUF` sized chunks. `BUF` must be a multiple of
+/// `F::WRITE_SIZE`. The last, incomplete chunk is padded with `0xFF` to
+/// `F::WRITE_SIZE` and programmed by [`NorFlashWriter::finish`].
+///
+/// On failure, the [`Write`] implementation reports a generic [`Error`]; the
+/// underlying flash error can be retrieved with [`NorFlashWriter::take_error`].
+#[derive(Debug)]
+pub struct NorFlashWriter<'a, F: NorFlash, const BUF: usize> {
+    flash: &'a mut F,
+    /// End of the flash region (exclusive).
+    end: u32,
+    /// Flash address the next chunk will be programmed at.
+    offset: u32,
+    /// End of the erased part of the region (exclusive).
+    erased: u32,
+    buf: [u8; BUF],
+    /// Number of bytes buffered in `buf`.
+    len: usize,
+    programmed: usize,
+    error: Option<F::Error>,
+}
+
+impl<'a, F: NorFlash, const BUF: usize> NorFlashWriter<'a, F, BUF> {
+    /// Create a writer programming the `len` bytes long region of `flash`
+    /// starting at `offset`.
+    ///
+    /// The region must be aligned to `F::ERASE_SIZE` and `BUF` must be a
+    /// non-zero multiple of `F::WRITE_SIZE`.
+    pub fn new(
+        flash: &'a mut F,
+        offset: u32,
+        len: u32,
+    ) -> core::result::Result<Self, NorFlashErrorKind> {
+        let end = offset
+            .checked_add(len)
+            .ok_or(NorFlashErrorKind::OutOfBounds)?;
+        if end as usize > flash.capacity() {
+            return Err(NorFlashErrorKind::OutOfBounds);
+        }
+        if offset as usize % F::ERASE_SIZE != 0
+            || end as usize % F::ERASE_SIZE != 0
+            || BUF == 0
+            || BUF % F::WRITE_SIZE != 0
+        {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        Ok(Self {
+            flash,
+            end,
+            offset,
+            erased: offset,
+            buf: [0; BUF],
+            len: 0,
+            programmed: 0,
+            error: None,
+        })
+    }
+
+    /// Number of bytes programmed into the flash so far, including the padding
+    /// once [`NorFlashWriter::finish`] has been called.
+    pub fn programmed(&self) -> usize {
+        self.programmed
+    }
+
+    /// Take the flash error that made the last operation fail, if any.
+    pub fn take_error(&mut self) -> Option<F::Error> {
+        self.error.take()
+    }
+
+    /// Pad and program the buffered data, if any.
+    pub fn finish(&mut self) -> Result<()> {
+        if self.len > 0 {
+            let padded = self.len.div_ceil(F::WRITE_SIZE) * F::WRITE_SIZE;
+            self.buf[self.len..padded]
+                .iter_mut()
+                .for_each(|v| *v = 0xFF);
+            self.program(padded)?;
+        }
+        Ok(())
+    }
+
+    /// Program the first `len` bytes of `buf`, erasing sectors as needed.
+    fn program(&mut self, len: usize) -> Result<()> {
+        let end = self.offset as usize + len;
+        if end > self.end as usize {
+            return Err(Error::new(ErrorKind::WriteZero, "NOR flash region is full"));
+        }
+        while (self.erased as usize) < end {
+            let to = self.erased + F::ERASE_SIZE as u32;
+            if let Err(e) = self.flash.erase(self.erased, to) {
+                return Err(self.flash_error(e));
+            }
+            self.erased = to;
+        }
+        if let Err(e) = self.flash.write(self.offset, &self.buf[..len]) {
+            return Err(self.flash_error(e));
+        }
+        self.offset += len as u32;
+        self.programmed += len;
+        self.len = 0;
+        Ok(())
+    }
+
+    #[cold]
+    fn flash_error(&mut self, e: F::Error) -> Error {
+        let error = flash_error(&e);
+        self.error = Some(e);
+        error
+    }
+}
+
+/// Generic [`Error`] reported for a flash error.
+#[cold]
+fn flash_error<E: NorFlashError>(e: &E) -> Error {
+    let msg = match e.kind() {
+        NorFlashErrorKind::NotAligned => "NOR flash operation is not aligned",
+        NorFlashErrorKind::OutOfBounds => "NOR flash operation is out of bounds",
+        _ => "NOR flash operation failed",
+    };
+    Error::new(ErrorKind::Other, msg)
+}
+
+impl<'a, F: NorFlash, const BUF: usize> Write for NorFlashWriter<'a, F, BUF> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let amt = core::cmp::min(BUF - self.len, buf.len());
+        self.buf[self.len..self.len + amt].copy_from_slice(&buf[..amt]);
+        self.len += amt;
+        if self.len == BUF {
+            self.program(BUF)?;
+        }
+        Ok(amt)
+    }
+
+    /// Incomplete chunks are kept buffered until [`NorFlashWriter::finish`].
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A reader over a region of a [`ReadNorFlash`] that is not memory-mapped,
+/// e.g. QSPI flash, so that compressed images can be decoded straight from
+/// it with the one-shot functions.
+///
+/// Data is read through the caller-provided `cache`, one chunk of
+/// `cache.len()` bytes at a time, which must be a non-zero multiple of
+/// `F::READ_SIZE`. The last chunk is rounded up to `F::READ_SIZE`, but only
+/// the bytes of the region are returned.
+///
+/// On failure, the [`Read`] implementation reports a generic [`Error`]; the
+/// underlying flash error can be retrieved with [`FlashReader::take_error`].
+#[derive(Debug)]
+pub struct FlashReader<'a, F: ReadNorFlash> {
+    flash: &'a mut F,
+    /// Flash address the next chunk will be read from.
+    offset: u32,
+    /// End of the flash region (exclusive).
+    end: u32,
+    cache: &'a mut [u8],
+    /// Position of the next byte to return in `cache`.
+    pos: usize,
+    /// Number of valid bytes in `cache`.
+    filled: usize,
+    error: Option<F::Error>,
+}
+
+impl<'a, F: ReadNorFlash> FlashReader<'a, F> {
+    /// Create a reader over the `len` bytes long region of `flash` starting
+    /// at `offset`, reading through `cache`.
+    ///
+    /// `offset` must be aligned to `F::READ_SIZE` and `cache.len()` must be a
+    /// non-zero multiple of `F::READ_SIZE`.
+    pub fn new(
+        flash: &'a mut F,
+        offset: u32,
+        len: u32,
+        cache: &'a mut [u8],
+    ) -> core::result::Result<Self, NorFlashErrorKind> {
+        let end = offset
+            .checked_add(len)
+            .ok_or(NorFlashErrorKind::OutOfBounds)?;
+        if end as usize > flash.capacity() {
+            return Err(NorFlashErrorKind::OutOfBounds);
+        }
+        if offset as usize % F::READ_SIZE != 0
+            || cache.is_empty()
+            || cache.len() % F::READ_SIZE != 0
+        {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        Ok(Self {
+            flash,
+            offset,
+            end,
+            cache,
+            pos: 0,
+            filled: 0,
+            error: None,
+        })
+    }
+
+    /// Number of bytes of the region left to read.
+    pub fn remaining(&self) -> usize {
+        (self.end - self.offset) as usize + self.filled - self.pos
+    }
+
+    /// Take the flash error that made the last operation fail, if any.
+    pub fn take_error(&mut self) -> Option<F::Error> {
+        self.error.take()
+    }
+}
+
+impl<'a, F: ReadNorFlash> BufRead for FlashReader<'a, F> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        if self.pos == self.filled && self.offset < self.end {
+            let left = (self.end - self.offset) as usize;
+            let len = core::cmp::min(self.cache.len(), left.div_ceil(F::READ_SIZE) * F::READ_SIZE);
+            if let Err(e) = self.flash.read(self.offset, &mut self.cache[..len]) {
+                let error = flash_error(&e);
+                self.error = Some(e);
+                return Err(error);
+            }
+            let len = core::cmp::min(len, left);
+            self.offset += len as u32;
+            self.pos = 0;
+            self.filled = len;
+        }
+        Ok(&self.cache[self.pos..self.filled])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = core::cmp::min(self.pos + amt, self.filled);
+    }
+}
+
+impl<'a, F: ReadNorFlash> Read for FlashReader<'a, F> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let available = self.fill_buf()?;
+        let amt = core::cmp::min(available.len(), buf.len());
+        buf[..amt].copy_from_slice(&available[..amt]);
+        self.consume(amt);
+        Ok(amt)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, ReadNorFlash};
+
+    /// In-memory flash recording erase and write operations
+    #[derive(Debug)]
+    struct MockFlash {
+        data: [u8; 256],
+        erases: Vec<(u32, u32)>,
+    }
+
+    impl ErrorType for MockFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for MockFlash {
+        const READ_SIZE: usize = 1;
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> core::result::Result<(), Self::Error> {
+            bytes.copy_from_slice(&self.data[offset as usize..offset as usize + bytes.len()]);
+            Ok(())
+        }
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl NorFlash for MockFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = 32;
+        fn erase(&mut self, from: u32, to: u32) -> core::result::Result<(), Self::Error> {
+            embedded_storage::nor_flash::check_erase(self, from, to)?;
+            self.data[from as usize..to as usize]
+                .iter_mut()
+                .for_each(|v| *v = 0xFF);
+            self.erases.push((from, to));
+            Ok(())
+        }
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> core::result::Result<(), Self::Error> {
+            embedded_storage::nor_flash::check_write(self, offset, bytes.len())?;
+            for (i, b) in bytes.iter().enumerate() {
+                // Programming can only clear bits
+                self.data[offset as usize + i] &= b;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_nor_flash_writer() {
+        let mut flash = MockFlash {
+            data: [0; 256],
+            erases: Vec::new(),
+        };
+        let data: Vec<u8> = (0..50).collect();
+        {
+            let mut writer = NorFlashWriter::<_, 8>::new(&mut flash, 32, 64).unwrap();
+            writer.write_all(&data).unwrap();
+            writer.finish().unwrap();
+            assert_eq!(writer.programmed(), 52);
+        }
+        assert_eq!(&flash.data[32..82], &data[..]);
+        assert_eq!(&flash.data[82..96], &[0xFF; 14]);
+        assert_eq!(flash.erases, vec![(32, 64), (64, 96)]);
+    }
+
+    #[test]
+    fn test_nor_flash_writer_full() {
+        let mut flash = MockFlash {
+            data: [0; 256],
+            erases: Vec::new(),
+        };
+        let mut writer = NorFlashWriter::<_, 8>::new(&mut flash, 0, 32).unwrap();
+        let error = writer.write_all(&[0; 40]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::WriteZero);
+        assert!(writer.take_error().is_none());
+    }
+
+    #[test]
+    fn test_nor_flash_writer_alignment() {
+        let mut flash = MockFlash {
+            data: [0; 256],
+            erases: Vec::new(),
+        };
+        assert_eq!(
+            NorFlashWriter::<_, 8>::new(&mut flash, 16, 32).unwrap_err(),
+            NorFlashErrorKind::NotAligned
+        );
+        assert_eq!(
+            NorFlashWriter::<_, 6>::new(&mut flash, 0, 32).unwrap_err(),
+            NorFlashErrorKind::NotAligned
+        );
+        assert_eq!(
+            NorFlashWriter::<_, 8>::new(&mut flash, 0, 512).unwrap_err(),
+            NorFlashErrorKind::OutOfBounds
+        );
+    }
+
+    /// Read-only flash checking the alignment of reads
+    #[derive(Debug)]
+    struct QspiFlash {
+        data: Vec<u8>,
+        reads: usize,
+    }
+
+    impl ErrorType for QspiFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for QspiFlash {
+        const READ_SIZE: usize = 4;
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> core::result::Result<(), Self::Error> {
+            embedded_storage::nor_flash::check_read(self, offset, bytes.len())?;
+            bytes.copy_from_slice(&self.data[offset as usize..offset as usize + bytes.len()]);
+            self.reads += 1;
+            Ok(())
+        }
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    #[test]
+    fn test_flash_reader_decompress() {
+        let compressed = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+        let mut data = vec![0xFF; 8];
+        data.extend_from_slice(compressed);
+        data.resize(data.len().div_ceil(4) * 4, 0xFF);
+        let mut flash = QspiFlash { data, reads: 0 };
+        let mut cache = [0; 64];
+        let mut reader =
+            FlashReader::new(&mut flash, 8, compressed.len() as u32, &mut cache).unwrap();
+        let mut output = Vec::new();
+        crate::lzma_decompress::<_, _, 4096, 8>(&mut reader, &mut output).unwrap();
+        assert_eq!(&output[..], &expected[..]);
+        assert_eq!(reader.remaining(), 0);
+        assert_eq!(flash.reads, compressed.len().div_ceil(64));
+    }
+
+    #[test]
+    fn test_flash_reader_errors() {
+        let mut flash = QspiFlash {
+            data: vec![0; 64],
+            reads: 0,
+        };
+        let mut cache = [0; 8];
+        assert_eq!(
+            FlashReader::new(&mut flash, 2, 8, &mut cache).unwrap_err(),
+            NorFlashErrorKind::NotAligned
+        );
+        assert_eq!(
+            FlashReader::new(&mut flash, 0, 8, &mut cache[..6]).unwrap_err(),
+            NorFlashErrorKind::NotAligned
+        );
+        assert_eq!(
+            FlashReader::new(&mut flash, 32, 64, &mut cache).unwrap_err(),
+            NorFlashErrorKind::OutOfBounds
+        );
+
+        // The region ends short of a read unit at the end of the flash
+        flash.data.truncate(62);
+        let mut reader = FlashReader::new(&mut flash, 56, 6, &mut cache).unwrap();
+        let error = reader.read(&mut [0; 8]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Other);
+        assert_eq!(reader.take_error(), Some(NorFlashErrorKind::OutOfBounds));
+    }
+}
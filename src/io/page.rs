@@ -0,0 +1,188 @@
+use super::chunked::{self, ChunkedWrite};
+use super::{Result, Write};
+
+/// A writer accumulating data into `PAGE` sized chunks before writing them to
+/// the inner sink, e.g. a flash programmer.
+///
+/// Every write issued to the inner sink is exactly `PAGE` bytes long. The last,
+/// incomplete page is padded (with `0xFF`, the erased state of NOR flash, by
+/// default) and written out by [`PageAlignedWriter::finish`].
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # {
+/// use lzma_rs::io::{PageAlignedWriter, Write};
+///
+/// let mut flash = Vec::new();
+/// let mut writer = PageAlignedWriter::<_, 4>::new(&mut flash);
+/// writer.write_all(b"Hello").unwrap();
+/// writer.finish().unwrap();
+/// assert_eq!(writer.pages_written(), 2);
+/// assert_eq!(flash, b"Hello\xff\xff\xff");
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct PageAlignedWriter<W, const PAGE: usize> {
+    inner: W,
+    page: [u8; PAGE],
+    /// Number of bytes buffered in `page`.
+    len: usize,
+    padding: u8,
+    pages_written: usize,
+}
+
+impl<W: Write, const PAGE: usize> PageAlignedWriter<W, PAGE> {
+    /// Create a writer padding the last page with `0xFF`.
+    pub const fn new(inner: W) -> Self {
+        Self::with_padding(inner, 0xFF)
+    }
+
+    /// Create a writer padding the last page with `padding`.
+    pub const fn with_padding(inner: W, padding: u8) -> Self {
+        Self {
+            inner,
+            page: [0; PAGE],
+            len: 0,
+            padding,
+            pages_written: 0,
+        }
+    }
+
+    /// Number of pages written to the inner sink so far, including the padded
+    /// last page once [`PageAlignedWriter::finish`] has been called.
+    pub fn pages_written(&self) -> usize {
+        self.pages_written
+    }
+
+    /// Number of bytes waiting for the current page to be completed.
+    pub fn pending(&self) -> usize {
+        self.len
+    }
+
+    /// Pad and write the incomplete page, if any, then flush the inner sink.
+    pub fn finish(&mut self) -> Result<()> {
+        if self.len > 0 {
+            let padding = self.padding;
+            self.page[self.len..].iter_mut().for_each(|v| *v = padding);
+            self.write_page()?;
+        }
+        self.inner.flush()
+    }
+
+    /// Get a reference to the inner sink.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner sink.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consume the writer and return the inner sink. Buffered data that does
+    /// not form a whole page is discarded; call [`PageAlignedWriter::finish`]
+    /// first to write it out.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn write_page(&mut self) -> Result<()> {
+        self.inner.write_all(&self.page)?;
+        self.len = 0;
+        self.pages_written += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write, const PAGE: usize> ChunkedWrite for PageAlignedWriter<W, PAGE> {
+    fn chunk(&mut self) -> (&mut [u8], &mut usize) {
+        (&mut self.page, &mut self.len)
+    }
+
+    fn hand_over(&mut self) -> Result<()> {
+        self.write_page()
+    }
+}
+
+impl<W: Write, const PAGE: usize> Write for PageAlignedWriter<W, PAGE> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        chunked::write(self, buf)
+    }
+
+    /// Write a full page left by a failed write, if any, and flush the inner
+    /// sink. An incomplete page is kept buffered.
+    fn flush(&mut self) -> Result<()> {
+        chunked::flush(self)?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_page_aligned_writer() {
+        let mut writer = PageAlignedWriter::<_, 4>::with_padding(Vec::new(), 0);
+        writer.write_all(b"ab").unwrap();
+        assert_eq!(writer.pages_written(), 0);
+        writer.write_all(b"cdefghij").unwrap();
+        assert_eq!(writer.pages_written(), 2);
+        assert_eq!(writer.pending(), 2);
+        writer.finish().unwrap();
+        assert_eq!(writer.pages_written(), 3);
+        assert_eq!(writer.pending(), 0);
+        assert_eq!(writer.into_inner(), b"abcdefghij\0\0");
+    }
+
+    #[test]
+    fn test_page_aligned_writer_exact() {
+        let mut writer = PageAlignedWriter::<_, 4>::new(Vec::new());
+        writer.write_all(b"abcd").unwrap();
+        writer.finish().unwrap();
+        assert_eq!(writer.pages_written(), 1);
+        assert_eq!(writer.into_inner(), b"abcd");
+    }
+
+    /// Sink failing the first `failures` writes.
+    struct Flaky {
+        failures: usize,
+        data: Vec<u8>,
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(std::io::ErrorKind::Other.into());
+            }
+            self.data.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_page_aligned_writer_error() {
+        let mut writer = PageAlignedWriter::<_, 4>::new(Flaky {
+            failures: 2,
+            data: Vec::new(),
+        });
+        // The bytes completing the page are taken even though writing it
+        // fails, and the error is reported by the next call
+        assert_eq!(writer.write(b"abcdef").unwrap(), 4);
+        assert_eq!(writer.pending(), 4);
+        assert_eq!(writer.pages_written(), 0);
+        assert!(writer.write(b"ef").is_err());
+        assert_eq!(writer.pending(), 4);
+
+        writer.get_mut().failures = 1;
+        assert!(writer.flush().is_err());
+        assert_eq!(writer.write(b"ef").unwrap(), 2);
+        writer.finish().unwrap();
+        assert_eq!(writer.pages_written(), 2);
+        assert_eq!(writer.into_inner().data, b"abcdef\xff\xff");
+    }
+}
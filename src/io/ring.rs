@@ -0,0 +1,346 @@
+use super::{BufRead, Read, Result};
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+
+/// Number of bytes moved out of a queue at once by [`Staging::refill`].
+const STAGING_LEN: usize = 32;
+
+/// Small buffer of bytes moved out of a queue, handed out through
+/// [`BufRead`] by the readers draining a queue byte by byte:
+/// [`RingBufferReader`] and the `heapless` queue reader.
+#[derive(Debug)]
+pub(super) struct Staging {
+    buf: [u8; STAGING_LEN],
+    pos: usize,
+    len: usize,
+}
+
+impl Staging {
+    pub(super) const fn new() -> Self {
+        Self {
+            buf: [0; STAGING_LEN],
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    /// Number of bytes not consumed yet.
+    pub(super) fn remaining(&self) -> usize {
+        self.len - self.pos
+    }
+
+    /// Bytes not consumed yet.
+    pub(super) fn data(&self) -> &[u8] {
+        &self.buf[self.pos..self.len]
+    }
+
+    pub(super) fn consume(&mut self, amt: usize) {
+        self.pos = core::cmp::min(self.pos + amt, self.len);
+    }
+
+    /// Replace the content of the buffer with the bytes returned by `next`,
+    /// until it returns `None` or the buffer is full. Returns the number of
+    /// bytes taken.
+    pub(super) fn refill(&mut self, mut next: impl FnMut() -> Option<u8>) -> usize {
+        self.pos = 0;
+        self.len = 0;
+        for byte in self.buf.iter_mut() {
+            match next() {
+                Some(value) => *byte = value,
+                None => break,
+            }
+            self.len += 1;
+        }
+        self.len
+    }
+}
+
+/// Single-producer single-consumer ring buffer of `N` bytes, meant to be
+/// filled from an interrupt handler (e.g. a UART/SPI DMA completion ISR) and
+/// drained by the decoder.
+///
+/// The buffer is owned by the caller (typically a `static`) and split into a
+/// [`RingBufferProducer`] and a [`RingBufferReader`] with
+/// [`RingBuffer::split`]. Only atomic loads and stores are used, so this also
+/// works on targets without compare-and-swap instructions.
+///
+/// ```
+/// use lzma_rs::io::{BufRead, RingBuffer};
+///
+/// let mut ring = RingBuffer::<16>::new();
+/// let (mut producer, mut reader) = ring.split();
+/// assert_eq!(producer.push(b"data"), 4);
+/// drop(producer);
+/// assert_eq!(reader.fill_buf().unwrap(), b"data");
+/// ```
+#[derive(Debug)]
+pub struct RingBuffer<const N: usize> {
+    buf: [AtomicU8; N],
+    /// Total number of bytes pushed by the producer, modulo `2 * N`.
+    head: AtomicUsize,
+    /// Total number of bytes taken by the reader, modulo `2 * N`.
+    tail: AtomicUsize,
+    /// Set when the producer is dropped; no more data will arrive.
+    closed: AtomicBool,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    /// Create an empty ring buffer.
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicU8 = AtomicU8::new(0);
+        Self {
+            buf: [ZERO; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Empty the buffer and split it into the producer and the consumer
+    /// handles.
+    pub fn split(&mut self) -> (RingBufferProducer<'_, N>, RingBufferReader<'_, N>) {
+        self.head.store(0, Ordering::Relaxed);
+        self.tail.store(0, Ordering::Relaxed);
+        self.closed.store(false, Ordering::Relaxed);
+        let ring: &Self = self;
+        (
+            RingBufferProducer { ring },
+            RingBufferReader {
+                ring,
+                staging: Staging::new(),
+            },
+        )
+    }
+}
+
+/// Number of bytes between the positions `tail` and `head`, both reduced
+/// modulo `2 * N` so that a full buffer is told apart from an empty one for
+/// any `N`.
+fn used<const N: usize>(head: usize, tail: usize) -> usize {
+    if head >= tail {
+        head - tail
+    } else {
+        2 * N - (tail - head)
+    }
+}
+
+/// Position `amt` bytes after `pos`, modulo `2 * N`.
+fn advance<const N: usize>(pos: usize, amt: usize) -> usize {
+    let pos = pos + amt;
+    if pos >= 2 * N {
+        pos - 2 * N
+    } else {
+        pos
+    }
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Producer side of a [`RingBuffer`].
+///
+/// Dropping the producer marks the end of the input for the reader.
+#[derive(Debug)]
+pub struct RingBufferProducer<'a, const N: usize> {
+    ring: &'a RingBuffer<N>,
+}
+
+impl<'a, const N: usize> RingBufferProducer<'a, N> {
+    /// Number of bytes that can be pushed without overwriting unread data.
+    pub fn free(&self) -> usize {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        N - used::<N>(head, tail)
+    }
+
+    /// Push as many bytes of `data` as fit into the buffer. Returns the number
+    /// of bytes pushed.
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let amt = core::cmp::min(self.free(), data.len());
+        for (i, &byte) in data[..amt].iter().enumerate() {
+            self.ring.buf[(head + i) % N].store(byte, Ordering::Relaxed);
+        }
+        self.ring
+            .head
+            .store(advance::<N>(head, amt), Ordering::Release);
+        amt
+    }
+}
+
+impl<'a, const N: usize> Drop for RingBufferProducer<'a, N> {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::Release);
+    }
+}
+
+/// Consumer side of a [`RingBuffer`], implementing [`BufRead`].
+///
+/// When the buffer is empty, reads spin until the producer pushes more data.
+/// End of input is reported once the producer has been dropped and all the
+/// data has been read.
+#[derive(Debug)]
+pub struct RingBufferReader<'a, const N: usize> {
+    ring: &'a RingBuffer<N>,
+    /// Bytes already moved out of the ring buffer.
+    staging: Staging,
+}
+
+impl<'a, const N: usize> RingBufferReader<'a, N> {
+    /// Number of bytes available without waiting for the producer.
+    pub fn available(&self) -> usize {
+        let head = self.ring.head.load(Ordering::Acquire);
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        used::<N>(head, tail) + self.staging.remaining()
+    }
+
+    /// Whether the producer has been dropped. Data pushed before may still
+    /// be unread.
+    pub fn is_closed(&self) -> bool {
+        self.ring.closed.load(Ordering::Acquire)
+    }
+
+    /// Return the data available without waiting for the producer, like
+    /// [`BufRead::fill_buf`] but never spinning. An empty slice does not
+    /// mean the end of the input unless [`RingBufferReader::is_closed`]
+    /// returned `true` before the call.
+    pub fn fill_available(&mut self) -> &[u8] {
+        if self.staging.remaining() == 0 {
+            self.try_refill();
+        }
+        self.staging.data()
+    }
+
+    /// Move the data available in the ring buffer into the empty staging
+    /// buffer. Returns `false` if there was none.
+    fn try_refill(&mut self) -> bool {
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        let head = self.ring.head.load(Ordering::Acquire);
+        let available = used::<N>(head, tail);
+        if available == 0 {
+            return false;
+        }
+        let ring = self.ring;
+        let mut i = 0;
+        let amt = self.staging.refill(|| {
+            if i == available {
+                return None;
+            }
+            let byte = ring.buf[(tail + i) % N].load(Ordering::Relaxed);
+            i += 1;
+            Some(byte)
+        });
+        self.ring
+            .tail
+            .store(advance::<N>(tail, amt), Ordering::Release);
+        true
+    }
+
+    /// Move data from the ring buffer into the staging buffer.
+    ///
+    /// Returns `false` if the producer has been dropped and no data is left.
+    fn refill(&mut self) -> bool {
+        loop {
+            // `closed` must be loaded before `head`, so that no data pushed
+            // before closing is missed.
+            let closed = self.is_closed();
+            if self.try_refill() {
+                return true;
+            }
+            if closed {
+                return false;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl<'a, const N: usize> Read for RingBufferReader<'a, N> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = Read::read(&mut self.fill_buf()?, buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'a, const N: usize> BufRead for RingBufferReader<'a, N> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        if self.staging.remaining() == 0 && !self.refill() {
+            return Ok(&[]);
+        }
+        Ok(self.staging.data())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.staging.consume(amt);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer_wraparound() {
+        let mut ring = RingBuffer::<5>::new();
+        let (mut producer, mut reader) = ring.split();
+        assert_eq!(producer.push(b"abc"), 3);
+        assert_eq!(reader.fill_buf().unwrap(), b"abc");
+        reader.consume(3);
+        assert_eq!(producer.free(), 5);
+        assert_eq!(producer.push(b"defghij"), 5);
+        assert_eq!(producer.free(), 0);
+        assert_eq!(reader.available(), 5);
+        drop(producer);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"defgh");
+    }
+
+    #[test]
+    fn test_ring_buffer_many_wraps() {
+        // Neither 3 nor 7 divide the buffer length, so the positions wrap at
+        // every offset
+        let mut ring = RingBuffer::<5>::new();
+        let (mut producer, mut reader) = ring.split();
+        let data: Vec<u8> = (0..=255).collect();
+        let mut read = Vec::new();
+        for chunk in data.chunks(3) {
+            assert_eq!(producer.push(chunk), chunk.len());
+            while reader.available() > 0 {
+                let buf = reader.fill_available();
+                let n = core::cmp::min(buf.len(), 7);
+                read.extend_from_slice(&buf[..n]);
+                reader.consume(n);
+            }
+            assert_eq!(producer.free(), 5);
+        }
+        assert_eq!(read, data);
+    }
+
+    #[cfg(feature = "encoder")]
+    #[test]
+    fn test_ring_buffer_decompress() {
+        let mut compressed = Vec::new();
+        let data = b"Hello world, hello ring buffer! ".repeat(64);
+        crate::lzma_compress(&mut &data[..], &mut compressed).unwrap();
+
+        let mut ring = RingBuffer::<8>::new();
+        let (mut producer, mut reader) = ring.split();
+        let mut decomp = Vec::new();
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                let mut input = &compressed[..];
+                while !input.is_empty() {
+                    let n = producer.push(input);
+                    input = &input[n..];
+                }
+            });
+            crate::lzma_decompress::<_, _, 4096, 66>(&mut reader, &mut decomp).unwrap();
+        });
+        assert_eq!(decomp, data);
+    }
+}
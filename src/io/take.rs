@@ -0,0 +1,118 @@
+use super::{BufRead, Read, Result};
+
+/// A reader yielding at most `limit` bytes of an inner [`BufRead`], then
+/// reporting the end of the input.
+///
+/// Like `std::io::Take`, but keeping the `fill_buf`/`consume` interface the
+/// decoder reads from. Containers often record the exact compressed size of
+/// an entry; wrapping the input keeps the decoder from running past the end
+/// of the entry:
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # {
+/// use lzma_rs::io::TakeReader;
+///
+/// let compressed = include_bytes!("../../tests/files/foo.txt.lzma");
+/// let mut record = compressed.to_vec();
+/// record.extend_from_slice(b"next record");
+///
+/// let mut input = TakeReader::new(&record[..], compressed.len() as u64);
+/// let mut decompressed = Vec::new();
+/// lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut input, &mut decompressed).unwrap();
+/// assert_eq!(input.into_inner(), b"next record");
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct TakeReader<R> {
+    inner: R,
+    limit: u64,
+}
+
+impl<R> TakeReader<R> {
+    /// Create a reader yielding at most `limit` bytes of `inner`.
+    pub const fn new(inner: R, limit: u64) -> Self {
+        Self { inner, limit }
+    }
+
+    /// Number of bytes that can still be read before reaching the limit.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Change the number of bytes that can still be read.
+    pub fn set_limit(&mut self, limit: u64) {
+        self.limit = limit;
+    }
+
+    /// Get a reference to the inner reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner reader. Reading from it directly
+    /// does not update the limit.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consume the reader, returning the inner reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: BufRead> Read for TakeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = Read::read(&mut self.fill_buf()?, buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for TakeReader<R> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        if self.limit == 0 {
+            return Ok(&[]);
+        }
+        let buf = self.inner.fill_buf()?;
+        // `limit` is below `buf.len()` when truncating, so it fits in `usize`
+        let len = if (buf.len() as u64) > self.limit {
+            self.limit as usize
+        } else {
+            buf.len()
+        };
+        Ok(buf.get(..len).unwrap_or(buf))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // Callers only consume what `fill_buf` returned, within the limit
+        let amt = core::cmp::min(amt as u64, self.limit);
+        self.limit -= amt;
+        self.inner.consume(amt as usize);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_take_reader() {
+        let mut reader = TakeReader::new(&b"abcdef"[..], 4);
+        assert_eq!(reader.fill_buf().unwrap(), b"abcd");
+        reader.consume(1);
+        assert_eq!(reader.limit(), 3);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"bcd");
+        assert!(reader.fill_buf().unwrap().is_empty());
+        assert_eq!(reader.into_inner(), b"ef");
+
+        let mut reader = TakeReader::new(&b"ab"[..], 4);
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, b"ab");
+        assert_eq!(reader.limit(), 2);
+    }
+}
@@ -0,0 +1,80 @@
+use super::{Result, Write};
+
+/// A writer duplicating everything written to it into two sinks.
+///
+/// Typical use is to feed decompressed data to a digest computation and to
+/// the final storage (e.g. a flash programmer) in a single decompression
+/// pass:
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # {
+/// use lzma_rs::io::{TeeWriter, Write};
+///
+/// let mut image = Vec::new();
+/// let mut copy = Vec::new();
+/// let mut tee = TeeWriter::new(&mut image, &mut copy);
+/// tee.write_all(b"payload").unwrap();
+/// assert_eq!(image, copy);
+/// # }
+/// ```
+///
+/// Every call writes the whole buffer to `A` and then to `B`, so both sinks
+/// always observe the same byte sequence. If a sink fails, the error is
+/// returned and the sinks may diverge by the content of the failed call.
+#[derive(Debug)]
+pub struct TeeWriter<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Write, B: Write> TeeWriter<A, B> {
+    /// Create a writer forwarding data to `first` and then to `second`.
+    pub const fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    /// Get references to the underlying sinks.
+    pub fn get_ref(&self) -> (&A, &B) {
+        (&self.first, &self.second)
+    }
+
+    /// Get mutable references to the underlying sinks.
+    pub fn get_mut(&mut self) -> (&mut A, &mut B) {
+        (&mut self.first, &mut self.second)
+    }
+
+    /// Consume the writer and return the underlying sinks.
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: Write, B: Write> Write for TeeWriter<A, B> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.first.write_all(buf)?;
+        self.second.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tee_writer() {
+        let mut tee = TeeWriter::new(Vec::new(), Vec::new());
+        tee.write_all(b"Hello ").unwrap();
+        tee.write_all(b"world").unwrap();
+        tee.flush().unwrap();
+        let (first, second) = tee.into_inner();
+        assert_eq!(first, b"Hello world");
+        assert_eq!(second, b"Hello world");
+    }
+}
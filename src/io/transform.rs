@@ -0,0 +1,130 @@
+use super::{Result, Write};
+
+/// Number of bytes transformed at once by [`TransformWriter`].
+const STAGING_LEN: usize = 64;
+
+/// Transform applied in place to decompressed data before it reaches the
+/// output sink, e.g. to undo an XOR obfuscation or fix up the endianness of
+/// data that a vendor applied before compression.
+///
+/// Blocks are given in order, with the `offset` of their first byte in the
+/// decompressed data, so that a transform does not depend on the block
+/// boundaries. Closures taking `(offset, block)` implement this trait.
+pub trait OutputTransform {
+    /// Transform `block`, which starts `offset` bytes into the decompressed
+    /// data.
+    fn apply(&mut self, offset: u64, block: &mut [u8]);
+}
+
+impl<F: FnMut(u64, &mut [u8])> OutputTransform for F {
+    fn apply(&mut self, offset: u64, block: &mut [u8]) {
+        self(offset, block)
+    }
+}
+
+/// A writer applying an [`OutputTransform`] to the data written to it before
+/// forwarding it to the inner sink. Pass it as the output of a
+/// [`Stream`](crate::decompress::Stream) or any decompression function.
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # {
+/// use lzma_rs::io::{TransformWriter, Write};
+///
+/// let key = b"key";
+/// let xor = |offset: u64, block: &mut [u8]| {
+///     for (i, byte) in block.iter_mut().enumerate() {
+///         *byte ^= key[(offset as usize + i) % key.len()];
+///     }
+/// };
+/// let mut writer = TransformWriter::new(Vec::new(), xor);
+/// writer.write_all(&[b'k' ^ 1, b'e' ^ 2]).unwrap();
+/// writer.write_all(&[b'y' ^ 3]).unwrap();
+/// assert_eq!(writer.into_inner(), [1, 2, 3]);
+/// # }
+/// ```
+///
+/// Data goes through a small staging buffer, as the decoder's own buffers
+/// must not be modified. The output of
+/// [`lzma_decompress_to_slice_with_transform`](crate::lzma_decompress_to_slice_with_transform)
+/// is transformed in place instead.
+#[derive(Debug)]
+pub struct TransformWriter<W, T> {
+    inner: W,
+    transform: T,
+    /// Number of bytes transformed so far.
+    offset: u64,
+    staging: [u8; STAGING_LEN],
+}
+
+impl<W: Write, T: OutputTransform> TransformWriter<W, T> {
+    /// Create a writer applying `transform` to the data forwarded to
+    /// `inner`.
+    pub const fn new(inner: W, transform: T) -> Self {
+        Self {
+            inner,
+            transform,
+            offset: 0,
+            staging: [0; STAGING_LEN],
+        }
+    }
+
+    /// Number of bytes transformed and forwarded so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Get a reference to the underlying sink.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying sink.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consume the writer and return the underlying sink.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write, T: OutputTransform> Write for TransformWriter<W, T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        for chunk in buf.chunks(STAGING_LEN) {
+            let staging = &mut self.staging[..chunk.len()];
+            staging.copy_from_slice(chunk);
+            self.transform.apply(self.offset, staging);
+            self.inner.write_all(staging)?;
+            self.offset += chunk.len() as u64;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transform_writer_offsets() {
+        let data: Vec<u8> = (0..200).collect();
+        let mut writer = TransformWriter::new(Vec::new(), |offset: u64, block: &mut [u8]| {
+            for (i, byte) in block.iter_mut().enumerate() {
+                assert_eq!(u64::from(*byte), offset + i as u64);
+                *byte = !*byte;
+            }
+        });
+        for chunk in data.chunks(70) {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(writer.offset(), 200);
+        let expected: Vec<u8> = data.iter().map(|b| !b).collect();
+        assert_eq!(writer.into_inner(), expected);
+    }
+}
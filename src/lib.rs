@@ -4,45 +4,163 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 #![deny(missing_docs)]
 #![deny(missing_debug_implementations)]
-#![deny(unsafe_code)]
+#![forbid(unsafe_code)]
+// `io::Error::other` is not available in the `no_std` `io` facade
+#![allow(clippy::io_other_error)]
 
 #[macro_use]
 mod macros;
 
+pub mod checksum;
+#[cfg(feature = "container")]
+pub mod container;
 mod decode;
-#[cfg(feature = "std")]
+#[cfg(any(feature = "encoder", feature = "telemetry-encoder"))]
 mod encode;
 pub mod error;
+pub mod properties;
 
 /// Module exposing `io` related traits and impls
+///
+/// The `std::io` traits with the `std` feature, an owned minimal equivalent
+/// otherwise. `std::io` readers and writers, e.g. a `BufReader<File>`, are
+/// therefore accepted by the codecs directly, without an adapter.
 pub mod io;
 
 /// Compression helpers.
-#[cfg(feature = "std")]
+#[cfg(any(feature = "encoder", feature = "telemetry-encoder"))]
 pub mod compress {
+    #[cfg(feature = "encoder")]
+    pub use crate::encode::header::rewrite_header;
+    #[cfg(feature = "encoder")]
     pub use crate::encode::options::*;
+    #[cfg(feature = "telemetry-encoder")]
+    pub use crate::encode::session::CompressSession;
+    #[cfg(feature = "telemetry-encoder")]
+    pub use crate::encode::telemetry::{
+        BorrowedWindowEncoder, GenericTelemetryEncoder, TelemetryEncoder,
+    };
+}
+
+/// Range coding primitives used by LZMA, for other range-coded formats.
+///
+/// Values are modelled with adaptive probabilities: single bits with a
+/// `u16` probability, multi-bit values with a [`BitTree`](rangecoder::BitTree),
+/// and LZMA match lengths with a [`LenDecoder`](rangecoder::LenDecoder). The
+/// encode side is available with the `encoder` or `telemetry-encoder`
+/// feature.
+///
+/// ```
+/// # #[cfg(feature = "encoder")]
+/// # {
+/// use lzma_rs::rangecoder::{BitTree, RangeDecoder, RangeEncoder};
+///
+/// let mut encoded = Vec::new();
+/// let mut enc = RangeEncoder::new(&mut encoded);
+/// let mut flag = 0x400;
+/// let mut tree = BitTree::<16>::new();
+/// tree.reset();
+/// enc.encode_bit(&mut flag, true).unwrap();
+/// tree.encode(&mut enc, 11).unwrap();
+/// enc.finish().unwrap();
+///
+/// let mut input = &encoded[..];
+/// let mut dec = RangeDecoder::new(&mut input).unwrap();
+/// let mut flag = 0x400;
+/// let mut tree = BitTree::<16>::new();
+/// tree.reset();
+/// assert!(dec.decode_bit(&mut flag, true).unwrap());
+/// assert_eq!(tree.parse(&mut dec, true).unwrap(), 11);
+/// # }
+/// ```
+pub mod rangecoder {
+    pub use crate::decode::rangecoder::{BitTree, LenDecoder, RangeDecoder};
+    #[cfg(any(feature = "encoder", feature = "telemetry-encoder"))]
+    pub use crate::encode::rangecoder::{LenEncoder, RangeEncoder, RangeEncoderState};
 }
 
 /// Decompression helpers.
 pub mod decompress {
+    #[cfg(feature = "std")]
+    pub use crate::decode::analysis::{analyze_dict_size, DictAnalysis};
+    #[cfg(feature = "futures-stream")]
+    pub use crate::decode::bytes_stream::BytesDecoder;
+    pub use crate::decode::chunks::{iter_chunks, OutputChunk, OutputChunks};
+    #[cfg(feature = "stream")]
+    pub use crate::decode::feed::{FeedDecoder, FeedStatus, Feeder};
+    pub use crate::decode::format::{detect_format, Format};
+    #[cfg(feature = "std")]
+    pub use crate::decode::fuzz::fuzz_decode;
+    #[cfg(feature = "instrumentation")]
+    pub use crate::decode::instrumentation::Instrumentation;
+    pub use crate::decode::lzma::LiteralCoder;
+    pub use crate::decode::lzma::LzmaParams;
+    #[cfg(feature = "stream")]
+    pub use crate::decode::lzma2::Lzma2Stream;
+    pub use crate::decode::memory::{worst_case_memory, MemoryFootprint};
     pub use crate::decode::options::*;
+    pub use crate::decode::session::DecompressSession;
+    pub use crate::decode::state::DecodeSummary;
+    pub use crate::decode::state::DecoderState;
+    #[cfg(feature = "stats")]
+    pub use crate::decode::stats::DecodeStats;
+    #[cfg(feature = "stream")]
+    pub use crate::decode::stream::BorrowedDictStream;
+    #[cfg(feature = "stream")]
+    pub use crate::decode::stream::BorrowedProbsStream;
+    #[cfg(feature = "stream")]
+    pub use crate::decode::stream::DebugSnapshot;
+    #[cfg(feature = "stream")]
+    pub use crate::decode::stream::DynStream;
+    #[cfg(feature = "stream")]
+    pub use crate::decode::stream::FinishSummary;
+    #[cfg(feature = "stream")]
+    pub use crate::decode::stream::GenericStream;
+    #[cfg(feature = "stream")]
+    pub use crate::decode::stream::ResyncStatus;
     #[cfg(feature = "stream")]
     pub use crate::decode::stream::Stream;
     #[cfg(feature = "stream")]
+    pub use crate::decode::stream::StreamDecoder;
+    #[cfg(feature = "stream")]
     pub use crate::decode::stream::StreamStatus;
+    #[cfg(feature = "stream")]
+    pub use crate::decode::stream::StreamTmp;
+    #[cfg(feature = "stream")]
+    pub use crate::decode::stream::WriteOutcome;
+    #[cfg(feature = "stream")]
+    pub use crate::decode::stream::WriteOverrides;
+    #[cfg(feature = "async")]
+    pub use crate::decode::yielding::{yield_now, YieldAfter, YieldHook, YieldNow};
 }
 
 /// Decompress LZMA data with default
-/// [`Options`](decompress/struct.Options.html).
+/// [`Options`](decompress/struct.Options.html). Returns the number of bytes
+/// decompressed, which may exceed 4 GiB even on 32-bit targets.
+///
+/// Like the other one-shot functions, this takes any [`io::BufRead`] by
+/// value: a compressed slice can be passed as is, and a reader that must be
+/// used afterwards by mutable reference.
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # {
+/// let compressed: &[u8] = include_bytes!("../tests/files/foo.txt.lzma");
+/// let mut decompressed = Vec::new();
+/// lzma_rs::lzma_decompress::<_, _, 4096, 8>(compressed, &mut decompressed)?;
+/// assert_eq!(decompressed, include_bytes!("../tests/files/foo.txt"));
+/// # }
+/// # Ok::<(), lzma_rs::error::Error>(())
+/// ```
 pub fn lzma_decompress<
     R: io::BufRead,
     W: io::Write,
     const DICT_MEM_LIMIT: usize,
     const PROBS_MEM_LIMIT: usize,
 >(
-    input: &mut R,
+    input: R,
     output: &mut W,
-) -> error::Result<()> {
+) -> error::Result<u64> {
     lzma_decompress_with_options::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(
         input,
         output,
@@ -50,56 +168,469 @@ pub fn lzma_decompress<
     )
 }
 
-/// Decompress LZMA data with the provided options.
+/// Decompress LZMA data with the provided options. Returns the number of
+/// bytes decompressed.
 pub fn lzma_decompress_with_options<
     R: io::BufRead,
     W: io::Write,
     const DICT_MEM_LIMIT: usize,
     const PROBS_MEM_LIMIT: usize,
 >(
-    input: &mut R,
+    input: R,
     output: &mut W,
     options: &decompress::Options,
+) -> error::Result<u64> {
+    let mut state =
+        decompress::DecoderState::<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>::new_with_options(options);
+    lzma_decompress_with_state(&mut state, input, output)
+}
+
+/// Decompress LZMA data reusing `state`, with the options it was created
+/// with. Returns the number of bytes decompressed.
+///
+/// Avoids building a new decoder state, which is large, for every call; see
+/// [`decompress::DecoderState`].
+pub fn lzma_decompress_with_state<
+    R: io::BufRead,
+    W: io::Write,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    state: &mut decompress::DecoderState<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>,
+    mut input: R,
+    output: &mut W,
+) -> error::Result<u64> {
+    let params = state.read_header(&mut input)?;
+    state.prepare(params)?;
+    state.decompress_prepared(&mut input, output)
+}
+
+/// Decompress LZMA data with the provided options and return the number of
+/// bytes consumed and produced.
+///
+/// Useful when the stream is embedded in a larger blob, to find where it
+/// ends. Unlike the other functions, data may follow the stream in `input`
+/// unless [`decompress::Options::trailing_bytes`] says otherwise; the stream
+/// must then end at the unpacked size given in its header or with an end
+/// marker, and `input` is left right after it:
+///
+/// ```
+/// # fn main() -> lzma_rs::error::Result<()> {
+/// # #[cfg(feature = "std")]
+/// # {
+/// let mut blob = include_bytes!("../tests/files/foo.txt.lzma").to_vec();
+/// blob.extend_from_slice(b"next record");
+///
+/// let mut input = &blob[..];
+/// let mut decompressed = Vec::new();
+/// let summary = lzma_rs::lzma_decompress_with_summary::<_, _, 4096, 8>(
+///     &mut input,
+///     &mut decompressed,
+///     &lzma_rs::decompress::Options::default(),
+/// )?;
+/// assert_eq!(&blob[summary.consumed as usize..], b"next record");
+/// assert_eq!(summary.produced, decompressed.len() as u64);
+/// # }
+/// # Ok(())
+/// # }
+/// ```
+pub fn lzma_decompress_with_summary<
+    R: io::BufRead,
+    W: io::Write,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    mut input: R,
+    output: &mut W,
+    options: &decompress::Options,
+) -> error::Result<decompress::DecodeSummary> {
+    let mut input = decode::util::CountingReader::new(&mut input);
+    let mut state =
+        decompress::DecoderState::<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>::new_with_options(options);
+    state.decoder.set_trailing_data(true);
+    let params = state.read_header(&mut input)?;
+    state.prepare(params)?;
+    let produced = state.decompress_prepared(&mut input, output)?;
+    let trailing_bytes = state.trailing_bytes();
+    Ok(decompress::DecodeSummary {
+        consumed: input.count - trailing_bytes,
+        produced,
+        trailing_bytes,
+    })
+}
+
+/// Decompress LZMA data pulled from `read_fn` and push the decompressed data
+/// to `write_fn`, with default [`Options`](decompress/struct.Options.html).
+///
+/// `read_fn` fills the given buffer with compressed data and returns the
+/// number of bytes read, `0` meaning the end of the input, like
+/// [`io::Read::read`]. `write_fn` receives the decompressed data in chunks.
+/// Errors returned by either closure stop decompression and are passed
+/// through.
+///
+/// This drives a [`decompress::Stream`] on the stack, which is convenient
+/// to read from a driver and write to flash without handling the stream
+/// state machine.
+///
+/// ```
+/// # fn main() -> lzma_rs::error::Result<()> {
+/// let mut input: &[u8] = include_bytes!("../tests/files/foo.txt.lzma");
+/// let mut decompressed = Vec::new();
+/// lzma_rs::lzma_decompress_chunked::<_, _, 4096, 8>(
+///     |buf| {
+///         let n = buf.len().min(input.len());
+///         buf[..n].copy_from_slice(&input[..n]);
+///         input = &input[n..];
+///         Ok(n)
+///     },
+///     |data| {
+///         decompressed.extend_from_slice(data);
+///         Ok(())
+///     },
+/// )?;
+/// assert_eq!(decompressed, include_bytes!("../tests/files/foo.txt"));
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "stream")]
+pub fn lzma_decompress_chunked<
+    I: FnMut(&mut [u8]) -> io::Result<usize>,
+    O: FnMut(&[u8]) -> io::Result<()>,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    mut read_fn: I,
+    write_fn: O,
 ) -> error::Result<()> {
+    /// Adapts `write_fn` to [`io::Write`].
+    struct FnWriter<O>(O);
+
+    impl<O: FnMut(&[u8]) -> io::Result<()>> io::Write for FnWriter<O> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            (self.0)(buf)?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut stream = decompress::Stream::<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>::new();
+    stream.reset();
+    let mut output = FnWriter(write_fn);
+    let mut buf = [0; 256];
+    loop {
+        let n = read_fn(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let chunk = buf.get(..n).ok_or_else(decode::util::out_of_bounds)?;
+        stream.write_all(&mut output, chunk)?;
+    }
+    stream.finish(&mut output)
+}
+
+/// Decompress LZMA data with the provided options and return statistics
+/// about the decoded symbols.
+///
+/// Useful to pick the smallest dictionary size a given input needs, see
+/// [`decompress::DecodeStats::dict_high_water`].
+#[cfg(feature = "stats")]
+pub fn lzma_decompress_with_stats<
+    R: io::BufRead,
+    W: io::Write,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    mut input: R,
+    output: &mut W,
+    options: &decompress::Options,
+) -> error::Result<decompress::DecodeStats> {
     use crate::decode::lzbuffer::LzBuffer;
     use crate::decode::lzbuffer::LzCircularBuffer;
-    let params = decode::lzma::LzmaParams::read_header(input, options)?;
-    let mut decoder =
-        decode::lzma::DecoderState::<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>::new();
+    let params = decode::lzma::LzmaParams::read_header(&mut input, options)?;
+    let mut decoder = decode::lzma::DecoderState::<
+        LzCircularBuffer<[u8; DICT_MEM_LIMIT]>,
+        [decode::lzma::LiteralCoder; PROBS_MEM_LIMIT],
+    >::new();
     decoder.reset();
     decoder.set_params(params)?;
 
-    let mut rangecoder = decode::rangecoder::RangeDecoder::new(input)
+    let mut rangecoder = decode::rangecoder::RangeDecoder::new(&mut input)
         .map_err(|_| error::lzma::LzmaError::DataStreamIsTooShort)?;
     decoder.process(output, &mut rangecoder)?;
     decoder.output.finish(output)?;
+    Ok(decoder.stats)
+}
+
+/// Decompress LZMA data with default
+/// [`Options`](decompress/struct.Options.html) and check that the SHA-256
+/// digest of the decompressed data equals `expected_sha256`.
+///
+/// Decompressed data is written to `output` as it is produced; on
+/// [`error::Error::DigestMismatch`] the caller must discard it.
+#[cfg(feature = "sha2")]
+pub fn lzma_decompress_verified<
+    R: io::BufRead,
+    W: io::Write,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    input: R,
+    output: &mut W,
+    expected_sha256: &[u8; 32],
+) -> error::Result<()> {
+    let mut writer = io::DigestWriter::<_, sha2::Sha256>::new(output);
+    lzma_decompress::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(input, &mut writer)?;
+    let (_, digest) = writer.finalize();
+    if digest[..] != expected_sha256[..] {
+        return Err(error::Error::DigestMismatch);
+    }
     Ok(())
 }
 
+/// Decompress LZMA data straight into the `output` slice with default
+/// [`Options`](decompress/struct.Options.html).
+///
+/// See [`lzma_decompress_to_slice_with_options`].
+pub fn lzma_decompress_to_slice<R: io::BufRead, const PROBS_MEM_LIMIT: usize>(
+    input: R,
+    output: &mut [u8],
+) -> error::Result<usize> {
+    lzma_decompress_to_slice_with_options::<_, PROBS_MEM_LIMIT>(
+        input,
+        output,
+        &decompress::Options::default(),
+    )
+}
+
+/// Decompress LZMA data straight into the `output` slice with the provided
+/// options. Returns the number of bytes written to `output`.
+///
+/// The `output` slice itself is used as the dictionary, so no dictionary
+/// buffer is needed and the dictionary size of the stream is not limited.
+/// Fails with [`error::lzma::LzmaError::UnpackedSizeTooLarge`] if the
+/// unpacked size in the header does not fit into `output`, and with
+/// [`error::Error::OutputBufferTooSmall`] if the decompressed data of a
+/// stream of unknown size does not fit.
+///
+/// Compressed data given as `&[u8]`, e.g. an image in memory-mapped flash on
+/// an execute-in-place target, is read in place: the range decoder takes
+/// each byte straight from the slice, and the input is never copied. Pass it
+/// as `&mut &[u8]` to have the slice advanced past the stream. This holds
+/// for all decompression functions; the streaming decoder only copies the
+/// few bytes of a symbol or header split across two writes.
+///
+/// ```
+/// // e.g. placed in flash by the linker
+/// static IMAGE: &[u8] = include_bytes!("../tests/files/foo.txt.lzma");
+///
+/// let mut input = IMAGE;
+/// let mut output = vec![0; 1 << 20];
+/// let len = lzma_rs::lzma_decompress_to_slice_with_options::<_, 8>(
+///     &mut input,
+///     &mut output,
+///     &lzma_rs::decompress::Options::default(),
+/// )?;
+/// assert_eq!(&output[..len], include_bytes!("../tests/files/foo.txt"));
+/// assert!(input.is_empty());
+/// # Ok::<(), lzma_rs::error::Error>(())
+/// ```
+pub fn lzma_decompress_to_slice_with_options<R: io::BufRead, const PROBS_MEM_LIMIT: usize>(
+    mut input: R,
+    output: &mut [u8],
+    options: &decompress::Options,
+) -> error::Result<usize> {
+    use crate::decode::lzbuffer::LzBuffer;
+    use crate::decode::lzbuffer::LzSliceBuffer;
+    let params = decode::lzma::LzmaParams::read_header(&mut input, options)?;
+    if let option::GuaranteedOption::Some(unpacked_size) = params.unpacked_size {
+        if unpacked_size > output.len() as u64 {
+            return Err(error::lzma::LzmaError::UnpackedSizeTooLarge {
+                unpacked_size,
+                available: output.len(),
+            }
+            .into());
+        }
+    }
+    let mut decoder =
+        decode::lzma::DecoderState::<_, [decode::lzma::LiteralCoder; PROBS_MEM_LIMIT]>::with_buffer(
+            LzSliceBuffer::new(output),
+        );
+    decoder.reset();
+    decoder.set_params(params)?;
+
+    let mut rangecoder = decode::rangecoder::RangeDecoder::new(&mut input)
+        .map_err(|_| error::lzma::LzmaError::DataStreamIsTooShort)?;
+    // `LzSliceBuffer` never writes to the sink; decompressed data stays in
+    // `output`
+    let mut sink: &mut [u8] = &mut [];
+    decoder.process(&mut sink, &mut rangecoder)?;
+    // Cannot truncate, the data fits in `output`
+    Ok(decoder.output.len() as usize)
+}
+
+/// Decompress LZMA data straight into the `output` slice like
+/// [`lzma_decompress_to_slice_with_options`], then apply `transform` to the
+/// decompressed data in place. Returns the number of bytes written to
+/// `output`.
+///
+/// The transform runs once decoding is complete, as the decoder reads back
+/// earlier output for matches; on error, `output` is left untransformed.
+pub fn lzma_decompress_to_slice_with_transform<
+    R: io::BufRead,
+    T: io::OutputTransform,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    input: R,
+    output: &mut [u8],
+    options: &decompress::Options,
+    transform: &mut T,
+) -> error::Result<usize> {
+    let len = lzma_decompress_to_slice_with_options::<_, PROBS_MEM_LIMIT>(input, output, options)?;
+    transform.apply(
+        0,
+        output
+            .get_mut(..len)
+            .ok_or_else(decode::util::out_of_bounds)?,
+    );
+    Ok(len)
+}
+
 /// Compresses data with LZMA and default
 /// [`Options`](compress/struct.Options.html). Kept for tests
-#[cfg(feature = "std")]
-pub fn lzma_compress<R: io::BufRead, W: io::Write>(
-    input: &mut R,
-    output: &mut W,
-) -> io::Result<()> {
+#[cfg(feature = "encoder")]
+pub fn lzma_compress<R: io::BufRead, W: io::Write>(input: R, output: &mut W) -> io::Result<()> {
     lzma_compress_with_options(input, output, &compress::Options::default())
 }
 
 /// Compress LZMA data with the provided options.
 /// Kept for tests
-#[cfg(feature = "std")]
+#[cfg(feature = "encoder")]
 pub fn lzma_compress_with_options<R: io::BufRead, W: io::Write>(
-    input: &mut R,
+    input: R,
     output: &mut W,
     options: &compress::Options,
 ) -> io::Result<()> {
+    if options.header == compress::HeaderFormat::Lzma2 {
+        return encode::lzma2::encode(input, output, options);
+    }
     let encoder = encode::dumbencoder::Encoder::from_stream(output, options)?;
     encoder.process(input)
 }
 
+/// Compress data given as a sequence of chunks with LZMA and the provided
+/// options, without concatenating them first.
+///
+/// ```
+/// let chunks = [&b"Hello"[..], b", ", b"world"];
+/// let mut compressed = Vec::new();
+/// lzma_rs::lzma_compress_from_iter(
+///     chunks.iter(),
+///     &mut compressed,
+///     &lzma_rs::compress::Options::default(),
+/// )
+/// .unwrap();
+///
+/// let mut decompressed = Vec::new();
+/// lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut &compressed[..], &mut decompressed).unwrap();
+/// assert_eq!(decompressed, b"Hello, world");
+/// ```
+#[cfg(feature = "encoder")]
+pub fn lzma_compress_from_iter<I, W>(
+    chunks: I,
+    output: &mut W,
+    options: &compress::Options,
+) -> io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+    W: io::Write,
+{
+    /// Adapts an iterator of chunks to [`io::BufRead`].
+    struct IterReader<I: Iterator> {
+        chunks: I,
+        current: Option<I::Item>,
+        pos: usize,
+    }
+
+    impl<I: Iterator> io::Read for IterReader<I>
+    where
+        I::Item: AsRef<[u8]>,
+    {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = io::Read::read(&mut io::BufRead::fill_buf(self)?, buf)?;
+            io::BufRead::consume(self, n);
+            Ok(n)
+        }
+    }
+
+    impl<I: Iterator> io::BufRead for IterReader<I>
+    where
+        I::Item: AsRef<[u8]>,
+    {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            // Skip exhausted and empty chunks
+            while self
+                .current
+                .as_ref()
+                .is_some_and(|chunk| self.pos >= chunk.as_ref().len())
+            {
+                self.current = self.chunks.next();
+                self.pos = 0;
+            }
+            Ok(match &self.current {
+                Some(chunk) => &chunk.as_ref()[self.pos..],
+                None => &[],
+            })
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.pos += amt;
+        }
+    }
+
+    let mut chunks = chunks.into_iter();
+    let input = IterReader {
+        current: chunks.next(),
+        chunks,
+        pos: 0,
+    };
+    if options.header == compress::HeaderFormat::Lzma2 {
+        return encode::lzma2::encode(input, output, options);
+    }
+    let encoder = encode::dumbencoder::Encoder::from_stream(output, options)?;
+    encoder.process(input)
+}
+
+/// Compress `input` with LZMA and the provided options straight into the
+/// `output` slice. Returns the number of bytes written to `output`.
+///
+/// Fails with [`error::Error::OutputBufferTooSmall`] if the compressed data
+/// does not fit into `output`.
+#[cfg(feature = "encoder")]
+pub fn lzma_compress_to_slice(
+    input: &[u8],
+    output: &mut [u8],
+    options: &compress::Options,
+) -> error::Result<usize> {
+    let available = output.len();
+    let mut remaining = &mut output[..];
+    match lzma_compress_with_options(&mut &input[..], &mut remaining, options) {
+        Ok(()) => Ok(available - remaining.len()),
+        Err(e) if e.kind() == io::ErrorKind::WriteZero => {
+            Err(error::Error::OutputBufferTooSmall { available })
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
 #[allow(missing_docs)]
 /// Module containing alternative [`Option`] type implementation
+///
+/// Only used inside the decoder state; the public API takes and returns
+/// [`core::option::Option`]. Convert with [`From`] if needed.
 pub mod option {
     /// Custom Option type guaranteed to have a proper variant ordering. This
     /// allows to achieve guaranteed 0-initializable
@@ -167,4 +698,20 @@ pub mod option {
             }
         }
     }
+
+    /// Serialized as an [`Option`].
+    #[cfg(feature = "serde")]
+    impl<T: serde::Serialize> serde::Serialize for GuaranteedOption<T> {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            Option::<&T>::from(self.as_ref()).serialize(serializer)
+        }
+    }
+
+    /// Deserialized from an [`Option`].
+    #[cfg(feature = "serde")]
+    impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for GuaranteedOption<T> {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Option::deserialize(deserializer).map(Self::from)
+        }
+    }
 }
@@ -39,3 +39,19 @@ macro_rules! lzma_debug {
 macro_rules! lzma_info {
     ($($arg:tt)+) => {};
 }
+
+/// Panic on API misuse (feature: disabled).
+#[cfg(not(feature = "no-panics"))]
+macro_rules! lzma_panic {
+    ($ret:expr, $($arg:tt)+) => {
+        panic!($($arg)+)
+    };
+}
+
+/// Return `$ret` instead of panicking on API misuse (feature: enabled).
+#[cfg(feature = "no-panics")]
+macro_rules! lzma_panic {
+    ($ret:expr, $($arg:tt)+) => {
+        return $ret
+    };
+}
@@ -0,0 +1,165 @@
+//! LZMA properties byte.
+//!
+//! ```
+//! use core::convert::TryFrom;
+//! use lzma_rs::properties::LzmaProperties;
+//!
+//! let props = LzmaProperties::try_from(0x5D).unwrap();
+//! assert_eq!(props, LzmaProperties::new(3, 0, 2).unwrap());
+//! assert_eq!(props.to_byte(), 0x5D);
+//! ```
+
+use crate::error::lzma::{InvalidPropertiesReason, LzmaError};
+use core::convert::TryFrom;
+
+/// Largest valid number of literal context bits.
+pub const MAX_LC: u32 = 8;
+/// Largest valid number of literal position bits.
+pub const MAX_LP: u32 = 4;
+/// Largest valid number of position bits.
+pub const MAX_PB: u32 = 4;
+
+/// Literal context, literal position and position bits of an LZMA stream,
+/// encoded as `(pb * 5 + lp) * 9 + lc` in the properties byte of the header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LzmaProperties {
+    /// Number of high bits of the previous byte used as literal context
+    /// (0 to 8).
+    pub lc: u32,
+    /// Number of low bits of the position used as literal context (0 to 4).
+    pub lp: u32,
+    /// Number of low bits of the position used as match context (0 to 4).
+    pub pb: u32,
+}
+
+impl LzmaProperties {
+    /// Properties used by most encoders: `lc = 3`, `lp = 0`, `pb = 2`.
+    pub const DEFAULT: Self = Self {
+        lc: 3,
+        lp: 0,
+        pb: 2,
+    };
+
+    /// Create properties, checking that each value is in range.
+    pub const fn new(lc: u32, lp: u32, pb: u32) -> Result<Self, LzmaError> {
+        if lc > MAX_LC {
+            Err(LzmaError::InvalidProperties {
+                value: lc,
+                reason: InvalidPropertiesReason::LcTooLarge,
+            })
+        } else if lp > MAX_LP {
+            Err(LzmaError::InvalidProperties {
+                value: lp,
+                reason: InvalidPropertiesReason::LpTooLarge,
+            })
+        } else if pb > MAX_PB {
+            Err(LzmaError::InvalidProperties {
+                value: pb,
+                reason: InvalidPropertiesReason::PbTooLarge,
+            })
+        } else {
+            Ok(Self { lc, lp, pb })
+        }
+    }
+
+    /// Encode the properties into the header byte.
+    pub const fn to_byte(&self) -> u8 {
+        ((self.pb * 5 + self.lp) * 9 + self.lc) as u8
+    }
+
+    /// Number of literal coders (`0x300` probabilities each) needed to
+    /// decode a stream with these properties, i.e. `1 << (lc + lp)`.
+    pub const fn literal_coders(&self) -> usize {
+        1 << (self.lc + self.lp)
+    }
+
+    /// Length in bytes of a snapshot of the probability model for these
+    /// properties, see
+    /// [`DecompressSession::save_model`](crate::decompress::DecompressSession::save_model).
+    pub const fn model_len(&self) -> usize {
+        (crate::decode::model::FIXED_PROBS + self.literal_coders() * 0x300) * 2
+    }
+}
+
+impl Default for LzmaProperties {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Renders the properties as `lc=3 lp=0 pb=2`.
+impl core::fmt::Display for LzmaProperties {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "lc={} lp={} pb={}", self.lc, self.lp, self.pb)
+    }
+}
+
+impl TryFrom<u8> for LzmaProperties {
+    type Error = LzmaError;
+
+    /// Decode the header byte. Any byte is a valid `lc` and `lp` combination;
+    /// bytes from 225 upwards have `pb` out of range.
+    fn try_from(value: u8) -> Result<Self, LzmaError> {
+        let value = value as u32;
+        if value >= (MAX_PB + 1) * (MAX_LP + 1) * (MAX_LC + 1) {
+            return Err(LzmaError::InvalidProperties {
+                value,
+                reason: InvalidPropertiesReason::PbTooLarge,
+            });
+        }
+        Ok(Self {
+            lc: value % 9,
+            lp: (value / 9) % 5,
+            pb: value / 45,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_properties_round_trip() {
+        for byte in 0..225u8 {
+            let props = LzmaProperties::try_from(byte).unwrap();
+            assert_eq!(LzmaProperties::new(props.lc, props.lp, props.pb), Ok(props));
+            assert_eq!(props.to_byte(), byte);
+        }
+        for byte in 225..=255u8 {
+            assert_eq!(
+                LzmaProperties::try_from(byte),
+                Err(LzmaError::InvalidProperties {
+                    value: byte as u32,
+                    reason: InvalidPropertiesReason::PbTooLarge,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_properties_out_of_range() {
+        assert_eq!(
+            LzmaProperties::new(9, 0, 0),
+            Err(LzmaError::InvalidProperties {
+                value: 9,
+                reason: InvalidPropertiesReason::LcTooLarge,
+            })
+        );
+        assert_eq!(
+            LzmaProperties::new(0, 5, 0),
+            Err(LzmaError::InvalidProperties {
+                value: 5,
+                reason: InvalidPropertiesReason::LpTooLarge,
+            })
+        );
+        assert_eq!(
+            LzmaProperties::new(0, 0, 5),
+            Err(LzmaError::InvalidProperties {
+                value: 5,
+                reason: InvalidPropertiesReason::PbTooLarge,
+            })
+        );
+    }
+}
@@ -0,0 +1,53 @@
+#![cfg(feature = "bin")]
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn lzma_ns(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lzma-ns"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn cli_round_trip() {
+    let input = include_bytes!("files/small.txt");
+    let compressed = lzma_ns(&["compress"], input);
+    assert!(compressed.status.success());
+    let decompressed = lzma_ns(&["decompress", "-"], &compressed.stdout);
+    assert!(decompressed.status.success());
+    assert_eq!(decompressed.stdout, input);
+}
+
+#[test]
+fn cli_probe_header_and_stats() {
+    let probe = lzma_ns(&["probe-header", "tests/files/hello.txt.lzma"], b"");
+    assert!(probe.status.success());
+    let probe = String::from_utf8(probe.stdout).unwrap();
+    assert!(probe.contains("format: Some(Lzma)"), "{}", probe);
+    assert!(probe.contains("dict_size: 8388608"), "{}", probe);
+
+    let stats = lzma_ns(&["stats", "tests/files/foo.txt.lzma"], b"");
+    assert!(stats.status.success());
+    let stats = String::from_utf8(stats.stdout).unwrap();
+    assert!(stats.contains("dict_high_water: 4096"), "{}", stats);
+
+    let dict_size = lzma_ns(&["dict-size", "tests/files/hugedict.txt.lzma"], b"");
+    assert!(dict_size.status.success());
+    let dict_size = String::from_utf8(dict_size.stdout).unwrap();
+    assert!(dict_size.contains("min_dict_size: 127916"), "{}", dict_size);
+}
+
+#[test]
+fn cli_errors() {
+    assert_eq!(lzma_ns(&["unknown"], b"").status.code(), Some(2));
+    let res = lzma_ns(&["decompress"], b"not lzma");
+    assert_eq!(res.status.code(), Some(1));
+    assert!(!res.stderr.is_empty());
+}
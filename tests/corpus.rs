@@ -0,0 +1,139 @@
+//! Regression tests decoding every file of `tests/corpus`, see
+//! `tests/corpus/README.md`.
+#![cfg(all(feature = "std", feature = "stream"))]
+
+use lzma_rs::decompress::{self, DynStream, LzmaParams};
+use lzma_rs::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Set to write the missing expectations from the current decoder output.
+const BLESS_VAR: &str = "LZMA_CORPUS_BLESS";
+
+/// Outcome of decoding a corpus file, compared with its expectation.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Data(Vec<u8>),
+    Error(String),
+}
+
+fn decode(compressed: &[u8]) -> Outcome {
+    let res = (|| {
+        let params =
+            LzmaParams::read_header(&mut &compressed[..], &decompress::Options::default())?;
+        let mut dict = vec![0; params.dict_size().max(4096) as usize];
+        let mut probs = vec![[0; 0x300]; params.props().literal_coders()];
+        let mut stream = DynStream::new(&mut dict, &mut probs, &mut []);
+        stream.reset();
+        let mut output = Vec::new();
+        stream.write_all(&mut output, compressed)?;
+        stream.finish(&mut output)?;
+        Ok::<_, Error>(output)
+    })();
+    match res {
+        Ok(output) => Outcome::Data(output),
+        Err(e) => Outcome::Error(variant(&e)),
+    }
+}
+
+/// Name the variant of `error`, e.g. `LzmaError(InvalidProperties)`, leaving
+/// out the fields and I/O error messages, which may change between versions.
+fn variant(error: &Error) -> String {
+    if let Error::IoError(e) = error {
+        return format!("IoError({:?})", e.kind());
+    }
+    let mut name = String::new();
+    let mut depth = 0;
+    for c in format!("{:?}", error).chars() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ if depth == 0 => name.push(c),
+            _ => {}
+        }
+    }
+    name.replace(" )", ")").trim_end().to_owned()
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+/// Read the expectation of `path`: `<name>.expected` holds the decompressed
+/// data and `<name>.expected-error` the variant of the error, see `variant`.
+fn expectation(path: &Path) -> Option<Outcome> {
+    if let Ok(data) = fs::read(path.with_extension("expected")) {
+        return Some(Outcome::Data(data));
+    }
+    let error = fs::read_to_string(path.with_extension("expected-error")).ok()?;
+    Some(Outcome::Error(error.trim_end().to_owned()))
+}
+
+fn bless(path: &Path, outcome: &Outcome) {
+    match outcome {
+        Outcome::Data(data) => fs::write(path.with_extension("expected"), data),
+        Outcome::Error(error) => fs::write(
+            path.with_extension("expected-error"),
+            format!("{}\n", error),
+        ),
+    }
+    .unwrap();
+    eprintln!("blessed {}", path.display());
+}
+
+#[test]
+fn decode_corpus() {
+    let bless_missing = std::env::var_os(BLESS_VAR).is_some();
+    let mut files = Vec::new();
+    collect_files(Path::new("tests/corpus"), &mut files);
+    files.sort();
+
+    let mut failures = Vec::new();
+    let mut decoded = 0;
+    for path in &files {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("lzma") => {}
+            Some("xz") => {
+                eprintln!("skipped {}: .xz is not supported", path.display());
+                continue;
+            }
+            _ => continue,
+        }
+        let outcome = decode(&fs::read(path).unwrap());
+        match expectation(path) {
+            Some(expected) if expected == outcome => decoded += 1,
+            Some(expected) => failures.push(format!(
+                "{}: expected {}, got {}",
+                path.display(),
+                describe(&expected),
+                describe(&outcome)
+            )),
+            None if bless_missing => {
+                bless(path, &outcome);
+                decoded += 1;
+            }
+            None => failures.push(format!(
+                "{}: no expectation, run with {}=1 to record {}",
+                path.display(),
+                BLESS_VAR,
+                describe(&outcome)
+            )),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+    assert!(decoded > 0, "empty corpus");
+}
+
+fn describe(outcome: &Outcome) -> String {
+    match outcome {
+        Outcome::Data(data) => format!("{} bytes of data", data.len()),
+        Outcome::Error(error) => format!("error {}", error),
+    }
+}
@@ -1,10 +1,11 @@
-#![cfg(feature = "std")]
+#![cfg(feature = "encoder")]
+// Options are built with `..Default::default()` so that tests keep compiling
+// when new fields are added
+#![allow(clippy::needless_update)]
 extern crate lzma;
 
 #[cfg(feature = "log")]
 use log::{debug, info};
-use lzma_rs::option::GuaranteedOption as Option;
-use lzma_rs::option::GuaranteedOption::*;
 use std::io::Read;
 
 /// Utility function to read a file into memory
@@ -19,7 +20,8 @@ fn round_trip(x: &[u8]) {
 
     // Do another round trip, but this time also write it to the header
     let encode_options = lzma_rs::compress::Options {
-        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(x.len() as u64).into()),
+        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(x.len() as u64)),
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::ReadFromHeader,
@@ -36,6 +38,17 @@ fn round_trip_no_options(x: &[u8]) {
     #[cfg(feature = "log")]
     debug!("Compressed content: {:?}", compressed);
 
+    // Compressing chunks gives the same result, empty chunks included
+    let mut from_iter: Vec<u8> = Vec::new();
+    let chunks = x.chunks(7).flat_map(|chunk| [chunk, &[]]);
+    lzma_rs::lzma_compress_from_iter(
+        chunks,
+        &mut from_iter,
+        &lzma_rs::compress::Options::default(),
+    )
+    .unwrap();
+    assert_eq!(from_iter, compressed);
+
     assert_decomp_eq::<4096>(&compressed, x, /* compare_to_liblzma */ true);
 }
 
@@ -86,9 +99,7 @@ fn assert_round_trip_with_options(
                 if error.kind() == std::io::ErrorKind::WriteZero {
                     match (encode_options.unpacked_size, decode_options.unpacked_size) {
                         (
-                            lzma_rs::compress::UnpackedSize::WriteToHeader(
-                                core::option::Option::<_>::None,
-                            ),
+                            lzma_rs::compress::UnpackedSize::WriteToHeader(None),
                             lzma_rs::decompress::UnpackedSize::ReadHeaderButUseProvided(Some(_)),
                         ) => {}
                         _ => panic!("{:?}", error),
@@ -124,6 +135,41 @@ fn assert_decomp_eq<const DICT_MEM_LIMIT: usize>(
         assert_eq!(decomp, expected);
     }
 
+    // Test decompression straight into a slice.
+    {
+        let mut input = compressed;
+        let mut decomp = vec![0; expected.len()];
+        let len = lzma_rs::lzma_decompress_to_slice::<_, 66>(&mut input, &mut decomp).unwrap();
+        assert_eq!(&decomp[..len], expected);
+    }
+
+    // Test that the input is consumed up to the end of the stream.
+    {
+        let mut blob = compressed.to_vec();
+        blob.extend_from_slice(b"trailing");
+        let mut input = &blob[..];
+        let mut decomp: Vec<u8> = Vec::new();
+        let summary = lzma_rs::lzma_decompress_with_summary::<_, _, DICT_MEM_LIMIT, 66>(
+            &mut input,
+            &mut decomp,
+            &lzma_rs::decompress::Options::default(),
+        )
+        .unwrap();
+        assert_eq!(decomp, expected);
+        assert_eq!(summary.consumed, compressed.len() as u64);
+        assert_eq!(summary.produced, expected.len() as u64);
+        assert_eq!(input, b"trailing");
+    }
+
+    // Test decompression of fragmented input.
+    {
+        let fragments: Vec<&[u8]> = compressed.chunks(7).collect();
+        let mut input = lzma_rs::io::ChainedSliceReader::new(&fragments);
+        let mut decomp: Vec<u8> = Vec::new();
+        lzma_rs::lzma_decompress::<_, _, DICT_MEM_LIMIT, 66>(&mut input, &mut decomp).unwrap();
+        assert_eq!(decomp, expected);
+    }
+
     // Test consistency with lzma crate. Sometimes that crate fails (e.g. huge
     // dictionary), so we have a flag to slip that.
     if compare_to_liblzma {
@@ -140,6 +186,15 @@ fn assert_decomp_eq<const DICT_MEM_LIMIT: usize>(
         stream.finish(&mut sink).unwrap();
         assert_eq!(sink, expected);
 
+        let mut sink = Vec::new();
+        let mut stream = lzma_rs::decompress::Stream::<DICT_MEM_LIMIT, 66>::new();
+        stream.reset();
+        let fragments: Vec<&[u8]> = compressed.chunks(7).collect();
+        let written = stream.write_vectored(&mut sink, &fragments).unwrap();
+        assert_eq!(written, compressed.len());
+        stream.finish(&mut sink).unwrap();
+        assert_eq!(sink, expected);
+
         const CHUNK_SIZES: &[usize] = &[1, 2, 3, 4, 5, 6, 7, 8, 16, 32, 64, 128, 256, 512, 1024];
         for &chunk_size in CHUNK_SIZES {
             let mut sink = Vec::new();
@@ -164,6 +219,39 @@ fn decompress_short_header() {
     lzma_rs::lzma_decompress::<_, _, 4096, 66>(&mut (b"" as &[u8]), &mut decomp).unwrap();
 }
 
+#[test]
+fn decompress_short_header_lengths() {
+    #[cfg(feature = "log")]
+    let _ = env_logger::try_init();
+    let mut decomp: Vec<u8> = Vec::new();
+    let header = b"\x5d\x00\x10\x00\x00\xff\xff";
+    match lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut &header[..], &mut decomp) {
+        Err(lzma_rs::error::Error::HeaderTooShort {
+            have: 7,
+            need: 13,
+            field: lzma_rs::error::lzma::HeaderField::UnpackedSize,
+        }) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+
+    let options = lzma_rs::decompress::Options {
+        unpacked_size: lzma_rs::decompress::UnpackedSize::ReadFromHeader32,
+        ..Default::default()
+    };
+    match lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+        &mut &header[..3],
+        &mut decomp,
+        &options,
+    ) {
+        Err(lzma_rs::error::Error::HeaderTooShort {
+            have: 3,
+            need: 9,
+            field: lzma_rs::error::lzma::HeaderField::DictSize,
+        }) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
 #[test]
 fn round_trip_basics() {
     #[cfg(feature = "log")]
@@ -207,6 +295,130 @@ fn decompress_big_file_with_huge_dict() {
     assert_decomp_eq::<140_000>(&compressed, &expected, /* compare_to_liblzma */ false);
 }
 
+#[test]
+fn decompress_std_files() {
+    // `std::io` types are used as is, without going through an adapter.
+    let path = std::env::temp_dir().join(format!("lzma-rs-std-{}.txt", std::process::id()));
+    let mut input =
+        std::io::BufReader::new(std::fs::File::open("tests/files/foo.txt.lzma").unwrap());
+    let mut output = std::fs::File::create(&path).unwrap();
+    let len = lzma_rs::lzma_decompress::<_, _, 4096, 66>(&mut input, &mut output).unwrap();
+    drop(output);
+
+    let decomp = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(decomp, read_all_file("tests/files/foo.txt").unwrap());
+    assert_eq!(len, decomp.len() as u64);
+}
+
+/// Hand-made stream decoding to a literal followed by `matches` rep matches
+/// of 273 bytes at distance 1, with `lc = lp = pb = 0`.
+fn repeated_byte_stream(matches: u64) -> Vec<u8> {
+    use lzma_rs::rangecoder::{LenEncoder, RangeEncoder};
+
+    let mut compressed = vec![0, 0, 0x10, 0, 0];
+    compressed.extend_from_slice(&(1 + 273 * matches).to_le_bytes());
+    let mut is_match = [0x400; 12];
+    let mut is_rep = [0x400; 12];
+    let mut is_rep_g0 = [0x400; 12];
+    let mut is_rep0_long = [0x400; 12];
+    let mut literal = [0x400; 0x300];
+    let mut len = LenEncoder::new();
+    let mut encoder = RangeEncoder::new(&mut compressed);
+    encoder.encode_bit(&mut is_match[0], false).unwrap();
+    encoder
+        .encode_bit_tree(&mut literal, 8, b'a'.into())
+        .unwrap();
+    let mut state = 0;
+    for _ in 0..matches {
+        encoder.encode_bit(&mut is_match[state], true).unwrap();
+        encoder.encode_bit(&mut is_rep[state], true).unwrap();
+        encoder.encode_bit(&mut is_rep_g0[state], false).unwrap();
+        encoder.encode_bit(&mut is_rep0_long[state], true).unwrap();
+        len.encode(&mut encoder, 0, 273 - 2).unwrap();
+        state = if state < 7 { 8 } else { 11 };
+    }
+    encoder.finish().unwrap();
+    compressed
+}
+
+#[test]
+fn decompress_repeated_byte_stream() {
+    let compressed = repeated_byte_stream(100);
+    let mut decomp = Vec::new();
+    let len =
+        lzma_rs::lzma_decompress::<_, _, 4096, 1>(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(len, decomp.len() as u64);
+    assert_eq!(decomp, vec![b'a'; 1 + 273 * 100]);
+}
+
+fn collect_chunks<const DICT_MEM_LIMIT: usize>(compressed: &[u8]) -> Vec<u8> {
+    let mut chunks = lzma_rs::decompress::iter_chunks::<_, DICT_MEM_LIMIT, 8>(compressed);
+    let mut decomp = Vec::new();
+    let mut count = 0;
+    while let Some(chunk) = chunks.next_chunk() {
+        count += 1;
+        let chunk = chunk.unwrap();
+        assert!(!chunk.data.is_empty() && chunk.data.len() <= DICT_MEM_LIMIT);
+        assert_eq!(chunk.position, decomp.len() as u64);
+        decomp.extend_from_slice(chunk.data);
+    }
+    assert!(chunks.next_chunk().is_none());
+    assert_eq!(chunks.position(), decomp.len() as u64);
+    // About a dictionary each, plus the bytes decoded past its end
+    assert!(count <= 2 * (decomp.len() / (DICT_MEM_LIMIT / 2) + 1));
+    decomp
+}
+
+#[test]
+fn decompress_iter_chunks() {
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    assert_eq!(collect_chunks::<4096>(&compressed), expected);
+    let compressed = read_all_file("tests/files/hugedict.txt.lzma").unwrap();
+    assert_eq!(collect_chunks::<140_000>(&compressed), expected);
+    // Matches of the maximum length, wrapping around the dictionary
+    let compressed = repeated_byte_stream(100);
+    assert_eq!(
+        collect_chunks::<4096>(&compressed),
+        vec![b'a'; 1 + 273 * 100]
+    );
+    let compressed = b"\x5d\x00\x10\x00\x00\xff\xff\xff\xff\xff\xff\xff\xff\x00\x83\xff\
+          \xfb\xff\xff\xc0\x00\x00\x00";
+    assert!(collect_chunks::<4096>(compressed).is_empty());
+
+    // Errors end the decoding
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let mut chunks =
+        lzma_rs::decompress::iter_chunks::<_, 4096, 8>(&compressed[..compressed.len() / 2]);
+    let mut len = 0;
+    let err = loop {
+        match chunks.next_chunk().unwrap() {
+            Ok(chunk) => len += chunk.data.len(),
+            Err(e) => break e,
+        }
+    };
+    assert!(
+        matches!(err, lzma_rs::error::Error::IoError(_)),
+        "{:?}",
+        err
+    );
+    assert!(len > 0 && len < expected.len());
+    assert!(chunks.next_chunk().is_none());
+}
+
+#[test]
+#[ignore = "decompresses more than 4 GiB"]
+fn decompress_more_than_4_gib() {
+    let matches = (1 << 32) / 273 + 1000;
+    let compressed = repeated_byte_stream(matches);
+    let len =
+        lzma_rs::lzma_decompress::<_, _, 4096, 1>(&mut compressed.as_slice(), &mut std::io::sink())
+            .unwrap();
+    assert_eq!(len, 1 + 273 * matches);
+    assert!(len > u64::from(u32::MAX));
+}
+
 #[test]
 fn decompress_range_coder_edge_case() {
     #[cfg(feature = "log")]
@@ -259,9 +471,8 @@ fn decompress_huge_dict() {
 fn unpacked_size_write_to_header() {
     let data = b"Some data";
     let encode_options = lzma_rs::compress::Options {
-        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(
-            Some(data.len() as u64).into(),
-        ),
+        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(data.len() as u64)),
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::ReadFromHeader,
@@ -270,11 +481,24 @@ fn unpacked_size_write_to_header() {
     assert_round_trip_with_options(&data[..], &encode_options, &decode_options);
 }
 
+#[test]
+fn unpacked_size_write_unknown_code_to_header() {
+    // The largest size is the code of an unknown size, so an end marker is
+    // written
+    let data = b"Some data";
+    let encode_options = lzma_rs::compress::Options {
+        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(u64::MAX)),
+        ..Default::default()
+    };
+    assert_round_trip_with_options(&data[..], &encode_options, &Default::default());
+}
+
 #[test]
 fn unpacked_size_provided_outside() {
     let data = b"Some data";
     let encode_options = lzma_rs::compress::Options {
         unpacked_size: lzma_rs::compress::UnpackedSize::SkipWritingToHeader,
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(data.len() as u64)),
@@ -287,9 +511,8 @@ fn unpacked_size_provided_outside() {
 fn unpacked_size_write_some_to_header_but_use_provided_on_read() {
     let data = b"Some data";
     let encode_options = lzma_rs::compress::Options {
-        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(
-            Some(data.len() as u64).into(),
-        ),
+        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(data.len() as u64)),
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::ReadHeaderButUseProvided(Some(
@@ -304,7 +527,8 @@ fn unpacked_size_write_some_to_header_but_use_provided_on_read() {
 fn unpacked_size_write_none_to_header_and_use_provided_on_read() {
     let data = b"Some data";
     let encode_options = lzma_rs::compress::Options {
-        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(None.into()),
+        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(None),
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::ReadHeaderButUseProvided(Some(
@@ -319,7 +543,8 @@ fn unpacked_size_write_none_to_header_and_use_provided_on_read() {
 fn unpacked_size_write_none_to_header_and_use_provided_none_on_read() {
     let data = b"Some data";
     let encode_options = lzma_rs::compress::Options {
-        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(None.into()),
+        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(None),
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::ReadHeaderButUseProvided(None),
@@ -328,11 +553,405 @@ fn unpacked_size_write_none_to_header_and_use_provided_none_on_read() {
     assert_round_trip_with_options(&data[..], &encode_options, &decode_options);
 }
 
+#[test]
+fn round_trip_properties() {
+    let data = read_all_file("tests/files/foo.txt").unwrap();
+    for &(lc, lp, pb) in &[(0, 0, 0), (1, 2, 4), (6, 0, 1)] {
+        let encode_options = lzma_rs::compress::Options {
+            properties: lzma_rs::properties::LzmaProperties::new(lc, lp, pb).unwrap(),
+            ..Default::default()
+        };
+        assert_round_trip_with_options(&data, &encode_options, &Default::default());
+    }
+}
+
+#[test]
+fn unpacked_size_32bit_header() {
+    let data = b"Some data";
+    // The largest size is the code of an unknown size
+    for unpacked_size in &[Some(data.len() as u32), None, Some(0xFFFF_FFFF)] {
+        let encode_options = lzma_rs::compress::Options {
+            unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader32(*unpacked_size),
+            ..Default::default()
+        };
+        let decode_options = lzma_rs::decompress::Options {
+            unpacked_size: lzma_rs::decompress::UnpackedSize::ReadFromHeader32,
+            ..Default::default()
+        };
+        assert_round_trip_with_options(&data[..], &encode_options, &decode_options);
+    }
+}
+
+#[test]
+fn decompress_to_slice_too_small() {
+    let data = b"Some data";
+    for unpacked_size in &[Some(data.len() as u64), None] {
+        let encode_options = lzma_rs::compress::Options {
+            unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(*unpacked_size),
+            ..Default::default()
+        };
+        let mut compressed: Vec<u8> = Vec::new();
+        lzma_rs::lzma_compress_with_options(&mut &data[..], &mut compressed, &encode_options)
+            .unwrap();
+
+        let mut decomp = [0; 4];
+        let error = lzma_rs::lzma_decompress_to_slice::<_, 66>(&mut &compressed[..], &mut decomp)
+            .unwrap_err();
+        match (unpacked_size, error) {
+            (
+                Some(_),
+                lzma_rs::error::Error::LzmaError(
+                    lzma_rs::error::lzma::LzmaError::UnpackedSizeTooLarge {
+                        unpacked_size: 9,
+                        available: 4,
+                    },
+                ),
+            ) => {}
+            (None, lzma_rs::error::Error::OutputBufferTooSmall { available: 4 }) => {}
+            (_, err) => panic!("Unexpected error: {:#?}", err),
+        }
+    }
+}
+
+#[test]
+fn decompress_with_transform() {
+    // Obfuscated before compression, as some vendors do
+    let key = b"secret";
+    let mut xor = |offset: u64, block: &mut [u8]| {
+        for (i, byte) in block.iter_mut().enumerate() {
+            *byte ^= key[(offset as usize + i) % key.len()];
+        }
+    };
+    let data = read_all_file("tests/files/foo.txt").unwrap();
+    let mut obfuscated = data.clone();
+    xor(0, &mut obfuscated);
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress(&mut obfuscated.as_slice(), &mut compressed).unwrap();
+
+    let mut writer = lzma_rs::io::TransformWriter::new(Vec::new(), xor);
+    lzma_rs::lzma_decompress::<_, _, 0x10000, 8>(&mut compressed.as_slice(), &mut writer).unwrap();
+    assert_eq!(writer.into_inner(), data);
+
+    let mut decomp = vec![0; data.len()];
+    let len = lzma_rs::lzma_decompress_to_slice_with_transform::<_, _, 8>(
+        &mut compressed.as_slice(),
+        &mut decomp,
+        &lzma_rs::decompress::Options::default(),
+        &mut xor,
+    )
+    .unwrap();
+    assert_eq!(&decomp[..len], &data[..]);
+}
+
+#[test]
+fn compress_raw() {
+    let data = read_all_file("tests/files/foo.txt").unwrap();
+    let mut alone = Vec::new();
+    lzma_rs::lzma_compress(&mut data.as_slice(), &mut alone).unwrap();
+    let options = lzma_rs::compress::Options {
+        header: lzma_rs::compress::HeaderFormat::Raw,
+        ..Default::default()
+    };
+    let mut raw = Vec::new();
+    lzma_rs::lzma_compress_with_options(&mut data.as_slice(), &mut raw, &options).unwrap();
+    // Same data without the 13 bytes long header
+    assert_eq!(raw, &alone[13..]);
+}
+
+#[test]
+fn compress_lzma2() {
+    let data = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::compress::Options {
+        header: lzma_rs::compress::HeaderFormat::Lzma2,
+        ..Default::default()
+    };
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress_with_options(&mut data.as_slice(), &mut compressed, &options).unwrap();
+
+    // Walk the chunks, decoding LZMA ones with an `.lzma` header built from
+    // the chunk header
+    let mut decomp = Vec::new();
+    let mut input = compressed.as_slice();
+    loop {
+        let control = input[0];
+        if control == 0x00 {
+            assert_eq!(input.len(), 1);
+            break;
+        }
+        let unpacked_len =
+            ((control as usize & 0x1F) << 16 | (input[1] as usize) << 8 | input[2] as usize) + 1;
+        match control {
+            0x01 => {
+                decomp.extend_from_slice(&input[3..3 + unpacked_len]);
+                input = &input[3 + unpacked_len..];
+            }
+            0xE0..=0xFF => {
+                let packed_len = ((input[3] as usize) << 8 | input[4] as usize) + 1;
+                let mut chunk = vec![input[5]];
+                chunk.extend_from_slice(&0x1000u32.to_le_bytes());
+                chunk.extend_from_slice(&input[6..6 + packed_len]);
+                let options = lzma_rs::decompress::Options {
+                    unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(
+                        unpacked_len as u64,
+                    )),
+                    ..Default::default()
+                };
+                lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+                    &mut chunk.as_slice(),
+                    &mut decomp,
+                    &options,
+                )
+                .unwrap();
+                input = &input[6 + packed_len..];
+            }
+            _ => panic!("Unexpected chunk control byte: {:#x}", control),
+        }
+    }
+    assert_eq!(decomp, data);
+}
+
+#[test]
+fn compress_golden_files() {
+    let data = read_all_file("tests/files/small.txt").unwrap();
+    let cases = [
+        (
+            "tests/files/small.txt.golden.lzma",
+            lzma_rs::compress::Options::default(),
+        ),
+        (
+            "tests/files/small.txt.golden-lc0-lp2-pb0.lzma",
+            lzma_rs::compress::Options {
+                unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(
+                    data.len() as u64
+                )),
+                properties: lzma_rs::properties::LzmaProperties::new(0, 2, 0).unwrap(),
+                ..Default::default()
+            },
+        ),
+    ];
+    for (filename, options) in cases.iter() {
+        let expected = read_all_file(filename).unwrap();
+        for deterministic in &[false, true] {
+            let options = lzma_rs::compress::Options {
+                deterministic: *deterministic,
+                ..*options
+            };
+            let mut compressed = Vec::new();
+            lzma_rs::lzma_compress_with_options(&mut data.as_slice(), &mut compressed, &options)
+                .unwrap();
+            assert_eq!(compressed, expected, "{}", filename);
+        }
+    }
+}
+
+#[test]
+fn compress_progress_and_cancel() {
+    use std::cell::{Cell, RefCell};
+
+    let data = vec![0x42; 200_000];
+    let reports = RefCell::new(Vec::new());
+    let progress = |p: lzma_rs::compress::Progress| reports.borrow_mut().push(p);
+    let options = lzma_rs::compress::Options {
+        progress: Some(&progress),
+        ..Default::default()
+    };
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress_with_options(&mut data.as_slice(), &mut compressed, &options).unwrap();
+    let reports = reports.into_inner();
+    let inputs: Vec<u64> = reports.iter().map(|p| p.input_bytes).collect();
+    assert_eq!(inputs, vec![0x10000, 0x20000, 0x30000, 200_000]);
+    assert!(reports
+        .windows(2)
+        .all(|w| w[0].output_bytes <= w[1].output_bytes));
+    assert_eq!(
+        reports.last().unwrap().output_bytes,
+        compressed.len() as u64
+    );
+
+    let polls = Cell::new(0);
+    let cancel = || {
+        polls.set(polls.get() + 1);
+        polls.get() == 2
+    };
+    let options = lzma_rs::compress::Options {
+        cancel: Some(&cancel),
+        ..Default::default()
+    };
+    let error =
+        lzma_rs::lzma_compress_with_options(&mut data.as_slice(), &mut Vec::new(), &options)
+            .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::Other);
+    assert_eq!(polls.get(), 2);
+}
+
+#[test]
+fn compress_to_slice() {
+    let data = read_all_file("tests/files/foo.txt").unwrap();
+    let mut expected = Vec::new();
+    lzma_rs::lzma_compress(&mut data.as_slice(), &mut expected).unwrap();
+
+    let mut compressed = vec![0; expected.len()];
+    let len = lzma_rs::lzma_compress_to_slice(&data, &mut compressed, &Default::default()).unwrap();
+    assert_eq!(&compressed[..len], &expected[..]);
+
+    let mut compressed = vec![0; expected.len() - 1];
+    match lzma_rs::lzma_compress_to_slice(&data, &mut compressed, &Default::default()) {
+        Err(lzma_rs::error::Error::OutputBufferTooSmall { available }) => {
+            assert_eq!(available, expected.len() - 1)
+        }
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[cfg(feature = "stream")]
+#[test]
+fn decompress_chunked_errors() {
+    use lzma_rs::io::{Error, ErrorKind};
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+
+    // Input errors are passed through
+    let error = lzma_rs::lzma_decompress_chunked::<_, _, 4096, 8>(
+        |_| Err(Error::new(ErrorKind::TimedOut, "uart timeout")),
+        |_| Ok(()),
+    )
+    .unwrap_err();
+    match error {
+        lzma_rs::error::Error::IoError(e) => assert_eq!(e.kind(), ErrorKind::TimedOut),
+        err => panic!("Unexpected error: {:#?}", err),
+    }
+
+    // So are output errors
+    let mut input = compressed.as_slice();
+    let error = lzma_rs::lzma_decompress_chunked::<_, _, 4096, 8>(
+        |buf| {
+            let n = buf.len().min(input.len());
+            buf[..n].copy_from_slice(&input[..n]);
+            input = &input[n..];
+            Ok(n)
+        },
+        |_| Err(Error::new(ErrorKind::PermissionDenied, "flash locked")),
+    )
+    .unwrap_err();
+    match error {
+        lzma_rs::error::Error::IoError(e) => assert_eq!(e.kind(), ErrorKind::PermissionDenied),
+        err => panic!("Unexpected error: {:#?}", err),
+    }
+
+    // Truncated input
+    let mut input = &compressed[..compressed.len() / 2];
+    let result = lzma_rs::lzma_decompress_chunked::<_, _, 4096, 8>(
+        |buf| {
+            let n = buf.len().min(input.len());
+            buf[..n].copy_from_slice(&input[..n]);
+            input = &input[n..];
+            Ok(n)
+        },
+        |_| Ok(()),
+    );
+    assert!(result.is_err());
+}
+
+#[cfg(feature = "sha2")]
+#[test]
+fn decompress_verified() {
+    use sha2::Digest;
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let digest: [u8; 32] = sha2::Sha256::digest(&expected).into();
+
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma_decompress_verified::<_, _, 4096, 66>(
+        &mut compressed.as_slice(),
+        &mut decomp,
+        &digest,
+    )
+    .unwrap();
+    assert_eq!(decomp, expected);
+
+    let mut wrong_digest = digest;
+    wrong_digest[0] ^= 1;
+    let error = lzma_rs::lzma_decompress_verified::<_, _, 4096, 66>(
+        &mut compressed.as_slice(),
+        &mut Vec::new(),
+        &wrong_digest,
+    )
+    .unwrap_err();
+    match error {
+        lzma_rs::error::Error::DigestMismatch => {}
+        err => panic!("Unexpected error: {:#?}", err),
+    }
+}
+
+#[test]
+fn small_dict_size() {
+    let data = b"Some data";
+    let mut compressed: Vec<u8> = Vec::new();
+    lzma_rs::lzma_compress(&mut &data[..], &mut compressed).unwrap();
+    // Declare a 16-byte dictionary, below the 4 KiB minimum
+    compressed[1..5].copy_from_slice(&16u32.to_le_bytes());
+
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, data);
+
+    let decode_options = lzma_rs::decompress::Options {
+        reject_small_dict: true,
+        ..Default::default()
+    };
+    let error = lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+        &mut compressed.as_slice(),
+        &mut Vec::new(),
+        &decode_options,
+    )
+    .unwrap_err();
+    match error {
+        lzma_rs::error::Error::LzmaError(lzma_rs::error::lzma::LzmaError::DictionaryTooSmall {
+            dict_size: 16,
+        }) => {}
+        err => panic!("Unexpected error: {:#?}", err),
+    }
+}
+
+#[test]
+fn max_dict_size() {
+    // 8 MiB dictionary
+    let compressed = read_all_file("tests/files/hello.txt.lzma").unwrap();
+    let decode = |max_dict_size| {
+        let options = lzma_rs::decompress::Options {
+            max_dict_size,
+            ..Default::default()
+        };
+        lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+            &mut compressed.as_slice(),
+            &mut Vec::new(),
+            &options,
+        )
+    };
+
+    // The policy limit is checked before the buffer size
+    match decode(Some(1 << 20)).unwrap_err() {
+        lzma_rs::error::Error::LzmaError(lzma_rs::error::lzma::LzmaError::DictionaryTooLarge {
+            dict_size: 0x80_0000,
+            max_dict_size: 0x10_0000,
+        }) => {}
+        err => panic!("Unexpected error: {:#?}", err),
+    }
+    match decode(Some(1 << 23)).unwrap_err() {
+        lzma_rs::error::Error::DictionaryBufferTooSmall { .. } => {}
+        err => panic!("Unexpected error: {:#?}", err),
+    }
+    match decode(None).unwrap_err() {
+        lzma_rs::error::Error::DictionaryBufferTooSmall { .. } => {}
+        err => panic!("Unexpected error: {:#?}", err),
+    }
+}
+
 #[test]
 fn memlimit() {
     let data = b"Some data";
     let encode_options = lzma_rs::compress::Options {
-        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(None.into()),
+        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(None),
+        ..Default::default()
     };
     let decode_options = lzma_rs::decompress::Options {
         unpacked_size: lzma_rs::decompress::UnpackedSize::ReadHeaderButUseProvided(None),
@@ -359,7 +978,10 @@ fn memlimit() {
         .unwrap_err();
 
         match error {
-            lzma_rs::error::Error::ProbabilitiesBufferTooSmall { .. } => {}
+            lzma_rs::error::Error::ProbabilitiesBufferTooSmall {
+                needed: 8,
+                available: 0,
+            } => {}
             err => panic!("Unexpected error: {:#?}", err),
         }
     }
@@ -420,3 +1042,340 @@ fn memlimit() {
         }
     }
 }
+
+#[cfg(feature = "stats")]
+#[test]
+fn decompress_with_stats() {
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+
+    let mut decomp: Vec<u8> = Vec::new();
+    let stats = lzma_rs::lzma_decompress_with_stats::<_, _, 4096, 66>(
+        &mut compressed.as_slice(),
+        &mut decomp,
+        &Default::default(),
+    )
+    .unwrap();
+    assert_eq!(decomp, expected);
+    assert_eq!(stats.literals + stats.match_bytes, expected.len() as u64);
+    // The whole dictionary is filled up
+    assert_eq!(stats.dict_high_water, 4096);
+    assert!(stats.max_distance <= 4096);
+}
+
+#[test]
+fn decompress_with_reused_state() {
+    let mut state = lzma_rs::decompress::DecoderState::<4096, 8>::new();
+    for filename in &[
+        "tests/files/foo.txt",
+        "tests/files/small.txt",
+        "tests/files/foo.txt",
+    ] {
+        let expected = read_all_file(filename).unwrap();
+        let mut compressed = Vec::new();
+        lzma_rs::lzma_compress(&mut expected.as_slice(), &mut compressed).unwrap();
+        let mut decomp = Vec::new();
+        lzma_rs::lzma_decompress_with_state(&mut state, &mut compressed.as_slice(), &mut decomp)
+            .unwrap();
+        assert_eq!(decomp, expected);
+    }
+}
+
+#[test]
+fn decompress_with_prepared_state() {
+    let mut state = lzma_rs::decompress::DecoderState::<4096, 8>::new();
+
+    // Rejected by `validate`, before anything is reset
+    let compressed = read_all_file("tests/files/hello.txt.lzma").unwrap();
+    let params = state.read_header(&mut compressed.as_slice()).unwrap();
+    assert_eq!(params.dict_size(), 0x80_0000);
+    assert_eq!(params.unpacked_size(), None);
+    match state.validate(&params).unwrap_err() {
+        lzma_rs::error::Error::DictionaryBufferTooSmall { .. } => {}
+        err => panic!("Unexpected error: {:#?}", err),
+    }
+    assert!(state.prepare(params).is_err());
+
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let mut input = compressed.as_slice();
+    let params = state.read_header(&mut input).unwrap();
+    state.validate(&params).unwrap();
+    state.prepare(params).unwrap();
+    let mut decomp = Vec::new();
+    state.decompress_prepared(&mut input, &mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+
+    // Each stream must be prepared
+    match state
+        .decompress_prepared(&mut compressed.as_slice(), &mut Vec::new())
+        .unwrap_err()
+    {
+        lzma_rs::error::Error::Uninitialized => {}
+        err => panic!("Unexpected error: {:#?}", err),
+    }
+}
+
+#[test]
+fn dict_size_written_to_header() {
+    use lzma_rs::compress::DictSize;
+    let long = read_all_file("tests/files/foo.txt").unwrap();
+    let short = b"Some data";
+    let auto = DictSize::Auto { max: 1 << 24 };
+    let cases: [(DictSize, &[u8], bool, u32); 7] = [
+        (DictSize::default(), &long, true, 4096),
+        (DictSize::Fixed(1 << 20), &long, false, 1 << 20),
+        (auto, &long, true, 1 << 18),
+        (auto, short, true, 4096),
+        (auto, &[], true, 4096),
+        (auto, &long, false, 1 << 24),
+        (DictSize::Auto { max: 1 << 16 }, &long, true, 1 << 16),
+    ];
+    for &(dict_size, data, known_size, expected) in &cases {
+        let encode_options = lzma_rs::compress::Options {
+            unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(
+                Some(data.len() as u64).filter(|_| known_size),
+            ),
+            dict_size,
+            ..Default::default()
+        };
+        let mut compressed = Vec::new();
+        lzma_rs::lzma_compress_with_options(&mut &data[..], &mut compressed, &encode_options)
+            .unwrap();
+        let written =
+            u32::from_le_bytes([compressed[1], compressed[2], compressed[3], compressed[4]]);
+        assert_eq!(
+            written,
+            expected,
+            "{:?} for {} bytes",
+            dict_size,
+            data.len()
+        );
+        // Decoded by liblzma, which allocates a window of the written size
+        assert_eq!(lzma::decompress(&compressed).unwrap(), data);
+    }
+}
+
+#[test]
+fn trailing_bytes_padding() {
+    use lzma_rs::decompress::{Options, TrailingBytes};
+    use lzma_rs::error::{lzma::LzmaError, Error};
+    let mut data = read_all_file("tests/files/foo.txt").unwrap();
+    data.truncate(10000);
+    for &known_size in &[true, false] {
+        let encode_options = lzma_rs::compress::Options {
+            unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(
+                Some(data.len() as u64).filter(|_| known_size),
+            ),
+            ..Default::default()
+        };
+        let mut compressed = Vec::new();
+        lzma_rs::lzma_compress_with_options(&mut &data[..], &mut compressed, &encode_options)
+            .unwrap();
+        for &padding in &[0, 16] {
+            for &pad in &[0xFF_u8, 0x00] {
+                let mut image = compressed.clone();
+                image.resize(compressed.len() + padding, pad);
+                let options = |trailing_bytes| Options {
+                    trailing_bytes: Some(trailing_bytes),
+                    ..Default::default()
+                };
+
+                let mut output = Vec::new();
+                let res = lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+                    &mut &image[..],
+                    &mut output,
+                    &options(TrailingBytes::Error),
+                );
+                if padding == 0 {
+                    assert_eq!(res.unwrap(), data.len() as u64);
+                } else {
+                    assert_eq!(res, Err(Error::LzmaError(LzmaError::TrailingBytes)));
+                }
+
+                let mut output = Vec::new();
+                lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+                    &mut &image[..],
+                    &mut output,
+                    &options(TrailingBytes::Ignore),
+                )
+                .unwrap();
+                assert_eq!(output, data);
+
+                let mut input = &image[..];
+                let mut output = Vec::new();
+                let summary = lzma_rs::lzma_decompress_with_summary::<_, _, 4096, 8>(
+                    &mut input,
+                    &mut output,
+                    &options(TrailingBytes::ReturnCount),
+                )
+                .unwrap();
+                assert_eq!(output, data);
+                assert_eq!(summary.consumed, compressed.len() as u64);
+                assert_eq!(summary.trailing_bytes, padding as u64);
+                assert!(input.is_empty());
+
+                #[cfg(feature = "stream")]
+                for &chunk_len in &[1, 7, image.len()] {
+                    let decode = |trailing_bytes| {
+                        let mut stream = lzma_rs::decompress::Stream::<4096, 8>::new_with_options(
+                            &options(trailing_bytes),
+                        );
+                        stream.reset();
+                        let mut output = Vec::new();
+                        for chunk in image.chunks(chunk_len) {
+                            stream.write_all(&mut output, chunk)?;
+                        }
+                        let summary = stream.finish_with_summary(&mut output)?;
+                        assert_eq!(output, data);
+                        Ok::<_, Error>(summary.trailing_bytes)
+                    };
+                    let res = decode(TrailingBytes::Error);
+                    if padding == 0 {
+                        assert_eq!(res, Ok(0));
+                    } else {
+                        assert_eq!(res, Err(Error::LzmaError(LzmaError::TrailingBytes)));
+                    }
+                    assert_eq!(decode(TrailingBytes::Ignore), Ok(0));
+                    assert_eq!(decode(TrailingBytes::ReturnCount), Ok(padding as u64));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn plain_slice_input() {
+    let data = read_all_file("tests/files/foo.txt").unwrap();
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress(&data[..], &mut compressed).unwrap();
+
+    let mut output = Vec::new();
+    lzma_rs::lzma_decompress::<_, _, 4096, 8>(&compressed[..], &mut output).unwrap();
+    assert_eq!(output, data);
+
+    let mut output = vec![0; data.len()];
+    let len = lzma_rs::lzma_decompress_to_slice::<_, 8>(&compressed[..], &mut output).unwrap();
+    assert_eq!(&output[..len], &data[..]);
+
+    let summary = lzma_rs::lzma_decompress_with_summary::<_, _, 4096, 8>(
+        &compressed[..],
+        &mut std::io::sink(),
+        &lzma_rs::decompress::Options::default(),
+    )
+    .unwrap();
+    assert_eq!(summary.consumed, compressed.len() as u64);
+}
+
+#[test]
+fn params_display() {
+    let options = lzma_rs::decompress::Options::default();
+    let cases: &[(&[u8], &str)] = &[
+        (
+            b"\x5d\x00\x00\x10\x00\xff\xff\xff\xff\xff\xff\xff\xff",
+            "lc=3 lp=0 pb=2 dict=1MiB size=unknown(eos)",
+        ),
+        (
+            b"\x00\x00\x00\x00\x00\x00\x02\x00\x00\x00\x00\x00\x00",
+            "lc=0 lp=0 pb=0 dict=4KiB size=512B",
+        ),
+        (
+            b"\x5e\x00\x00\x10\x00\x00\xfc\xff\x3f\x00\x00\x00\x00",
+            "lc=4 lp=0 pb=2 dict=1MiB size=1.0GiB",
+        ),
+        (
+            b"\x5d\x00\x00\x00\x80\x00\x00\x00\x00\x0a\x00\x00\x00",
+            "lc=3 lp=0 pb=2 dict=2GiB size=40GiB",
+        ),
+    ];
+    for (header, expected) in cases {
+        let params =
+            lzma_rs::decompress::LzmaParams::read_header(&mut &header[..], &options).unwrap();
+        assert_eq!(params.to_string(), *expected);
+    }
+}
+
+#[test]
+fn rewrite_header() {
+    let data = b"Hello world, hello header";
+    let options = lzma_rs::compress::Options {
+        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(None),
+        dict_size: lzma_rs::compress::DictSize::Fixed(1 << 30),
+        ..Default::default()
+    };
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress_with_options(&data[..], &mut compressed, &options).unwrap();
+    let mut decomp = Vec::new();
+    match lzma_rs::lzma_decompress::<_, _, 4096, 66>(&compressed[..], &mut decomp) {
+        Err(lzma_rs::error::Error::DictionaryBufferTooSmall { .. }) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+
+    let mut patched = Vec::new();
+    lzma_rs::compress::rewrite_header(
+        &compressed[..],
+        &mut patched,
+        Some(data.len() as u64),
+        Some(4096),
+    )
+    .unwrap();
+    assert_eq!(patched.len(), compressed.len());
+    assert_eq!(&patched[13..], &compressed[13..]);
+    let params = lzma_rs::decompress::LzmaParams::read_header(
+        &mut &patched[..],
+        &lzma_rs::decompress::Options::default(),
+    )
+    .unwrap();
+    assert_eq!(params.dict_size(), 4096);
+    assert_eq!(params.unpacked_size(), Some(data.len() as u64));
+    lzma_rs::lzma_decompress::<_, _, 4096, 66>(&patched[..], &mut decomp).unwrap();
+    assert_eq!(decomp, data);
+
+    // Unchanged fields are kept
+    let mut unchanged = Vec::new();
+    lzma_rs::compress::rewrite_header(&compressed[..], &mut unchanged, None, None).unwrap();
+    assert_eq!(unchanged, compressed);
+
+    match lzma_rs::compress::rewrite_header(&compressed[..7], &mut Vec::new(), None, None) {
+        Err(lzma_rs::error::Error::HeaderTooShort {
+            have: 7,
+            need: 13,
+            field: lzma_rs::error::lzma::HeaderField::UnpackedSize,
+        }) => {}
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn analyze_dict_size() {
+    let compressed = include_bytes!("files/hugedict.txt.lzma");
+    let expected = include_bytes!("files/foo.txt");
+    let analysis =
+        lzma_rs::decompress::analyze_dict_size(&compressed[..], &Default::default()).unwrap();
+    assert_eq!(analysis.params.dict_size(), 0x20000);
+    assert_eq!(analysis.unpacked_size, expected.len() as u64);
+    assert_eq!(analysis.max_distance, 127916);
+    assert_eq!(analysis.min_dict_size(), 127916);
+
+    #[cfg(feature = "stream")]
+    for (dict_size, fits) in &[(127916, true), (127915, false)] {
+        let mut patched = Vec::new();
+        lzma_rs::compress::rewrite_header(&compressed[..], &mut patched, None, Some(*dict_size))
+            .unwrap();
+        let mut dict = vec![0; *dict_size as usize];
+        let mut probs = vec![[0; 0x300]; 8];
+        let mut stream = lzma_rs::decompress::DynStream::new(&mut dict, &mut probs, &mut []);
+        stream.reset();
+        let mut decomp = Vec::new();
+        let res = stream
+            .write_all(&mut decomp, &patched)
+            .and_then(|_| stream.finish(&mut decomp));
+        match res {
+            Ok(()) if *fits => assert_eq!(decomp, &expected[..]),
+            Err(lzma_rs::error::Error::LzmaError(
+                lzma_rs::error::lzma::LzmaError::LzDistanceIsBeyondDictionarySize { .. },
+            )) if !fits => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
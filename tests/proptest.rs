@@ -0,0 +1,126 @@
+//! Property-based round-trip tests, enabled with the `proptest-tests`
+//! feature: `cargo test --release --features proptest-tests --test proptest`.
+#![cfg(feature = "proptest-tests")]
+
+use lzma_rs::compress::{self, UnpackedSize};
+use lzma_rs::decompress::{self, DynStream, LiteralCoder};
+use lzma_rs::properties::LzmaProperties;
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestRunner};
+
+/// Largest dictionary size declared by the tested streams.
+const MAX_DICT_SIZE: usize = 1 << 20;
+
+/// Inputs made of random bytes, runs of a single byte and repeated text, to
+/// exercise literal contexts beyond plain noise.
+fn input() -> impl Strategy<Value = Vec<u8>> {
+    let segment = prop_oneof![
+        prop::collection::vec(any::<u8>(), 0..256),
+        (any::<u8>(), 0..512usize).prop_map(|(byte, len)| vec![byte; len]),
+        (0..64usize).prop_map(|len| b"lorem ipsum dolor sit amet ".repeat(len)),
+    ];
+    prop::collection::vec(segment, 0..8).prop_map(|segments| segments.concat())
+}
+
+fn properties() -> impl Strategy<Value = LzmaProperties> {
+    (0..=8u32, 0..=4u32, 0..=4u32).prop_map(|(lc, lp, pb)| LzmaProperties::new(lc, lp, pb).unwrap())
+}
+
+/// Dictionary size written to the header. The encoder only emits literals,
+/// so any size is valid for decoding.
+fn dict_size() -> impl Strategy<Value = usize> {
+    prop_oneof![Just(4096), Just(8192), Just(1 << 16), Just(MAX_DICT_SIZE)]
+}
+
+fn chunks() -> impl Strategy<Value = Vec<usize>> {
+    prop::collection::vec(1..64usize, 1..16)
+}
+
+fn compress(
+    data: &[u8],
+    properties: LzmaProperties,
+    size_in_header: bool,
+    dict_size: usize,
+) -> Vec<u8> {
+    let size = if size_in_header {
+        Some(data.len() as u64)
+    } else {
+        None
+    };
+    let options = compress::Options {
+        unpacked_size: UnpackedSize::WriteToHeader(size),
+        properties,
+        ..Default::default()
+    };
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress_with_options(&mut &data[..], &mut compressed, &options).unwrap();
+    compressed[1..5].copy_from_slice(&(dict_size as u32).to_le_bytes());
+    compressed
+}
+
+fn decompress_one_shot(compressed: &[u8]) -> Vec<u8> {
+    let mut decomp = Vec::new();
+    lzma_rs::lzma_decompress::<_, _, MAX_DICT_SIZE, 4096>(&mut &compressed[..], &mut decomp)
+        .unwrap();
+    decomp
+}
+
+fn decompress_chunked(
+    compressed: &[u8],
+    properties: LzmaProperties,
+    dict_size: usize,
+    chunks: &[usize],
+) -> Vec<u8> {
+    let mut dict = vec![0; dict_size];
+    let mut probs: Vec<LiteralCoder> = vec![[0; 0x300]; properties.literal_coders()];
+    let mut stream = DynStream::new_with_options(
+        &mut dict,
+        &mut probs,
+        &mut [],
+        &decompress::Options::default(),
+    );
+    stream.reset();
+    let mut decomp = Vec::new();
+    let mut input = compressed;
+    for len in chunks.iter().cycle() {
+        if input.is_empty() {
+            break;
+        }
+        let (chunk, rest) = input.split_at(std::cmp::min(*len, input.len()));
+        stream.write_all(&mut decomp, chunk).unwrap();
+        input = rest;
+    }
+    stream.finish(&mut decomp).unwrap();
+    decomp
+}
+
+#[test]
+fn round_trip() {
+    // The one-shot decoder keeps up to 4096 literal coders on the stack
+    std::thread::Builder::new()
+        .stack_size(64 << 20)
+        .spawn(|| {
+            let mut runner = TestRunner::new(Config {
+                cases: 512,
+                ..Config::default()
+            });
+            let strategy = (input(), properties(), any::<bool>(), dict_size(), chunks());
+            runner
+                .run(
+                    &strategy,
+                    |(data, properties, size_in_header, dict_size, chunks)| {
+                        let compressed = compress(&data, properties, size_in_header, dict_size);
+                        prop_assert_eq!(&decompress_one_shot(&compressed), &data);
+                        prop_assert_eq!(
+                            &decompress_chunked(&compressed, properties, dict_size, &chunks),
+                            &data
+                        );
+                        Ok(())
+                    },
+                )
+                .unwrap();
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
@@ -0,0 +1,83 @@
+#![cfg(feature = "serde")]
+
+#[cfg(feature = "encoder")]
+use lzma_rs::compress;
+use lzma_rs::decompress;
+
+#[test]
+fn decompress_options_roundtrip() {
+    let options = decompress::Options {
+        unpacked_size: decompress::UnpackedSize::UseProvided(Some(42)),
+        reject_small_dict: true,
+        max_dict_size: Some(1 << 20),
+        max_symbols_per_write: Some(64),
+        trailing_bytes: Some(decompress::TrailingBytes::ReturnCount),
+    };
+    let json = serde_json::to_string(&options).unwrap();
+    assert_eq!(
+        json,
+        r#"{"unpacked_size":{"UseProvided":42},"reject_small_dict":true,"max_dict_size":1048576,"max_symbols_per_write":64,"trailing_bytes":"ReturnCount"}"#
+    );
+    let parsed: decompress::Options = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, options);
+
+    // Missing fields take their default value
+    let parsed: decompress::Options =
+        serde_json::from_str(r#"{"reject_small_dict":true}"#).unwrap();
+    assert_eq!(
+        parsed,
+        decompress::Options {
+            reject_small_dict: true,
+            ..Default::default()
+        }
+    );
+}
+
+#[cfg(feature = "encoder")]
+#[test]
+fn compress_options_roundtrip() {
+    let options = compress::Options {
+        unpacked_size: compress::UnpackedSize::WriteToHeader32(Some(7)),
+        properties: lzma_rs::properties::LzmaProperties {
+            lc: 0,
+            lp: 2,
+            pb: 0,
+        },
+        header: compress::HeaderFormat::Raw,
+        dict_size: compress::DictSize::Auto { max: 1 << 20 },
+        deterministic: true,
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&options).unwrap();
+    let parsed: compress::Options = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.unpacked_size, options.unpacked_size);
+    assert_eq!(parsed.properties, options.properties);
+    assert_eq!(parsed.header, options.header);
+    assert!(parsed.deterministic);
+    assert!(parsed.progress.is_none() && parsed.cancel.is_none());
+}
+
+#[cfg(feature = "stream")]
+#[test]
+fn memory_footprint_serialize() {
+    let footprint = decompress::Stream::<4096, 8>::memory_footprint();
+    let json = serde_json::to_value(footprint).unwrap();
+    assert_eq!(json["dict"], 4096);
+    assert_eq!(json["total"], footprint.total);
+}
+
+#[cfg(all(feature = "stats", feature = "encoder"))]
+#[test]
+fn decode_stats_roundtrip() {
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress(&mut &b"Hello, hello, hello"[..], &mut compressed).unwrap();
+    let stats = lzma_rs::lzma_decompress_with_stats::<_, _, 4096, 8>(
+        &mut &compressed[..],
+        &mut Vec::new(),
+        &decompress::Options::default(),
+    )
+    .unwrap();
+    let json = serde_json::to_string(&stats).unwrap();
+    let parsed: decompress::DecodeStats = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, stats);
+}
@@ -0,0 +1,85 @@
+//! Differential tests against liblzma, enabled with the `xz-compat-tests`
+//! feature.
+//!
+//! Every `.lzma` file of the corpus directory is decompressed with both
+//! lzma-rs and liblzma, which must agree on the result: either the same
+//! decompressed data or both an error. The corpus defaults to `tests/files`
+//! and can be set with the `LZMA_RS_COMPAT_CORPUS` environment variable.
+#![cfg(feature = "xz-compat-tests")]
+
+use lzma_rs::decompress::{DynStream, LiteralCoder};
+use lzma_rs::properties::LzmaProperties;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+/// Largest dictionary allocated for a corpus file; larger ones are reported
+/// as an error, as liblzma does past its memory limit.
+const MAX_DICT_SIZE: usize = 1 << 26;
+
+fn decompress_lzma_rs(compressed: &[u8]) -> Result<Vec<u8>, String> {
+    // Size the buffers from the header
+    if compressed.len() < 5 {
+        return Err("header too short".to_string());
+    }
+    let properties = LzmaProperties::try_from(compressed[0]).map_err(|e| format!("{:?}", e))?;
+    let dict_size =
+        u32::from_le_bytes([compressed[1], compressed[2], compressed[3], compressed[4]]);
+    let dict_size = std::cmp::max(dict_size as usize, 4096);
+    if dict_size > MAX_DICT_SIZE {
+        return Err(format!("dictionary too large: {}", dict_size));
+    }
+    let mut dict = vec![0; dict_size];
+    let mut probs: Vec<LiteralCoder> = vec![[0; 0x300]; properties.literal_coders()];
+    let mut stream = DynStream::new(&mut dict, &mut probs, &mut []);
+    stream.reset();
+    let mut decomp = Vec::new();
+    stream
+        .write_all(&mut decomp, compressed)
+        .and_then(|_| stream.finish(&mut decomp))
+        .map_err(|e| format!("{:?}", e))?;
+    Ok(decomp)
+}
+
+fn decompress_liblzma(compressed: &[u8]) -> Result<Vec<u8>, String> {
+    lzma::decompress(compressed).map_err(|e| format!("{:?}", e))
+}
+
+#[test]
+fn compare_corpus_to_liblzma() {
+    let corpus = std::env::var_os("LZMA_RS_COMPAT_CORPUS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("tests/files"));
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&corpus)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lzma"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "no .lzma file in {}", corpus.display());
+
+    let mut mismatches = Vec::new();
+    for path in &files {
+        let compressed = std::fs::read(path).unwrap();
+        match (
+            decompress_lzma_rs(&compressed),
+            decompress_liblzma(&compressed),
+        ) {
+            (Ok(ours), Ok(theirs)) if ours == theirs => {}
+            (Err(_), Err(_)) => {}
+            (ours, theirs) => mismatches.push(format!(
+                "{}: lzma-rs {}, liblzma {}",
+                path.display(),
+                describe(&ours),
+                describe(&theirs)
+            )),
+        }
+    }
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
+
+fn describe(result: &Result<Vec<u8>, String>) -> String {
+    match result {
+        Ok(data) => format!("decoded {} bytes", data.len()),
+        Err(e) => format!("failed: {}", e),
+    }
+}